@@ -0,0 +1,245 @@
+// A regression gate for the benchmarks in src/bench.rs.
+//
+// Criterion records the results of every benchmark it runs under
+// target/criterion/<group>/<benchmark>/<baseline>/, where the baseline is
+// named with `--save-baseline` (and the latest run is always also recorded as
+// `new`). This compares the throughput of every benchmark between two of
+// those baselines, prints the change for each one, and exits with a failure
+// if any of them got slower by more than a threshold. For example:
+//
+//     $ cargo bench -- --save-baseline before    # on the old code
+//     $ cargo bench                              # on the new code
+//     $ cargo run --release --bin gate -- before
+//
+// Benchmark results vary from run to run, so the threshold shouldn't be much
+// lower than the noise on the machine running them. Only baselines recorded
+// on the same machine are comparable.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use serde_json::Value;
+
+const USAGE: &str = "\
+Usage: gate [options] <baseline> [<candidate>]
+
+Compares the throughput of every benchmark recorded by Criterion under the
+baseline named <baseline> with the one named <candidate> (default: new), and
+fails if any benchmark regressed by more than the threshold.
+
+Options:
+    --threshold <percent>   The largest slowdown allowed (default: 5).
+    --report                Print the comparison, but never fail.
+    --dir <path>            Criterion's output directory
+                            (default: target/criterion in the workspace).
+";
+
+/// The slowdown, in percent, that fails the gate unless --threshold is
+/// given.
+const DEFAULT_THRESHOLD: f64 = 5.0;
+
+/// The throughput of every benchmark in a baseline, in bytes per second,
+/// keyed by `<group>/<benchmark>`.
+type Results = BTreeMap<String, f64>;
+
+fn main() {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+    let baseline = read_baseline(&args.dir, &args.baseline);
+    let candidate = read_baseline(&args.dir, &args.candidate);
+    if baseline.is_empty() {
+        eprintln!(
+            "no benchmarks found for baseline '{}' in {}",
+            args.baseline,
+            args.dir.display()
+        );
+        process::exit(2);
+    }
+
+    println!(
+        "{:<40} {:>14} {:>14} {:>9}",
+        "benchmark", args.baseline, args.candidate, "change"
+    );
+    let mut regressions = 0;
+    for (name, &before) in &baseline {
+        let after = match candidate.get(name) {
+            Some(&after) => after,
+            None => {
+                println!("{:<40} {:>14} {:>14}", name, mbps(before), "-");
+                continue;
+            }
+        };
+        let change = 100.0 * (after / before - 1.0);
+        let regressed = -change > args.threshold;
+        if regressed {
+            regressions += 1;
+        }
+        println!(
+            "{:<40} {:>14} {:>14} {:>+8.2}%{}",
+            name,
+            mbps(before),
+            mbps(after),
+            change,
+            if regressed { "  REGRESSED" } else { "" }
+        );
+    }
+    for (name, &after) in &candidate {
+        if !baseline.contains_key(name) {
+            println!("{:<40} {:>14} {:>14}", name, "-", mbps(after));
+        }
+    }
+
+    if regressions > 0 {
+        println!(
+            "\n{} benchmark(s) regressed by more than {}%",
+            regressions, args.threshold
+        );
+        if !args.report {
+            process::exit(1);
+        }
+    }
+}
+
+struct Args {
+    baseline: String,
+    candidate: String,
+    threshold: f64,
+    report: bool,
+    dir: PathBuf,
+}
+
+impl Args {
+    fn parse<I: Iterator<Item = String>>(mut it: I) -> Result<Args, String> {
+        let mut names = vec![];
+        let mut threshold = DEFAULT_THRESHOLD;
+        let mut report = false;
+        let mut dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .join("target")
+            .join("criterion");
+        while let Some(arg) = it.next() {
+            match &*arg {
+                "-h" | "--help" => {
+                    print!("{}", USAGE);
+                    process::exit(0);
+                }
+                "--threshold" => {
+                    let value =
+                        it.next().ok_or("missing --threshold value")?;
+                    threshold = match value.parse::<f64>() {
+                        Ok(t) if t >= 0.0 => t,
+                        _ => {
+                            return Err(format!(
+                                "invalid threshold: {}",
+                                value
+                            ))
+                        }
+                    };
+                }
+                "--report" => report = true,
+                "--dir" => {
+                    dir =
+                        PathBuf::from(it.next().ok_or("missing --dir value")?);
+                }
+                _ if arg.starts_with('-') => {
+                    return Err(format!("unknown option: {}", arg));
+                }
+                _ => names.push(arg),
+            }
+        }
+        let mut names = names.into_iter();
+        let baseline = names.next().ok_or("missing baseline name")?;
+        let candidate = names.next().unwrap_or_else(|| "new".to_string());
+        if let Some(extra) = names.next() {
+            return Err(format!("unexpected argument: {}", extra));
+        }
+        Ok(Args { baseline, candidate, threshold, report, dir })
+    }
+}
+
+/// Reads the throughput of every benchmark recorded under `baseline` in
+/// Criterion's output directory `dir`.
+///
+/// Benchmarks without a recorded throughput, or whose results can't be read,
+/// are skipped with a warning.
+fn read_baseline(dir: &Path, baseline: &str) -> Results {
+    let mut results = Results::new();
+    for group in subdirs(dir) {
+        for bench in subdirs(&group) {
+            let path = bench.join(baseline);
+            if !path.is_dir() {
+                continue;
+            }
+            match read_result(&path) {
+                Ok((name, throughput)) => {
+                    results.insert(name, throughput);
+                }
+                Err(msg) => eprintln!("skipping {}: {}", path.display(), msg),
+            }
+        }
+    }
+    results
+}
+
+/// Reads the name and throughput, in bytes per second, of the benchmark
+/// result recorded in `path`.
+///
+/// The throughput is computed from the median time per iteration, which is
+/// less sensitive to outliers than the mean.
+fn read_result(path: &Path) -> Result<(String, f64), String> {
+    let benchmark = read_json(&path.join("benchmark.json"))?;
+    let estimates = read_json(&path.join("estimates.json"))?;
+    let name = benchmark["full_id"]
+        .as_str()
+        .ok_or("benchmark.json has no full_id")?
+        .to_string();
+    let bytes = benchmark["throughput"]["Bytes"]
+        .as_f64()
+        .ok_or("benchmark.json has no throughput in bytes")?;
+    // Older versions of Criterion capitalize the names of estimates.
+    let median = if estimates["median"].is_null() {
+        &estimates["Median"]
+    } else {
+        &estimates["median"]
+    };
+    let nanos = median["point_estimate"]
+        .as_f64()
+        .filter(|&nanos| nanos > 0.0)
+        .ok_or("estimates.json has no median")?;
+    Ok((name, bytes / (nanos / 1e9)))
+}
+
+fn read_json(path: &Path) -> Result<Value, String> {
+    let data = fs::read(path).map_err(|err| err.to_string())?;
+    serde_json::from_slice(&data).map_err(|err| err.to_string())
+}
+
+/// Returns the directories in `dir`, except for Criterion's HTML reports.
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_dir() && !path.ends_with("report"))
+            .collect(),
+        Err(_) => vec![],
+    };
+    dirs.sort();
+    dirs
+}
+
+/// Formats a throughput in bytes per second the way Criterion does.
+fn mbps(bytes_per_sec: f64) -> String {
+    let mb = bytes_per_sec / (1 << 20) as f64;
+    if mb >= 1024.0 {
+        format!("{:.1} GB/sec", mb / 1024.0)
+    } else {
+        format!("{:.1} MB/sec", mb)
+    }
+}
@@ -21,7 +21,16 @@ fn try_main() -> Result<()> {
         Some(out_dir) => PathBuf::from(out_dir),
     };
     write_tag_lookup_table(&out_dir)?;
-    write_crc_tables(&out_dir)?;
+    let crc32c = crc32c_backend();
+    write_crc_tables(&out_dir, crc32c)?;
+    println!("cargo:rustc-cfg=crc32c=\"{}\"", crc32c);
+    println!(
+        "cargo:rustc-check-cfg=cfg(crc32c, values(\"none\", \"slice16\", \
+         \"nibble\", \"sse4.2\", \"riscv-zbc\", \"s390x-vector\"))"
+    );
+    // The proof harnesses in decompress.rs are only compiled by Kani, which
+    // sets the kani cfg. Declare it so that other builds don't warn about it.
+    println!("cargo:rustc-check-cfg=cfg(kani)");
     Ok(())
 }
 
@@ -34,6 +43,41 @@ fn write_tag_lookup_table(out_dir: &Path) -> Result<()> {
         writeln!(out, "    {},", tag_entry(b))?;
     }
     writeln!(out, "];")?;
+
+    // The SSSE3 copy kernel of the decoder is only compiled in when SSSE3 is
+    // enabled at compile time, so its masks are only generated then too.
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let features = env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default();
+    if (arch == "x86" || arch == "x86_64")
+        && features.split(',').any(|f| f == "ssse3")
+    {
+        writeln!(out)?;
+        write_pattern_masks(&mut out, "PATTERN_SHUFFLE", 0)?;
+        writeln!(out)?;
+        write_pattern_masks(&mut out, "PATTERN_RESHUFFLE", 16)?;
+    }
+    Ok(())
+}
+
+/// Writes a table of `pshufb` masks indexed by a copy offset from `1` to
+/// `15`, where lane `i` of the mask for `offset` is `(shift + i) % offset`.
+///
+/// With a shift of `0`, the mask repeats the first `offset` bytes of a vector
+/// across all 16 lanes. With a shift of `16`, the mask turns one 16 byte
+/// piece of such a repeated pattern into the next one.
+fn write_pattern_masks<W: Write>(
+    out: &mut W,
+    name: &str,
+    shift: usize,
+) -> Result<()> {
+    writeln!(out, "pub const {}: [[u8; 16]; 16] = [", name)?;
+    writeln!(out, "    [0; 16],")?;
+    for offset in 1..16 {
+        let lanes: Vec<String> =
+            (0..16).map(|i| ((shift + i) % offset).to_string()).collect();
+        writeln!(out, "    [{}],", lanes.join(", "))?;
+    }
+    writeln!(out, "];")?;
     Ok(())
 }
 
@@ -66,10 +110,59 @@ fn tag_entry(b: u8) -> u16 {
     }
 }
 
-fn write_crc_tables(out_dir: &Path) -> Result<()> {
+/// Returns the CRC32C implementation to compile, which the crate selects
+/// with the `crc32c` cfg. Checksums are only computed by the frame encoders
+/// and decoders, so none is compiled without them. The accelerated ones need
+/// their instructions to be enabled at compile time, since CPUID can't be
+/// used inside an SGX enclave to detect them at runtime. The RISC-V and IBM Z
+/// ones are written in inline assembly, which needs a newer Rust than the
+/// crate otherwise supports, so they also need the `unstable` feature. Otherwise,
+/// the portable one is used, which is the nibble at a time one with the
+/// `small-crc` feature.
+fn crc32c_backend() -> &'static str {
+    let feature = |name: &str| env::var_os(name).is_some();
+    if !feature("CARGO_FEATURE_FRAME")
+        || !(feature("CARGO_FEATURE_COMPRESS")
+            || feature("CARGO_FEATURE_DECOMPRESS"))
+    {
+        return "none";
+    }
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let features = env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default();
+    let has = |feature: &str| features.split(',').any(|f| f == feature);
+    match &*arch {
+        "x86" | "x86_64" if has("sse4.2") => "sse4.2",
+        "riscv64" if has("zbc") && feature("CARGO_FEATURE_UNSTABLE") => {
+            "riscv-zbc"
+        }
+        "s390x" if has("vector") && feature("CARGO_FEATURE_UNSTABLE") => {
+            "s390x-vector"
+        }
+        _ if feature("CARGO_FEATURE_SMALL_CRC") => "nibble",
+        _ => "slice16",
+    }
+}
+
+fn write_crc_tables(out_dir: &Path, crc32c: &str) -> Result<()> {
+    // Only the portable implementations need tables.
+    if crc32c != "slice16" && crc32c != "nibble" {
+        return Ok(());
+    }
     let out_path = out_dir.join("crc32_table.rs");
     let mut out = io::BufWriter::new(File::create(out_path)?);
 
+    // The small-crc feature trades the 17KB of tables below for a single
+    // 16 entry table that checksums a nibble at a time.
+    if crc32c == "nibble" {
+        writeln!(out, "pub const TABLE4: [u32; 16] = [")?;
+        for &x in make_table4(CASTAGNOLI_POLY).iter() {
+            writeln!(out, "    {},", x)?;
+        }
+        writeln!(out, "];")?;
+        out.flush()?;
+        return Ok(());
+    }
+
     let table = make_table(CASTAGNOLI_POLY);
     let table16 = make_table16(CASTAGNOLI_POLY);
 
@@ -107,6 +200,22 @@ fn make_table16(poly: u32) -> [[u32; 256]; 16] {
     tab
 }
 
+fn make_table4(poly: u32) -> [u32; 16] {
+    let mut tab = [0; 16];
+    for i in 0u32..16u32 {
+        let mut crc = i;
+        for _ in 0..4 {
+            if crc & 1 == 1 {
+                crc = (crc >> 1) ^ poly;
+            } else {
+                crc >>= 1;
+            }
+        }
+        tab[i as usize] = crc;
+    }
+    tab
+}
+
 fn make_table(poly: u32) -> [u32; 256] {
     let mut tab = [0; 256];
     for i in 0u32..256u32 {
@@ -0,0 +1,44 @@
+// Checks that masking a CRC32C checksum, as the frame format does, can be
+// undone, and that the checksum in the header of a chunk written by the frame
+// encoder unmasks to the CRC32C of the chunk's data.
+
+#![no_main]
+
+use std::io::Write;
+
+use libfuzzer_sys::fuzz_target;
+use snap::frame::{mask_checksum, unmask_checksum, STREAM_IDENTIFIER};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() >= 4 {
+        let sum = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        assert_eq!(sum, unmask_checksum(mask_checksum(sum)));
+        assert_eq!(sum, mask_checksum(unmask_checksum(sum)));
+    }
+
+    // Chunks hold at most 64KB, so this is always a single chunk.
+    let data = &data[..data.len().min(snap::frame::MAX_BLOCK_SIZE)];
+    if data.is_empty() {
+        return;
+    }
+    let mut wtr = snap::write::FrameEncoder::new(vec![]);
+    wtr.write_all(data).unwrap();
+    let framed = wtr.into_inner().unwrap();
+    let header = &framed[STREAM_IDENTIFIER.len()..];
+    let masked =
+        u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    assert_eq!(crc32c(data), unmask_checksum(masked));
+});
+
+/// Computes the CRC32C checksum of `data` a bit at a time, independently of
+/// the table driven implementations in snap.
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0x82F63B78 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
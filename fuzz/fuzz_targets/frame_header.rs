@@ -0,0 +1,86 @@
+// Checks that the frame decoder's handling of chunk headers doesn't depend on
+// how its input is split up between reads.
+//
+// The first byte of the fuzz input gives the number of bytes after it that
+// make up a schedule of read sizes, and the rest is a Snappy frame formatted
+// stream. The stream is decoded once from a reader that returns all of it at
+// once, and once from a reader that hands it out in pieces of the sizes in
+// the schedule (a size of 0 interrupts a read once). Both must produce the
+// same bytes, or fail with the same error.
+
+#![no_main]
+
+use std::io::{self, Read};
+
+use libfuzzer_sys::fuzz_target;
+use snap::read::FrameDecoder;
+
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+    let n = 1 + (data[0] as usize % 16);
+    if data.len() < 1 + n {
+        return;
+    }
+    let (sizes, stream) = (&data[1..1 + n], &data[1 + n..]);
+
+    let whole = decode(FrameDecoder::new(stream), 1 << 16);
+    let split = decode(
+        FrameDecoder::new(Split {
+            data: stream,
+            sizes,
+            next: 0,
+            interrupted: false,
+        }),
+        // Also vary the size of the reads from the decoder.
+        1 + sizes[0] as usize * 3,
+    );
+    assert_eq!(whole, split);
+});
+
+/// Reads everything from `dec`, `buf_size` bytes at a time, returning either
+/// the decompressed bytes or a description of the error that stopped it.
+fn decode<R: Read>(
+    mut dec: FrameDecoder<R>,
+    buf_size: usize,
+) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    let mut buf = vec![0; buf_size];
+    loop {
+        match dec.read(&mut buf) {
+            Ok(0) => return Ok(out),
+            Ok(n) => out.extend_from_slice(&buf[..n]),
+            Err(err) => return Err(format!("{:?}: {}", err.kind(), err)),
+        }
+    }
+}
+
+/// A reader that returns `data` in pieces whose sizes cycle through `sizes`.
+///
+/// A size of 0 makes a read fail with `ErrorKind::Interrupted`, after which
+/// the read is retried with a size of 1, so that a schedule of all zeros
+/// still makes progress.
+struct Split<'a> {
+    data: &'a [u8],
+    sizes: &'a [u8],
+    next: usize,
+    interrupted: bool,
+}
+
+impl<'a> Read for Split<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.sizes[self.next % self.sizes.len()] as usize;
+        if size == 0 && !self.interrupted {
+            self.interrupted = true;
+            return Err(io::Error::from(io::ErrorKind::Interrupted));
+        }
+        self.next += 1;
+        self.interrupted = false;
+        let size = size.max(1);
+        let n = size.min(buf.len()).min(self.data.len());
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        Ok(n)
+    }
+}
@@ -0,0 +1,148 @@
+/*!
+A check that decompressing untrusted input with snap can't panic, only return
+errors.
+
+Every function below wraps a snap decoding routine in a guard whose drop
+calls a function that doesn't exist. The guard is only dropped if the routine
+unwinds, so if the optimizer can't prove that the routine never panics, then
+linking fails with an error naming the missing function. Build it with:
+
+    $ cargo build --release --manifest-path nopanic/Cargo.toml
+
+The check covers decoding a single raw Snappy block, and parsing and
+decoding the chunks of a frame formatted stream with
+`frame::decompress_to_sink`, which decodes into a buffer given by the caller.
+The other frame decoders call back into readers, filters and digests, and
+report errors as `io::Error`s that are dropped through trait objects, any of
+which the optimizer must assume may panic.
+*/
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{self, Read};
+use std::mem;
+use std::process;
+
+/// The system allocator, except that running out of memory aborts inside
+/// `alloc`, where the optimizer can see it. Aborting is what the standard
+/// library does by default anyway, but only once the caller has passed the
+/// null pointer to a handler that the optimizer must assume may panic.
+struct AbortOnOom;
+
+unsafe impl GlobalAlloc for AbortOnOom {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if ptr.is_null() {
+            process::abort();
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOC: AbortOnOom = AbortOnOom;
+
+/// Dropping this fails to link, so it must be forgotten on every path that
+/// doesn't unwind.
+struct Guard;
+
+extern "C" {
+    #[link_name = "\n\nERROR: a snap decoding routine may panic\n\n"]
+    fn may_panic() -> !;
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        unsafe { may_panic() }
+    }
+}
+
+/// Calls `f`, failing to link if it may panic.
+#[inline(always)]
+fn no_panic<T, F: FnOnce() -> T>(f: F) -> T {
+    let guard = Guard;
+    let t = f();
+    mem::forget(guard);
+    t
+}
+
+#[no_mangle]
+pub extern "C" fn decompress_len(input: *const u8, input_len: usize) -> isize {
+    let input = unsafe { std::slice::from_raw_parts(input, input_len) };
+    no_panic(|| match snap::raw::decompress_len(input) {
+        Ok(n) => n as isize,
+        Err(_) => -1,
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn decompress(
+    input: *const u8,
+    input_len: usize,
+    output: *mut u8,
+    output_len: usize,
+) -> isize {
+    let input = unsafe { std::slice::from_raw_parts(input, input_len) };
+    let output = unsafe { std::slice::from_raw_parts_mut(output, output_len) };
+    no_panic(|| match snap::raw::Decoder::new().decompress(input, output) {
+        Ok(n) => n as isize,
+        Err(_) => -1,
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn frame_decompress(
+    input: *const u8,
+    input_len: usize,
+    window: *mut u8,
+    window_len: usize,
+) -> isize {
+    let input = unsafe { std::slice::from_raw_parts(input, input_len) };
+    let window = unsafe { std::slice::from_raw_parts_mut(window, window_len) };
+    no_panic(|| {
+        let mut len = 0;
+        let res = snap::frame::decompress_to_sink(input, window, |data| {
+            len += data.len();
+            Ok::<(), snap::Error>(())
+        });
+        match res {
+            Ok(()) => len as isize,
+            Err(_) => -1,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn unmask_checksum(masked: u32) -> u32 {
+    no_panic(|| snap::frame::unmask_checksum(masked))
+}
+
+fn main() {
+    // The checks happen when linking, so there's nothing to run. The
+    // functions above are called on whatever is read from stdin, since calls
+    // on constant inputs would be folded away by the optimizer, along with
+    // any paths that such inputs don't take.
+    let mut input = vec![];
+    io::stdin().read_to_end(&mut input).unwrap();
+    let mut output = vec![0; 1 << 16];
+    println!(
+        "{} {} {} {}",
+        decompress_len(input.as_ptr(), input.len()),
+        decompress(
+            input.as_ptr(),
+            input.len(),
+            output.as_mut_ptr(),
+            output.len()
+        ),
+        frame_decompress(
+            input.as_ptr(),
+            input.len(),
+            output.as_mut_ptr(),
+            output.len()
+        ),
+        unmask_checksum(input.len() as u32),
+    );
+}
@@ -0,0 +1,212 @@
+/*!
+This module provides a way to compress many files concurrently.
+
+[`compress_paths`](fn.compress_paths.html) compresses each file it's given
+to a new file next to it, with the `.sz` extension appended, using the Snappy
+frame format. Files are compressed independently on a fixed number of
+threads, and each is streamed from disk, so memory use is bounded by the
+number of threads regardless of how many files there are or how big they are.
+*/
+
+use std::prelude::v1::*;
+use std::cmp;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::frame::EncoderOptions;
+use crate::write::FrameEncoder;
+
+/// Options for configuring [`compress_paths`](fn.compress_paths.html).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BatchOptions {
+    encoder: EncoderOptions,
+    threads: usize,
+    overwrite: bool,
+}
+
+impl Default for BatchOptions {
+    fn default() -> BatchOptions {
+        BatchOptions::new()
+    }
+}
+
+impl BatchOptions {
+    /// Create the default options.
+    pub fn new() -> BatchOptions {
+        BatchOptions {
+            encoder: EncoderOptions::new(),
+            threads: 1,
+            overwrite: false,
+        }
+    }
+
+    /// Set the options that every file is compressed with.
+    pub fn encoder(&mut self, options: &EncoderOptions) -> &mut BatchOptions {
+        self.encoder = options.clone();
+        self
+    }
+
+    /// Set the number of files compressed concurrently, each on its own
+    /// thread.
+    ///
+    /// Each thread holds the buffers of one frame encoder, so this also
+    /// bounds the memory used.
+    ///
+    /// This is set to `1` by default. A value of `0` is treated as `1`.
+    pub fn threads(&mut self, threads: usize) -> &mut BatchOptions {
+        self.threads = cmp::max(1, threads);
+        self
+    }
+
+    /// When enabled, compressed files that already exist are replaced.
+    /// Otherwise, a file whose compressed file already exists is skipped
+    /// and reported with an `AlreadyExists` error.
+    ///
+    /// This is disabled by default.
+    pub fn overwrite(&mut self, yes: bool) -> &mut BatchOptions {
+        self.overwrite = yes;
+        self
+    }
+}
+
+/// A report describing a file compressed by
+/// [`compress_paths`](fn.compress_paths.html).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileReport {
+    output: PathBuf,
+    original_len: u64,
+    compressed_len: u64,
+}
+
+impl FileReport {
+    /// Returns the path of the compressed file that was written.
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    /// Returns the size of the original file, in bytes.
+    pub fn original_len(&self) -> u64 {
+        self.original_len
+    }
+
+    /// Returns the size of the compressed file, in bytes.
+    pub fn compressed_len(&self) -> u64 {
+        self.compressed_len
+    }
+}
+
+/// Compresses every file in `paths` to a file of the same name with `.sz`
+/// appended, and returns the outcome for each path, in the order the paths
+/// were given.
+///
+/// The original files are left in place. Paths are taken from `paths` only
+/// as threads become free, so it may be a lazy walk of a directory tree.
+///
+/// A failure to compress one file doesn't stop the others from being
+/// compressed. When a file fails, whatever was written of its compressed file
+/// is removed, unless the compressed file already existed and
+/// [`overwrite`](struct.BatchOptions.html#method.overwrite) is disabled.
+pub fn compress_paths<I, P>(
+    paths: I,
+    options: &BatchOptions,
+) -> Vec<(PathBuf, io::Result<FileReport>)>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    let (jobs, jobs_rx) = mpsc::sync_channel::<(usize, PathBuf)>(0);
+    let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+    let (results_tx, results) = mpsc::channel();
+    let mut handles = Vec::with_capacity(options.threads);
+    for _ in 0..options.threads {
+        let jobs_rx = Arc::clone(&jobs_rx);
+        let results_tx = results_tx.clone();
+        let options = options.clone();
+        handles.push(thread::spawn(move || loop {
+            // The lock is released as soon as a job is received, so that
+            // the other threads can receive jobs while this one works.
+            let job = jobs_rx.lock().unwrap().recv();
+            let (i, path) = match job {
+                Ok(job) => job,
+                Err(_) => break,
+            };
+            let result = compress_path(&path, &options);
+            if results_tx.send((i, path, result)).is_err() {
+                break;
+            }
+        }));
+    }
+    drop(results_tx);
+
+    for (i, path) in paths.into_iter().enumerate() {
+        // This only fails if every thread has panicked, which is reported
+        // when they are joined below.
+        if jobs.send((i, path.as_ref().to_path_buf())).is_err() {
+            break;
+        }
+    }
+    drop(jobs);
+
+    let mut done: Vec<_> = results.iter().collect();
+    for handle in handles {
+        if let Err(err) = handle.join() {
+            panic::resume_unwind(err);
+        }
+    }
+    done.sort_by_key(|&(i, _, _)| i);
+    done.into_iter().map(|(_, path, result)| (path, result)).collect()
+}
+
+/// Compresses the file at `path` to the same path with `.sz` appended.
+fn compress_path(
+    path: &Path,
+    options: &BatchOptions,
+) -> io::Result<FileReport> {
+    let mut name = match path.file_name() {
+        Some(name) => name.to_os_string(),
+        None => {
+            let msg = format!("{}: missing file name", path.display());
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+        }
+    };
+    name.push(".sz");
+    let output = path.with_file_name(name);
+
+    let mut src = File::open(path)?;
+    let mut open = OpenOptions::new();
+    open.write(true);
+    if options.overwrite {
+        open.create(true).truncate(true);
+    } else {
+        open.create_new(true);
+    }
+    let dst = open.open(&output)?;
+    match copy_compressed(&mut src, dst, &options.encoder) {
+        Ok((original_len, compressed_len)) => {
+            Ok(FileReport { output, original_len, compressed_len })
+        }
+        Err(err) => {
+            let _ = fs::remove_file(&output);
+            Err(err)
+        }
+    }
+}
+
+/// Compresses everything in `src` to `dst`, and returns the number of bytes
+/// read and written.
+fn copy_compressed(
+    src: &mut File,
+    dst: File,
+    options: &EncoderOptions,
+) -> io::Result<(u64, u64)> {
+    let mut wtr = FrameEncoder::with_options(io::BufWriter::new(dst), options);
+    let original_len = io::copy(src, &mut wtr)?;
+    wtr.flush()?;
+    wtr.get_mut().flush()?;
+    let compressed_len = wtr.get_ref().get_ref().metadata()?.len();
+    Ok((original_len, compressed_len))
+}
@@ -1,9 +1,11 @@
 use std::prelude::v1::*;
 use std::convert::TryInto;
+#[cfg(all(feature = "frame", feature = "decompress"))]
 use std::io;
 
 /// Read a u16 in little endian format from the beginning of the given slice.
 /// This panics if the slice has length less than 2.
+#[cfg(feature = "decompress")]
 pub fn read_u16_le(slice: &[u8]) -> u16 {
     u16::from_le_bytes(slice[..2].try_into().unwrap())
 }
@@ -11,18 +13,21 @@ pub fn read_u16_le(slice: &[u8]) -> u16 {
 /// Read a u24 (returned as a u32 with the most significant 8 bits always set
 /// to 0) in little endian format from the beginning of the given slice. This
 /// panics if the slice has length less than 3.
+#[cfg(all(feature = "frame", feature = "decompress"))]
 pub fn read_u24_le(slice: &[u8]) -> u32 {
     slice[0] as u32 | (slice[1] as u32) << 8 | (slice[2] as u32) << 16
 }
 
 /// Read a u32 in little endian format from the beginning of the given slice.
 /// This panics if the slice has length less than 4.
+#[cfg(any(feature = "compress", feature = "decompress"))]
 pub fn read_u32_le(slice: &[u8]) -> u32 {
     u32::from_le_bytes(slice[..4].try_into().unwrap())
 }
 
 /// Like read_u32_le, but from an io::Read implementation. If io::Read does
 /// not yield at least 4 bytes, then this returns an unexpected EOF error.
+#[cfg(all(feature = "frame", feature = "decompress"))]
 pub fn io_read_u32_le<R: io::Read>(mut rdr: R) -> io::Result<u32> {
     let mut buf = [0; 4];
     rdr.read_exact(&mut buf)?;
@@ -31,6 +36,10 @@ pub fn io_read_u32_le<R: io::Read>(mut rdr: R) -> io::Result<u32> {
 
 /// Write a u16 in little endian format to the beginning of the given slice.
 /// This panics if the slice has length less than 2.
+#[cfg(any(
+    feature = "compress",
+    all(feature = "frame", feature = "decompress")
+))]
 pub fn write_u16_le(n: u16, slice: &mut [u8]) {
     assert!(slice.len() >= 2);
     let bytes = n.to_le_bytes();
@@ -41,6 +50,7 @@ pub fn write_u16_le(n: u16, slice: &mut [u8]) {
 /// Write a u24 (given as a u32 where the most significant 8 bits are ignored)
 /// in little endian format to the beginning of the given slice. This panics
 /// if the slice has length less than 3.
+#[cfg(feature = "compress")]
 pub fn write_u24_le(n: u32, slice: &mut [u8]) {
     slice[0] = n as u8;
     slice[1] = (n >> 8) as u8;
@@ -49,6 +59,10 @@ pub fn write_u24_le(n: u32, slice: &mut [u8]) {
 
 /// Write a u32 in little endian format to the beginning of the given slice.
 /// This panics if the slice has length less than 4.
+#[cfg(any(
+    all(feature = "frame", feature = "compress"),
+    feature = "long-range"
+))]
 pub fn write_u32_le(n: u32, slice: &mut [u8]) {
     assert!(slice.len() >= 4);
     let bytes = n.to_le_bytes();
@@ -59,6 +73,10 @@ pub fn write_u32_le(n: u32, slice: &mut [u8]) {
 }
 
 /// https://developers.google.com/protocol-buffers/docs/encoding#varints
+#[cfg(any(
+    feature = "compress",
+    all(feature = "frame", feature = "decompress")
+))]
 pub fn write_varu64(data: &mut [u8], mut n: u64) -> usize {
     let mut i = 0;
     while n >= 0b1000_0000 {
@@ -71,6 +89,7 @@ pub fn write_varu64(data: &mut [u8], mut n: u64) -> usize {
 }
 
 /// https://developers.google.com/protocol-buffers/docs/encoding#varints
+#[cfg(feature = "decompress")]
 pub fn read_varu64(data: &[u8]) -> (u64, usize) {
     let mut n: u64 = 0;
     let mut shift: u32 = 0;
@@ -90,30 +109,20 @@ pub fn read_varu64(data: &[u8]) -> (u64, usize) {
     (0, 0)
 }
 
-/// Does an unaligned load of a little endian encoded u32.
+/// Read a u32 in little endian format from `slice[i..i + 4]`, without
+/// bounds checks.
 ///
-/// This is unsafe because `data` must point to some memory of size at least 4.
-pub unsafe fn loadu_u32_le(data: *const u8) -> u32 {
-    loadu_u32_ne(data).to_le()
+/// This is unsafe because `slice` must have length at least `i + 4`.
+#[cfg(any(feature = "compress", feature = "decompress"))]
+pub unsafe fn read_u32_le_unchecked(slice: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes(slice.get_unchecked(i..i + 4).try_into().unwrap())
 }
 
-/// Does an unaligned load of a native endian encoded u32.
+/// Read a u64 in little endian format from `slice[i..i + 8]`, without
+/// bounds checks.
 ///
-/// This is unsafe because `data` must point to some memory of size at least 4.
-pub unsafe fn loadu_u32_ne(data: *const u8) -> u32 {
-    (data as *const u32).read_unaligned()
-}
-
-/// Does an unaligned load of a little endian encoded u64.
-///
-/// This is unsafe because `data` must point to some memory of size at least 8.
-pub unsafe fn loadu_u64_le(data: *const u8) -> u64 {
-    loadu_u64_ne(data).to_le()
-}
-
-/// Does an unaligned load of a native endian encoded u64.
-///
-/// This is unsafe because `data` must point to some memory of size at least 8.
-pub unsafe fn loadu_u64_ne(data: *const u8) -> u64 {
-    (data as *const u64).read_unaligned()
+/// This is unsafe because `slice` must have length at least `i + 8`.
+#[cfg(feature = "compress")]
+pub unsafe fn read_u64_le_unchecked(slice: &[u8], i: usize) -> u64 {
+    u64::from_le_bytes(slice.get_unchecked(i..i + 8).try_into().unwrap())
 }
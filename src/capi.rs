@@ -0,0 +1,275 @@
+/*!
+This module provides a C ABI for compressing and decompressing the Snappy
+frame format.
+
+Every function in this module is exported with an unmangled name, so linking
+this crate into a `cdylib` or `staticlib` makes them callable from C. The
+corresponding declarations are:
+
+```c
+typedef enum {
+  SNAPPY_OK = 0,
+  SNAPPY_INVALID_INPUT = 1,
+  SNAPPY_BUFFER_TOO_SMALL = 2,
+  SNAPPY_IO_ERROR = 3,
+  SNAPPY_INTERNAL_ERROR = 4
+} snappy_status;
+
+typedef ptrdiff_t (*snappy_read_callback)(void *ctx, uint8_t *buf, size_t len);
+typedef ptrdiff_t (*snappy_write_callback)(void *ctx, const uint8_t *buf,
+                                           size_t len);
+typedef struct snappy_frame_encoder snappy_frame_encoder;
+
+snappy_status snappy_frame_compress_stream(
+    snappy_read_callback read, void *read_ctx,
+    snappy_write_callback write, void *write_ctx);
+snappy_status snappy_frame_decompress_stream(
+    snappy_read_callback read, void *read_ctx,
+    snappy_write_callback write, void *write_ctx);
+
+snappy_frame_encoder *snappy_frame_encoder_new(
+    snappy_write_callback write, void *write_ctx);
+snappy_status snappy_frame_encoder_write(
+    snappy_frame_encoder *enc, const uint8_t *buf, size_t len);
+snappy_status snappy_frame_encoder_flush(snappy_frame_encoder *enc);
+snappy_status snappy_frame_encoder_finish(snappy_frame_encoder *enc);
+```
+
+The status values match those of the C interface of the reference Snappy
+library, with the addition of `SNAPPY_IO_ERROR` for failed callbacks and
+`SNAPPY_INTERNAL_ERROR` for bugs in this crate.
+
+No function in this module unwinds into its caller. A panic is caught at the
+boundary and reported as `SNAPPY_INTERNAL_ERROR`, or as a null encoder by
+`snappy_frame_encoder_new`.
+*/
+
+#![allow(non_camel_case_types)]
+
+use std::prelude::v1::*;
+use core::ffi::c_void;
+use std::io::{self, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use crate::error::Error;
+use crate::{read, write};
+
+/// The outcome of a call to one of the functions in this module.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum snappy_status {
+    /// The call succeeded.
+    SNAPPY_OK = 0,
+    /// The compressed data given was not valid Snappy frame formatted data.
+    SNAPPY_INVALID_INPUT = 1,
+    /// A buffer was too small. This is never returned by the functions in
+    /// this module, and is only defined for compatibility.
+    SNAPPY_BUFFER_TOO_SMALL = 2,
+    /// A callback reported an error.
+    SNAPPY_IO_ERROR = 3,
+    /// This crate panicked, which is a bug. An encoder that this is returned
+    /// for must still be finished, but the stream it was writing is unusable.
+    SNAPPY_INTERNAL_ERROR = 4,
+}
+
+/// A callback that reads up to `len` bytes into `buf`.
+///
+/// It returns the number of bytes read, which is `0` only at the end of the
+/// input, or a negative number if an error occurred.
+pub type snappy_read_callback =
+    extern "C" fn(ctx: *mut c_void, buf: *mut u8, len: usize) -> isize;
+
+/// A callback that writes up to `len` bytes from `buf`.
+///
+/// It returns the number of bytes written, which must not be `0` unless
+/// `len` is, or a negative number if an error occurred.
+pub type snappy_write_callback =
+    extern "C" fn(ctx: *mut c_void, buf: *const u8, len: usize) -> isize;
+
+/// A frame encoder that writes through a callback, created by
+/// [`snappy_frame_encoder_new`](fn.snappy_frame_encoder_new.html).
+pub struct snappy_frame_encoder {
+    enc: write::FrameEncoder<CallbackWriter>,
+}
+
+/// Compresses everything read through `read` to the Snappy frame format,
+/// and writes it through `write`.
+#[no_mangle]
+pub extern "C" fn snappy_frame_compress_stream(
+    read: snappy_read_callback,
+    read_ctx: *mut c_void,
+    write: snappy_write_callback,
+    write_ctx: *mut c_void,
+) -> snappy_status {
+    catch_panic(|| {
+        let mut rdr = CallbackReader { f: read, ctx: read_ctx };
+        let wtr = CallbackWriter { f: write, ctx: write_ctx };
+        let mut enc = write::FrameEncoder::new(wtr);
+        status(io::copy(&mut rdr, &mut enc).and_then(|_| enc.flush()))
+    })
+}
+
+/// Decompresses Snappy frame formatted data read through `read`, and writes
+/// the decompressed bytes through `write`.
+#[no_mangle]
+pub extern "C" fn snappy_frame_decompress_stream(
+    read: snappy_read_callback,
+    read_ctx: *mut c_void,
+    write: snappy_write_callback,
+    write_ctx: *mut c_void,
+) -> snappy_status {
+    catch_panic(|| {
+        let rdr = CallbackReader { f: read, ctx: read_ctx };
+        let mut dec = read::FrameDecoder::new(rdr);
+        let mut wtr = CallbackWriter { f: write, ctx: write_ctx };
+        status(io::copy(&mut dec, &mut wtr))
+    })
+}
+
+/// Creates an encoder that writes Snappy frame formatted data through
+/// `write`.
+///
+/// The encoder must be released with
+/// [`snappy_frame_encoder_finish`](fn.snappy_frame_encoder_finish.html).
+/// If this crate panics while creating it, then this returns null.
+#[no_mangle]
+pub extern "C" fn snappy_frame_encoder_new(
+    write: snappy_write_callback,
+    write_ctx: *mut c_void,
+) -> *mut snappy_frame_encoder {
+    panic::catch_unwind(|| {
+        let wtr = CallbackWriter { f: write, ctx: write_ctx };
+        let enc = snappy_frame_encoder { enc: write::FrameEncoder::new(wtr) };
+        Box::into_raw(Box::new(enc))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Compresses the `len` bytes at `buf`.
+///
+/// Bytes are buffered until a full chunk is available, or until the encoder
+/// is flushed or finished.
+///
+/// # Safety
+///
+/// `enc` must have been returned by `snappy_frame_encoder_new` and not yet
+/// finished, and `buf` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn snappy_frame_encoder_write(
+    enc: *mut snappy_frame_encoder,
+    buf: *const u8,
+    len: usize,
+) -> snappy_status {
+    let buf = if len == 0 { &[] } else { slice::from_raw_parts(buf, len) };
+    catch_panic(|| status((*enc).enc.write_all(buf)))
+}
+
+/// Writes a chunk containing every byte buffered by `enc`, so that the data
+/// written so far can be decompressed by a reader of the output.
+///
+/// # Safety
+///
+/// `enc` must have been returned by `snappy_frame_encoder_new` and not yet
+/// finished.
+#[no_mangle]
+pub unsafe extern "C" fn snappy_frame_encoder_flush(
+    enc: *mut snappy_frame_encoder,
+) -> snappy_status {
+    catch_panic(|| status((*enc).enc.flush()))
+}
+
+/// Writes a chunk containing every byte buffered by `enc`, and releases it.
+///
+/// `enc` is released even if writing fails.
+///
+/// # Safety
+///
+/// `enc` must have been returned by `snappy_frame_encoder_new` and not yet
+/// finished.
+#[no_mangle]
+pub unsafe extern "C" fn snappy_frame_encoder_finish(
+    enc: *mut snappy_frame_encoder,
+) -> snappy_status {
+    catch_panic(|| match Box::from_raw(enc).enc.into_inner() {
+        Ok(_) => snappy_status::SNAPPY_OK,
+        Err(err) => {
+            let status = error_status(err.error());
+            // Dropping the encoder would flush it again, calling the write
+            // callback on a stream that has already failed.
+            err.into_inner().discard();
+            status
+        }
+    })
+}
+
+/// Calls `f`, and returns `SNAPPY_INTERNAL_ERROR` if it panics instead of
+/// unwinding across the C ABI, which is undefined behavior.
+fn catch_panic<F: FnOnce() -> snappy_status>(f: F) -> snappy_status {
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or(snappy_status::SNAPPY_INTERNAL_ERROR)
+}
+
+/// Converts the result of a call into a status. Snappy errors and streams
+/// that end in the middle of a chunk are invalid input, while every other
+/// error must have come from a callback.
+fn status<T>(res: io::Result<T>) -> snappy_status {
+    match res {
+        Ok(_) => snappy_status::SNAPPY_OK,
+        Err(ref err) => error_status(err),
+    }
+}
+
+/// Converts an error into a status, as `status` does.
+fn error_status(err: &io::Error) -> snappy_status {
+    if err.kind() == io::ErrorKind::UnexpectedEof
+        || err.get_ref().map_or(false, |e| e.is::<Error>())
+    {
+        snappy_status::SNAPPY_INVALID_INPUT
+    } else {
+        snappy_status::SNAPPY_IO_ERROR
+    }
+}
+
+/// An `io::Read` implementation that calls a C callback.
+struct CallbackReader {
+    f: snappy_read_callback,
+    ctx: *mut c_void,
+}
+
+impl Read for CallbackReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = (self.f)(self.ctx, buf.as_mut_ptr(), buf.len());
+        if n < 0 || n as usize > buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "snappy: read callback failed",
+            ));
+        }
+        Ok(n as usize)
+    }
+}
+
+/// An `io::Write` implementation that calls a C callback.
+struct CallbackWriter {
+    f: snappy_write_callback,
+    ctx: *mut c_void,
+}
+
+impl Write for CallbackWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = (self.f)(self.ctx, buf.as_ptr(), buf.len());
+        if n < 0 || n as usize > buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "snappy: write callback failed",
+            ));
+        }
+        Ok(n as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+use std::prelude::v1::*;
+use std::fmt;
+
+#[cfg(feature = "compress")]
+use crate::compress::{max_compress_len, Encoder, EncoderBuilder};
+#[cfg(feature = "decompress")]
+use crate::decompress::{decompress_len, Decoder};
+use crate::error::Error;
+
+/// A block compressor that the frame format readers and writers can use in
+/// place of Snappy.
+///
+/// The frame encoders and decoders in this crate split a stream into blocks,
+/// checksum them and write them as chunks. Only the compression of each
+/// block is delegated to a `Codec`, so swapping it reuses all of that
+/// machinery, e.g., to test an application with a trivial codec, or to
+/// experiment with another compression algorithm. Note that a stream written
+/// with any codec other than [`SnappyCodec`](struct.SnappyCodec.html) is not a
+/// Snappy stream, and can only be read back with the same codec.
+///
+/// The frame format stores blocks of at most 64KB, and a compressed block is
+/// only stored if it's smaller than the block itself. Since the buffers of
+/// the frame encoders are sized for Snappy, `max_compress_len` must never
+/// return more than
+/// [`raw::max_compress_len`](../raw/fn.max_compress_len.html) does for
+/// the same length.
+pub trait Codec {
+    /// Returns the maximum number of bytes that compressing `input_len`
+    /// bytes can produce.
+    fn max_compress_len(&self, input_len: usize) -> usize;
+
+    /// Compresses all bytes in `input` into `output`, which is at least
+    /// `max_compress_len(input.len())` bytes long, and returns the number of
+    /// bytes written to `output`.
+    fn compress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<usize, Error>;
+
+    /// Returns the number of bytes that the compressed bytes in `input`
+    /// decompress to.
+    fn decompress_len(&self, input: &[u8]) -> Result<usize, Error>;
+
+    /// Decompresses all bytes in `input` into `output`, which is exactly
+    /// `decompress_len(input)` bytes long, and returns the number of bytes
+    /// written to `output`.
+    fn decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<usize, Error>;
+
+    /// Returns the number of bytes held by this codec's tables and buffers.
+    ///
+    /// This returns `0` by default.
+    fn memory_usage(&self) -> usize {
+        0
+    }
+}
+
+/// The raw Snappy format as a [`Codec`](trait.Codec.html).
+///
+/// This is the codec used by the frame encoders and decoders unless another
+/// one is given.
+///
+/// # Panics
+///
+/// Compressing panics when the `compress` feature is disabled, and
+/// decompressing panics when the `decompress` feature is disabled. The frame
+/// encoders and decoders only exist when the features they need are enabled,
+/// so this can only happen when a `SnappyCodec` is used directly.
+pub struct SnappyCodec {
+    /// The encoder, built the first time something is compressed, so that a
+    /// codec that only decompresses doesn't hold its hash table.
+    #[cfg(feature = "compress")]
+    enc: Option<Encoder>,
+    /// The configuration of `enc`.
+    #[cfg(feature = "compress")]
+    builder: EncoderBuilder,
+    /// The decoder, when decompression is enabled.
+    #[cfg(feature = "decompress")]
+    dec: Decoder,
+}
+
+impl SnappyCodec {
+    /// Create a new Snappy codec with the default configuration.
+    pub fn new() -> SnappyCodec {
+        SnappyCodec {
+            #[cfg(feature = "compress")]
+            enc: None,
+            #[cfg(feature = "compress")]
+            builder: EncoderBuilder::new(),
+            #[cfg(feature = "decompress")]
+            dec: Decoder::new(),
+        }
+    }
+
+    /// Create a new Snappy codec that compresses with an encoder built from
+    /// `builder`.
+    #[cfg(feature = "compress")]
+    pub fn with_builder(builder: &EncoderBuilder) -> SnappyCodec {
+        SnappyCodec {
+            enc: None,
+            builder: builder.clone(),
+            #[cfg(feature = "decompress")]
+            dec: Decoder::new(),
+        }
+    }
+}
+
+impl Clone for SnappyCodec {
+    fn clone(&self) -> SnappyCodec {
+        SnappyCodec {
+            #[cfg(feature = "compress")]
+            enc: None,
+            #[cfg(feature = "compress")]
+            builder: self.builder.clone(),
+            #[cfg(feature = "decompress")]
+            dec: self.dec.clone(),
+        }
+    }
+}
+
+impl Default for SnappyCodec {
+    fn default() -> SnappyCodec {
+        SnappyCodec::new()
+    }
+}
+
+impl fmt::Debug for SnappyCodec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut d = f.debug_struct("SnappyCodec");
+        #[cfg(feature = "compress")]
+        d.field("enc", &self.enc).field("builder", &self.builder);
+        #[cfg(feature = "decompress")]
+        d.field("dec", &self.dec);
+        d.finish()
+    }
+}
+
+impl Codec for SnappyCodec {
+    #[cfg(feature = "compress")]
+    fn max_compress_len(&self, input_len: usize) -> usize {
+        max_compress_len(input_len)
+    }
+
+    #[cfg(not(feature = "compress"))]
+    fn max_compress_len(&self, _input_len: usize) -> usize {
+        panic!("snappy: compression requires the compress feature")
+    }
+
+    #[cfg(feature = "compress")]
+    fn compress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<usize, Error> {
+        let builder = &self.builder;
+        self.enc.get_or_insert_with(|| builder.build()).compress(input, output)
+    }
+
+    #[cfg(not(feature = "compress"))]
+    fn compress(
+        &mut self,
+        _input: &[u8],
+        _output: &mut [u8],
+    ) -> Result<usize, Error> {
+        panic!("snappy: compression requires the compress feature")
+    }
+
+    #[cfg(feature = "decompress")]
+    fn decompress_len(&self, input: &[u8]) -> Result<usize, Error> {
+        decompress_len(input)
+    }
+
+    #[cfg(not(feature = "decompress"))]
+    fn decompress_len(&self, _input: &[u8]) -> Result<usize, Error> {
+        panic!("snappy: decompression requires the decompress feature")
+    }
+
+    #[cfg(feature = "decompress")]
+    fn decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<usize, Error> {
+        self.dec.decompress(input, output)
+    }
+
+    #[cfg(not(feature = "decompress"))]
+    fn decompress(
+        &mut self,
+        _input: &[u8],
+        _output: &mut [u8],
+    ) -> Result<usize, Error> {
+        panic!("snappy: decompression requires the decompress feature")
+    }
+
+    #[cfg(feature = "compress")]
+    fn memory_usage(&self) -> usize {
+        self.enc.as_ref().map_or(0, |enc| enc.memory_usage())
+    }
+}
@@ -1,20 +1,43 @@
 use std::prelude::v1::*;
+use std::cmp;
+#[cfg(feature = "unstable")]
+use std::collections::VecDeque;
 use std::fmt;
+use std::io::IoSlice;
+use std::mem;
 use std::ops::{Deref, DerefMut};
 use std::ptr;
+use std::slice;
 
 use crate::bytes;
 use crate::error::{Error, Result};
+use crate::stats::EncoderStats;
 use crate::{MAX_BLOCK_SIZE, MAX_INPUT_SIZE};
 
 /// The total number of slots we permit for our hash table of 4 byte repeat
 /// sequences.
 const MAX_TABLE_SIZE: usize = 1 << 14;
 
+/// The smallest hash table we ever use.
+const MIN_TABLE_SIZE: usize = 1 << 8;
+
 /// The size of a small hash table. This is useful for reducing overhead when
 /// compressing very small blocks of bytes.
 const SMALL_TABLE_SIZE: usize = 1 << 10;
 
+/// The number of entries in the table of hash chain heads used when
+/// searching for matches with a non-zero search depth.
+const CHAIN_TABLE_SIZE: usize = 1 << 15;
+
+/// The deepest search for matches permitted.
+const MAX_SEARCH_DEPTH: usize = 1 << 12;
+
+/// The length at which the optimal parser takes a match whole instead of
+/// weighing every place it could end. Without this, a long run of repeated
+/// bytes would take time quadratic in its length.
+#[cfg(feature = "unstable")]
+const SUFFICIENT_MATCH_LEN: usize = 256;
+
 /// The total number of bytes that we always leave uncompressed at the end
 /// of the buffer. This in particular affords us some wiggle room during
 /// compression such that faster copy operations can be used.
@@ -29,10 +52,10 @@ enum Tag {
     Literal = 0b00,
     Copy1 = 0b01,
     Copy2 = 0b10,
-    // Compression never actually emits a Copy4 operation and decompression
-    // uses tricks so that we never explicitly do case analysis on the copy
-    // operation type, therefore leading to the fact that we never use Copy4.
-    #[allow(dead_code)]
+    // Compression only emits a Copy4 operation in the long-range blocks of
+    // the `long` module, and decompression uses tricks so that we never
+    // explicitly do case analysis on the copy operation type.
+    #[cfg_attr(not(feature = "long-range"), allow(dead_code))]
     Copy4 = 0b11,
 }
 
@@ -40,16 +63,233 @@ enum Tag {
 ///
 /// If the uncompressed size exceeds the maximum allowable size then this
 /// returns 0.
-pub fn max_compress_len(input_len: usize) -> usize {
+///
+/// This is a `const fn`, so it can size arrays at compile time:
+///
+/// ```
+/// use snap::raw::{max_compress_len, Encoder};
+///
+/// const MSG_LEN: usize = 512;
+/// let mut buf = [0; max_compress_len(MSG_LEN)];
+/// let n = Encoder::new().compress(&[b'a'; MSG_LEN], &mut buf).unwrap();
+/// assert!(n < MSG_LEN);
+/// ```
+pub const fn max_compress_len(input_len: usize) -> usize {
+    // Branching isn't permitted in a const fn on the oldest Rust we support,
+    // so the limits are applied by multiplying with 0 or 1 instead. Inputs
+    // that are too big are first clamped to one past the limit, so that
+    // computing `max` can't overflow and `max` is over the limit too.
     let input_len = input_len as u64;
-    if input_len > MAX_INPUT_SIZE {
-        return 0;
-    }
+    let too_big = (input_len > MAX_INPUT_SIZE) as u64;
+    let input_len = input_len * (1 - too_big) + (MAX_INPUT_SIZE + 1) * too_big;
     let max = 32 + input_len + (input_len / 6);
-    if max > MAX_INPUT_SIZE {
-        0
-    } else {
-        max as usize
+    (max * (max <= MAX_INPUT_SIZE) as u64) as usize
+}
+
+/// Compresses `input` into a sequence of independent raw Snappy blocks, where
+/// each block holds at most `chunk_size` bytes of uncompressed data.
+///
+/// Every block yielded is a complete raw Snappy block that can be
+/// decompressed on its own with a `Decoder`. Since every block starts with
+/// its uncompressed size, that size can be read back with `decompress_len`
+/// without decompressing the block. All blocks except for possibly the last
+/// one hold exactly `chunk_size` bytes of uncompressed data. An empty `input`
+/// yields no blocks.
+///
+/// # Panics
+///
+/// This panics if `chunk_size` is `0`, or if a single chunk is too big to
+/// compress (i.e., `max_compress_len` returns `0` for it).
+pub fn compress_chunked(
+    input: &[u8],
+    chunk_size: usize,
+) -> CompressChunked<'_> {
+    assert!(chunk_size > 0, "chunk size must be non-zero");
+    let largest = cmp::min(chunk_size, input.len());
+    assert!(
+        largest == 0 || max_compress_len(largest) > 0,
+        "chunk size {} is too big to compress",
+        chunk_size
+    );
+    CompressChunked { enc: Encoder::new(), chunks: input.chunks(chunk_size) }
+}
+
+/// An iterator over independently compressed raw Snappy blocks.
+///
+/// This iterator is created by the
+/// [`compress_chunked`](fn.compress_chunked.html) function.
+#[derive(Debug)]
+pub struct CompressChunked<'a> {
+    enc: Encoder,
+    chunks: slice::Chunks<'a, u8>,
+}
+
+impl<'a> Iterator for CompressChunked<'a> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let chunk = self.chunks.next()?;
+        // compress_chunked guarantees that every chunk is small enough to be
+        // compressed, which is the only way compression can fail.
+        Some(self.enc.compress_vec(chunk).unwrap())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.chunks.size_hint()
+    }
+}
+
+/// A builder for configuring a raw Snappy [`Encoder`](struct.Encoder.html).
+///
+/// Every encoder built is deterministic: compressing the same input with
+/// encoders built from the same configuration always produces the same
+/// output, regardless of what the encoder compressed previously.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EncoderBuilder {
+    max_table_size: usize,
+    search_depth: usize,
+    collect_stats: bool,
+    #[cfg(feature = "unstable")]
+    optimal_parse: bool,
+}
+
+impl Default for EncoderBuilder {
+    fn default() -> EncoderBuilder {
+        EncoderBuilder::new()
+    }
+}
+
+impl EncoderBuilder {
+    /// Create a new builder with the default configuration.
+    pub fn new() -> EncoderBuilder {
+        EncoderBuilder {
+            max_table_size: MAX_TABLE_SIZE,
+            search_depth: 0,
+            collect_stats: false,
+            #[cfg(feature = "unstable")]
+            optimal_parse: false,
+        }
+    }
+
+    /// Build an encoder from this configuration.
+    pub fn build(&self) -> Encoder {
+        Encoder {
+            small: [0; SMALL_TABLE_SIZE],
+            big: vec![],
+            max_table_size: self.max_table_size,
+            search_depth: self.search_depth,
+            chains: HashChains::default(),
+            stats: if self.collect_stats {
+                Some(EncoderStats::default())
+            } else {
+                None
+            },
+            block_stats: None,
+            #[cfg(feature = "unstable")]
+            optimal_parse: self.optimal_parse,
+            #[cfg(feature = "unstable")]
+            parse: OptimalParse::default(),
+        }
+    }
+
+    /// Set the maximum number of entries in the hash table used to find
+    /// repeated byte sequences.
+    ///
+    /// A smaller table uses less memory and is cheaper to reset for every
+    /// block, at the expense of finding fewer matches and therefore usually
+    /// compressing less. The size given is rounded up to the nearest power of
+    /// two and is clamped to the range `[256, 16384]`.
+    ///
+    /// This is set to `16384` by default, which is also the maximum.
+    pub fn max_table_size(&mut self, size: usize) -> &mut EncoderBuilder {
+        let size = cmp::min(size, MAX_TABLE_SIZE).next_power_of_two();
+        self.max_table_size = cmp::max(size, MIN_TABLE_SIZE);
+        self
+    }
+
+    /// Returns the maximum number of entries in the hash table used to find
+    /// repeated byte sequences, after rounding and clamping.
+    pub fn get_max_table_size(&self) -> usize {
+        self.max_table_size
+    }
+
+    /// Set how many earlier occurrences of a byte sequence to compare when
+    /// looking for the longest match, or `0` to use the default matcher.
+    ///
+    /// By default, the encoder remembers only the most recent occurrence of
+    /// each 4 byte sequence, takes the first match it finds, and skips
+    /// through data that doesn't seem to repeat. With a non-zero depth, it
+    /// instead links every position in a block into hash chains, compares up
+    /// to `depth` earlier positions at each position to find the longest
+    /// match, and puts off a match by a byte when the next position has a
+    /// longer one. This compresses noticeably better, but several times more
+    /// slowly, which suits data that is written once and read many times.
+    /// The output is standard Snappy either way, and decompresses just as
+    /// quickly.
+    ///
+    /// Deeper searches find longer matches, with diminishing returns beyond
+    /// a few dozen. The depth given is clamped to at most `4096`. When it's
+    /// non-zero, [`max_table_size`](#method.max_table_size) has no effect.
+    ///
+    /// This is set to `0` by default.
+    pub fn search_depth(&mut self, depth: usize) -> &mut EncoderBuilder {
+        self.search_depth = cmp::min(depth, MAX_SEARCH_DEPTH);
+        self
+    }
+
+    /// Returns how many earlier occurrences of a byte sequence are compared
+    /// when looking for the longest match, after clamping.
+    pub fn get_search_depth(&self) -> usize {
+        self.search_depth
+    }
+
+    /// Set whether the encoder counts the literals and copies it emits, and
+    /// its hash table lookups, for
+    /// [`Encoder::stats`](struct.Encoder.html#method.stats).
+    ///
+    /// Counting costs a little time for every literal and copy, so this is
+    /// meant for tuning the other settings on a sample of data rather than
+    /// for regular use.
+    ///
+    /// This is disabled by default.
+    pub fn collect_stats(&mut self, yes: bool) -> &mut EncoderBuilder {
+        self.collect_stats = yes;
+        self
+    }
+
+    /// Returns whether the encoder counts what it emits.
+    pub fn get_collect_stats(&self) -> bool {
+        self.collect_stats
+    }
+
+    /// Set whether to choose the copies and literals of each block so that
+    /// it's encoded in as few bytes as possible. This is experimental, and
+    /// only available with the `unstable` feature.
+    ///
+    /// Every match that a search of
+    /// [`search_depth`](#method.search_depth) earlier positions turns up is
+    /// collected first, or of `4096` positions if that's `0`. A second pass
+    /// then works out which of those matches to use, and where to end each
+    /// one, by counting exactly how many bytes every copy and literal would
+    /// take. Only matches longer than 256 bytes are taken whole, without
+    /// weighing where else they could end.
+    ///
+    /// This is much slower than any search depth on its own, and isn't meant
+    /// for regular use. Rather, it shows how small a block can get with the
+    /// matches found, which is a bound to measure other settings against.
+    ///
+    /// This is disabled by default.
+    #[cfg(feature = "unstable")]
+    pub fn optimal_parse(&mut self, yes: bool) -> &mut EncoderBuilder {
+        self.optimal_parse = yes;
+        self
+    }
+
+    /// Returns whether the copies and literals of each block are chosen to
+    /// encode it in as few bytes as possible.
+    #[cfg(feature = "unstable")]
+    pub fn get_optimal_parse(&self) -> bool {
+        self.optimal_parse
     }
 }
 
@@ -65,9 +305,23 @@ pub fn max_compress_len(input_len: usize) -> usize {
 /// instead, which compresses to the Snappy frame format.
 ///
 /// It is beneficial to reuse an Encoder when possible.
+///
+/// An encoder with a non-default configuration can be built with an
+/// [`EncoderBuilder`](struct.EncoderBuilder.html).
 pub struct Encoder {
     small: [u16; SMALL_TABLE_SIZE],
     big: Vec<u16>,
+    max_table_size: usize,
+    search_depth: usize,
+    chains: HashChains,
+    /// The counts for every block compressed, if they're collected.
+    stats: Option<EncoderStats>,
+    /// The counts for the last block compressed.
+    block_stats: Option<EncoderStats>,
+    #[cfg(feature = "unstable")]
+    optimal_parse: bool,
+    #[cfg(feature = "unstable")]
+    parse: OptimalParse,
 }
 
 impl fmt::Debug for Encoder {
@@ -79,7 +333,76 @@ impl fmt::Debug for Encoder {
 impl Encoder {
     /// Return a new encoder that can be used for compressing bytes.
     pub fn new() -> Encoder {
-        Encoder { small: [0; SMALL_TABLE_SIZE], big: vec![] }
+        EncoderBuilder::new().build()
+    }
+
+    /// Returns the number of bytes held by this encoder's hash tables.
+    ///
+    /// Every encoder holds a small table inline. A bigger table, sized by
+    /// [`EncoderBuilder::max_table_size`](struct.EncoderBuilder.html#method.max_table_size),
+    /// or the hash chains used with a non-zero
+    /// [`EncoderBuilder::search_depth`](struct.EncoderBuilder.html#method.search_depth),
+    /// are allocated the first time an input needs them and are then kept
+    /// for reuse, so this grows at most once.
+    pub fn memory_usage(&self) -> usize {
+        let usage = mem::size_of_val(&self.small)
+            + self.big.capacity() * mem::size_of::<u16>()
+            + self.chains.memory_usage();
+        #[cfg(feature = "unstable")]
+        let usage = usage + self.parse.memory_usage();
+        usage
+    }
+
+    /// Returns the counts of what this encoder emitted for every block it
+    /// compressed since it was built, or since
+    /// [`reset_stats`](#method.reset_stats) was called.
+    ///
+    /// This returns `None` unless
+    /// [`EncoderBuilder::collect_stats`](struct.EncoderBuilder.html#method.collect_stats)
+    /// was enabled.
+    pub fn stats(&self) -> Option<&EncoderStats> {
+        self.stats.as_ref()
+    }
+
+    /// Returns the counts of what this encoder emitted for the last block it
+    /// compressed. Inputs bigger than 64KB are compressed in several blocks.
+    ///
+    /// This returns `None` if stats aren't collected, or if nothing has been
+    /// compressed since they were last reset.
+    pub fn last_block_stats(&self) -> Option<&EncoderStats> {
+        self.block_stats.as_ref()
+    }
+
+    /// Resets the counts returned by [`stats`](#method.stats) and
+    /// [`last_block_stats`](#method.last_block_stats) to zero. This does
+    /// nothing if stats aren't collected.
+    pub fn reset_stats(&mut self) {
+        if let Some(ref mut stats) = self.stats {
+            *stats = EncoderStats::default();
+            self.block_stats = None;
+        }
+    }
+
+    /// Restores the configuration of `builder`, and resets the stats, but
+    /// keeps the tables already allocated if they fit it. This lets a pool
+    /// hand out an encoder again as though it had just been built.
+    #[cfg(feature = "decompress")]
+    pub(crate) fn reset(&mut self, builder: &EncoderBuilder) {
+        if self.max_table_size != builder.max_table_size {
+            self.big = vec![];
+        }
+        self.max_table_size = builder.max_table_size;
+        self.search_depth = builder.search_depth;
+        self.stats = if builder.collect_stats {
+            Some(EncoderStats::default())
+        } else {
+            None
+        };
+        self.block_stats = None;
+        #[cfg(feature = "unstable")]
+        {
+            self.optimal_parse = builder.optimal_parse;
+        }
     }
 
     /// Compresses all bytes in `input` into `output`.
@@ -95,7 +418,8 @@ impl Encoder {
     ///
     /// This method returns an error in the following circumstances:
     ///
-    /// * The total number of bytes to compress exceeds `2^32 - 1`.
+    /// * The total number of bytes to compress exceeds
+    ///   [`MAX_INPUT_SIZE`](constant.MAX_INPUT_SIZE.html).
     /// * `output` has length less than `max_compress_len(input.len())`.
     pub fn compress(
         &mut self,
@@ -134,26 +458,185 @@ impl Encoder {
                 src = &src[..MAX_BLOCK_SIZE as usize];
             }
             input = &input[src.len()..];
+            d = self.compress_block(src, output, d);
+        }
+        Ok(d)
+    }
 
-            // If the block is smallish, then don't waste time on it and just
-            // emit a literal.
-            let mut block = Block::new(src, output, d);
-            if block.src.len() < MIN_NON_LITERAL_BLOCK_SIZE {
-                let lit_end = block.src.len();
-                unsafe {
-                    // SAFETY: next_emit is zero (in bounds) and the end is
-                    // the length of the block (in bounds).
-                    block.emit_literal(lit_end);
+    /// Returns the exact number of bytes that `compress` would write for
+    /// `input`, without an output buffer big enough for all of it.
+    ///
+    /// This compresses `input` one block at a time into a scratch buffer
+    /// that holds at most one compressed block (about 76KB), and throws the
+    /// output away. So it takes as long as `compress`, but its memory use
+    /// doesn't grow with the size of `input`. Since the size is exact, it can
+    /// be checked against a quota, or used to allocate a buffer of exactly
+    /// the right size, before compressing for real.
+    ///
+    /// Blocks compressed to find their size aren't counted in
+    /// [`stats`](#method.stats).
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the number of bytes in `input`
+    /// exceeds [`MAX_INPUT_SIZE`](constant.MAX_INPUT_SIZE.html).
+    pub fn compressed_size(&mut self, mut input: &[u8]) -> Result<usize> {
+        if max_compress_len(input.len()) == 0 {
+            return Err(Error::TooBig {
+                given: input.len() as u64,
+                max: MAX_INPUT_SIZE,
+            });
+        }
+        let mut scratch =
+            vec![0; max_compress_len(cmp::min(input.len(), MAX_BLOCK_SIZE))];
+        let stats = self.stats.take();
+        let mut size = bytes::write_varu64(&mut scratch, input.len() as u64);
+        while !input.is_empty() {
+            let n = cmp::min(input.len(), MAX_BLOCK_SIZE);
+            size += self.compress_block(&input[..n], &mut scratch, 0);
+            input = &input[n..];
+        }
+        self.stats = stats;
+        Ok(size)
+    }
+
+    /// Compresses the concatenation of all slices in `input` into `output`.
+    ///
+    /// This produces exactly the same output as calling `compress` on the
+    /// concatenation of `input`, including matches that span the boundaries
+    /// between slices, but without requiring the caller to concatenate them
+    /// first. This is useful when the data to compress lives in a rope or a
+    /// ring buffer.
+    ///
+    /// At most one block's worth (64KB) of input is copied at a time, and
+    /// only for blocks that span more than one slice.
+    ///
+    /// `output` must be large enough to hold the maximum possible compressed
+    /// size of the concatenation of `input`, which can be computed using
+    /// `max_compress_len`.
+    ///
+    /// On success, this returns the number of bytes written to `output`.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error under the same circumstances that
+    /// `compress` does.
+    pub fn compress_slices(
+        &mut self,
+        input: &[IoSlice<'_>],
+        output: &mut [u8],
+    ) -> Result<usize> {
+        let total: u64 = input.iter().map(|buf| buf.len() as u64).sum();
+        if total > MAX_INPUT_SIZE {
+            return Err(Error::TooBig { given: total, max: MAX_INPUT_SIZE });
+        }
+        let min = max_compress_len(total as usize);
+        if output.len() < min {
+            return Err(Error::BufferTooSmall {
+                given: output.len() as u64,
+                min: min as u64,
+            });
+        }
+        if total == 0 {
+            output[0] = 0;
+            return Ok(1);
+        }
+        let mut d = bytes::write_varu64(output, total);
+        let mut scratch = vec![];
+        let mut bufs =
+            input.iter().map(|buf| &**buf).filter(|b| !b.is_empty());
+        let mut cur: &[u8] = &[];
+        let mut remaining = total as usize;
+        while remaining > 0 {
+            let block_len = cmp::min(remaining, MAX_BLOCK_SIZE);
+            remaining -= block_len;
+            if cur.is_empty() {
+                cur = bufs.next().unwrap();
+            }
+            // When the whole block lives in the current slice, compress it
+            // in place. Otherwise, gather it into a contiguous buffer.
+            if cur.len() >= block_len {
+                let (src, rest) = cur.split_at(block_len);
+                cur = rest;
+                d = self.compress_block(src, output, d);
+                continue;
+            }
+            scratch.clear();
+            while scratch.len() < block_len {
+                if cur.is_empty() {
+                    cur = bufs.next().unwrap();
                 }
-            } else {
-                let table = self.block_table(block.src.len());
-                block.compress(table);
+                let n = cmp::min(cur.len(), block_len - scratch.len());
+                scratch.extend_from_slice(&cur[..n]);
+                cur = &cur[n..];
             }
-            d = block.d;
+            d = self.compress_block(&scratch, output, d);
         }
         Ok(d)
     }
 
+    /// Compresses a single block, `src`, which must be no bigger than
+    /// `MAX_BLOCK_SIZE`, into `output` starting at `d`. This returns the
+    /// position in `output` immediately following the compressed block.
+    fn compress_block(
+        &mut self,
+        src: &[u8],
+        output: &mut [u8],
+        d: usize,
+    ) -> usize {
+        debug_assert!(src.len() <= MAX_BLOCK_SIZE);
+        let mut block = Block::new(src, output, d);
+        if self.stats.is_some() {
+            block.stats = Some(Box::new(EncoderStats::default()));
+        }
+        self.compress_block_with(&mut block);
+        if let Some(mut stats) = block.stats.take() {
+            stats.record_block(src.len(), block.d - d, block.hash_probes);
+            if let Some(ref mut total) = self.stats {
+                total.add(&stats);
+            }
+            self.block_stats = Some(*stats);
+        }
+        block.d
+    }
+
+    /// Compresses `block` with whichever matcher this encoder is configured
+    /// to use.
+    fn compress_block_with(&mut self, block: &mut Block<'_, '_>) {
+        #[cfg(feature = "unstable")]
+        {
+            if self.optimal_parse
+                && block.src.len() >= MIN_NON_LITERAL_BLOCK_SIZE
+            {
+                let depth = match self.search_depth {
+                    0 => MAX_SEARCH_DEPTH,
+                    depth => depth,
+                };
+                block.compress_optimal(
+                    &mut self.chains,
+                    &mut self.parse,
+                    depth,
+                );
+                return;
+            }
+        }
+        // If the block is smallish, then don't waste time on it and just
+        // emit a literal.
+        if block.src.len() < MIN_NON_LITERAL_BLOCK_SIZE {
+            let lit_end = block.src.len();
+            unsafe {
+                // SAFETY: next_emit is zero (in bounds) and the end is
+                // the length of the block (in bounds).
+                block.emit_literal(lit_end);
+            }
+        } else if self.search_depth > 0 {
+            block.compress_chains(&mut self.chains, self.search_depth);
+        } else {
+            let table = self.block_table(block.src.len());
+            block.compress(table);
+        }
+    }
+
     /// Compresses all bytes in `input` into a freshly allocated `Vec`.
     ///
     /// This is just like the `compress` method, except it allocates a `Vec`
@@ -177,6 +660,12 @@ struct Block<'s, 'd> {
     dst: &'d mut [u8],
     d: usize,
     next_emit: usize,
+    /// The number of hash table lookups made by `compress`, if stats are
+    /// collected.
+    hash_probes: u64,
+    /// The counts of what this block emits, if they're collected. These are
+    /// boxed to keep this small, which matters to how quickly it compresses.
+    stats: Option<Box<EncoderStats>>,
 }
 
 impl<'s, 'd> Block<'s, 'd> {
@@ -189,14 +678,20 @@ impl<'s, 'd> Block<'s, 'd> {
             dst: dst,
             d: d,
             next_emit: 0,
+            hash_probes: 0,
+            stats: None,
         }
     }
 
     #[inline(always)]
-    fn compress(&mut self, mut table: BlockTable<'_>) {
+    fn compress<P: Position>(&mut self, mut table: BlockTable<'_, P>) {
         debug_assert!(!table.is_empty());
         debug_assert!(self.src.len() >= MIN_NON_LITERAL_BLOCK_SIZE);
 
+        // Lookups are counted here rather than in self.hash_probes, which
+        // slows this loop down noticeably, and only when stats are collected.
+        let count_probes = self.stats.is_some();
+        let mut probes = 0;
         self.s += 1;
         self.s_limit -= INPUT_MARGIN;
         let mut next_hash =
@@ -211,19 +706,22 @@ impl<'s, 'd> Block<'s, 'd> {
                 s_next = self.s + bytes_between_hash_lookups;
                 skip += bytes_between_hash_lookups;
                 if s_next > self.s_limit {
+                    self.hash_probes = probes;
                     return self.done();
                 }
+                if count_probes {
+                    probes += 1;
+                }
                 unsafe {
                     // SAFETY: next_hash is always computed by table.hash
                     // which is guaranteed to be in bounds.
-                    candidate = *table.get_unchecked(next_hash) as usize;
-                    *table.get_unchecked_mut(next_hash) = self.s as u16;
+                    candidate = table.get_unchecked(next_hash).to_usize();
+                    *table.get_unchecked_mut(next_hash) = P::new(self.s);
 
-                    let srcp = self.src.as_ptr();
                     // SAFETY: s_next is guaranteed to be less than s_limit by
                     // the conditional above, which implies s_next is in
                     // bounds.
-                    let x = bytes::loadu_u32_le(srcp.add(s_next));
+                    let x = bytes::read_u32_le_unchecked(self.src, s_next);
                     next_hash = table.hash(x);
                     // SAFETY: self.s is always less than s_next, so it is also
                     // in bounds by the argument above.
@@ -232,13 +730,13 @@ impl<'s, 'd> Block<'s, 'd> {
                     // set to valid positions in the block and is therefore
                     // also in bounds.
                     //
-                    // We only need to compare y/z for equality, so we don't
-                    // need to both with endianness. cur corresponds to the
-                    // bytes at the current position and cand corresponds to
-                    // a potential match. If they're equal, we declare victory
-                    // and move below to try and extend the match.
-                    let cur = bytes::loadu_u32_ne(srcp.add(self.s));
-                    let cand = bytes::loadu_u32_ne(srcp.add(candidate));
+                    // cur corresponds to the bytes at the current position and
+                    // cand corresponds to a potential match. If they're
+                    // equal, we declare victory and move below to try and
+                    // extend the match.
+                    let cur = bytes::read_u32_le_unchecked(self.src, self.s);
+                    let cand =
+                        bytes::read_u32_le_unchecked(self.src, candidate);
                     if cur == cand {
                         break;
                     }
@@ -271,9 +769,25 @@ impl<'s, 'd> Block<'s, 'd> {
                     self.extend_match(candidate + 4);
                 }
                 let (offset, len) = (base - candidate, self.s - base);
+                if P::LONG && offset > 65535 && len < 5 {
+                    // A copy this far back takes 5 bytes, so a 4 byte match
+                    // is left in the next literal instead. This keeps the
+                    // worst case expansion within `max_compress_len`. The
+                    // literal must be emitted before any other copy, so go
+                    // back to looking for a match.
+                    self.next_emit = base;
+                    if self.s >= self.s_limit {
+                        self.hash_probes = probes;
+                        return self.done();
+                    }
+                    next_hash =
+                        table.hash(bytes::read_u32_le(&self.src[self.s..]));
+                    break;
+                }
                 self.emit_copy(offset, len);
                 self.next_emit = self.s;
                 if self.s >= self.s_limit {
+                    self.hash_probes = probes;
                     return self.done();
                 }
                 // Update the hash table with the byte sequences
@@ -284,26 +798,28 @@ impl<'s, 'd> Block<'s, 'd> {
                 // If we happen to get a hit on self.src[self.s..self.s + 4],
                 // then continue this loop and extend the match.
                 unsafe {
-                    let srcp = self.src.as_ptr();
                     // SAFETY: self.s can never exceed s_limit given by the
                     // conditional above and self.s is guaranteed to be
                     // non-zero and is therefore in bounds.
-                    let x = bytes::loadu_u64_le(srcp.add(self.s - 1));
+                    let x = bytes::read_u64_le_unchecked(self.src, self.s - 1);
                     // The lower 4 bytes of x correspond to
                     // self.src[self.s - 1..self.s + 3].
                     let prev_hash = table.hash(x as u32);
                     // SAFETY: Hash values are guaranteed to be in bounds.
-                    *table.get_unchecked_mut(prev_hash) = (self.s - 1) as u16;
+                    *table.get_unchecked_mut(prev_hash) = P::new(self.s - 1);
                     // The lower 4 bytes of x>>8 correspond to
                     // self.src[self.s..self.s + 4].
                     let cur_hash = table.hash((x >> 8) as u32);
+                    if count_probes {
+                        probes += 1;
+                    }
                     // SAFETY: Hash values are guaranteed to be in bounds.
-                    candidate = *table.get_unchecked(cur_hash) as usize;
-                    *table.get_unchecked_mut(cur_hash) = self.s as u16;
+                    candidate = table.get_unchecked(cur_hash).to_usize();
+                    *table.get_unchecked_mut(cur_hash) = P::new(self.s);
 
                     // SAFETY: candidate is set from table, which always
                     // contains valid positions in the current block.
-                    let y = bytes::loadu_u32_le(srcp.add(candidate));
+                    let y = bytes::read_u32_le_unchecked(self.src, candidate);
                     if (x >> 8) as u32 != y {
                         // If we didn't get a hit, update the next hash
                         // and move on. Our initial 8 byte read continues to
@@ -317,18 +833,177 @@ impl<'s, 'd> Block<'s, 'd> {
         }
     }
 
+    /// Compresses this block by finding the longest match at each position
+    /// among up to `depth` earlier positions in `chains`.
+    ///
+    /// Unlike `compress`, this looks for a match at every position, and
+    /// before emitting a match, checks whether the match at the next position
+    /// is longer. If so, the current byte is left for the next literal and
+    /// the longer match is taken instead, which often saves more than the
+    /// byte costs.
+    fn compress_chains(&mut self, chains: &mut HashChains, depth: usize) {
+        debug_assert!(self.src.len() >= MIN_NON_LITERAL_BLOCK_SIZE);
+        debug_assert!(self.src.len() <= MAX_BLOCK_SIZE);
+
+        let src = self.src;
+        chains.reset(src.len());
+        // The last position at which a 4 byte sequence starts.
+        let last = src.len() - 4;
+        let mut s = 0;
+        while s <= last {
+            chains.insert_until(src, s);
+            let (mut len, mut offset) = chains.longest_match(src, s, depth);
+            if len < 4 {
+                s += 1;
+                continue;
+            }
+            while s < last {
+                chains.insert_until(src, s + 1);
+                let (next_len, next_offset) =
+                    chains.longest_match(src, s + 1, depth);
+                if next_len <= len {
+                    break;
+                }
+                s += 1;
+                len = next_len;
+                offset = next_offset;
+            }
+            if self.next_emit < s {
+                unsafe {
+                    // SAFETY: next_emit is only ever set to the end of a
+                    // match, which is at most the length of the block, and
+                    // is less than s (in bounds) by the conditional above.
+                    self.emit_literal(s);
+                }
+            }
+            // Blocks are at most 64KB, so the offset always fits in the
+            // two bytes of a copy 2 operation.
+            self.emit_copy(offset, len);
+            s += len;
+            self.next_emit = s;
+        }
+        self.done();
+    }
+
+    /// Compresses this block into as few bytes as possible, given the
+    /// matches among up to `depth` earlier positions in `chains`.
+    ///
+    /// The first pass goes forward through the block, finding the cheapest
+    /// way to encode every prefix of it. The cheapest way to end a prefix
+    /// with a copy is found from the prefixes that each match could start
+    /// at, and the cheapest way to end it with a literal from the prefixes
+    /// ending in a copy that the literal could follow. The second pass
+    /// follows those choices back from the end of the block, and then emits
+    /// them in order.
+    #[cfg(feature = "unstable")]
+    fn compress_optimal(
+        &mut self,
+        chains: &mut HashChains,
+        parse: &mut OptimalParse,
+        depth: usize,
+    ) {
+        debug_assert!(self.src.len() >= MIN_NON_LITERAL_BLOCK_SIZE);
+        debug_assert!(self.src.len() <= MAX_BLOCK_SIZE);
+
+        let src = self.src;
+        let n = src.len();
+        chains.reset(n);
+        parse.reset(n);
+        // Matches found at positions before this are taken whole, so no
+        // other match needs to be looked for until it's reached.
+        let mut skip_until = 0;
+        for i in 0..=n {
+            if i > 0 {
+                parse.end_literal(i);
+            }
+            if i + 4 > n || i < skip_until {
+                continue;
+            }
+            chains.insert_until(src, i);
+            let mut matches = mem::replace(&mut parse.matches, vec![]);
+            matches.clear();
+            chains.for_each_longer_match(src, i, depth, |len, offset| {
+                if len >= 4 {
+                    matches.push((len, offset));
+                }
+            });
+            let cost = parse.cost(i);
+            match matches.last() {
+                Some(&(len, offset)) if len >= SUFFICIENT_MATCH_LEN => {
+                    parse.end_copy(i, len, offset, cost);
+                    skip_until = i + len;
+                }
+                _ => {
+                    // Each match is longer than the one before it, so
+                    // lengths up to the previous match are cheaper to copy
+                    // from that match, whose offset is smaller.
+                    let mut shorter = 3;
+                    for &(len, offset) in &matches {
+                        for l in shorter + 1..=len {
+                            parse.end_copy(i, l, offset, cost);
+                        }
+                        shorter = len;
+                    }
+                }
+            }
+            parse.matches = matches;
+        }
+
+        parse.copies.clear();
+        let mut i = n;
+        let mut in_copy = parse.ends_in_copy(n);
+        while i > 0 {
+            if in_copy {
+                let (start, offset) = parse.copy_from[i];
+                let start = start as usize;
+                parse.copies.push((start, i - start, offset as usize));
+                i = start;
+                in_copy = parse.ends_in_copy(i);
+            } else {
+                // A literal always follows a copy, or starts the block.
+                i = parse.literal_from[i] as usize;
+                in_copy = true;
+            }
+        }
+        for &(start, len, offset) in parse.copies.iter().rev() {
+            if self.next_emit < start {
+                unsafe {
+                    // SAFETY: next_emit is only ever set to the end of a
+                    // copy, which is at most the length of the block, and
+                    // is less than start (in bounds) by the conditional
+                    // above.
+                    self.emit_literal(start);
+                }
+            }
+            self.emit_copy(offset, len);
+            self.next_emit = start + len;
+        }
+        self.done();
+    }
+
     /// Emits one or more copy operations with the given offset and length.
     /// offset must be in the range [1, 65535] and len must be in the range
-    /// [4, 65535].
+    /// [4, 65535], except in the long-range blocks of the `long` module,
+    /// where both may be bigger.
     #[inline(always)]
     fn emit_copy(&mut self, offset: usize, mut len: usize) {
-        debug_assert!(1 <= offset && offset <= 65535);
+        if let Some(ref mut stats) = self.stats {
+            stats.record_copy(offset, len);
+        }
         // Copy operations only allow lengths up to 64, but we'll allow bigger
         // lengths and emit as many operations as we need.
         //
         // N.B. Since our block size is 64KB, we never actually emit a copy 4
-        // operation.
-        debug_assert!(4 <= len && len <= 65535);
+        // operation outside of the `long` module.
+        #[cfg(feature = "long-range")]
+        {
+            if offset > 65535 {
+                self.emit_copy4(offset, len);
+                return;
+            }
+        }
+        debug_assert!((1..=65535).contains(&offset));
+        debug_assert!(4 <= len);
 
         // Emit copy 2 operations until we don't have to.
         // We check on 68 here and emit a shorter copy than 64 below because
@@ -369,17 +1044,45 @@ impl<'s, 'd> Block<'s, 'd> {
         self.d += 3;
     }
 
+    /// Emits as many "copy 4" operations as needed for a copy with the given
+    /// offset and length, which must be at least 65536 and 4 respectively.
+    #[cfg(feature = "long-range")]
+    #[inline(never)]
+    fn emit_copy4(&mut self, offset: usize, mut len: usize) {
+        debug_assert!(65536 <= offset && offset <= std::u32::MAX as usize);
+        debug_assert!(4 <= len);
+        while len > 0 {
+            let n = cmp::min(len, 64);
+            self.dst[self.d] = (((n - 1) as u8) << 2) | (Tag::Copy4 as u8);
+            bytes::write_u32_le(offset as u32, &mut self.dst[self.d + 1..]);
+            self.d += 5;
+            len -= n;
+        }
+    }
+
     /// Attempts to extend a match from the current position in self.src with
     /// the candidate position given.
     ///
-    /// This method uses unaligned loads and elides bounds checks, so the
+    /// This method elides bounds checks, so the
     /// caller must guarantee that cand points to a valid location in self.src
     /// and is less than the current position in src.
     #[inline(always)]
     unsafe fn extend_match(&mut self, mut cand: usize) {
         debug_assert!(cand < self.s);
+        #[cfg(feature = "nightly-simd")]
+        while self.s + 16 <= self.src.len() {
+            // SAFETY: As below, but for 16 bytes.
+            let n = crate::simd::common_prefix16(
+                self.src.as_ptr().add(self.s),
+                self.src.as_ptr().add(cand),
+            );
+            self.s += n;
+            if n < 16 {
+                return;
+            }
+            cand += 16;
+        }
         while self.s + 8 <= self.src.len() {
-            let srcp = self.src.as_ptr();
             // SAFETY: The loop invariant guarantees that there is at least
             // 8 bytes to read at self.src + self.s. Since cand must be
             // guaranteed by the caller to be valid and less than self.s, it
@@ -388,18 +1091,18 @@ impl<'s, 'd> Block<'s, 'd> {
             // TODO(ag): Despite my best efforts, I couldn't get this to
             // autovectorize with 128-bit loads. The logic after the loads
             // appears to be a little too clever...
-            let x = bytes::loadu_u64_ne(srcp.add(self.s));
-            let y = bytes::loadu_u64_ne(srcp.add(cand));
+            let x = bytes::read_u64_le_unchecked(self.src, self.s);
+            let y = bytes::read_u64_le_unchecked(self.src, cand);
             if x == y {
                 // If all 8 bytes are equal, move on...
                 self.s += 8;
                 cand += 8;
             } else {
                 // Otherwise, find the last byte that was equal. We can do
-                // this efficiently by interpreted x/y as little endian
+                // this efficiently since x/y were read as little endian
                 // numbers, which lets us use the number of trailing zeroes
                 // as a proxy for the number of equivalent bits (after an XOR).
-                let z = x.to_le() ^ y.to_le();
+                let z = x ^ y;
                 self.s += z.trailing_zeros() as usize / 8;
                 return;
             }
@@ -435,6 +1138,9 @@ impl<'s, 'd> Block<'s, 'd> {
         let lit_start = self.next_emit;
         let len = lit_end - lit_start;
         let n = len.checked_sub(1).unwrap();
+        if let Some(ref mut stats) = self.stats {
+            stats.record_literal(len);
+        }
         if n <= 59 {
             self.dst[self.d] = ((n as u8) << 2) | (Tag::Literal as u8);
             self.d += 1;
@@ -457,10 +1163,16 @@ impl<'s, 'd> Block<'s, 'd> {
             self.dst[self.d] = (60 << 2) | (Tag::Literal as u8);
             self.dst[self.d + 1] = n as u8;
             self.d += 2;
-        } else {
+        } else if n < 1 << 16 {
             self.dst[self.d] = (61 << 2) | (Tag::Literal as u8);
             bytes::write_u16_le(n as u16, &mut self.dst[self.d + 1..]);
             self.d += 3;
+        } else {
+            // Only the long-range blocks of the `long` module are big enough
+            // for this.
+            self.dst[self.d] = (62 << 2) | (Tag::Literal as u8);
+            bytes::write_u24_le(n as u32, &mut self.dst[self.d + 1..]);
+            self.d += 4;
         }
         // SAFETY: lit_start is equivalent to self.next_emit, which is only set
         // to self.s immediately following a copy, which implies that it always
@@ -475,14 +1187,351 @@ impl<'s, 'd> Block<'s, 'd> {
     }
 }
 
+/// Hash chains that link each position in a block to the previous position
+/// whose next 4 bytes have the same hash. These let an encoder with a
+/// non-zero search depth look at many earlier occurrences of a sequence,
+/// where a `BlockTable` only remembers the most recent one.
+///
+/// Positions are stored plus one, so that `0` marks the end of a chain.
+#[derive(Clone, Debug, Default)]
+struct HashChains {
+    /// The start of the chain for each hash.
+    head: Vec<u32>,
+    /// The next link of the chain at each position in the block.
+    prev: Vec<u32>,
+    /// The next position in the block to insert into the chains.
+    next: usize,
+}
+
+impl HashChains {
+    /// Returns the number of bytes held by these chains.
+    fn memory_usage(&self) -> usize {
+        (self.head.capacity() + self.prev.capacity()) * mem::size_of::<u32>()
+    }
+
+    /// Empties the chains for a new block of `block_len` bytes.
+    fn reset(&mut self, block_len: usize) {
+        if self.head.is_empty() {
+            self.head = vec![0; CHAIN_TABLE_SIZE];
+            self.prev = vec![0; MAX_BLOCK_SIZE];
+        } else {
+            for x in &mut self.head {
+                *x = 0;
+            }
+        }
+        // Links at positions beyond `next` are never followed, so they
+        // don't need to be cleared.
+        debug_assert!(block_len <= self.prev.len());
+        self.next = 0;
+    }
+
+    #[inline(always)]
+    fn hash(x: u32) -> usize {
+        let bits = CHAIN_TABLE_SIZE.trailing_zeros();
+        (x.wrapping_mul(0x1E35A7BD) >> (32 - bits)) as usize
+    }
+
+    /// Inserts every position in `src` before `end` that hasn't been
+    /// inserted yet and that starts a 4 byte sequence.
+    fn insert_until(&mut self, src: &[u8], end: usize) {
+        let end = cmp::min(end, src.len() - 3);
+        while self.next < end {
+            let h = HashChains::hash(bytes::read_u32_le(&src[self.next..]));
+            self.prev[self.next] = self.head[h];
+            self.head[h] = self.next as u32 + 1;
+            self.next += 1;
+        }
+    }
+
+    /// Returns the length and offset of the longest match for the bytes at
+    /// position `s` in `src`, among the first `depth` positions in its
+    /// chain. The length is less than 4 if there's no usable match.
+    ///
+    /// Every position before `s`, and not `s` itself, must be inserted.
+    #[inline(always)]
+    fn longest_match(
+        &self,
+        src: &[u8],
+        s: usize,
+        depth: usize,
+    ) -> (usize, usize) {
+        let (mut best_len, mut best_offset) = (0, 0);
+        self.for_each_longer_match(src, s, depth, |len, offset| {
+            best_len = len;
+            best_offset = offset;
+        });
+        (best_len, best_offset)
+    }
+
+    /// Calls `found` with the length and offset of each match for the bytes
+    /// at position `s` in `src` that's longer than every match before it,
+    /// among the first `depth` positions in its chain. Since the chain goes
+    /// back from the most recent position, the offsets only grow.
+    ///
+    /// Every position before `s`, and not `s` itself, must be inserted.
+    #[inline(always)]
+    fn for_each_longer_match<F: FnMut(usize, usize)>(
+        &self,
+        src: &[u8],
+        s: usize,
+        depth: usize,
+        mut found: F,
+    ) {
+        let mut best_len = 0;
+        let mut link =
+            self.head[HashChains::hash(bytes::read_u32_le(&src[s..]))];
+        for _ in 0..depth {
+            if link == 0 {
+                break;
+            }
+            let cand = link as usize - 1;
+            // Only a candidate that also matches the byte just past the best
+            // match so far can beat it.
+            if src[cand + best_len] == src[s + best_len] {
+                let len = match_len(src, cand, s);
+                if len > best_len {
+                    best_len = len;
+                    found(len, s - cand);
+                    if s + len == src.len() {
+                        break;
+                    }
+                }
+            }
+            link = self.prev[cand];
+        }
+    }
+}
+
+/// The cost of every way found to encode each prefix of a block, which the
+/// optimal parser fills in.
+///
+/// Costs are in bytes of output. A prefix can end with a literal or a copy,
+/// and the cheapest of each is kept separately, since a literal that follows
+/// another literal would just be part of it.
+#[cfg(feature = "unstable")]
+#[derive(Clone, Debug, Default)]
+struct OptimalParse {
+    /// The cheapest way to encode each prefix ending with a literal.
+    literal_cost: Vec<u32>,
+    /// Where the literal that ends each prefix starts.
+    literal_from: Vec<u32>,
+    /// The cheapest way to encode each prefix ending with a copy.
+    copy_cost: Vec<u32>,
+    /// Where the copy that ends each prefix starts, and its offset.
+    copy_from: Vec<(u32, u16)>,
+    /// The prefixes ending with a copy that a literal ending at the current
+    /// position could follow, if the literal's header takes 1 byte, or 2.
+    windows: [MinWindow; 2],
+    /// The cheapest prefix ending with a copy that a literal of more than
+    /// 256 bytes, whose header takes 3 bytes, could follow.
+    far: Option<(usize, i64)>,
+    /// The matches found at the current position.
+    matches: Vec<(usize, usize)>,
+    /// The copies chosen, as their start, length and offset, from the end of
+    /// the block back.
+    copies: Vec<(usize, usize, usize)>,
+}
+
+#[cfg(feature = "unstable")]
+impl OptimalParse {
+    /// A cost higher than any way to encode a block, for prefixes that no
+    /// way has been found to end with yet.
+    const UNREACHED: u32 = std::u32::MAX;
+
+    /// Returns the number of bytes held by this parse.
+    fn memory_usage(&self) -> usize {
+        (self.literal_cost.capacity()
+            + self.literal_from.capacity()
+            + self.copy_cost.capacity())
+            * mem::size_of::<u32>()
+            + self.copy_from.capacity() * mem::size_of::<(u32, u16)>()
+    }
+
+    /// Resets the costs for a new block of `block_len` bytes. The empty
+    /// prefix is treated as ending with a copy, since a literal can follow
+    /// it.
+    fn reset(&mut self, block_len: usize) {
+        self.literal_cost.clear();
+        self.literal_cost.resize(block_len + 1, OptimalParse::UNREACHED);
+        self.literal_from.clear();
+        self.literal_from.resize(block_len + 1, 0);
+        self.copy_cost.clear();
+        self.copy_cost.resize(block_len + 1, OptimalParse::UNREACHED);
+        self.copy_from.clear();
+        self.copy_from.resize(block_len + 1, (0, 0));
+        self.copy_cost[0] = 0;
+        for window in &mut self.windows {
+            window.clear();
+        }
+        self.far = None;
+    }
+
+    /// Returns the cost of the cheapest way found to encode the prefix of
+    /// length `i`.
+    fn cost(&self, i: usize) -> u32 {
+        cmp::min(self.literal_cost[i], self.copy_cost[i])
+    }
+
+    /// Returns whether the cheapest way found to encode the prefix of length
+    /// `i` ends with a copy.
+    fn ends_in_copy(&self, i: usize) -> bool {
+        self.copy_cost[i] <= self.literal_cost[i]
+    }
+
+    /// Records a copy of `len` bytes from `offset` back, starting at `i`,
+    /// if it's the cheapest way found to end the prefix it ends.
+    #[inline(always)]
+    fn end_copy(&mut self, i: usize, len: usize, offset: usize, cost: u32) {
+        let cost = cost + copy_cost(offset, len);
+        if cost < self.copy_cost[i + len] {
+            self.copy_cost[i + len] = cost;
+            self.copy_from[i + len] = (i as u32, offset as u16);
+        }
+    }
+
+    /// Finds the cheapest literal that ends the prefix of length `i`, which
+    /// must be called for every prefix in order, once the prefixes before it
+    /// are all done.
+    ///
+    /// A literal from `j` to `i` costs `i - j` bytes, plus a header of 1, 2
+    /// or 3 bytes depending on its length. So with the cost of each prefix
+    /// `j` ending with a copy, minus `j`, kept in a window for each header
+    /// size, the cheapest literal is the cheapest of each window, plus the
+    /// header and `i`.
+    fn end_literal(&mut self, i: usize) {
+        let j = i - 1;
+        if self.copy_cost[j] != OptimalParse::UNREACHED {
+            self.windows[0].push(j, self.copy_cost[j] as i64 - j as i64);
+        }
+        if i > 60 && self.copy_cost[i - 61] != OptimalParse::UNREACHED {
+            let j = i - 61;
+            self.windows[1].push(j, self.copy_cost[j] as i64 - j as i64);
+        }
+        if i > 256 && self.copy_cost[i - 257] != OptimalParse::UNREACHED {
+            let j = i - 257;
+            let key = self.copy_cost[j] as i64 - j as i64;
+            if self.far.map_or(true, |(_, far)| key < far) {
+                self.far = Some((j, key));
+            }
+        }
+        self.windows[0].expire(i.saturating_sub(60));
+        self.windows[1].expire(i.saturating_sub(256));
+
+        let candidates = [
+            self.windows[0].min().map(|(j, key)| (j, key + 1)),
+            self.windows[1].min().map(|(j, key)| (j, key + 2)),
+            self.far.map(|(j, key)| (j, key + 3)),
+        ];
+        let mut best: Option<(usize, i64)> = None;
+        for &(j, key) in candidates.iter().flatten() {
+            if best.map_or(true, |(_, best)| key < best) {
+                best = Some((j, key));
+            }
+        }
+        if let Some((j, key)) = best {
+            self.literal_cost[i] = (key + i as i64) as u32;
+            self.literal_from[i] = j as u32;
+        }
+    }
+}
+
+/// A sliding window of positions in a block, each with a key, that can
+/// quickly return the position with the smallest key.
+///
+/// Positions are kept in increasing order, and any position whose key is at
+/// least that of a later position is dropped, since it can never be the
+/// smallest again.
+#[cfg(feature = "unstable")]
+#[derive(Clone, Debug, Default)]
+struct MinWindow {
+    entries: VecDeque<(usize, i64)>,
+}
+
+#[cfg(feature = "unstable")]
+impl MinWindow {
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Adds position `j`, which must be after every position in the window.
+    fn push(&mut self, j: usize, key: i64) {
+        while self.entries.back().map_or(false, |&(_, k)| k >= key) {
+            self.entries.pop_back();
+        }
+        self.entries.push_back((j, key));
+    }
+
+    /// Removes every position before `start` from the window.
+    fn expire(&mut self, start: usize) {
+        while self.entries.front().map_or(false, |&(j, _)| j < start) {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Returns the position with the smallest key, and that key.
+    fn min(&self) -> Option<(usize, i64)> {
+        self.entries.front().cloned()
+    }
+}
+
+/// Returns the number of bytes that `Block::emit_copy` takes to encode a
+/// copy of `len` bytes from `offset` back.
+#[cfg(feature = "unstable")]
+#[inline(always)]
+fn copy_cost(offset: usize, mut len: usize) -> u32 {
+    let mut cost = 0;
+    while len >= 68 {
+        cost += 3;
+        len -= 64;
+    }
+    if len > 64 {
+        cost += 3;
+        len -= 60;
+    }
+    if len <= 11 && offset <= 2047 {
+        cost + 2
+    } else {
+        cost + 3
+    }
+}
+
+/// Returns the number of bytes that match in `src` starting at the earlier
+/// position `cand` and the later position `s`.
+#[inline(always)]
+fn match_len(src: &[u8], mut cand: usize, s: usize) -> usize {
+    debug_assert!(cand < s);
+    let mut end = s;
+    while end + 8 <= src.len() {
+        let (x, y) = unsafe {
+            // SAFETY: The loop condition guarantees that there are at least
+            // 8 bytes to read at end, and cand is less than end, so there
+            // are at least 8 bytes to read at cand too.
+            (
+                bytes::read_u64_le_unchecked(src, cand),
+                bytes::read_u64_le_unchecked(src, end),
+            )
+        };
+        if x != y {
+            return end - s + (x ^ y).trailing_zeros() as usize / 8;
+        }
+        cand += 8;
+        end += 8;
+    }
+    while end < src.len() && src[cand] == src[end] {
+        cand += 1;
+        end += 1;
+    }
+    end - s
+}
+
 /// `BlockTable` is a map from 4 byte sequences to positions of their most
 /// recent occurrence in a block. In particular, this table lets us quickly
 /// find candidates for compression.
 ///
 /// We expose the `hash` method so that callers can be fastidious about the
 /// number of times a hash is computed.
-struct BlockTable<'a> {
-    table: &'a mut [u16],
+struct BlockTable<'a, P = u16> {
+    table: &'a mut [P],
     /// The number of bits required to shift the hash such that the result
     /// is less than table.len().
     shift: u32,
@@ -491,8 +1540,8 @@ struct BlockTable<'a> {
 impl Encoder {
     fn block_table(&mut self, block_size: usize) -> BlockTable<'_> {
         let mut shift: u32 = 32 - 8;
-        let mut table_size = 256;
-        while table_size < MAX_TABLE_SIZE && table_size < block_size {
+        let mut table_size = MIN_TABLE_SIZE;
+        while table_size < self.max_table_size && table_size < block_size {
             shift -= 1;
             table_size *= 2;
         }
@@ -508,7 +1557,7 @@ impl Encoder {
                 // very weird code getting generated that led to a large
                 // slow down. Forcing the issue with a new vec seems to
                 // fix it. ---AG
-                self.big = vec![0; MAX_TABLE_SIZE];
+                self.big = vec![0; self.max_table_size];
             }
             &mut self.big[0..table_size]
         };
@@ -519,22 +1568,100 @@ impl Encoder {
     }
 }
 
-impl<'a> BlockTable<'a> {
+impl<'a, P> BlockTable<'a, P> {
     #[inline(always)]
     fn hash(&self, x: u32) -> usize {
         (x.wrapping_mul(0x1E35A7BD) >> self.shift) as usize
     }
 }
 
-impl<'a> Deref for BlockTable<'a> {
-    type Target = [u16];
-    fn deref(&self) -> &[u16] {
+impl<'a, P> Deref for BlockTable<'a, P> {
+    type Target = [P];
+    fn deref(&self) -> &[P] {
         self.table
     }
 }
 
-impl<'a> DerefMut for BlockTable<'a> {
-    fn deref_mut(&mut self) -> &mut [u16] {
+impl<'a, P> DerefMut for BlockTable<'a, P> {
+    fn deref_mut(&mut self) -> &mut [P] {
         self.table
     }
 }
+
+/// A position in a block, as stored in a `BlockTable`.
+///
+/// Tables hold `u16` positions, since blocks are at most 64KB, except for
+/// the long-range blocks of the `long` module, which use `u32` positions.
+trait Position: Copy {
+    /// Whether blocks can be big enough for copies to need a 4 byte offset.
+    const LONG: bool;
+
+    fn new(pos: usize) -> Self;
+
+    fn to_usize(self) -> usize;
+}
+
+impl Position for u16 {
+    const LONG: bool = false;
+
+    #[inline(always)]
+    fn new(pos: usize) -> u16 {
+        pos as u16
+    }
+
+    #[inline(always)]
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+#[cfg(feature = "long-range")]
+impl Position for u32 {
+    const LONG: bool = true;
+
+    #[inline(always)]
+    fn new(pos: usize) -> u32 {
+        pos as u32
+    }
+
+    #[inline(always)]
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+/// Compresses `src`, a single block of the long-range format of the `long`
+/// module, into `output` starting at `d`, with `table` as its hash table.
+/// This returns the position in `output` immediately following the
+/// compressed block.
+///
+/// `table` may have any power of two length from `MIN_TABLE_SIZE` up, and
+/// `src` may be as big as `u32::MAX` bytes, so copies may reach back further
+/// than 64KB.
+#[cfg(feature = "long-range")]
+pub(crate) fn compress_long_block(
+    src: &[u8],
+    output: &mut [u8],
+    d: usize,
+    table: &mut [u32],
+) -> usize {
+    debug_assert!(src.len() as u64 <= std::u32::MAX as u64);
+    debug_assert!(table.len().is_power_of_two());
+    debug_assert!(table.len() >= MIN_TABLE_SIZE);
+    let mut block = Block::new(src, output, d);
+    if block.src.len() < MIN_NON_LITERAL_BLOCK_SIZE {
+        let lit_end = block.src.len();
+        unsafe {
+            // SAFETY: next_emit is zero (in bounds) and the end is
+            // the length of the block (in bounds).
+            block.emit_literal(lit_end);
+        }
+    } else {
+        for x in &mut *table {
+            *x = 0;
+        }
+        let shift = 32 - table.len().trailing_zeros();
+        block.compress(BlockTable { table, shift });
+    }
+    block.d
+}
@@ -0,0 +1,162 @@
+use std::prelude::v1::*;
+#[cfg(feature = "frame")]
+use std::io;
+
+#[cfg(feature = "compress")]
+use crate::compress::Encoder;
+#[cfg(feature = "decompress")]
+use crate::decompress::{decompress_len, Decoder};
+use crate::error::Result;
+
+/// Bytes in the raw Snappy format.
+///
+/// Raw and framed Snappy data can't be told apart by their type when both are
+/// just bytes, and feeding one to the decoder of the other fails with a
+/// confusing "corrupt input" error. Wrapping compressed bytes in this type, or
+/// in [`frame::FrameCompressed`](../frame/struct.FrameCompressed.html) for
+/// the frame format, lets the compiler catch such mix ups instead.
+///
+/// The type parameter `B` is the storage of the bytes, e.g., `Vec<u8>` or
+/// `&[u8]`. [`compress`](#method.compress) returns a `RawCompressed<Vec<u8>>`,
+/// while compressed bytes from elsewhere can be wrapped with
+/// [`new`](#method.new).
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct RawCompressed<B>(B);
+
+impl<B> RawCompressed<B> {
+    /// Wrap bytes that are known to be in the raw Snappy format.
+    ///
+    /// The bytes aren't checked. If they aren't a raw Snappy block,
+    /// decompressing them returns an error.
+    pub fn new(bytes: B) -> RawCompressed<B> {
+        RawCompressed(bytes)
+    }
+
+    /// Returns a reference to the underlying storage.
+    pub fn get_ref(&self) -> &B {
+        &self.0
+    }
+
+    /// Unwraps the underlying storage.
+    pub fn into_inner(self) -> B {
+        self.0
+    }
+}
+
+impl<B: AsRef<[u8]>> RawCompressed<B> {
+    /// Returns the compressed bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+
+    /// Returns the decompressed size (in bytes) of these compressed bytes,
+    /// as given by the raw Snappy header.
+    ///
+    /// This returns an error if the header is invalid.
+    #[cfg(feature = "decompress")]
+    pub fn decompress_len(&self) -> Result<usize> {
+        decompress_len(self.as_bytes())
+    }
+
+    /// Decompresses these bytes into a freshly allocated `Vec`.
+    ///
+    /// This returns an error under the same circumstances that
+    /// [`Decoder::decompress`](../raw/struct.Decoder.html#method.decompress)
+    /// does.
+    #[cfg(feature = "decompress")]
+    pub fn decompress(&self) -> Result<Vec<u8>> {
+        Decoder::new().decompress_vec(self.as_bytes())
+    }
+}
+
+#[cfg(feature = "compress")]
+impl RawCompressed<Vec<u8>> {
+    /// Compresses `input` as a single raw Snappy block.
+    ///
+    /// This returns an error if `input` is too big for a raw block. See
+    /// [`Encoder::compress`](../raw/struct.Encoder.html#method.compress).
+    pub fn compress(input: &[u8]) -> Result<RawCompressed<Vec<u8>>> {
+        Encoder::new().compress_vec(input).map(RawCompressed)
+    }
+}
+
+impl<B: AsRef<[u8]>> AsRef<[u8]> for RawCompressed<B> {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+/// Bytes in the Snappy frame format.
+///
+/// This is the frame format counterpart to
+/// [`raw::RawCompressed`](../raw/struct.RawCompressed.html). Since a
+/// framed stream is read through `std::io`, its errors are `io::Error`s.
+#[cfg(feature = "frame")]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct FrameCompressed<B>(B);
+
+#[cfg(feature = "frame")]
+impl<B> FrameCompressed<B> {
+    /// Wrap bytes that are known to be in the Snappy frame format.
+    ///
+    /// The bytes aren't checked. If they aren't a Snappy frame stream,
+    /// decompressing them returns an error.
+    pub fn new(bytes: B) -> FrameCompressed<B> {
+        FrameCompressed(bytes)
+    }
+
+    /// Returns a reference to the underlying storage.
+    pub fn get_ref(&self) -> &B {
+        &self.0
+    }
+
+    /// Unwraps the underlying storage.
+    pub fn into_inner(self) -> B {
+        self.0
+    }
+}
+
+#[cfg(feature = "frame")]
+impl<B: AsRef<[u8]>> FrameCompressed<B> {
+    /// Returns the compressed bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+
+    /// Decompresses this stream into a freshly allocated `Vec`.
+    ///
+    /// This returns an error if the stream is invalid or any of its
+    /// checksums don't match.
+    #[cfg(feature = "decompress")]
+    pub fn decompress(&self) -> io::Result<Vec<u8>> {
+        use std::io::Read;
+
+        let mut buf = vec![];
+        crate::read::FrameDecoder::new(self.as_bytes())
+            .read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+#[cfg(all(feature = "frame", feature = "compress"))]
+impl FrameCompressed<Vec<u8>> {
+    /// Compresses `input` as a Snappy frame stream.
+    pub fn compress(input: &[u8]) -> io::Result<FrameCompressed<Vec<u8>>> {
+        use std::io::Write;
+
+        let mut buf = vec![];
+        {
+            let mut wtr = crate::write::FrameEncoder::new(&mut buf);
+            wtr.write_all(input)?;
+            wtr.flush()?;
+        }
+        Ok(FrameCompressed(buf))
+    }
+}
+
+#[cfg(feature = "frame")]
+impl<B: AsRef<[u8]>> AsRef<[u8]> for FrameCompressed<B> {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
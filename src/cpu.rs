@@ -0,0 +1,148 @@
+/*!
+This module reports which accelerated code paths this build of the crate
+uses.
+
+Every accelerated path in this crate is selected at compile time, from the
+target features the crate is built with (e.g., with `-C target-cpu=native`),
+rather than detected at runtime, since the CPUID instruction that runtime
+detection relies on can't be used inside an SGX enclave. A build for a
+generic target therefore always runs the portable code, even on a CPU that
+supports more. [`active_features`](fn.active_features.html) lets operators
+check which paths a deployed build actually runs, e.g., by logging it at
+startup.
+
+There are no PCLMUL, AVX2 or NEON paths in this crate, so they are never
+reported.
+*/
+
+use std::prelude::v1::*;
+use std::fmt;
+
+/// Returns the accelerated code paths that this build of the crate uses.
+///
+/// Since they are selected at compile time, this always returns the same
+/// value in a given build.
+pub fn active_features() -> Features {
+    // The build script selects the CRC32C implementation.
+    let crc32c = if cfg!(crc32c = "none") {
+        None
+    } else if cfg!(crc32c = "sse4.2") {
+        Some(Crc32c::Sse42)
+    } else if cfg!(crc32c = "riscv-zbc") {
+        Some(Crc32c::RiscvZbc)
+    } else if cfg!(crc32c = "s390x-vector") {
+        Some(Crc32c::S390xVector)
+    } else if cfg!(crc32c = "nibble") {
+        Some(Crc32c::Nibble)
+    } else {
+        Some(Crc32c::Slice16)
+    };
+    Features {
+        crc32c,
+        ssse3_copy: cfg!(all(
+            feature = "decompress",
+            any(target_arch = "x86", target_arch = "x86_64"),
+            target_feature = "ssse3"
+        )),
+        bmi2_tags: cfg!(all(
+            feature = "decompress",
+            any(target_arch = "x86", target_arch = "x86_64"),
+            target_feature = "bmi2"
+        )),
+        portable_simd: cfg!(feature = "nightly-simd"),
+    }
+}
+
+/// The accelerated code paths used by a build of this crate, as returned by
+/// [`active_features`](fn.active_features.html).
+///
+/// The `Display` impl writes all of them on a single line, which is suitable
+/// for logs.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Features {
+    crc32c: Option<Crc32c>,
+    ssse3_copy: bool,
+    bmi2_tags: bool,
+    portable_simd: bool,
+}
+
+impl Features {
+    /// Returns the CRC32C implementation that the frame format uses for its
+    /// checksums, or `None` when the `frame` feature is disabled.
+    pub fn crc32c(&self) -> Option<Crc32c> {
+        self.crc32c
+    }
+
+    /// Returns true if the decoder replicates copies with an offset less
+    /// than 16 with SSSE3 shuffles, which requires SSSE3 to be enabled at
+    /// compile time.
+    pub fn ssse3_copy(&self) -> bool {
+        self.ssse3_copy
+    }
+
+    /// Returns true if the decoder masks copy offsets and literal lengths
+    /// with the BMI2 `bzhi` instruction, which requires BMI2 to be enabled
+    /// at compile time.
+    pub fn bmi2_tags(&self) -> bool {
+        self.bmi2_tags
+    }
+
+    /// Returns true if the copy and match-compare loops are implemented with
+    /// `core::simd`, i.e., if the `nightly-simd` feature is enabled.
+    pub fn portable_simd(&self) -> bool {
+        self.portable_simd
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn on_off(yes: bool) -> &'static str {
+            if yes {
+                "on"
+            } else {
+                "off"
+            }
+        }
+
+        match self.crc32c {
+            None => write!(f, "crc32c=none")?,
+            Some(crc32c) => write!(f, "crc32c={}", crc32c)?,
+        }
+        write!(
+            f,
+            " ssse3-copy={} bmi2-tags={} portable-simd={}",
+            on_off(self.ssse3_copy),
+            on_off(self.bmi2_tags),
+            on_off(self.portable_simd)
+        )
+    }
+}
+
+/// An implementation of the CRC32C checksums of the frame format.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Crc32c {
+    /// The portable "slicing by 16" technique, which uses 17KB of tables.
+    Slice16,
+    /// The portable nibble at a time technique of the `small-crc` feature,
+    /// which is several times slower than `Slice16`.
+    Nibble,
+    /// The `crc32` instruction of SSE 4.2 on x86.
+    Sse42,
+    /// Carry-less multiplication with the RISC-V Zbc extension.
+    RiscvZbc,
+    /// Carry-less multiplication with the IBM Z vector facility.
+    S390xVector,
+}
+
+impl fmt::Display for Crc32c {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Crc32c::Slice16 => "slice16",
+            Crc32c::Nibble => "nibble",
+            Crc32c::Sse42 => "sse4.2",
+            Crc32c::RiscvZbc => "riscv-zbc",
+            Crc32c::S390xVector => "s390x-vector",
+        };
+        f.write_str(name)
+    }
+}
@@ -1,24 +1,66 @@
-use std::prelude::v1::*;
+#[cfg(crc32c = "slice16")]
 use crate::bytes;
+#[cfg(crc32c = "nibble")]
+use crate::crc32_table::TABLE4;
+#[cfg(crc32c = "slice16")]
 use crate::crc32_table::{TABLE, TABLE16};
+#[cfg(not(crc32c = "none"))]
+use std::prelude::v1::*;
+
+#[cfg(all(crc32c = "sse4.2", target_arch = "x86"))]
+use core::arch::x86 as arch;
+#[cfg(all(crc32c = "sse4.2", target_arch = "x86_64"))]
+use core::arch::x86_64 as arch;
+
+/// The Castagnoli polynomial, bit reflected.
+#[cfg(any(crc32c = "riscv-zbc", crc32c = "s390x-vector"))]
+const CASTAGNOLI_POLY: u32 = 0x82f63b78;
+
+/// The quotient of `x^96` divided by the Castagnoli polynomial, bit reflected
+/// and without its implicit `x^64` term, for Barrett reduction.
+#[cfg(any(crc32c = "riscv-zbc", crc32c = "s390x-vector"))]
+const CASTAGNOLI_QUOTIENT: u64 = 0xa434f61c6f5389f8;
+
+/// The constant added to a rotated CRC32C checksum to mask it.
+const MASK_DELTA: u32 = 0xA282EAD8;
+
+/// Masks a CRC32C checksum the way the Snappy frame format requires, which
+/// is what chunk headers store.
+pub fn mask(sum: u32) -> u32 {
+    sum.rotate_right(15).wrapping_add(MASK_DELTA)
+}
+
+/// Recovers the CRC32C checksum that `mask` turned into `masked`.
+pub fn unmask(masked: u32) -> u32 {
+    masked.wrapping_sub(MASK_DELTA).rotate_left(15)
+}
 
 /// Provides a simple API to generate "masked" CRC32C checksums specifically
-/// for use in Snappy. When available, this will make use of SSE 4.2 to compute
-/// checksums. Otherwise, it falls back to only-marginally-slower "slicing by
-/// 16" technique.
+/// for use in Snappy. On x86 targets compiled with SSE 4.2 enabled (e.g.,
+/// with `-C target-feature=+sse4.2`), this uses the `crc32` instruction. It
+/// can't be detected at runtime, since CPUID can't be used inside an SGX
+/// enclave. On RISC-V targets compiled with the Zbc extension enabled (e.g.,
+/// with `-C target-feature=+zbc`), and on IBM Z targets compiled with the
+/// vector facility enabled (e.g., with `-C target-cpu=z13`), it uses
+/// carry-less multiplication if the `unstable` feature is enabled.
+/// Otherwise, it falls back to only-marginally-slower "slicing by 16"
+/// technique, or to a much slower nibble at a time technique when the
+/// `small-crc` feature is enabled. The implementation is selected once, by
+/// the build script, which sets the `crc32c` cfg to its name.
 ///
-/// The main purpose of this type is to cache the CPU feature check and expose
-/// a safe API.
+/// Since the implementation is selected at compile time, there is no CPU
+/// feature check to cache, and the main purpose of this type is to expose a
+/// safe API.
+#[cfg(not(crc32c = "none"))]
 #[derive(Clone, Copy, Debug)]
-pub struct CheckSummer {
-    sse42: bool,
-}
+pub struct CheckSummer;
 
+#[cfg(not(crc32c = "none"))]
 impl CheckSummer {
     /// Create a new checksummer that can compute CRC32C checksums on arbitrary
     /// bytes.
     pub fn new() -> CheckSummer {
-        CheckSummer { sse42: false }
+        CheckSummer
     }
 
     /// Returns the "masked" CRC32 checksum of `buf` using the Castagnoli
@@ -26,18 +68,166 @@ impl CheckSummer {
     /// format. Masking is supposed to make the checksum robust with respect to
     /// the data that contains the checksum itself.
     pub fn crc32c_masked(&self, buf: &[u8]) -> u32 {
-        let sum = self.crc32c(buf);
-        (sum.wrapping_shr(15) | sum.wrapping_shl(17)).wrapping_add(0xA282EAD8)
+        mask(self.crc32c(buf))
     }
 
     /// Returns the CRC32 checksum of `buf` using the Castagnoli polynomial.
+    #[cfg(any(crc32c = "riscv-zbc", crc32c = "s390x-vector"))]
+    fn crc32c(&self, buf: &[u8]) -> u32 {
+        crc32c_clmul(buf)
+    }
+
+    /// Returns the CRC32 checksum of `buf` using the Castagnoli polynomial.
+    #[cfg(crc32c = "sse4.2")]
+    fn crc32c(&self, buf: &[u8]) -> u32 {
+        crc32c_sse42(buf)
+    }
+
+    /// Returns the CRC32 checksum of `buf` using the Castagnoli polynomial.
+    #[cfg(crc32c = "slice16")]
     fn crc32c(&self, buf: &[u8]) -> u32 {
         crc32c_slice16(buf)
     }
 
+    /// Returns the CRC32 checksum of `buf` using the Castagnoli polynomial.
+    #[cfg(crc32c = "nibble")]
+    fn crc32c(&self, buf: &[u8]) -> u32 {
+        crc32c_nibble(buf)
+    }
+}
+
+/// Returns the CRC32 checksum of `buf` using the Castagnoli polynomial, eight
+/// bytes at a time with carry-less multiplication. The few bytes left over
+/// are checksummed a bit at a time, which needs no table.
+#[cfg(any(crc32c = "riscv-zbc", crc32c = "s390x-vector"))]
+fn crc32c_clmul(buf: &[u8]) -> u32 {
+    let mut crc: u32 = !0;
+    let mut words = buf.chunks_exact(8);
+    for word in &mut words {
+        let mut le = [0; 8];
+        le.copy_from_slice(word);
+        crc = crc32c_clmul_u64(crc, u64::from_le_bytes(le));
+    }
+    for &b in words.remainder() {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (CASTAGNOLI_POLY & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Returns the CRC32 state `crc` updated with the eight bytes of `word`,
+/// read as a little endian integer, by Barrett reduction with the clmul and
+/// clmulr instructions of the RISC-V Zbc extension.
+#[cfg(crc32c = "riscv-zbc")]
+#[inline(always)]
+fn crc32c_clmul_u64(crc: u32, word: u64) -> u32 {
+    let s = crc as u64 ^ word;
+    let out: u64;
+    // SAFETY: These instructions only compute on registers, and the Zbc
+    // extension that provides clmul and clmulr is enabled at compile time.
+    unsafe {
+        core::arch::asm!(
+            "clmul {t}, {s}, {q}",
+            "slli {t}, {t}, 1",
+            "xor {t}, {t}, {s}",
+            "clmulr {t}, {t}, {p}",
+            "srli {t}, {t}, 32",
+            t = out(reg) out,
+            s = in(reg) s,
+            q = in(reg) CASTAGNOLI_QUOTIENT,
+            p = in(reg) (CASTAGNOLI_POLY as u64) << 32,
+            options(pure, nomem, nostack),
+        );
+    }
+    out as u32
+}
+
+/// Like the RISC-V version above, but with the vector Galois field multiply
+/// instruction of the IBM Z vector facility, which yields the full 128 bit
+/// product. The bits that `clmulr` would shift down are then taken from its
+/// upper half.
+#[cfg(crc32c = "s390x-vector")]
+#[inline(always)]
+fn crc32c_clmul_u64(crc: u32, word: u64) -> u32 {
+    let s = crc as u64 ^ word;
+    let (_, lo) = vgfmg(s, CASTAGNOLI_QUOTIENT);
+    let (hi, _) = vgfmg((lo << 1) ^ s, (CASTAGNOLI_POLY as u64) << 32);
+    (hi >> 31) as u32
+}
+
+/// Returns the upper and lower halves of the carry-less product of `a` and
+/// `b`.
+#[cfg(crc32c = "s390x-vector")]
+#[inline(always)]
+fn vgfmg(a: u64, b: u64) -> (u64, u64) {
+    let (hi, lo): (u64, u64);
+    // SAFETY: These instructions only compute on registers, and the vector
+    // facility that provides them is enabled at compile time. Each operand
+    // is put in the low doubleword of a vector whose high doubleword is
+    // zero, so the sum of products that vgfmg computes is just `a * b`.
+    unsafe {
+        core::arch::asm!(
+            "vlvgp %v16, {z}, {a}",
+            "vlvgp %v17, {z}, {b}",
+            "vgfmg %v16, %v16, %v17",
+            "vlgvg {hi}, %v16, 0",
+            "vlgvg {lo}, %v16, 1",
+            a = in(reg) a,
+            b = in(reg) b,
+            z = in(reg) 0u64,
+            hi = lateout(reg) hi,
+            lo = lateout(reg) lo,
+            out("v16") _,
+            out("v17") _,
+            options(pure, nomem, nostack),
+        );
+    }
+    (hi, lo)
+}
+
+/// Returns the CRC32 checksum of `buf` using the Castagnoli polynomial, eight
+/// bytes at a time with the `crc32` instruction of SSE 4.2.
+#[cfg(crc32c = "sse4.2")]
+fn crc32c_sse42(buf: &[u8]) -> u32 {
+    let mut crc: u32 = !0;
+    let mut words = buf.chunks_exact(8);
+    for word in &mut words {
+        let mut le = [0; 8];
+        le.copy_from_slice(word);
+        crc = crc32c_sse42_u64(crc, u64::from_le_bytes(le));
+    }
+    for &b in words.remainder() {
+        // SAFETY: SSE 4.2 is enabled at compile time.
+        crc = unsafe { arch::_mm_crc32_u8(crc, b) };
+    }
+    !crc
+}
+
+/// Returns the CRC32 state `crc` updated with the eight bytes of `word`,
+/// read as a little endian integer.
+#[cfg(all(crc32c = "sse4.2", target_arch = "x86_64"))]
+#[inline(always)]
+fn crc32c_sse42_u64(crc: u32, word: u64) -> u32 {
+    // SAFETY: SSE 4.2 is enabled at compile time.
+    unsafe { arch::_mm_crc32_u64(crc as u64, word) as u32 }
+}
+
+/// Like the x86-64 version above, but in two halves, since 32 bit x86 only
+/// has the 32 bit form of the instruction.
+#[cfg(all(crc32c = "sse4.2", target_arch = "x86"))]
+#[inline(always)]
+fn crc32c_sse42_u64(crc: u32, word: u64) -> u32 {
+    // SAFETY: SSE 4.2 is enabled at compile time.
+    unsafe {
+        let crc = arch::_mm_crc32_u32(crc, word as u32);
+        arch::_mm_crc32_u32(crc, (word >> 32) as u32)
+    }
 }
 
 /// Returns the CRC32 checksum of `buf` using the Castagnoli polynomial.
+#[cfg(crc32c = "slice16")]
 fn crc32c_slice16(mut buf: &[u8]) -> u32 {
     let mut crc: u32 = !0;
     while buf.len() >= 16 {
@@ -65,3 +255,16 @@ fn crc32c_slice16(mut buf: &[u8]) -> u32 {
     }
     !crc
 }
+
+/// Returns the CRC32 checksum of `buf` using the Castagnoli polynomial, four
+/// bits at a time. This only needs a 64 byte table.
+#[cfg(crc32c = "nibble")]
+fn crc32c_nibble(buf: &[u8]) -> u32 {
+    let mut crc: u32 = !0;
+    for &b in buf {
+        crc ^= b as u32;
+        crc = TABLE4[(crc & 0xF) as usize] ^ (crc >> 4);
+        crc = TABLE4[(crc & 0xF) as usize] ^ (crc >> 4);
+    }
+    !crc
+}
@@ -1,4 +1,6 @@
 use std::prelude::v1::*;
+use std::cmp::{self, Ordering};
+use std::io::{self, IoSliceMut};
 use std::ptr;
 
 use crate::bytes;
@@ -15,8 +17,36 @@ const TAG_LOOKUP_TABLE: TagLookupTable = TagLookupTable(tag::TAG_LOOKUP_TABLE);
 /// integer and we know there are at least 4 bytes to read from a buffer. In
 /// this case, we can read a 32 bit little endian integer and mask out only the
 /// bits we need. This in particular saves a branch.
+#[cfg(not(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "bmi2"
+)))]
 const WORD_MASK: [usize; 5] = [0, 0xFF, 0xFFFF, 0xFFFFFF, 0xFFFFFFFF];
 
+/// Returns the `n` least significant bytes of `word`, where `n` is at most
+/// `4`.
+///
+/// When BMI2 is enabled at compile time, this is a single `bzhi` instruction,
+/// which saves loading the mask from `WORD_MASK` in the decoder's hot loop.
+#[inline(always)]
+fn low_bytes(word: u32, n: usize) -> u32 {
+    #[cfg(all(target_arch = "x86", target_feature = "bmi2"))]
+    unsafe {
+        core::arch::x86::_bzhi_u32(word, (n * 8) as u32)
+    }
+    #[cfg(all(target_arch = "x86_64", target_feature = "bmi2"))]
+    unsafe {
+        core::arch::x86_64::_bzhi_u32(word, (n * 8) as u32)
+    }
+    #[cfg(not(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "bmi2"
+    )))]
+    {
+        (word as usize & WORD_MASK[n]) as u32
+    }
+}
+
 /// Returns the decompressed size (in bytes) of the compressed bytes given.
 ///
 /// `input` must be a sequence of bytes returned by a conforming Snappy
@@ -27,7 +57,8 @@ const WORD_MASK: [usize; 5] = [0, 0xFF, 0xFFFF, 0xFFFFFF, 0xFFFFFFFF];
 /// This function returns an error in the following circumstances:
 ///
 /// * An invalid Snappy header was seen.
-/// * The total space required for decompression exceeds `2^32 - 1`.
+/// * The total space required for decompression exceeds
+///   [`MAX_INPUT_SIZE`](constant.MAX_INPUT_SIZE.html).
 pub fn decompress_len(input: &[u8]) -> Result<usize> {
     if input.is_empty() {
         return Ok(0);
@@ -35,6 +66,114 @@ pub fn decompress_len(input: &[u8]) -> Result<usize> {
     Ok(Header::read(input)?.decompress_len)
 }
 
+/// Reads the decompressed size (in bytes) declared at the start of a
+/// compressed block from `rdr`, without reading any of the block after it.
+///
+/// This returns the decompressed size along with the number of bytes read,
+/// so that callers can allocate space for the block, or enforce their own
+/// limit on its size, before reading the rest of it into memory. The bytes
+/// read are part of the block, so they must be passed to the decoder along
+/// with the rest of it.
+///
+/// Bytes are read one at a time, so `rdr` should be buffered. If `rdr` is
+/// empty, then this returns `(0, 0)`, just as `decompress_len` returns `0`
+/// for empty input.
+///
+/// # Errors
+///
+/// Besides errors from `rdr`, this returns an error that wraps one of this
+/// crate's errors in the following circumstances:
+///
+/// * An invalid Snappy header was seen, including one cut short by the end
+///   of `rdr`.
+/// * The total space required for decompression exceeds
+///   [`MAX_INPUT_SIZE`](constant.MAX_INPUT_SIZE.html).
+pub fn read_decompress_len<R: io::Read>(
+    rdr: &mut R,
+) -> io::Result<(u64, usize)> {
+    // The longest varint that fits in a u64.
+    let mut buf = [0; 10];
+    let mut n = 0;
+    while n == 0 || buf[n - 1] >= 0b1000_0000 {
+        if n == buf.len() {
+            return Err(io::Error::from(Error::Header));
+        }
+        match rdr.read(&mut buf[n..n + 1]) {
+            Ok(0) if n == 0 => return Ok((0, 0)),
+            Ok(0) => return Err(io::Error::from(Error::Header)),
+            Ok(_) => n += 1,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    let header = Header::read(&buf[..n])?;
+    Ok((header.decompress_len as u64, header.len))
+}
+
+/// Calls `visitor` with each literal and copy in the compressed bytes in
+/// `input`, in order, without decompressing them.
+///
+/// This parses `input` exactly as
+/// [`Decoder::decompress`](struct.Decoder.html#method.decompress) does, so
+/// that tools that inspect or convert the Snappy format, or decoders writing
+/// to something other than a buffer, can reuse this crate's parser. Every
+/// element is checked before it's passed on: a literal must be within
+/// `input`, a copy must start within the data before it, and neither may go
+/// past the decompressed length given in the header.
+///
+/// On success, this returns that decompressed length, which is also the
+/// total length of every literal and copy passed to `visitor`.
+///
+/// # Errors
+///
+/// This returns an error under the same circumstances that `decompress`
+/// does, apart from those about the size of the output. When it does,
+/// `visitor` has already been passed the elements before the one that was
+/// invalid.
+pub fn visit<V: TagVisitor>(input: &[u8], visitor: V) -> Result<usize> {
+    if input.is_empty() {
+        return Err(Error::Empty);
+    }
+    let hdr = Header::read(input)?;
+    let mut visit = Visit {
+        src: &input[hdr.len..],
+        s: 0,
+        visitor,
+        d: 0,
+        len: hdr.decompress_len,
+    };
+    visit.visit()?;
+    Ok(hdr.decompress_len)
+}
+
+/// A visitor of the literals and copies that make up a block in the raw
+/// Snappy format, as passed to [`visit`](fn.visit.html).
+///
+/// Decompressing a block amounts to appending each literal to the output,
+/// and, for each copy, appending the `len` bytes that start `offset` bytes
+/// before the end of the output so far.
+pub trait TagVisitor {
+    /// Called with the bytes of a literal.
+    fn on_literal(&mut self, literal: &[u8]);
+
+    /// Called with a copy of `len` bytes from `offset` bytes back.
+    ///
+    /// When `len` is bigger than `offset`, the copy overlaps the bytes it
+    /// writes, and repeats the `offset` bytes it starts at until `len` bytes
+    /// are written. `offset` is never `0`, and `len` is between `1` and `64`.
+    fn on_copy(&mut self, offset: usize, len: usize);
+}
+
+impl<V: TagVisitor + ?Sized> TagVisitor for &mut V {
+    fn on_literal(&mut self, literal: &[u8]) {
+        (**self).on_literal(literal)
+    }
+
+    fn on_copy(&mut self, offset: usize, len: usize) {
+        (**self).on_copy(offset, len)
+    }
+}
+
 /// Decoder is a raw decoder for decompressing bytes in the Snappy format.
 ///
 /// This decoder does not use the Snappy frame format and simply decompresses
@@ -55,6 +194,15 @@ impl Decoder {
         Decoder { _dummy: () }
     }
 
+    /// Returns the number of bytes held by this decoder.
+    ///
+    /// A decoder decompresses straight into the output it's given and holds
+    /// no tables or buffers, so this is always `0`. It's provided so that
+    /// codec memory can be accounted for the same way as for an `Encoder`.
+    pub fn memory_usage(&self) -> usize {
+        0
+    }
+
     /// Decompresses all bytes in `input` into `output`.
     ///
     /// `input` must be a sequence of bytes returned by a conforming Snappy
@@ -66,12 +214,16 @@ impl Decoder {
     ///
     /// On success, this returns the number of bytes written to `output`.
     ///
+    /// This never panics, whatever `input` holds, so it's safe to use on
+    /// untrusted data. Corrupt data only ever results in an error.
+    ///
     /// # Errors
     ///
     /// This method returns an error in the following circumstances:
     ///
     /// * Invalid compressed Snappy data was seen.
-    /// * The total space required for decompression exceeds `2^32 - 1`.
+    /// * The total space required for decompression exceeds
+    ///   [`MAX_INPUT_SIZE`](constant.MAX_INPUT_SIZE.html).
     /// * `output` has length less than `decompress_len(input)`.
     pub fn decompress(
         &mut self,
@@ -89,12 +241,140 @@ impl Decoder {
             });
         }
         let dst = &mut output[..hdr.decompress_len];
-        let mut dec =
-            Decompress { src: &input[hdr.len..], s: 0, dst: dst, d: 0 };
+        // The header is never longer than the input, but the compiler can't
+        // tell, and this must not panic.
+        let src = input.get(hdr.len..).unwrap_or(&[]);
+        let mut dec = Decompress { src, s: 0, dst, d: 0 };
         dec.decompress()?;
         Ok(dec.dst.len())
     }
 
+    /// Decompresses a single compressed block at the beginning of `input`
+    /// into `output`, ignoring any bytes that follow it.
+    ///
+    /// Unlike `decompress`, `input` may contain arbitrary trailing bytes after
+    /// the compressed block, which is useful when a compressed block is
+    /// embedded in some other format. Decompression stops as soon as the
+    /// number of bytes given in the block's header have been written.
+    ///
+    /// On success, this returns a tuple of the number of bytes of `input`
+    /// that make up the compressed block, and the number of bytes written to
+    /// `output`.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error under the same circumstances that
+    /// `decompress` does. Additionally, an error is returned if `input` ends
+    /// before the compressed block is complete.
+    pub fn decompress_prefix(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(usize, usize)> {
+        if input.is_empty() {
+            return Err(Error::Empty);
+        }
+        let hdr = Header::read(input)?;
+        if hdr.decompress_len > output.len() {
+            return Err(Error::BufferTooSmall {
+                given: output.len() as u64,
+                min: hdr.decompress_len as u64,
+            });
+        }
+        let dst = &mut output[..hdr.decompress_len];
+        let mut dec = Decompress { src: &input[hdr.len..], s: 0, dst, d: 0 };
+        dec.decompress_prefix()?;
+        Ok((hdr.len + dec.s, dec.dst.len()))
+    }
+
+    /// Decompresses as many bytes in `input` into `output` as possible,
+    /// keeping whatever was decompressed before any corruption was found.
+    ///
+    /// This is like `decompress`, except that progress isn't discarded when
+    /// an error occurs. It always returns the number of bytes at the start
+    /// of `output` that were decompressed from valid elements of `input`,
+    /// along with the outcome of decompression. This is useful for recovery
+    /// tools that want to salvage the readable prefix of a damaged block.
+    ///
+    /// Note that Snappy blocks carry no checksum, so corruption that still
+    /// decodes to valid elements can't be detected. Bytes reported as
+    /// decompressed are only as trustworthy as `input` itself.
+    ///
+    /// # Errors
+    ///
+    /// The result is an error under the same circumstances that `decompress`
+    /// returns an error. If the header of `input` can't be read or `output`
+    /// is too small, then no bytes are decompressed.
+    pub fn decompress_partial(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> (usize, Result<()>) {
+        if input.is_empty() {
+            return (0, Err(Error::Empty));
+        }
+        let hdr = match Header::read(input) {
+            Ok(hdr) => hdr,
+            Err(err) => return (0, Err(err)),
+        };
+        if hdr.decompress_len > output.len() {
+            let err = Error::BufferTooSmall {
+                given: output.len() as u64,
+                min: hdr.decompress_len as u64,
+            };
+            return (0, Err(err));
+        }
+        let dst = &mut output[..hdr.decompress_len];
+        let mut dec = Decompress { src: &input[hdr.len..], s: 0, dst, d: 0 };
+        let result = dec.decompress();
+        (dec.d, result)
+    }
+
+    /// Decompresses all bytes in `input` into a sequence of output buffers.
+    ///
+    /// This is just like the `decompress` method, except the decompressed
+    /// bytes are written across the buffers in `output`, in order, as if they
+    /// were one contiguous buffer. This is useful when a single contiguous
+    /// buffer isn't available, e.g., when output is written to fixed size
+    /// pages from a buffer pool.
+    ///
+    /// Note that this is slower than `decompress`, since copies may need to
+    /// read from and write to more than one buffer.
+    ///
+    /// On success, this returns the number of bytes written to `output`.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error under the same circumstances that
+    /// `decompress` does, where the size of `output` is the total size of all
+    /// of its buffers.
+    pub fn decompress_slices(
+        &mut self,
+        input: &[u8],
+        output: &mut [IoSliceMut<'_>],
+    ) -> Result<usize> {
+        if input.is_empty() {
+            return Err(Error::Empty);
+        }
+        let hdr = Header::read(input)?;
+        let given: u64 = output.iter().map(|buf| buf.len() as u64).sum();
+        if hdr.decompress_len as u64 > given {
+            return Err(Error::BufferTooSmall {
+                given,
+                min: hdr.decompress_len as u64,
+            });
+        }
+        let mut dec = Visit {
+            src: &input[hdr.len..],
+            s: 0,
+            visitor: Slices::new(output),
+            d: 0,
+            len: hdr.decompress_len,
+        };
+        dec.visit()?;
+        Ok(hdr.decompress_len)
+    }
+
     /// Decompresses all bytes in `input` into a freshly allocated `Vec`.
     ///
     /// This is just like the `decompress` method, except it allocates a `Vec`
@@ -130,15 +410,36 @@ impl<'s, 'd> Decompress<'s, 'd> {
     /// big enough to store all decompressed bytes.
     fn decompress(&mut self) -> Result<()> {
         while self.s < self.src.len() {
-            let byte = self.src[self.s];
-            self.s += 1;
-            if byte & 0b000000_11 == 0 {
-                let len = (byte >> 2) as usize + 1;
-                self.read_literal(len)?;
-            } else {
-                self.read_copy(byte)?;
-            }
+            self.read_element()?;
         }
+        self.check_len()
+    }
+
+    /// Like `decompress`, but stops as soon as `dst` is full, leaving any
+    /// remaining bytes in `src` unread.
+    fn decompress_prefix(&mut self) -> Result<()> {
+        while self.d < self.dst.len() && self.s < self.src.len() {
+            self.read_element()?;
+        }
+        self.check_len()
+    }
+
+    /// Decompresses a single literal or copy from `src` starting at `s` to
+    /// `dst` starting at `d`. `s` must point to a tag byte.
+    #[inline(always)]
+    fn read_element(&mut self) -> Result<()> {
+        let byte = self.src[self.s];
+        self.s += 1;
+        if byte & 0b000000_11 == 0 {
+            let len = (byte >> 2) as usize + 1;
+            self.read_literal(len)
+        } else {
+            self.read_copy(byte)
+        }
+    }
+
+    /// Returns an error if `dst` hasn't been filled exactly.
+    fn check_len(&self) -> Result<()> {
         if self.d != self.dst.len() {
             return Err(Error::HeaderMismatch {
                 expected_len: self.dst.len() as u64,
@@ -179,7 +480,7 @@ impl<'s, 'd> Decompress<'s, 'd> {
                 let srcp = self.src.as_ptr().add(self.s);
                 let dstp = self.dst.as_mut_ptr().add(self.d);
                 // Hopefully uses SIMD registers for 128 bit load/store.
-                ptr::copy_nonoverlapping(srcp, dstp, 16);
+                copy16(srcp, dstp);
             }
             self.d += len as usize;
             self.s += len as usize;
@@ -189,19 +490,20 @@ impl<'s, 'd> Decompress<'s, 'd> {
         // an additional 1-4 bytes to get the real length of the literal.
         if len >= 61 {
             // If there aren't at least 4 bytes left to read then we know this
-            // is corrupt because the literal must have length >=61.
-            if self.s as u64 + 4 > self.src.len() as u64 {
-                return Err(Error::Literal {
-                    len: 4,
-                    src_len: (self.src.len() - self.s) as u64,
-                    dst_len: (self.dst.len() - self.d) as u64,
-                });
-            }
-            // Since we know there are 4 bytes left to read, read a 32 bit LE
-            // integer and mask away the bits we don't need.
+            // is corrupt because the literal must have length >=61. Otherwise,
+            // read a 32 bit LE integer and mask away the bits we don't need.
+            let word = match self.src.get(self.s..self.s + 4) {
+                Some(bytes) => bytes::read_u32_le(bytes),
+                None => {
+                    return Err(Error::Literal {
+                        len: 4,
+                        src_len: (self.src.len() - self.s) as u64,
+                        dst_len: (self.dst.len() - self.d) as u64,
+                    });
+                }
+            };
             let byte_count = len as usize - 60;
-            len = bytes::read_u32_le(&self.src[self.s..]) as u64;
-            len = (len & (WORD_MASK[byte_count] as u64)) + 1;
+            len = low_bytes(word, byte_count) as u64 + 1;
             self.s += byte_count;
         }
         // If there's not enough buffer left to load or store this literal,
@@ -244,6 +546,9 @@ impl<'s, 'd> Decompress<'s, 'd> {
         // `0`, then `offset.wrapping_sub(1)` will be usize::MAX which is also
         // the max value of `d`.
         if self.d <= offset.wrapping_sub(1) {
+            if offset == 0 {
+                return Err(Error::ZeroOffset { dst_pos: self.d as u64 });
+            }
             return Err(Error::Offset {
                 offset: offset as u64,
                 dst_pos: self.d as u64,
@@ -299,20 +604,31 @@ impl<'s, 'd> Decompress<'s, 'd> {
                 // guaranteed by the conditional above.
                 let mut dstp = self.dst.as_mut_ptr().add(self.d);
                 let mut srcp = dstp.sub(offset);
+                #[cfg(all(
+                    any(target_arch = "x86", target_arch = "x86_64"),
+                    target_feature = "ssse3"
+                ))]
+                {
+                    if offset < 16 {
+                        copy_pattern_ssse3(srcp, dstp, offset, len);
+                        self.d = end;
+                        return Ok(());
+                    }
+                }
                 loop {
                     debug_assert!(dstp >= srcp);
                     let diff = (dstp as usize) - (srcp as usize);
                     if diff >= 16 {
                         break;
                     }
-                    // srcp and dstp can overlap, so use ptr::copy.
+                    // srcp and dstp can overlap, which copy16 permits.
                     debug_assert!(self.d + 16 <= self.dst.len());
-                    ptr::copy(srcp, dstp, 16);
+                    copy16(srcp, dstp);
                     self.d += diff as usize;
                     dstp = dstp.add(diff);
                 }
                 while self.d < end {
-                    ptr::copy_nonoverlapping(srcp, dstp, 16);
+                    copy16(srcp, dstp);
                     srcp = srcp.add(16);
                     dstp = dstp.add(16);
                     self.d += 16;
@@ -321,17 +637,25 @@ impl<'s, 'd> Decompress<'s, 'd> {
                 // returning. It's correct value is `end`.
             }
         } else {
-            if end > self.dst.len() {
-                return Err(Error::CopyWrite {
-                    len: len as u64,
-                    dst_len: (self.dst.len() - self.d) as u64,
-                });
-            }
+            let window = match self.dst.get_mut(self.d - offset..end) {
+                Some(window) => window,
+                None => {
+                    return Err(Error::CopyWrite {
+                        len: len as u64,
+                        dst_len: (self.dst.len() - self.d) as u64,
+                    });
+                }
+            };
             // Finally, the slow byte-by-byte case, which should only be used
-            // for the last few bytes of decompression.
-            while self.d != end {
-                self.dst[self.d] = self.dst[self.d - offset];
-                self.d += 1;
+            // for the last few bytes of decompression. Every byte written
+            // repeats the one `offset` bytes before it, so the bytes written
+            // are the `offset` bytes before `d`, over and over.
+            // Zipping them up, rather than indexing, leaves no bounds checks
+            // for the compiler to fail to prove, and so no way to panic.
+            let mid = cmp::min(offset, window.len());
+            let (pattern, dst) = window.split_at_mut(mid);
+            for (b, &p) in dst.iter_mut().zip(pattern.iter().cycle()) {
+                *b = p;
             }
         }
         self.d = end;
@@ -339,6 +663,249 @@ impl<'s, 'd> Decompress<'s, 'd> {
     }
 }
 
+/// Visit is the state of the Snappy parser when passing each element to a
+/// visitor, such as when decompressing to a sequence of output buffers.
+///
+/// This is a straight-forward version of `Decompress` that doesn't use any
+/// unsafe tricks, since it has no output buffer of its own to use them on.
+struct Visit<'s, V> {
+    /// The original compressed bytes not including the header.
+    src: &'s [u8],
+    /// The current position in the compressed bytes.
+    s: usize,
+    /// The visitor that each literal and copy is passed to.
+    visitor: V,
+    /// The current position in the decompressed output.
+    d: usize,
+    /// The total number of decompressed bytes, as given by the header.
+    len: usize,
+}
+
+impl<'s, V: TagVisitor> Visit<'s, V> {
+    fn visit(&mut self) -> Result<()> {
+        while self.s < self.src.len() {
+            let byte = self.src[self.s];
+            self.s += 1;
+            if byte & 0b000000_11 == 0 {
+                self.read_literal((byte >> 2) as usize + 1)?;
+            } else {
+                self.read_copy(byte)?;
+            }
+        }
+        if self.d != self.len {
+            return Err(Error::HeaderMismatch {
+                expected_len: self.len as u64,
+                got_len: self.d as u64,
+            });
+        }
+        Ok(())
+    }
+
+    /// Like `Decompress::read_literal`.
+    fn read_literal(&mut self, len: usize) -> Result<()> {
+        let mut len = len as u64;
+        if len >= 61 {
+            if self.s as u64 + 4 > self.src.len() as u64 {
+                return Err(Error::Literal {
+                    len: 4,
+                    src_len: (self.src.len() - self.s) as u64,
+                    dst_len: (self.len - self.d) as u64,
+                });
+            }
+            let byte_count = len as usize - 60;
+            let word = bytes::read_u32_le(&self.src[self.s..]);
+            len = low_bytes(word, byte_count) as u64 + 1;
+            self.s += byte_count;
+        }
+        if ((self.src.len() - self.s) as u64) < len
+            || ((self.len - self.d) as u64) < len
+        {
+            return Err(Error::Literal {
+                len,
+                src_len: (self.src.len() - self.s) as u64,
+                dst_len: (self.len - self.d) as u64,
+            });
+        }
+        let len = len as usize;
+        self.visitor.on_literal(&self.src[self.s..self.s + len]);
+        self.s += len;
+        self.d += len;
+        Ok(())
+    }
+
+    /// Like `Decompress::read_copy`.
+    fn read_copy(&mut self, tag_byte: u8) -> Result<()> {
+        let entry = TAG_LOOKUP_TABLE.entry(tag_byte);
+        let offset = entry.offset(self.src, self.s)?;
+        let len = entry.len();
+        self.s += entry.num_tag_bytes();
+        if offset == 0 {
+            return Err(Error::ZeroOffset { dst_pos: self.d as u64 });
+        }
+        if offset > self.d {
+            return Err(Error::Offset {
+                offset: offset as u64,
+                dst_pos: self.d as u64,
+            });
+        }
+        if len > self.len - self.d {
+            return Err(Error::CopyWrite {
+                len: len as u64,
+                dst_len: (self.len - self.d) as u64,
+            });
+        }
+        self.visitor.on_copy(offset, len);
+        self.d += len;
+        Ok(())
+    }
+}
+
+/// A sequence of output buffers that are addressed as if they were one
+/// contiguous buffer.
+struct Slices<'d, 'b> {
+    bufs: &'d mut [IoSliceMut<'b>],
+    /// The position of the start of each buffer in `bufs`.
+    starts: Vec<usize>,
+    /// The position that the next literal or copy visited is written to.
+    pos: usize,
+}
+
+impl<'d, 'b> TagVisitor for Slices<'d, 'b> {
+    fn on_literal(&mut self, literal: &[u8]) {
+        self.write(self.pos, literal);
+        self.pos += literal.len();
+    }
+
+    fn on_copy(&mut self, offset: usize, len: usize) {
+        self.copy(self.pos - offset, self.pos, len);
+        self.pos += len;
+    }
+}
+
+impl<'d, 'b> Slices<'d, 'b> {
+    fn new(bufs: &'d mut [IoSliceMut<'b>]) -> Slices<'d, 'b> {
+        let mut starts = Vec::with_capacity(bufs.len());
+        let mut start = 0;
+        for buf in bufs.iter() {
+            starts.push(start);
+            start += buf.len();
+        }
+        Slices { bufs, starts, pos: 0 }
+    }
+
+    /// Returns the index of the buffer containing the byte at `pos`, along
+    /// with the position of that byte within it. `pos` must be in bounds.
+    fn locate(&self, pos: usize) -> (usize, usize) {
+        // Find the last buffer starting at or before pos. When some buffers
+        // are empty, several may start at the same position, but the last of
+        // those is never empty.
+        let i = match self.starts.binary_search_by(|&start| {
+            if start <= pos {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+        }) {
+            Ok(i) | Err(i) => i - 1,
+        };
+        (i, pos - self.starts[i])
+    }
+
+    /// Writes `bytes` starting at `pos`.
+    fn write(&mut self, mut pos: usize, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let (i, at) = self.locate(pos);
+            let n = cmp::min(bytes.len(), self.bufs[i].len() - at);
+            self.bufs[i][at..at + n].copy_from_slice(&bytes[..n]);
+            bytes = &bytes[n..];
+            pos += n;
+        }
+    }
+
+    /// Copies `len` bytes starting at `from` to `to`, where `from < to`. The
+    /// copy proceeds as if it were done one byte at a time, so when the
+    /// ranges overlap, bytes written by the copy are copied again.
+    fn copy(&mut self, mut from: usize, mut to: usize, mut len: usize) {
+        debug_assert!(from < to);
+        while len > 0 {
+            let (i, i_at) = self.locate(from);
+            let (j, j_at) = self.locate(to);
+            // Never copy more than `to - from` bytes at once, so that the
+            // source and destination of each copy don't overlap.
+            let n = cmp::min(
+                cmp::min(len, to - from),
+                cmp::min(self.bufs[i].len() - i_at, self.bufs[j].len() - j_at),
+            );
+            if i == j {
+                self.bufs[i].copy_within(i_at..i_at + n, j_at);
+            } else {
+                let (before, after) = self.bufs.split_at_mut(j);
+                after[0][j_at..j_at + n]
+                    .copy_from_slice(&before[i][i_at..i_at + n]);
+            }
+            from += n;
+            to += n;
+            len -= n;
+        }
+    }
+}
+
+/// Copies the 16 bytes at `src` to `dst`, which may overlap.
+///
+/// The caller must guarantee that `src` is valid for reading 16 bytes and
+/// that `dst` is valid for writing 16 bytes.
+#[inline(always)]
+unsafe fn copy16(src: *const u8, dst: *mut u8) {
+    #[cfg(feature = "nightly-simd")]
+    crate::simd::copy16(src, dst);
+    #[cfg(not(feature = "nightly-simd"))]
+    ptr::copy(src, dst, 16);
+}
+
+/// Writes `len` bytes to `dst` that repeat the `offset` bytes before it, i.e.,
+/// decompresses a copy whose offset is less than 16.
+///
+/// The copy loop in `read_copy` only advances by `offset` bytes at a time
+/// while the source and destination overlap, which makes run length encoded
+/// data slow to decompress. This instead repeats the pattern across a vector
+/// with `pshufb`, then writes 16 bytes at a time, shuffling the vector into
+/// the next piece of the pattern after each write.
+///
+/// The caller must guarantee that `src` is `dst - offset`, that `offset` is
+/// between `1` and `15`, that `src` is valid for reading 16 bytes and that
+/// `dst` is valid for writing `len` bytes rounded up to a multiple of 16.
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "ssse3"
+))]
+#[inline(always)]
+unsafe fn copy_pattern_ssse3(
+    src: *const u8,
+    dst: *mut u8,
+    offset: usize,
+    len: usize,
+) {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::*;
+
+    debug_assert!(0 < offset && offset < 16);
+    let shuffle = tag::PATTERN_SHUFFLE[offset].as_ptr() as *const __m128i;
+    let reshuffle = tag::PATTERN_RESHUFFLE[offset].as_ptr() as *const __m128i;
+    // Only the first `offset` bytes loaded are used, and they're the ones
+    // before `dst`, so it doesn't matter what the rest of them are.
+    let bytes = _mm_loadu_si128(src as *const __m128i);
+    let mut pattern = _mm_shuffle_epi8(bytes, _mm_loadu_si128(shuffle));
+    let reshuffle = _mm_loadu_si128(reshuffle);
+    let mut i = 0;
+    while i < len {
+        _mm_storeu_si128(dst.add(i) as *mut __m128i, pattern);
+        pattern = _mm_shuffle_epi8(pattern, reshuffle);
+        i += 16;
+    }
+}
+
 /// Header represents the single varint that starts every Snappy compressed
 /// block.
 #[derive(Debug)]
@@ -436,13 +1003,14 @@ impl TagEntry {
                 unsafe {
                     // SAFETY: The conditional above guarantees that
                     // src[s..s+4] is valid to read from.
-                    let p = src.as_ptr().add(s);
-                    // We use WORD_MASK here to mask out the bits we don't
+                    //
+                    // We use low_bytes here to mask out the bits we don't
                     // need. While we're guaranteed to read 4 valid bytes,
                     // not all of those bytes are necessarily part of the
                     // offset. This is the key optimization: we don't need to
                     // branch on num_tag_bytes.
-                    bytes::loadu_u32_le(p) as usize & WORD_MASK[num_tag_bytes]
+                    let word = bytes::read_u32_le_unchecked(src, s);
+                    low_bytes(word, num_tag_bytes) as usize
                 }
             } else if num_tag_bytes == 1 {
                 if s >= src.len() {
@@ -453,13 +1021,15 @@ impl TagEntry {
                 }
                 src[s] as usize
             } else if num_tag_bytes == 2 {
-                if s + 1 >= src.len() {
-                    return Err(Error::CopyRead {
-                        len: 2,
-                        src_len: (src.len() - s) as u64,
-                    });
+                match src.get(s..s + 2) {
+                    Some(bytes) => bytes::read_u16_le(bytes) as usize,
+                    None => {
+                        return Err(Error::CopyRead {
+                            len: 2,
+                            src_len: (src.len() - s) as u64,
+                        });
+                    }
                 }
-                bytes::read_u16_le(&src[s..]) as usize
             } else {
                 return Err(Error::CopyRead {
                     len: num_tag_bytes as u64,
@@ -469,3 +1039,75 @@ impl TagEntry {
         Ok((self.0 & 0b0000_0111_0000_0000) | trailer)
     }
 }
+
+/// Proof harnesses for [Kani](https://github.com/model-checking/kani), run
+/// with `cargo kani`.
+///
+/// Fuzzing only samples inputs, while these check every input up to a small
+/// size, including every starting state of the decompressor, that no read
+/// or write in the fast paths above, unsafe or not, goes out of bounds.
+/// Bigger inputs only take more iterations of the same code, but the sizes
+/// are kept large enough to reach the paths that need 16 or 24 bytes of
+/// room in `dst`.
+#[cfg(kani)]
+mod verify {
+    use super::{Decoder, Decompress, TAG_LOOKUP_TABLE};
+
+    /// Decompressing any input into a buffer of any length up to 32 bytes
+    /// never goes out of bounds, and never claims to write more than fits.
+    #[kani::proof]
+    #[kani::unwind(34)]
+    fn decompress_any_input() {
+        let input: [u8; 8] = kani::any();
+        let input_len: usize = kani::any();
+        kani::assume(input_len <= input.len());
+        let mut output = [0; 32];
+        let output_len: usize = kani::any();
+        kani::assume(output_len <= output.len());
+
+        let result = Decoder::new()
+            .decompress(&input[..input_len], &mut output[..output_len]);
+        if let Ok(n) = result {
+            assert!(n <= output_len);
+        }
+    }
+
+    /// Reading one literal or copy from any position in `src`, into any
+    /// position in `dst`, never goes out of bounds, and leaves both
+    /// positions within their buffers on success.
+    #[kani::proof]
+    #[kani::unwind(66)]
+    fn read_element_any_state() {
+        let src: [u8; 24] = kani::any();
+        let s: usize = kani::any();
+        kani::assume(s < src.len());
+        let mut dst: [u8; 64] = kani::any();
+        let dst_len: usize = kani::any();
+        kani::assume(dst_len <= dst.len());
+        let d: usize = kani::any();
+        kani::assume(d <= dst_len);
+
+        let mut dec = Decompress { src: &src, s, dst: &mut dst[..dst_len], d };
+        if dec.read_element().is_ok() {
+            assert!(dec.s <= dec.src.len());
+            assert!(dec.d <= dec.dst.len());
+        }
+    }
+
+    /// Reading the offset of a copy never reads past the end of `src`, even
+    /// when fewer than 4 bytes are left after the tag byte.
+    #[kani::proof]
+    fn tag_offset_any_position() {
+        let tag: u8 = kani::any();
+        kani::assume(tag & 0b11 != 0);
+        let src: [u8; 6] = kani::any();
+        let s: usize = kani::any();
+        kani::assume(s <= src.len());
+
+        let entry = TAG_LOOKUP_TABLE.entry(tag);
+        if let Ok(offset) = entry.offset(&src, s) {
+            assert!(s + entry.num_tag_bytes() <= src.len());
+            assert!(offset < 1 << 16 || entry.num_tag_bytes() == 4);
+        }
+    }
+}
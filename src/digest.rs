@@ -0,0 +1,178 @@
+use std::prelude::v1::*;
+use std::fmt;
+
+/// A hash function that computes a digest of the uncompressed data of a
+/// whole Snappy frame formatted stream.
+///
+/// A digest is given to
+/// [`write::FrameEncoder::digest`](../write/struct.FrameEncoder.html#method.digest)
+/// to append it to a stream in a trailing chunk, and to
+/// [`read::FrameDecoder::verify_digest`](../read/struct.FrameDecoder.html#method.verify_digest)
+/// to check it. [`Sha256`](struct.Sha256.html) is provided, and any other
+/// hash function can be used by implementing this trait for it.
+pub trait StreamDigest {
+    /// Returns the name of this digest, such as `"sha256"`, which is stored
+    /// in the trailer alongside the digest. A decoder only checks trailers
+    /// whose name matches its own digest. The name must be at most 255 bytes
+    /// long.
+    fn name(&self) -> &str;
+
+    /// Adds `data` to the data being digested.
+    fn update(&mut self, data: &[u8]);
+
+    /// Returns the digest of all the data given to `update`, and resets this
+    /// digest, so that it starts over as though it had just been created.
+    fn finish(&mut self) -> Vec<u8>;
+}
+
+/// The initial hash value of SHA-256.
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c,
+    0x1f83d9ab, 0x5be0cd19,
+];
+
+/// The round constants of SHA-256.
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1,
+    0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3,
+    0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+    0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147,
+    0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+    0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+    0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+    0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208,
+    0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// A [`StreamDigest`](trait.StreamDigest.html) that computes SHA-256, as
+/// defined by FIPS 180-4. Its name is `"sha256"`.
+///
+/// This is a straightforward portable implementation, which is several times
+/// slower than compression itself, so it's mostly useful for streams where
+/// integrity matters more than speed.
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    /// A partial block that hasn't been hashed yet.
+    buf: [u8; 64],
+    buf_len: usize,
+    /// The total number of bytes given to `update`.
+    len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Sha256 {
+        Sha256::new()
+    }
+}
+
+impl Sha256 {
+    /// Create a new SHA-256 digest of no data.
+    pub fn new() -> Sha256 {
+        Sha256 { state: H0, buf: [0; 64], buf_len: 0, len: 0 }
+    }
+
+    /// Hashes one 64 byte block into `state`.
+    fn block(state: &mut [u32; 8], block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7)
+                ^ w[i - 15].rotate_right(18)
+                ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17)
+                ^ w[i - 2].rotate_right(19)
+                ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let mut v = *state;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6)
+                ^ v[4].rotate_right(11)
+                ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2)
+                ^ v[0].rotate_right(13)
+                ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+            v = [
+                t1.wrapping_add(t2),
+                v[0],
+                v[1],
+                v[2],
+                v[3].wrapping_add(t1),
+                v[4],
+                v[5],
+                v[6],
+            ];
+        }
+        for (s, v) in state.iter_mut().zip(v.iter()) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+}
+
+impl StreamDigest for Sha256 {
+    fn name(&self) -> &str {
+        "sha256"
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        if self.buf_len > 0 {
+            let n = (64 - self.buf_len).min(data.len());
+            self.buf[self.buf_len..self.buf_len + n]
+                .copy_from_slice(&data[..n]);
+            self.buf_len += n;
+            data = &data[n..];
+            if self.buf_len < 64 {
+                return;
+            }
+            Sha256::block(&mut self.state, &self.buf);
+            self.buf_len = 0;
+        }
+        while data.len() >= 64 {
+            Sha256::block(&mut self.state, &data[..64]);
+            data = &data[64..];
+        }
+        self.buf[..data.len()].copy_from_slice(data);
+        self.buf_len = data.len();
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        let bits = self.len.wrapping_mul(8);
+        let mut pad = [0; 72];
+        pad[0] = 0x80;
+        // Pad to 8 bytes short of a whole block, then append the length.
+        let n = 1 + (119 - self.buf_len) % 64;
+        pad[n..n + 8].copy_from_slice(&bits.to_be_bytes());
+        self.update(&pad[..n + 8]);
+        debug_assert_eq!(0, self.buf_len);
+
+        let mut digest = Vec::with_capacity(32);
+        for word in &self.state {
+            digest.extend_from_slice(&word.to_be_bytes());
+        }
+        *self = Sha256::new();
+        digest
+    }
+}
+
+impl fmt::Debug for Sha256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sha256").field("len", &self.len).finish()
+    }
+}
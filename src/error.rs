@@ -13,11 +13,13 @@ pub type Result<T> = result::Result<T, Error>;
 /// error that occurred.
 ///
 /// The type parameter `W` is the unconsumed writer.
+#[cfg(all(feature = "frame", feature = "compress"))]
 pub struct IntoInnerError<W> {
     wtr: W,
     err: io::Error,
 }
 
+#[cfg(all(feature = "frame", feature = "compress"))]
 impl<W> IntoInnerError<W> {
     pub(crate) fn new(wtr: W, err: io::Error) -> IntoInnerError<W> {
         IntoInnerError { wtr, err }
@@ -39,14 +41,17 @@ impl<W> IntoInnerError<W> {
     }
 }
 
+#[cfg(all(feature = "frame", feature = "compress"))]
 impl<W: std::any::Any> std::error::Error for IntoInnerError<W> {}
 
+#[cfg(all(feature = "frame", feature = "compress"))]
 impl<W> fmt::Display for IntoInnerError<W> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.err.fmt(f)
     }
 }
 
+#[cfg(all(feature = "frame", feature = "compress"))]
 impl<W> fmt::Debug for IntoInnerError<W> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.err.fmt(f)
@@ -125,13 +130,19 @@ pub enum Error {
         /// The number of remaining bytes in the decompression buffer.
         dst_len: u64,
     },
-    /// This error occurs during decompression when an invalid copy offset
-    /// is found. An offset is invalid if it is zero or if it is out of bounds.
+    /// This error occurs during decompression when a copy offset is found
+    /// that points before the start of the decompression buffer.
     Offset {
         /// The offset that was read.
         offset: u64,
-        /// The current position in the decompression buffer. If the offset is
-        /// non-zero, then the offset must be greater than this position.
+        /// The current position in the decompression buffer. The offset must
+        /// not be greater than this position.
+        dst_pos: u64,
+    },
+    /// This error occurs during decompression when a copy offset of zero is
+    /// found.
+    ZeroOffset {
+        /// The current position in the decompression buffer.
         dst_pos: u64,
     },
     /// This error occurs when a stream header chunk type was expected but got
@@ -148,7 +159,8 @@ pub enum Error {
         /// The bytes that were read.
         bytes: Vec<u8>,
     },
-    /// This error occurs when an unsupported chunk type is seen.
+    /// This error occurs when an unsupported chunk type is seen. In strict
+    /// mode, padding and reserved skippable chunks are also unsupported.
     /// This error only occurs when reading a Snappy frame formatted stream.
     UnsupportedChunkType {
         /// The chunk type byte that was read.
@@ -164,6 +176,24 @@ pub enum Error {
         /// True when this error occured while reading the stream header.
         header: bool,
     },
+    /// This error occurs in strict mode when a stream ends before a stream
+    /// identifier has been read.
+    /// This error only occurs when reading a Snappy frame formatted stream.
+    EmptyStream,
+    /// This error occurs when a Snappy frame formatted stream held in memory
+    /// or checked by `frame::validate` ends in the middle of a chunk.
+    /// (Streaming readers report an unexpected EOF I/O error instead.)
+    /// This error only occurs when reading a Snappy frame formatted stream.
+    TruncatedChunk,
+    /// This error occurs when a chunk compressed by a frame encoder with
+    /// verification enabled does not decompress back to the bytes that were
+    /// compressed.
+    /// This error only occurs when writing a Snappy frame formatted stream.
+    Verify,
+    /// This error occurs when the bytes given to `write::Checkpoint` aren't
+    /// a checkpoint, or were corrupted.
+    /// This error only occurs when resuming a Snappy frame formatted stream.
+    Checkpoint,
     /// This error occurs when a checksum validity check fails.
     /// This error only occurs when reading a Snappy frame formatted stream.
     Checksum {
@@ -172,6 +202,35 @@ pub enum Error {
         /// The computed checksum.
         got: u32,
     },
+    /// This error occurs when a decoder verifying digests reads a digest
+    /// trailer that doesn't match the data before it.
+    /// This error only occurs when reading a Snappy frame formatted stream.
+    Digest {
+        /// The expected digest read from the stream.
+        expected: Vec<u8>,
+        /// The computed digest.
+        got: Vec<u8>,
+    },
+    /// This error occurs when a decoder verifying digests reaches the end of
+    /// a stream whose data isn't followed by a digest trailer.
+    /// This error only occurs when reading a Snappy frame formatted stream.
+    MissingDigest,
+    /// This error occurs when a stream names a filter that the decoder
+    /// wasn't given, so the data after it can't be unfiltered. It also
+    /// occurs when a `write::FrameEncoder` resumes a stream without the
+    /// filter recorded by its checkpoint, or with a different one.
+    /// This error only occurs with Snappy frame formatted streams.
+    UnknownFilter {
+        /// The name of the filter, as read from the stream.
+        name: Vec<u8>,
+    },
+    /// This error occurs when a string parsed as `frame::EncoderOptions`
+    /// has a setting that is unknown, malformed or out of range.
+    /// This error only occurs when parsing encoder options.
+    Options {
+        /// The setting that couldn't be parsed, as given.
+        setting: String,
+    },
 }
 
 impl From<Error> for io::Error {
@@ -194,7 +253,13 @@ impl PartialEq for Error {
                 &BufferTooSmall { given: given1, min: min1 },
                 &BufferTooSmall { given: given2, min: min2 },
             ) => (given1, min1) == (given2, min2),
-            (&Empty, &Empty) | (&Header, &Header) => true,
+            (&Empty, &Empty)
+            | (&Header, &Header)
+            | (&EmptyStream, &EmptyStream)
+            | (&TruncatedChunk, &TruncatedChunk)
+            | (&Verify, &Verify)
+            | (&Checkpoint, &Checkpoint)
+            | (&MissingDigest, &MissingDigest) => true,
             (
                 &HeaderMismatch { expected_len: elen1, got_len: glen1 },
                 &HeaderMismatch { expected_len: elen2, got_len: glen2 },
@@ -215,6 +280,10 @@ impl PartialEq for Error {
                 &Offset { offset: offset1, dst_pos: dst_pos1 },
                 &Offset { offset: offset2, dst_pos: dst_pos2 },
             ) => (offset1, dst_pos1) == (offset2, dst_pos2),
+            (
+                &ZeroOffset { dst_pos: dst_pos1 },
+                &ZeroOffset { dst_pos: dst_pos2 },
+            ) => dst_pos1 == dst_pos2,
             (&StreamHeader { byte: byte1 }, &StreamHeader { byte: byte2 }) => {
                 byte1 == byte2
             }
@@ -234,6 +303,14 @@ impl PartialEq for Error {
                 &Checksum { expected: e1, got: g1 },
                 &Checksum { expected: e2, got: g2 },
             ) => (e1, g1) == (e2, g2),
+            (
+                Digest { expected: e1, got: g1 },
+                Digest { expected: e2, got: g2 },
+            ) => (e1, g1) == (e2, g2),
+            (UnknownFilter { name: n1 }, UnknownFilter { name: n2 }) => {
+                n1 == n2
+            }
+            (Options { setting: s1 }, Options { setting: s2 }) => s1 == s2,
             _ => false,
         }
     }
@@ -290,6 +367,12 @@ impl fmt::Display for Error {
                          got offset {}; dst position: {})",
                 offset, dst_pos
             ),
+            Error::ZeroOffset { dst_pos } => write!(
+                f,
+                "snappy: corrupt input (expected valid offset but \
+                         got offset 0; dst position: {})",
+                dst_pos
+            ),
             Error::StreamHeader { byte } => write!(
                 f,
                 "snappy: corrupt input (expected stream header but \
@@ -319,12 +402,45 @@ impl fmt::Display for Error {
                          (invalid stream header length: {})",
                 len
             ),
+            Error::EmptyStream => {
+                write!(f, "snappy: corrupt input (missing stream header)")
+            }
+            Error::TruncatedChunk => write!(
+                f,
+                "snappy: corrupt input (stream ends in the middle of a chunk)"
+            ),
+            Error::Verify => write!(
+                f,
+                "snappy: compressed chunk does not decompress to its \
+                         input (verification failed)"
+            ),
+            Error::Checkpoint => {
+                write!(f, "snappy: invalid or corrupt encoder checkpoint")
+            }
             Error::Checksum { expected, got } => write!(
                 f,
                 "snappy: corrupt input (bad checksum; \
                          expected: {}, got: {})",
                 expected, got
             ),
+            Error::Digest { ref expected, ref got } => write!(
+                f,
+                "snappy: corrupt input (bad stream digest; \
+                         expected: {}, got: {})",
+                hex(expected),
+                hex(got)
+            ),
+            Error::MissingDigest => {
+                write!(f, "snappy: corrupt input (missing stream digest)")
+            }
+            Error::UnknownFilter { ref name } => write!(
+                f,
+                "snappy: unknown filter '{}' (the decoder must be given it)",
+                escape(name)
+            ),
+            Error::Options { ref setting } => {
+                write!(f, "snappy: invalid encoder option '{}'", setting)
+            }
         }
     }
 }
@@ -333,3 +449,7 @@ fn escape(bytes: &[u8]) -> String {
     use std::ascii::escape_default;
     bytes.iter().flat_map(|&b| escape_default(b)).map(|b| b as char).collect()
 }
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,122 @@
+use std::prelude::v1::*;
+
+use crate::bytes;
+
+/// The number of bytes in each sample probed by `estimate_compressibility`.
+const SAMPLE_SIZE: usize = 4 * (1 << 10);
+
+/// The maximum number of samples probed by `estimate_compressibility`.
+const MAX_SAMPLES: usize = 16;
+
+/// The number of bits in a hash used to index the probe's hash table.
+const TABLE_BITS: u32 = 12;
+
+/// The approximate number of bytes it costs to encode a copy.
+const COPY_COST: usize = 3;
+
+/// The entropy, in bits per byte, at or above which `looks_incompressible`
+/// considers a block to be incompressible. Random data comes very close to 8,
+/// while even fairly dense binary formats that compress well tend to stay
+/// around 7.
+#[cfg(feature = "frame")]
+const INCOMPRESSIBLE_ENTROPY: f64 = 7.8;
+
+/// Returns a cheap estimate of how compressible `input` is, as the fraction
+/// of its size that Snappy compression is expected to save.
+///
+/// The estimate is in the range `[0.0, 1.0]`, where `0.0` indicates that
+/// compressing `input` is unlikely to save anything (e.g., because it is
+/// random or already compressed) and values close to `1.0` indicate highly
+/// repetitive data.
+///
+/// This doesn't compress anything. Instead, it probes up to 64KB of `input`,
+/// taken from evenly spaced samples, for repeated byte sequences. The time it
+/// takes is therefore bounded regardless of the size of `input`, which makes
+/// it suitable for deciding whether compressing `input` is worth it at all.
+/// Since it is only a heuristic, the estimate may be quite far from the
+/// actual savings, particularly for data whose repetitions are farther apart
+/// than the size of a sample.
+pub fn estimate_compressibility(input: &[u8]) -> f32 {
+    let mut table = [0u16; 1 << TABLE_BITS];
+    let (mut probed, mut saved) = (0, 0);
+    let mut probe = |sample: &[u8]| {
+        probed += sample.len();
+        saved += probe_sample(sample, &mut table);
+    };
+    if input.len() <= SAMPLE_SIZE * MAX_SAMPLES {
+        input.chunks(SAMPLE_SIZE).for_each(&mut probe);
+    } else {
+        let stride = input.len() / MAX_SAMPLES;
+        for i in 0..MAX_SAMPLES {
+            let start = i * stride;
+            probe(&input[start..start + SAMPLE_SIZE]);
+        }
+    }
+    if probed == 0 {
+        return 0.0;
+    }
+    saved as f32 / probed as f32
+}
+
+/// Returns true if the distribution of bytes in `block` suggests that it is
+/// random or already compressed, such that compressing it is very unlikely to
+/// be worth it.
+///
+/// This only looks at the frequency of each byte, so it's much cheaper than
+/// compressing `block`. But it can't see repetition, so a block made up of
+/// repeated random data will be misjudged.
+#[cfg(feature = "frame")]
+pub(crate) fn looks_incompressible(block: &[u8]) -> bool {
+    if block.is_empty() {
+        return false;
+    }
+    let mut counts = [0u32; 256];
+    for &b in block {
+        counts[b as usize] += 1;
+    }
+    // The Shannon entropy of the bytes in block, rearranged as
+    // log2(n) - (1/n) * sum(c * log2(c)) to avoid a division per count.
+    let n = block.len() as f64;
+    let sum: f64 = counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| c as f64 * (c as f64).log2())
+        .sum();
+    n.log2() - sum / n >= INCOMPRESSIBLE_ENTROPY
+}
+
+/// Scans `sample` for repeated 4 byte sequences, much like the compressor
+/// does, and returns the approximate number of bytes that encoding them as
+/// copies would save. `sample` must be no bigger than `SAMPLE_SIZE`.
+fn probe_sample(sample: &[u8], table: &mut [u16]) -> usize {
+    debug_assert!(sample.len() <= SAMPLE_SIZE);
+    // Positions are stored plus one, so that zero means there is no
+    // candidate.
+    for x in &mut *table {
+        *x = 0;
+    }
+    let mut saved = 0;
+    let mut i = 0;
+    while i + 4 <= sample.len() {
+        let cur = bytes::read_u32_le(&sample[i..]);
+        let hash =
+            (cur.wrapping_mul(0x1E35A7BD) >> (32 - TABLE_BITS)) as usize;
+        let candidate = table[hash] as usize;
+        table[hash] = (i + 1) as u16;
+        if candidate == 0
+            || bytes::read_u32_le(&sample[candidate - 1..]) != cur
+        {
+            i += 1;
+            continue;
+        }
+        let start = candidate - 1;
+        let mut len = 4;
+        while i + len < sample.len() && sample[start + len] == sample[i + len]
+        {
+            len += 1;
+        }
+        saved += len.saturating_sub(COPY_COST);
+        i += len;
+    }
+    saved
+}
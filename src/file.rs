@@ -0,0 +1,379 @@
+use std::prelude::v1::*;
+use std::cmp;
+use std::fmt;
+use std::io::{self, SeekFrom};
+
+use crate::bytes;
+use crate::crc32::CheckSummer;
+use crate::decompress::{decompress_len, Decoder};
+use crate::error::Error;
+use crate::frame::{
+    ChunkType, FILTER_CHUNK_TYPE, MAX_COMPRESS_BLOCK_SIZE, STREAM_BODY,
+};
+use crate::MAX_BLOCK_SIZE;
+
+/// The number of decompressed chunks cached by default.
+const DEFAULT_CACHE_CHUNKS: usize = 8;
+
+/// A random access reader over a Snappy frame formatted file.
+///
+/// When it is created, a `SnappyFile` scans the chunk headers of the file to
+/// build an index from uncompressed offsets to chunks. This only reads the
+/// header of each chunk (and the few bytes of a compressed chunk that record
+/// its decompressed length), so it is much faster than decompressing the
+/// file. After that, [`read_at`](#method.read_at) decompresses only the
+/// chunks that a read touches, and keeps the most recently used ones in a
+/// cache so that nearby reads don't decompress them again.
+///
+/// A `SnappyFile` also implements `std::io::Read` and `std::io::Seek` over
+/// the uncompressed data, so it can be used like a plain file.
+///
+/// Every chunk is checksummed when it is decompressed, but chunks that are
+/// never read are never checked.
+pub struct SnappyFile<R: io::Read + io::Seek> {
+    /// The underlying reader.
+    r: R,
+    /// The data chunks of the file, in order.
+    index: Vec<IndexEntry>,
+    /// The total number of uncompressed bytes in the file.
+    len: u64,
+    /// The current position for `io::Read` and `io::Seek`.
+    pos: u64,
+    /// A Snappy decoder that we reuse to decompress chunks.
+    dec: Decoder,
+    /// A CRC32 checksummer for verifying chunks.
+    checksummer: CheckSummer,
+    /// The compressed bytes of the chunk being read.
+    src: Vec<u8>,
+    /// Decompressed chunks and their positions in `index`, with the most
+    /// recently used last.
+    cache: Vec<(usize, Vec<u8>)>,
+    /// The maximum number of chunks in `cache`.
+    cache_chunks: usize,
+}
+
+/// The location of a data chunk in a file.
+#[derive(Clone, Copy, Debug)]
+struct IndexEntry {
+    /// The offset of the chunk's data, just after its header, in the file.
+    offset: u64,
+    /// The length of the chunk's data, including its checksum.
+    len: usize,
+    /// Whether the chunk's data is compressed.
+    compressed: bool,
+    /// The uncompressed offset of the first byte in the chunk.
+    start: u64,
+}
+
+impl<R: io::Read + io::Seek> SnappyFile<R> {
+    /// Create a new random access reader over the Snappy frame formatted
+    /// data in `rdr`, starting at its beginning.
+    ///
+    /// This returns an error if the chunk headers can't be read or aren't
+    /// valid, or if the data ends in the middle of a chunk. Files written
+    /// with a [filter](../frame/trait.ChunkFilter.html) aren't supported,
+    /// and are rejected with `Error::UnknownFilter`.
+    pub fn new(mut rdr: R) -> io::Result<SnappyFile<R>> {
+        let (index, len) = build_index(&mut rdr)?;
+        Ok(SnappyFile {
+            r: rdr,
+            index,
+            len,
+            pos: 0,
+            dec: Decoder::new(),
+            checksummer: CheckSummer::new(),
+            src: vec![],
+            cache: vec![],
+            cache_chunks: DEFAULT_CACHE_CHUNKS,
+        })
+    }
+
+    /// Set the maximum number of decompressed chunks to cache.
+    ///
+    /// Each chunk takes up to 64KB. At least one chunk is always cached, so
+    /// `0` is treated as `1`.
+    ///
+    /// This is set to `8` by default.
+    pub fn cache_chunks(&mut self, n: usize) -> &mut SnappyFile<R> {
+        self.cache_chunks = cmp::max(1, n);
+        let excess = self.cache.len().saturating_sub(self.cache_chunks);
+        self.cache.drain(..excess);
+        self
+    }
+
+    /// Returns the total number of uncompressed bytes in the file.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the file contains no uncompressed bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of data chunks in the file.
+    pub fn chunk_count(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Reads uncompressed bytes starting at `offset` into `buf`, returning
+    /// the number of bytes read.
+    ///
+    /// This reads across chunk boundaries, so fewer than `buf.len()` bytes
+    /// are read only when the end of the file is reached. If `offset` is at
+    /// or past the end of the file, then this returns `0`.
+    ///
+    /// This doesn't change the position used by `io::Read` and `io::Seek`.
+    pub fn read_at(
+        &mut self,
+        mut offset: u64,
+        mut buf: &mut [u8],
+    ) -> io::Result<usize> {
+        let mut total = 0;
+        while !buf.is_empty() && offset < self.len {
+            // The chunk containing offset is the last one starting at or
+            // before it.
+            let i = match self.index.binary_search_by_key(&offset, |e| e.start)
+            {
+                Ok(i) => i,
+                Err(i) => i - 1,
+            };
+            let start = self.index[i].start;
+            let chunk = self.chunk(i)?;
+            let from = (offset - start) as usize;
+            let n = cmp::min(chunk.len() - from, buf.len());
+            buf[..n].copy_from_slice(&chunk[from..from + n]);
+            buf = &mut buf[n..];
+            offset += n as u64;
+            total += n;
+        }
+        Ok(total)
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.r
+    }
+
+    /// Gets a mutable reference to the underlying reader.
+    ///
+    /// The position of the underlying reader may be changed freely, since
+    /// every read seeks to the chunk it needs first. Changing the data it
+    /// reads is not supported.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.r
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
+    /// Returns the decompressed data of the `i`th chunk, reading it into the
+    /// cache if necessary.
+    fn chunk(&mut self, i: usize) -> io::Result<&[u8]> {
+        if let Some(pos) = self.cache.iter().position(|&(j, _)| j == i) {
+            let entry = self.cache.remove(pos);
+            self.cache.push(entry);
+        } else {
+            let mut dst = if self.cache.len() >= self.cache_chunks {
+                self.cache.remove(0).1
+            } else {
+                vec![]
+            };
+            self.read_chunk(i, &mut dst)?;
+            self.cache.push((i, dst));
+        }
+        Ok(&self.cache.last().unwrap().1)
+    }
+
+    /// Reads, decompresses and verifies the `i`th chunk into `dst`.
+    fn read_chunk(&mut self, i: usize, dst: &mut Vec<u8>) -> io::Result<()> {
+        let entry = self.index[i];
+        self.src.resize(entry.len, 0);
+        self.r.seek(SeekFrom::Start(entry.offset))?;
+        self.r.read_exact(&mut self.src)?;
+        let expected_sum = bytes::read_u32_le(&self.src);
+        let data = &self.src[4..];
+        if entry.compressed {
+            dst.resize(decompress_len(data)?, 0);
+            self.dec.decompress(data, dst)?;
+        } else {
+            dst.clear();
+            dst.extend_from_slice(data);
+        }
+        let got_sum = self.checksummer.crc32c_masked(dst);
+        if expected_sum != got_sum {
+            return Err(io::Error::from(Error::Checksum {
+                expected: expected_sum,
+                got: got_sum,
+            }));
+        }
+        Ok(())
+    }
+}
+
+impl<R: io::Read + io::Seek> io::Read for SnappyFile<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.read_at(self.pos, buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: io::Read + io::Seek> io::Seek for SnappyFile<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::End(n) => (self.len, n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+        let new = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.wrapping_neg() as u64)
+        };
+        match new {
+            Some(n) => {
+                self.pos = n;
+                Ok(n)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+impl<R: fmt::Debug + io::Read + io::Seek> fmt::Debug for SnappyFile<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SnappyFile")
+            .field("r", &self.r)
+            .field("index", &"[...]")
+            .field("len", &self.len)
+            .field("pos", &self.pos)
+            .field("dec", &self.dec)
+            .field("checksummer", &self.checksummer)
+            .field("src", &"[...]")
+            .field("cache", &"[...]")
+            .field("cache_chunks", &self.cache_chunks)
+            .finish()
+    }
+}
+
+/// Scans the chunk headers of the Snappy frame formatted data in `rdr`, and
+/// returns its data chunks along with its total uncompressed length.
+fn build_index<R: io::Read + io::Seek>(
+    rdr: &mut R,
+) -> io::Result<(Vec<IndexEntry>, u64)> {
+    macro_rules! fail {
+        ($err:expr) => {
+            return Err(io::Error::from($err));
+        };
+    }
+    let size = rdr.seek(SeekFrom::End(0))?;
+    let mut offset = rdr.seek(SeekFrom::Start(0))?;
+    let mut index = vec![];
+    let mut start = 0;
+    let mut buf = [0; 9];
+    while offset < size {
+        rdr.read_exact(&mut buf[..4])?;
+        let ty = ChunkType::from_u8(buf[0]);
+        if offset == 0 && ty != Ok(ChunkType::Stream) {
+            fail!(Error::StreamHeader { byte: buf[0] });
+        }
+        let len = bytes::read_u24_le(&buf[1..]) as usize;
+        if len > MAX_COMPRESS_BLOCK_SIZE {
+            fail!(Error::UnsupportedChunkLength {
+                len: len as u64,
+                header: false,
+            });
+        }
+        let data_offset = offset + 4;
+        if data_offset + len as u64 > size {
+            fail!(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "snappy: file ends in the middle of a chunk",
+            ));
+        }
+        let n = match ty {
+            Ok(ChunkType::Stream) => {
+                let mut body = [0; 6];
+                if len != STREAM_BODY.len() {
+                    fail!(Error::UnsupportedChunkLength {
+                        len: len as u64,
+                        header: true,
+                    });
+                }
+                rdr.read_exact(&mut body)?;
+                if &body[..] != STREAM_BODY {
+                    fail!(Error::StreamHeaderMismatch {
+                        bytes: body.to_vec()
+                    });
+                }
+                None
+            }
+            Ok(ChunkType::Uncompressed) if len >= 4 => {
+                let n = len - 4;
+                if n > MAX_BLOCK_SIZE {
+                    fail!(Error::UnsupportedChunkLength {
+                        len: n as u64,
+                        header: false,
+                    });
+                }
+                Some((n, false))
+            }
+            Ok(ChunkType::Compressed) if len >= 4 => {
+                // A checksum, followed by the varint encoded decompressed
+                // length, which is at most 5 bytes long.
+                let m = cmp::min(len, buf.len());
+                rdr.read_exact(&mut buf[..m])?;
+                let n = decompress_len(&buf[4..m])?;
+                if n > MAX_BLOCK_SIZE {
+                    fail!(Error::UnsupportedChunkLength {
+                        len: n as u64,
+                        header: false,
+                    });
+                }
+                Some((n, true))
+            }
+            Ok(ChunkType::Uncompressed) | Ok(ChunkType::Compressed) => {
+                fail!(Error::UnsupportedChunkLength {
+                    len: len as u64,
+                    header: false,
+                });
+            }
+            // Chunks are decompressed independently of the filter chunk
+            // that precedes them, so filtered data can't be undone.
+            Err(FILTER_CHUNK_TYPE) => {
+                let mut name = vec![0; len];
+                rdr.read_exact(&mut name)?;
+                fail!(Error::UnknownFilter { name });
+            }
+            Ok(ChunkType::Padding) => None,
+            Err(b) if (0x80..=0xFD).contains(&b) => None,
+            Err(b) => {
+                fail!(Error::UnsupportedChunkType { byte: b });
+            }
+        };
+        if let Some((n, compressed)) = n {
+            // Empty chunks contain nothing to read, and would make the
+            // chunk containing an offset ambiguous.
+            if n > 0 {
+                index.push(IndexEntry {
+                    offset: data_offset,
+                    len,
+                    compressed,
+                    start,
+                });
+                start += n as u64;
+            }
+        }
+        offset = rdr.seek(SeekFrom::Start(data_offset + len as u64))?;
+    }
+    Ok((index, start))
+}
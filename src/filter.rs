@@ -0,0 +1,207 @@
+use std::prelude::v1::*;
+
+/// A reversible transform of the data in each chunk of a Snappy frame
+/// formatted stream, applied before compression and undone after
+/// decompression.
+///
+/// A filter rearranges data so that it compresses better, such as by storing
+/// the differences between numbers rather than the numbers themselves. It's
+/// given to
+/// [`write::FrameEncoder::filter`](../write/struct.FrameEncoder.html#method.filter),
+/// which records its name in a chunk of type
+/// [`FILTER_CHUNK_TYPE`](constant.FILTER_CHUNK_TYPE.html), and to
+/// [`read::FrameDecoder::filter`](../read/struct.FrameDecoder.html#method.filter)
+/// to undo it.
+///
+/// Each chunk is filtered on its own, so that chunks can still be decoded
+/// independently of each other. A filter doesn't change the length of the
+/// data, and the checksum of a chunk is computed over the filtered data.
+///
+/// [`Delta`](struct.Delta.html) and [`Transpose`](struct.Transpose.html)
+/// are provided for arrays of numbers, and any other transform can be used
+/// by implementing this trait for it.
+pub trait ChunkFilter {
+    /// Returns the name of this filter, such as `"delta32"`, which identifies
+    /// it in the stream. The name must be at most 255 bytes long.
+    fn name(&self) -> &str;
+
+    /// Transforms the data of one chunk in place, before it's compressed.
+    fn encode(&mut self, data: &mut [u8]);
+
+    /// Undoes `encode` in place, after a chunk is decompressed.
+    fn decode(&mut self, data: &mut [u8]);
+}
+
+/// A [`ChunkFilter`](trait.ChunkFilter.html) that replaces each integer in
+/// an array of little endian integers with its difference from the one
+/// before it.
+///
+/// Integers that grow steadily, such as timestamps, counters or sensor
+/// readings, become runs of small and often equal differences, which
+/// compress much better than the integers themselves. Its name is `"delta"`
+/// followed by the width of the integers in bits, such as `"delta32"`.
+///
+/// The first integer of each chunk is kept as is, and any bytes at the end
+/// of a chunk that don't make up a whole integer are left alone. Chunks are
+/// as long as the encoder's block size, so for the integers to line up in
+/// every chunk, the block size should be a multiple of their width, as the
+/// default is.
+#[derive(Clone, Debug)]
+pub struct Delta {
+    width: usize,
+}
+
+impl Delta {
+    /// Create a delta filter for integers that are `width` bytes wide.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `width` is not 1, 2, 4 or 8.
+    pub fn new(width: usize) -> Delta {
+        match width {
+            1 | 2 | 4 | 8 => Delta { width },
+            _ => panic!("delta width must be 1, 2, 4 or 8, got {}", width),
+        }
+    }
+
+    /// Returns the mask of the bits of an integer of this filter's width.
+    fn mask(&self) -> u64 {
+        !0 >> (64 - 8 * self.width)
+    }
+}
+
+impl ChunkFilter for Delta {
+    fn name(&self) -> &str {
+        match self.width {
+            1 => "delta8",
+            2 => "delta16",
+            4 => "delta32",
+            _ => "delta64",
+        }
+    }
+
+    fn encode(&mut self, data: &mut [u8]) {
+        let mask = self.mask();
+        let mut prev = 0;
+        for int in data.chunks_exact_mut(self.width) {
+            let n = read_uint_le(int);
+            write_uint_le(n.wrapping_sub(prev) & mask, int);
+            prev = n;
+        }
+    }
+
+    fn decode(&mut self, data: &mut [u8]) {
+        let mask = self.mask();
+        let mut prev: u64 = 0;
+        for int in data.chunks_exact_mut(self.width) {
+            prev = prev.wrapping_add(read_uint_le(int)) & mask;
+            write_uint_le(prev, int);
+        }
+    }
+}
+
+/// A [`ChunkFilter`](trait.ChunkFilter.html) that transposes an array of
+/// fixed size records, so that the first byte of every record comes first,
+/// then the second byte of every record, and so on.
+///
+/// In arrays of floating point numbers, or of integers whose high bytes are
+/// mostly zero, the same byte of neighbouring records is often similar, so
+/// grouping those bytes together makes runs that compress much better. Its
+/// name is `"transpose"` followed by the stride, such as `"transpose8"`.
+///
+/// Any bytes at the end of a chunk that don't make up a whole record are
+/// left alone. As with [`Delta`](struct.Delta.html), the encoder's block
+/// size should be a multiple of the stride for records to line up in every
+/// chunk.
+#[derive(Clone, Debug)]
+pub struct Transpose {
+    stride: usize,
+    name: String,
+    /// Space for the transposed bytes, before they're copied back.
+    buf: Vec<u8>,
+}
+
+impl Transpose {
+    /// Create a transpose filter for records that are `stride` bytes long.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `stride` is `0`.
+    pub fn new(stride: usize) -> Transpose {
+        assert!(stride > 0, "transpose stride must not be 0");
+        let name = format!("transpose{}", stride);
+        Transpose { stride, name, buf: vec![] }
+    }
+}
+
+impl ChunkFilter for Transpose {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn encode(&mut self, data: &mut [u8]) {
+        let n = data.len() / self.stride;
+        let len = n * self.stride;
+        self.buf.clear();
+        self.buf.resize(len, 0);
+        for (i, record) in data[..len].chunks_exact(self.stride).enumerate() {
+            for (j, &b) in record.iter().enumerate() {
+                self.buf[j * n + i] = b;
+            }
+        }
+        data[..len].copy_from_slice(&self.buf);
+    }
+
+    fn decode(&mut self, data: &mut [u8]) {
+        let n = data.len() / self.stride;
+        let len = n * self.stride;
+        self.buf.clear();
+        self.buf.resize(len, 0);
+        for (i, record) in self.buf.chunks_exact_mut(self.stride).enumerate() {
+            for (j, b) in record.iter_mut().enumerate() {
+                *b = data[j * n + i];
+            }
+        }
+        data[..len].copy_from_slice(&self.buf);
+    }
+}
+
+/// Returns the filter provided by this crate whose name is `name`, if there
+/// is one.
+#[cfg(feature = "compress")]
+pub(crate) fn by_name(name: &str) -> Option<Box<dyn ChunkFilter + Send>> {
+    let filter: Box<dyn ChunkFilter + Send> = match name {
+        "delta8" => Box::new(Delta::new(1)),
+        "delta16" => Box::new(Delta::new(2)),
+        "delta32" => Box::new(Delta::new(4)),
+        "delta64" => Box::new(Delta::new(8)),
+        _ if name.starts_with("transpose") => {
+            let stride = name["transpose".len()..].parse().ok()?;
+            // Only the name the filter gives itself is accepted, so that
+            // names such as "transpose08" aren't.
+            if stride == 0 {
+                return None;
+            }
+            let filter = Transpose::new(stride);
+            if filter.name() != name {
+                return None;
+            }
+            Box::new(filter)
+        }
+        _ => return None,
+    };
+    Some(filter)
+}
+
+/// Reads a little endian integer as wide as `bytes`.
+fn read_uint_le(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |n, &b| n << 8 | b as u64)
+}
+
+/// Writes the low bytes of `n` to `bytes`, in little endian order.
+fn write_uint_le(mut n: u64, bytes: &mut [u8]) {
+    for b in bytes {
+        *b = n as u8;
+        n >>= 8;
+    }
+}
@@ -1,15 +1,58 @@
+/*!
+This module provides helpers for working with the
+[Snappy frame format](https://github.com/google/snappy/blob/master/framing_format.txt).
+
+The streaming readers and writers in the [`read`](../read/index.html) and
+[`write`](../write/index.html) modules are the main way to produce and consume
+framed data. This module contains lower level routines that are useful when
+working with framed data more directly, and the [`Codec`](trait.Codec.html)
+trait for using those readers and writers with another block compressor.
+*/
+
 use std::prelude::v1::*;
+#[cfg(feature = "compress")]
+use std::cmp;
+#[cfg(all(feature = "compress", feature = "decompress"))]
+use std::io::{self, Write};
+#[cfg(feature = "decompress")]
+use std::result;
+
+#[cfg(any(feature = "compress", feature = "decompress"))]
 use crate::bytes;
-use crate::compress::{max_compress_len, Encoder};
+#[cfg(feature = "compress")]
+use crate::compress::max_compress_len as raw_max_compress_len;
+use crate::crc32;
+#[cfg(any(feature = "compress", feature = "decompress"))]
 use crate::crc32::CheckSummer;
+#[cfg(feature = "decompress")]
+use crate::decompress::{decompress_len, Decoder};
+#[cfg(any(feature = "compress", feature = "decompress"))]
 use crate::error::Error;
-use crate::MAX_BLOCK_SIZE;
+#[cfg(feature = "compress")]
+use crate::estimate::looks_incompressible;
 
-/// The maximum chunk of compressed bytes that can be processed at one time.
+pub use crate::codec::{Codec, SnappyCodec};
+#[cfg(any(feature = "compress", feature = "decompress"))]
+pub use crate::compressed::FrameCompressed;
+pub use crate::digest::{Sha256, StreamDigest};
+pub use crate::filter::{ChunkFilter, Delta, Transpose};
+#[cfg(feature = "compress")]
+pub use crate::options::{autotune, EncoderOptions, Trial};
+#[cfg(feature = "decompress")]
+pub use crate::recover::{recover, RecoverPolicy, RecoverReport};
+#[cfg(feature = "decompress")]
+pub use crate::validate::{validate, StreamReport};
+
+/// The maximum number of uncompressed bytes in a single chunk.
 ///
-/// This is computed via `max_compress_len(MAX_BLOCK_SIZE)`.
+/// The Snappy frame format requires that decoders reject chunks that would
+/// decompress to more than this.
+pub const MAX_BLOCK_SIZE: usize = crate::MAX_BLOCK_SIZE;
+
+/// The maximum chunk of compressed bytes that can be processed at one time.
 ///
-/// TODO(ag): Replace with const fn once they support nominal branching.
+/// This is `raw::max_compress_len(MAX_BLOCK_SIZE)`, written out because this
+/// constant is needed even when the `compress` feature is disabled.
 pub const MAX_COMPRESS_BLOCK_SIZE: usize = 76490;
 
 /// The special magic string that starts any stream.
@@ -21,17 +64,43 @@ pub const STREAM_IDENTIFIER: &'static [u8] = b"\xFF\x06\x00\x00sNaPpY";
 /// The body of the special stream identifier.
 pub const STREAM_BODY: &'static [u8] = b"sNaPpY";
 
+/// The type of the chunk that holds a whole-stream digest, written by
+/// [`write::FrameEncoder::digest`](../write/struct.FrameEncoder.html#method.digest).
+///
+/// This is one of the chunk types that the Snappy frame format reserves as
+/// skippable, so decoders that don't know about digests ignore the chunk.
+/// Its body is the length of the digest's name as one byte, the name, and
+/// then the digest itself.
+pub const DIGEST_CHUNK_TYPE: u8 = 0xFD;
+
+/// The type of the chunk that names the filter applied to the data chunks
+/// after it, written by
+/// [`write::FrameEncoder::filter`](../write/struct.FrameEncoder.html#method.filter).
+///
+/// Its body is the name of the filter. Since a decoder that doesn't undo the
+/// filter would return filtered data, `read::FrameDecoder` returns an error
+/// when it reads this chunk for a filter it doesn't know, rather than
+/// skipping it.
+pub const FILTER_CHUNK_TYPE: u8 = 0xFC;
+
 /// The length of a snappy chunk type (1 byte), packet length (3 bytes)
 /// and CRC field (4 bytes). This is technically the chunk header _plus_
 /// the CRC present in most chunks.
 pub const CHUNK_HEADER_AND_CRC_SIZE: usize = 8;
 
 /// An enumeration describing each of the 4 main chunk types.
+///
+/// The byte that identifies a chunk type in a stream is its discriminant,
+/// e.g., `ChunkType::Compressed as u8`.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ChunkType {
+    /// The stream identifier, whose body is `STREAM_BODY`.
     Stream = 0xFF,
+    /// A chunk containing Snappy compressed data and its checksum.
     Compressed = 0x00,
+    /// A chunk containing uncompressed data and its checksum.
     Uncompressed = 0x01,
+    /// A chunk whose contents are ignored.
     Padding = 0xFE,
 }
 
@@ -50,6 +119,460 @@ impl ChunkType {
     }
 }
 
+/// Returns the masked form of the CRC32C checksum `sum`, as stored in the
+/// header of compressed and uncompressed chunks.
+///
+/// The frame format masks checksums (by rotating them and adding a constant)
+/// because computing the CRC of data that itself contains CRCs is a known
+/// weakness of CRCs. Use [`unmask_checksum`](fn.unmask_checksum.html) to go
+/// back to a plain CRC32C, e.g., to compare it with one computed elsewhere.
+pub fn mask_checksum(sum: u32) -> u32 {
+    crc32::mask(sum)
+}
+
+/// Returns the CRC32C checksum that was masked into `masked`, the inverse of
+/// [`mask_checksum`](fn.mask_checksum.html).
+pub fn unmask_checksum(masked: u32) -> u32 {
+    crc32::unmask(masked)
+}
+
+/// Returns the maximum size of a Snappy frame formatted stream given the
+/// total number of uncompressed bytes in the stream.
+///
+/// This accounts for the stream identifier, the header (including the
+/// checksum) of every chunk and the maximum compressed size of the data in
+/// every chunk, where the data is split into chunks of 64KB. This is how the
+/// frame encoders in this crate split their input with the default options.
+/// The bound doesn't hold for streams written any other way:
+///
+/// * An encoder with a smaller block size writes more chunks, and so more
+///   chunk headers. Use
+///   [`EncoderOptions::max_compress_len`](struct.EncoderOptions.html#method.max_compress_len)
+///   to compute the bound for the options given to the encoder.
+/// * Every flush may emit a smaller chunk with its own header, so callers
+///   that flush frequently need to add `CHUNK_HEADER_AND_CRC_SIZE` bytes
+///   for every flush.
+/// * An encoder with a [filter](../write/struct.FrameEncoder.html#method.filter)
+///   writes a chunk naming it, which adds 4 bytes plus the length of its
+///   name.
+/// * An encoder with a [digest](../write/struct.FrameEncoder.html#method.digest)
+///   writes a trailer, which adds 5 bytes plus the length of its name and of
+///   the digest itself.
+///
+/// If the maximum size cannot be represented by a `usize`, then this returns
+/// 0.
+#[cfg(feature = "compress")]
+pub fn max_compress_len(input_len: usize) -> usize {
+    max_stream_len(input_len, MAX_BLOCK_SIZE)
+}
+
+/// Returns the maximum size of a Snappy frame formatted stream given the
+/// total number of uncompressed bytes in the stream, when it's split into
+/// chunks of `block_size` bytes.
+///
+/// If the maximum size cannot be represented by a `usize`, then this returns
+/// 0.
+#[cfg(feature = "compress")]
+pub(crate) fn max_stream_len(input_len: usize, block_size: usize) -> usize {
+    let full_chunks = input_len / block_size;
+    let partial_len = input_len % block_size;
+    let mut max = STREAM_IDENTIFIER.len() as u64
+        + full_chunks as u64
+            * (CHUNK_HEADER_AND_CRC_SIZE + raw_max_compress_len(block_size))
+                as u64;
+    if partial_len > 0 {
+        max += (CHUNK_HEADER_AND_CRC_SIZE + raw_max_compress_len(partial_len))
+            as u64;
+    }
+    if max > std::usize::MAX as u64 {
+        0
+    } else {
+        max as usize
+    }
+}
+
+/// Estimates the size of the Snappy frame formatted stream that the frame
+/// encoders in this crate would produce for `input`, by compressing a sample
+/// of at most `max_samples` of its chunks.
+///
+/// The sampled chunks are spread evenly across `input`, and the compression
+/// ratio observed for them is assumed to hold for all of `input`. If `input`
+/// has no more than `max_samples` chunks, then every chunk is compressed and
+/// the size returned is exact (assuming the encoder isn't flushed before a
+/// chunk is full). At least one chunk is always sampled, even if
+/// `max_samples` is `0`.
+///
+/// This is useful for reserving space or choosing between codecs for a large
+/// input without paying for a full compression pass. Its cost is roughly
+/// proportional to `max_samples`, regardless of the size of `input`.
+#[cfg(feature = "compress")]
+pub fn estimate_compressed_size(input: &[u8], max_samples: usize) -> u64 {
+    let chunks = (input.len() + MAX_BLOCK_SIZE - 1) / MAX_BLOCK_SIZE;
+    if chunks == 0 {
+        return 0;
+    }
+    let samples = cmp::min(cmp::max(max_samples, 1), chunks);
+    let mut enc = SnappyCodec::new();
+    let checksummer = CheckSummer::new();
+    let mut header = [0; CHUNK_HEADER_AND_CRC_SIZE];
+    let mut dst = vec![0; MAX_COMPRESS_BLOCK_SIZE];
+    let (mut sampled_len, mut sampled_data_len) = (0u64, 0u64);
+    for i in 0..samples {
+        let start = (i * chunks / samples) * MAX_BLOCK_SIZE;
+        let end = cmp::min(start + MAX_BLOCK_SIZE, input.len());
+        let data = compress_frame(
+            &mut enc,
+            checksummer,
+            &input[start..end],
+            &mut header,
+            &mut dst,
+            false,
+            ChunkPolicy::default(),
+        )
+        // Chunks are never too big to compress, and dst is big enough.
+        .unwrap();
+        sampled_len += (end - start) as u64;
+        sampled_data_len += data.len() as u64;
+    }
+    let data_len = if samples == chunks {
+        sampled_data_len
+    } else {
+        let ratio = sampled_data_len as f64 / sampled_len as f64;
+        (ratio * input.len() as f64).round() as u64
+    };
+    STREAM_IDENTIFIER.len() as u64
+        + (chunks * CHUNK_HEADER_AND_CRC_SIZE) as u64
+        + data_len
+}
+
+/// Decompresses the Snappy frame formatted stream in `input`, passing the
+/// decompressed data of each chunk to `sink` in order.
+///
+/// `window` is the only buffer used to decompress chunks into, so this never
+/// allocates (except to report a stream identifier with the wrong bytes). A
+/// window of [`MAX_BLOCK_SIZE`](constant.MAX_BLOCK_SIZE.html) bytes can hold
+/// any chunk, but a smaller one is enough if every chunk in the stream is
+/// known to be smaller, e.g., if it was written with a smaller
+/// [`EncoderOptions::block_size`](struct.EncoderOptions.html#method.block_size).
+/// Uncompressed chunks are passed to `sink` straight from `input`. This lets
+/// memory constrained systems, such as firmware decompressing an update image
+/// from flash, decompress streams far bigger than their RAM.
+///
+/// Every chunk's checksum is verified before it is passed to `sink`. If
+/// `sink` returns an error, then decompression stops and that error is
+/// returned. Any Snappy error is converted to the sink's error type, for
+/// example, to `std::io::Error`.
+///
+/// Unless `sink` or the conversion to its error type panics, this never
+/// panics, whatever `input` holds.
+///
+/// # Errors
+///
+/// Besides the errors that `read::FrameDecoder` reports for corrupt data,
+/// this returns `Error::BufferTooSmall` if a chunk doesn't fit in `window`
+/// and `Error::TruncatedChunk` if `input` ends in the middle of a chunk.
+/// Since this can't be given a filter, a stream that names one is rejected
+/// with `Error::UnknownFilter`.
+#[cfg(feature = "decompress")]
+pub fn decompress_to_sink<E, F>(
+    input: &[u8],
+    window: &mut [u8],
+    mut sink: F,
+) -> result::Result<(), E>
+where
+    E: From<Error>,
+    F: FnMut(&[u8]) -> result::Result<(), E>,
+{
+    visit_chunks(input, window, |_, data| sink(data))
+}
+
+/// Like `decompress_to_sink`, but also passes `visit` the compressed data of
+/// each chunk, which is `None` for uncompressed chunks.
+#[cfg(feature = "decompress")]
+fn visit_chunks<E, F>(
+    mut input: &[u8],
+    window: &mut [u8],
+    mut visit: F,
+) -> result::Result<(), E>
+where
+    E: From<Error>,
+    F: FnMut(Option<&[u8]>, &[u8]) -> result::Result<(), E>,
+{
+    let checksummer = CheckSummer::new();
+    let mut dec = Decoder::new();
+    let mut read_stream_ident = false;
+    while !input.is_empty() {
+        if input.len() < 4 {
+            return Err(E::from(Error::TruncatedChunk));
+        }
+        let ty = ChunkType::from_u8(input[0]);
+        if !read_stream_ident {
+            if ty != Ok(ChunkType::Stream) {
+                return Err(E::from(Error::StreamHeader { byte: input[0] }));
+            }
+            read_stream_ident = true;
+        }
+        let len = bytes::read_u24_le(&input[1..]) as usize;
+        if len > MAX_COMPRESS_BLOCK_SIZE {
+            return Err(E::from(Error::UnsupportedChunkLength {
+                len: len as u64,
+                header: false,
+            }));
+        }
+        if input.len() < 4 + len {
+            return Err(E::from(Error::TruncatedChunk));
+        }
+        let body = &input[4..4 + len];
+        input = &input[4 + len..];
+        let (raw, data) = match ty {
+            Err(b) if (0x02..=0x7F).contains(&b) => {
+                return Err(E::from(Error::UnsupportedChunkType { byte: b }));
+            }
+            // The data after a filter chunk is filtered, and there's no
+            // filter here to undo it with.
+            Err(FILTER_CHUNK_TYPE) => {
+                return Err(E::from(Error::UnknownFilter {
+                    name: body.to_vec(),
+                }));
+            }
+            // Padding and reserved skippable chunks are ignored.
+            Err(_) | Ok(ChunkType::Padding) => continue,
+            Ok(ChunkType::Stream) => {
+                if len != STREAM_BODY.len() {
+                    return Err(E::from(Error::UnsupportedChunkLength {
+                        len: len as u64,
+                        header: true,
+                    }));
+                }
+                if body != STREAM_BODY {
+                    return Err(E::from(Error::StreamHeaderMismatch {
+                        bytes: body.to_vec(),
+                    }));
+                }
+                continue;
+            }
+            Ok(_) if len < 4 => {
+                return Err(E::from(Error::UnsupportedChunkLength {
+                    len: len as u64,
+                    header: false,
+                }));
+            }
+            Ok(ChunkType::Uncompressed) => {
+                let data = &body[4..];
+                if data.len() > MAX_BLOCK_SIZE {
+                    return Err(E::from(Error::UnsupportedChunkLength {
+                        len: data.len() as u64,
+                        header: false,
+                    }));
+                }
+                (None, data)
+            }
+            Ok(ChunkType::Compressed) => {
+                let n = decompress_len(&body[4..])?;
+                if n > MAX_BLOCK_SIZE {
+                    return Err(E::from(Error::UnsupportedChunkLength {
+                        len: n as u64,
+                        header: false,
+                    }));
+                }
+                if n > window.len() {
+                    return Err(E::from(Error::BufferTooSmall {
+                        given: window.len() as u64,
+                        min: n as u64,
+                    }));
+                }
+                dec.decompress(&body[4..], &mut window[..n])?;
+                (Some(&body[4..]), &window[..n])
+            }
+        };
+        let expected_sum = bytes::read_u32_le(body);
+        let got_sum = checksummer.crc32c_masked(data);
+        if expected_sum != got_sum {
+            return Err(E::from(Error::Checksum {
+                expected: expected_sum,
+                got: got_sum,
+            }));
+        }
+        visit(raw, data)?;
+    }
+    Ok(())
+}
+
+/// Converts a raw Snappy block, such as one written by
+/// [`raw::Encoder`](../raw/struct.Encoder.html), into a Snappy frame
+/// formatted stream.
+///
+/// A raw block of at most 64KB of data is exactly what a compressed chunk
+/// holds, so such a block is copied into the stream as is. It's still
+/// decompressed, to compute the checksum that the chunk needs, but it isn't
+/// compressed again. Bigger blocks are decompressed and compressed again in
+/// chunks of 64KB, just as
+/// [`write::FrameEncoder`](../write/struct.FrameEncoder.html) would.
+///
+/// # Errors
+///
+/// This returns an error if `raw` isn't a valid raw Snappy block.
+#[cfg(all(feature = "compress", feature = "decompress"))]
+pub fn from_raw_block(raw: &[u8]) -> Result<Vec<u8>, Error> {
+    let data = Decoder::new().decompress_vec(raw)?;
+    let checksummer = CheckSummer::new();
+    let mut header = [0; CHUNK_HEADER_AND_CRC_SIZE];
+    let mut stream = STREAM_IDENTIFIER.to_vec();
+    if data.is_empty() {
+        return Ok(stream);
+    }
+    if data.len() <= MAX_BLOCK_SIZE && raw.len() <= MAX_COMPRESS_BLOCK_SIZE {
+        header[0] = ChunkType::Compressed as u8;
+        bytes::write_u24_le(4 + raw.len() as u32, &mut header[1..]);
+        bytes::write_u32_le(
+            checksummer.crc32c_masked(&data),
+            &mut header[4..],
+        );
+        stream.extend_from_slice(&header);
+        stream.extend_from_slice(raw);
+        return Ok(stream);
+    }
+    let mut enc = SnappyCodec::new();
+    let mut dst = vec![0; MAX_COMPRESS_BLOCK_SIZE];
+    for src in data.chunks(MAX_BLOCK_SIZE) {
+        let chunk = compress_frame(
+            &mut enc,
+            checksummer,
+            src,
+            &mut header,
+            &mut dst,
+            false,
+            ChunkPolicy::default(),
+        )?;
+        stream.extend_from_slice(&header);
+        stream.extend_from_slice(chunk);
+    }
+    Ok(stream)
+}
+
+/// Converts a Snappy frame formatted stream into a sequence of raw Snappy
+/// blocks, one for each chunk of data in the stream, in order.
+///
+/// A compressed chunk already holds a raw block, which is copied out as is
+/// once the chunk's checksum is verified. An uncompressed chunk becomes a raw
+/// block that holds its data as a single literal, so nothing is compressed
+/// again. Each block holds at most 64KB of data, and can be decompressed on
+/// its own with a [`raw::Decoder`](../raw/struct.Decoder.html).
+///
+/// # Errors
+///
+/// This returns the same errors as
+/// [`decompress_to_sink`](fn.decompress_to_sink.html) does for a corrupt
+/// stream, including `Error::UnknownFilter` for a stream that names a
+/// filter, since filtered data would be copied into the blocks as is.
+#[cfg(feature = "decompress")]
+pub fn to_raw_blocks(input: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    let mut window = vec![0; MAX_BLOCK_SIZE];
+    let mut blocks = vec![];
+    visit_chunks::<Error, _>(input, &mut window, |raw, data| {
+        blocks.push(match raw {
+            Some(raw) => raw.to_vec(),
+            None => literal_raw_block(data),
+        });
+        Ok(())
+    })?;
+    Ok(blocks)
+}
+
+/// Returns a raw Snappy block that holds `data`, which must be at most 64KB,
+/// as a single literal.
+#[cfg(feature = "decompress")]
+fn literal_raw_block(data: &[u8]) -> Vec<u8> {
+    debug_assert!(data.len() <= MAX_BLOCK_SIZE);
+    let mut block = vec![0; 10 + 3 + data.len()];
+    let mut i = bytes::write_varu64(&mut block, data.len() as u64);
+    // The tag of a literal is 0, and its length less one is stored in the
+    // tag byte if it's less than 60, and otherwise in the 1 or 2 bytes after
+    // a tag byte of 60 or 61.
+    if !data.is_empty() {
+        let n = data.len() - 1;
+        if n < 60 {
+            block[i] = (n as u8) << 2;
+            i += 1;
+        } else if n < 256 {
+            block[i] = 60 << 2;
+            block[i + 1] = n as u8;
+            i += 2;
+        } else {
+            block[i] = 61 << 2;
+            bytes::write_u16_le(n as u16, &mut block[i + 1..]);
+            i += 3;
+        }
+    }
+    block[i..i + data.len()].copy_from_slice(data);
+    block.truncate(i + data.len());
+    block
+}
+
+/// Decompresses the Snappy frame formatted stream read from `rdr` and
+/// compresses it again to `wtr` with `options`, such as to change its block
+/// size or to compress it harder for archiving. This returns the number of
+/// bytes of decompressed data that passed through.
+///
+/// Only one chunk at a time is held in memory on either side, so streams of
+/// any size can be transcoded without decompressing them to a file first.
+/// Every chunk's checksum is verified as it's read, and `wtr` is flushed at
+/// the end.
+///
+/// Streams written with a
+/// [`ChunkFilter`](trait.ChunkFilter.html) can't be read here, since the
+/// filter must be given to the decoder. For those, or to write a digest,
+/// copy from a [`read::FrameDecoder`](../read/struct.FrameDecoder.html) to a
+/// [`write::FrameEncoder`](../write/struct.FrameEncoder.html) configured as
+/// needed.
+///
+/// # Errors
+///
+/// This returns an error if reading from `rdr` or writing to `wtr` fails, or
+/// if the stream read is corrupt. Whatever was transcoded before the error
+/// has been written to `wtr`.
+#[cfg(all(feature = "compress", feature = "decompress"))]
+pub fn transcode<R: io::Read, W: io::Write>(
+    rdr: R,
+    wtr: W,
+    options: &EncoderOptions,
+) -> io::Result<u64> {
+    let mut dec = crate::read::FrameDecoder::new(rdr);
+    let mut enc = crate::write::FrameEncoder::with_options(wtr, options);
+    let n = io::copy(&mut dec, &mut enc)?;
+    enc.flush()?;
+    enc.get_mut().flush()?;
+    Ok(n)
+}
+
+/// How `compress_frame` decides whether to write a block compressed.
+#[cfg(feature = "compress")]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct ChunkPolicy {
+    /// Whether to skip compressing blocks that look incompressible.
+    pub(crate) skip_incompressible: bool,
+    /// The minimum percentage by which compressing a block must shrink it
+    /// for the compressed block to be kept, or `None` for 12.5%.
+    pub(crate) min_savings: Option<u8>,
+}
+
+#[cfg(feature = "compress")]
+impl ChunkPolicy {
+    /// Returns whether a block of `src_len` bytes that compressed to
+    /// `compress_len` bytes should be written compressed. A compressed block
+    /// that isn't smaller than the block is never kept.
+    fn keep_compressed(&self, src_len: usize, compress_len: usize) -> bool {
+        match self.min_savings {
+            None => compress_len < src_len - (src_len / 8),
+            Some(percent) => {
+                compress_len < src_len
+                    && compress_len as u64 * 100
+                        <= src_len as u64 * (100 - percent as u64)
+            }
+        }
+    }
+}
+
 /// Compress a single frame (or decide to pass it through uncompressed). This
 /// will output a frame header in `dst_chunk_header`, and it will return a slice
 /// pointing to the data to use in the frame. The `dst_chunk_header` array must
@@ -60,38 +583,68 @@ impl ChunkType {
 /// compress). If `always_use_dst` is true, the data will always be in `dst`.
 /// This is a bit weird, but because of Rust's ownership rules, it's easiest
 /// for a single function to always be in charge of writing to `dst`.
-pub fn compress_frame<'a>(
-    enc: &mut Encoder,
+#[cfg(feature = "compress")]
+pub(crate) fn compress_frame<'a, C: Codec>(
+    enc: &mut C,
     checksummer: CheckSummer,
     src: &'a [u8],
     dst_chunk_header: &mut [u8],
     dst: &'a mut [u8],
     always_use_dst: bool,
+    policy: ChunkPolicy,
+) -> Result<&'a [u8], Error> {
+    // Build a checksum of our _uncompressed_ data.
+    let checksum = checksummer.crc32c_masked(src);
+    let data = compress_frame_data(
+        enc,
+        src,
+        dst_chunk_header,
+        dst,
+        always_use_dst,
+        policy,
+    )?;
+    bytes::write_u32_le(checksum, &mut dst_chunk_header[4..]);
+    Ok(data)
+}
+
+/// Like `compress_frame`, but leaves the checksum in `dst_chunk_header` for
+/// the caller to fill in with `checksummer.crc32c_masked(src)`.
+#[cfg(feature = "compress")]
+pub(crate) fn compress_frame_data<'a, C: Codec>(
+    enc: &mut C,
+    src: &'a [u8],
+    dst_chunk_header: &mut [u8],
+    dst: &'a mut [u8],
+    always_use_dst: bool,
+    policy: ChunkPolicy,
 ) -> Result<&'a [u8], Error> {
     // This is a purely internal function, with a bunch of preconditions.
     assert!(src.len() <= MAX_BLOCK_SIZE);
-    assert!(dst.len() >= max_compress_len(MAX_BLOCK_SIZE));
+    assert!(dst.len() >= enc.max_compress_len(src.len()));
     assert_eq!(dst_chunk_header.len(), CHUNK_HEADER_AND_CRC_SIZE);
 
-    // Build a checksum of our _uncompressed_ data.
-    let checksum = checksummer.crc32c_masked(src);
-
     // Compress the buffer. If compression sucked, throw it out and
     // write uncompressed bytes instead. Since our buffer is at most
     // MAX_BLOCK_SIZE and our dst buffer has size
     // max_compress_len(MAX_BLOCK_SIZE), we have enough space.
-    let compress_len = enc.compress(src, dst)?;
+    //
+    // If the buffer looks like it won't compress, don't even try.
+    let compress_len =
+        if policy.skip_incompressible && looks_incompressible(src) {
+            src.len()
+        } else {
+            enc.compress(src, dst)?
+        };
     let (chunk_type, chunk_len) =
         // We add 4 to the chunk_len because of the checksum.
-        if compress_len >= src.len() - (src.len() / 8) {
-            (ChunkType::Uncompressed, 4 + src.len())
-        } else {
+        if policy.keep_compressed(src.len(), compress_len) {
             (ChunkType::Compressed, 4 + compress_len)
+        } else {
+            (ChunkType::Uncompressed, 4 + src.len())
         };
 
     dst_chunk_header[0] = chunk_type as u8;
     bytes::write_u24_le(chunk_len as u32, &mut dst_chunk_header[1..]);
-    bytes::write_u32_le(checksum, &mut dst_chunk_header[4..]);
 
     // Return the data to put in our frame.
     if chunk_type == ChunkType::Compressed {
@@ -103,3 +656,49 @@ pub fn compress_frame<'a>(
         Ok(src)
     }
 }
+
+/// Checks that the chunk with header `chunk_header` and data `data`, as
+/// produced by `compress_frame`, holds exactly the bytes in `src`.
+///
+/// `scratch` is used to decompress the chunk into, and is grown as needed.
+#[cfg(all(feature = "compress", feature = "decompress"))]
+pub(crate) fn verify_frame<C: Codec>(
+    dec: &mut C,
+    chunk_header: &[u8],
+    data: &[u8],
+    src: &[u8],
+    scratch: &mut Vec<u8>,
+) -> Result<(), Error> {
+    let ok = if chunk_header[0] == ChunkType::Compressed as u8 {
+        match dec.decompress_len(data) {
+            Ok(n) if n == src.len() => {
+                scratch.resize(n, 0);
+                match dec.decompress(data, scratch) {
+                    Ok(n) => &scratch[..n] == src,
+                    Err(_) => false,
+                }
+            }
+            _ => false,
+        }
+    } else {
+        data == src
+    };
+    if ok {
+        Ok(())
+    } else {
+        Err(Error::Verify)
+    }
+}
+
+/// Verification can only be enabled with the `decompress` feature, so there
+/// is never anything to check here.
+#[cfg(all(feature = "compress", not(feature = "decompress")))]
+pub(crate) fn verify_frame<C: Codec>(
+    _dec: &mut C,
+    _chunk_header: &[u8],
+    _data: &[u8],
+    _src: &[u8],
+    _scratch: &mut Vec<u8>,
+) -> Result<(), Error> {
+    Ok(())
+}
@@ -18,6 +18,35 @@ simply add it as a dependency to your `Cargo.toml`:
 snap = "1"
 ```
 
+The compressor, the decompressor and the frame format are all enabled by
+default. Programs that only need some of them can disable default features
+and enable just the `compress`, `decompress` or `frame` features they use,
+which leaves the rest out of the build entirely. For example, this only
+builds the raw format decompressor:
+
+```ignore
+[dependencies]
+snap = { version = "1", default-features = false, features = ["decompress"] }
+```
+
+On a nightly compiler, the `nightly-simd` feature implements the copy and
+match-compare loops of the compressor and decompressor with `core::simd`,
+which vectorizes them on every architecture the compiler supports.
+
+The `small-input` feature lowers
+[`raw::MAX_INPUT_SIZE`](raw/constant.MAX_INPUT_SIZE.html) from 4GB to 1MB,
+which caps the memory that compressing or decompressing a raw block can ever
+require.
+
+The experimental `long-range` feature adds the [`long`](long/index.html)
+module, which compresses large, repetitive inputs better by finding matches
+up to 4MB back instead of 64KB. Its output is **not** Snappy, and can only be
+decompressed by the same module.
+
+The `unstable` feature enables encoder settings that are still experimental
+and may change in any release, such as
+[`raw::EncoderBuilder::optimal_parse`](raw/struct.EncoderBuilder.html#method.optimal_parse).
+
 # Overview
 
 This crate provides two ways to use Snappy. The first way is through the
@@ -83,6 +112,7 @@ fn main() {
 */
 
 #![deny(missing_docs)]
+#![cfg_attr(feature = "nightly-simd", feature(portable_simd))]
 
 #![no_std]
 #[macro_use]
@@ -92,22 +122,78 @@ extern crate sgx_tstd as std;
 doc_comment::doctest!("../README.md");
 
 pub use crate::error::{Error, Result};
+#[cfg(feature = "compress")]
+pub use crate::estimate::estimate_compressibility;
 
 /// We don't permit compressing a block bigger than what can fit in a u32.
+#[cfg(not(feature = "small-input"))]
 const MAX_INPUT_SIZE: u64 = std::u32::MAX as u64;
 
+/// With the `small-input` feature, blocks are limited to 1MB instead, so that
+/// neither a caller nor a hostile header can make a raw block that needs
+/// more memory than that.
+#[cfg(feature = "small-input")]
+const MAX_INPUT_SIZE: u64 = 1 << 20;
+
 /// The maximum number of bytes that we process at once. A block is the unit
 /// at which we scan for candidates for compression.
+#[cfg(any(feature = "compress", feature = "frame"))]
 const MAX_BLOCK_SIZE: usize = 1 << 16;
 
+#[cfg(all(feature = "frame", feature = "compress"))]
+pub mod batch;
+#[cfg(any(feature = "compress", feature = "decompress"))]
 mod bytes;
+#[cfg(feature = "frame")]
+mod codec;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "compress")]
 mod compress;
+#[cfg(any(feature = "compress", feature = "decompress"))]
+mod compressed;
+#[cfg(any(feature = "compress", feature = "decompress"))]
+pub mod cpu;
+#[cfg(feature = "frame")]
 mod crc32;
+#[cfg(any(crc32c = "slice16", crc32c = "nibble"))]
 mod crc32_table;
+#[cfg(feature = "decompress")]
 mod decompress;
+#[cfg(feature = "frame")]
+mod digest;
 mod error;
-mod frame;
+#[cfg(feature = "compress")]
+mod estimate;
+#[cfg(all(feature = "frame", feature = "decompress"))]
+mod file;
+#[cfg(feature = "frame")]
+mod filter;
+#[cfg(feature = "long-range")]
+pub mod long;
+#[cfg(all(feature = "frame", feature = "compress"))]
+mod options;
+#[cfg(feature = "frame")]
+pub mod frame;
 pub mod raw;
+#[cfg(all(
+    feature = "frame",
+    any(feature = "compress", feature = "decompress")
+))]
 pub mod read;
+#[cfg(all(feature = "frame", feature = "decompress"))]
+mod recover;
+#[cfg(all(feature = "compress", feature = "decompress"))]
+pub mod pool;
+#[cfg(feature = "nightly-simd")]
+mod simd;
+#[cfg(feature = "compress")]
+mod stats;
+#[cfg(feature = "decompress")]
 mod tag;
+#[cfg(all(feature = "frame", feature = "decompress"))]
+mod validate;
+#[cfg(all(feature = "compress", feature = "decompress"))]
+mod vec;
+#[cfg(all(feature = "frame", feature = "compress"))]
 pub mod write;
@@ -0,0 +1,230 @@
+/*!
+This module provides an experimental, non-standard extension of the raw
+Snappy format that finds matches much further back than Snappy does.
+
+A Snappy encoder splits its input into blocks of 64KB and only looks for
+matches within the current block, so data that repeats at a distance of more
+than 64KB, such as several copies of the same large document, compresses no
+better than a single copy. [`Encoder`](struct.Encoder.html) instead splits
+its input into blocks of up to
+[`MAX_BLOCK_SIZE`](constant.MAX_BLOCK_SIZE.html) bytes, so that copies can
+reach back that far. Compression is about as fast as with
+[`raw::Encoder`](../raw/struct.Encoder.html), but its hash table is bigger.
+
+# Compatibility
+
+**The output of this module is not Snappy.** It can only be decompressed by
+[`Decoder`](struct.Decoder.html), and `Decoder` can only decompress the output
+of `Encoder`. Compressed data starts with [`MAGIC`](constant.MAGIC.html),
+which no other Snappy decoder accepts, so it's rejected rather than misread
+if it's given to one. The format may change incompatibly in any release, so
+it shouldn't be used for data that is kept around longer than the program
+that compressed it.
+
+This module is only available with the `long-range` feature.
+*/
+
+use std::prelude::v1::*;
+use std::cmp;
+
+use crate::bytes;
+use crate::compress::{self, compress_long_block};
+use crate::decompress::{self, decompress_len as raw_decompress_len};
+use crate::error::{Error, Result};
+use crate::MAX_INPUT_SIZE;
+
+/// The maximum number of bytes in a single block, which is also the furthest
+/// back that a copy can reach.
+pub const MAX_BLOCK_SIZE: usize = 1 << 22;
+
+/// The bytes that all compressed data produced by this module starts with.
+///
+/// These start with a varint that is too big to be the length of a raw
+/// Snappy block, and with a frame format stream identifier of the wrong
+/// length, so that both raw and framed Snappy decoders reject them.
+pub const MAGIC: &[u8] = b"\xFF\xFF\xFF\xFF\x7FsNaPpY-LR";
+
+/// The maximum number of entries in the hash table used to compress a
+/// block.
+const MAX_TABLE_SIZE: usize = 1 << 17;
+
+/// The smallest hash table used to compress a block.
+const MIN_TABLE_SIZE: usize = 1 << 8;
+
+/// Returns the maximum compressed size given the uncompressed size.
+///
+/// If the uncompressed size exceeds the maximum allowable size then this
+/// returns 0.
+pub fn max_compress_len(input_len: usize) -> usize {
+    match compress::max_compress_len(input_len) {
+        0 => 0,
+        n => n + MAGIC.len(),
+    }
+}
+
+/// Returns the decompressed size (in bytes) of the compressed bytes given.
+///
+/// `input` must be a sequence of bytes returned by
+/// [`Encoder`](struct.Encoder.html).
+///
+/// # Errors
+///
+/// This function returns an error in the following circumstances:
+///
+/// * `input` doesn't start with [`MAGIC`](constant.MAGIC.html).
+/// * An invalid header was seen after the magic bytes.
+/// * The total space required for decompression exceeds
+///   [`raw::MAX_INPUT_SIZE`](../raw/constant.MAX_INPUT_SIZE.html).
+pub fn decompress_len(input: &[u8]) -> Result<usize> {
+    raw_decompress_len(strip_magic(input)?)
+}
+
+/// An encoder for the long-range format.
+///
+/// This encoder holds a hash table that it reuses for every block, which is
+/// allocated the first time a big enough block is compressed.
+#[derive(Clone, Debug, Default)]
+pub struct Encoder {
+    table: Vec<u32>,
+}
+
+impl Encoder {
+    /// Return a new encoder that can be used for compressing bytes.
+    pub fn new() -> Encoder {
+        Encoder { table: vec![] }
+    }
+
+    /// Returns the number of bytes held by this encoder's hash table.
+    pub fn memory_usage(&self) -> usize {
+        self.table.capacity() * 4
+    }
+
+    /// Compresses all bytes in `input` into `output`.
+    ///
+    /// `output` must be large enough to hold the maximum possible compressed
+    /// size of `input`, which can be computed using
+    /// [`max_compress_len`](fn.max_compress_len.html).
+    ///
+    /// On success, this returns the number of bytes written to `output`.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error in the following circumstances:
+    ///
+    /// * The total number of bytes to compress exceeds
+    ///   [`raw::MAX_INPUT_SIZE`](../raw/constant.MAX_INPUT_SIZE.html).
+    /// * `output` has length less than `max_compress_len(input.len())`.
+    pub fn compress(
+        &mut self,
+        mut input: &[u8],
+        output: &mut [u8],
+    ) -> Result<usize> {
+        match max_compress_len(input.len()) {
+            0 => {
+                return Err(Error::TooBig {
+                    given: input.len() as u64,
+                    max: MAX_INPUT_SIZE,
+                });
+            }
+            min if output.len() < min => {
+                return Err(Error::BufferTooSmall {
+                    given: output.len() as u64,
+                    min: min as u64,
+                });
+            }
+            _ => {}
+        }
+        output[..MAGIC.len()].copy_from_slice(MAGIC);
+        let mut d = MAGIC.len();
+        d += bytes::write_varu64(&mut output[d..], input.len() as u64);
+        while !input.is_empty() {
+            let src = &input[..cmp::min(input.len(), MAX_BLOCK_SIZE)];
+            input = &input[src.len()..];
+            let table = self.table(src.len());
+            d = compress_long_block(src, output, d, table);
+        }
+        Ok(d)
+    }
+
+    /// Compresses all bytes in `input` into a freshly allocated `Vec`.
+    ///
+    /// This method returns an error under the same circumstances that
+    /// `compress` does.
+    pub fn compress_vec(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+        let mut buf = vec![0; max_compress_len(input.len())];
+        let n = self.compress(input, &mut buf)?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    /// Returns a hash table sized for a block of `block_size` bytes.
+    fn table(&mut self, block_size: usize) -> &mut [u32] {
+        let mut size = MIN_TABLE_SIZE;
+        while size < MAX_TABLE_SIZE && size < block_size {
+            size *= 2;
+        }
+        if self.table.len() < size {
+            self.table = vec![0; size];
+        }
+        &mut self.table[..size]
+    }
+}
+
+/// A decoder for the long-range format.
+#[derive(Clone, Debug, Default)]
+pub struct Decoder {
+    dec: decompress::Decoder,
+}
+
+impl Decoder {
+    /// Return a new decoder that can be used for decompressing bytes.
+    pub fn new() -> Decoder {
+        Decoder { dec: decompress::Decoder::new() }
+    }
+
+    /// Decompresses all bytes in `input` into `output`.
+    ///
+    /// `input` must be a sequence of bytes returned by
+    /// [`Encoder`](struct.Encoder.html). The size of `output` must be large
+    /// enough to hold all decompressed bytes, which can be queried with
+    /// [`decompress_len`](fn.decompress_len.html).
+    ///
+    /// On success, this returns the number of bytes written to `output`.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error in the following circumstances:
+    ///
+    /// * `input` doesn't start with [`MAGIC`](constant.MAGIC.html), in which
+    ///   case the error is `Error::Header`.
+    /// * Invalid compressed data was seen after the magic bytes.
+    /// * The total space required for decompression exceeds
+    ///   [`raw::MAX_INPUT_SIZE`](../raw/constant.MAX_INPUT_SIZE.html).
+    /// * `output` has length less than `decompress_len(input)`.
+    pub fn decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<usize> {
+        self.dec.decompress(strip_magic(input)?, output)
+    }
+
+    /// Decompresses all bytes in `input` into a freshly allocated `Vec`.
+    ///
+    /// This method returns an error under the same circumstances that
+    /// `decompress` does.
+    pub fn decompress_vec(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+        let mut buf = vec![0; decompress_len(input)?];
+        let n = self.decompress(input, &mut buf)?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+}
+
+/// Returns `input` without the magic bytes it must start with.
+fn strip_magic(input: &[u8]) -> Result<&[u8]> {
+    if input.len() < MAGIC.len() || &input[..MAGIC.len()] != MAGIC {
+        return Err(Error::Header);
+    }
+    Ok(&input[MAGIC.len()..])
+}
@@ -0,0 +1,467 @@
+use std::prelude::v1::*;
+use std::cmp;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::codec::SnappyCodec;
+use crate::compress::EncoderBuilder;
+use crate::crc32::CheckSummer;
+use crate::digest::{Sha256, StreamDigest};
+use crate::error::Error;
+use crate::filter::{self, ChunkFilter};
+use crate::frame::{
+    compress_frame, max_stream_len, ChunkPolicy, CHUNK_HEADER_AND_CRC_SIZE,
+    MAX_COMPRESS_BLOCK_SIZE, STREAM_IDENTIFIER,
+};
+use crate::MAX_BLOCK_SIZE;
+
+/// The block sizes tried by [`autotune`](fn.autotune.html).
+const AUTOTUNE_BLOCK_SIZES: [usize; 3] = [1 << 16, 1 << 15, 1 << 14];
+
+/// The hash table sizes tried by [`autotune`](fn.autotune.html).
+const AUTOTUNE_TABLE_SIZES: [usize; 3] = [1 << 14, 1 << 12, 1 << 10];
+
+/// Options for configuring the frame encoders in this crate.
+///
+/// Options are given to
+/// [`write::FrameEncoder::with_options`](../write/struct.FrameEncoder.html#method.with_options)
+/// or
+/// [`read::FrameEncoder::with_options`](../read/struct.FrameEncoder.html#method.with_options).
+/// Whatever the options, the encoders always produce a stream that any
+/// conforming Snappy frame decoder can read.
+///
+/// The `Display` impl writes every setting on a single line, such as
+/// `block-size=65536 max-table-size=16384 search-depth=0
+/// skip-incompressible=off min-savings=none checksum=crc32c filter=none`,
+/// and the `FromStr` impl parses
+/// it back into the same options. This lets a service record the exact
+/// configuration it compressed with, e.g., in its own metadata, and pin it
+/// across deployments. When parsing, settings may come in any order, and
+/// those left out keep their defaults. A setting that is unknown, or whose
+/// value the corresponding setter would round or clamp, results in
+/// `Error::Options`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EncoderOptions {
+    pub(crate) block_size: usize,
+    pub(crate) encoder: EncoderBuilder,
+    pub(crate) skip_incompressible: bool,
+    pub(crate) min_savings: Option<u8>,
+    /// The name of the filter applied to every block, which is always one
+    /// that `filter::by_name` knows.
+    pub(crate) filter: Option<String>,
+    pub(crate) digest_sha256: bool,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> EncoderOptions {
+        EncoderOptions::new()
+    }
+}
+
+impl EncoderOptions {
+    /// Create the default options, which are what
+    /// [`write::FrameEncoder::new`](../write/struct.FrameEncoder.html#method.new)
+    /// uses.
+    pub fn new() -> EncoderOptions {
+        EncoderOptions {
+            block_size: MAX_BLOCK_SIZE,
+            encoder: EncoderBuilder::new(),
+            skip_incompressible: false,
+            min_savings: None,
+            filter: None,
+            digest_sha256: false,
+        }
+    }
+
+    /// Set the maximum number of uncompressed bytes in each chunk.
+    ///
+    /// Smaller blocks let a decoder produce output sooner and with less
+    /// memory, but usually compress less. The size given is clamped to the
+    /// range `[1, 65536]`.
+    ///
+    /// This is set to `65536` by default, which is also the maximum.
+    pub fn block_size(&mut self, size: usize) -> &mut EncoderOptions {
+        self.block_size = cmp::max(1, cmp::min(size, MAX_BLOCK_SIZE));
+        self
+    }
+
+    /// Set the maximum number of entries in the hash table used to compress
+    /// each block.
+    ///
+    /// See
+    /// [`raw::EncoderBuilder::max_table_size`](../raw/struct.EncoderBuilder.html#method.max_table_size)
+    /// for details.
+    ///
+    /// This is set to `16384` by default, which is also the maximum.
+    pub fn max_table_size(&mut self, size: usize) -> &mut EncoderOptions {
+        self.encoder.max_table_size(size);
+        self
+    }
+
+    /// Set how many earlier occurrences of a byte sequence to compare when
+    /// looking for the longest match in each block, or `0` to use the
+    /// default matcher.
+    ///
+    /// See
+    /// [`raw::EncoderBuilder::search_depth`](../raw/struct.EncoderBuilder.html#method.search_depth)
+    /// for details.
+    ///
+    /// This is set to `0` by default.
+    pub fn search_depth(&mut self, depth: usize) -> &mut EncoderOptions {
+        self.encoder.search_depth(depth);
+        self
+    }
+
+    /// Set whether to skip compressing blocks that look incompressible.
+    ///
+    /// See
+    /// [`write::FrameEncoder::skip_incompressible`](../write/struct.FrameEncoder.html#method.skip_incompressible)
+    /// for details.
+    ///
+    /// This is disabled by default.
+    pub fn skip_incompressible(&mut self, yes: bool) -> &mut EncoderOptions {
+        self.skip_incompressible = yes;
+        self
+    }
+
+    /// Set the minimum percentage by which compressing a block must shrink it
+    /// for the block to be written compressed.
+    ///
+    /// See
+    /// [`write::FrameEncoder::min_savings`](../write/struct.FrameEncoder.html#method.min_savings)
+    /// for details.
+    ///
+    /// This is set to `None` by default, which keeps blocks that compress by
+    /// at least 12.5%.
+    pub fn min_savings(&mut self, percent: Option<u8>) -> &mut EncoderOptions {
+        self.min_savings = percent.map(|p| cmp::min(p, 100));
+        self
+    }
+
+    /// Set the filter to apply to every block before it's compressed, by
+    /// name, or `None` for no filter.
+    ///
+    /// The filters provided by this crate can be named:
+    /// [`Delta`](struct.Delta.html) as `"delta8"`, `"delta16"`, `"delta32"`
+    /// or `"delta64"`, and [`Transpose`](struct.Transpose.html) as
+    /// `"transpose"` followed by its stride, such as `"transpose8"`. See
+    /// [`write::FrameEncoder::filter`](../write/struct.FrameEncoder.html#method.filter)
+    /// for details. `read::FrameEncoder` doesn't support filters, and
+    /// ignores this setting.
+    ///
+    /// This is set to `None` by default.
+    ///
+    /// # Panics
+    ///
+    /// This panics if `name` isn't the name of one of these filters.
+    pub fn filter(&mut self, name: Option<&str>) -> &mut EncoderOptions {
+        if let Some(name) = name {
+            assert!(
+                filter::by_name(name).is_some(),
+                "unknown filter {}",
+                name
+            );
+        }
+        self.filter = name.map(|name| name.to_string());
+        self
+    }
+
+    /// Set whether to append a SHA-256 digest of all the uncompressed data
+    /// to the stream, in addition to the CRC32C checksum of every chunk.
+    ///
+    /// See
+    /// [`write::FrameEncoder::digest`](../write/struct.FrameEncoder.html#method.digest)
+    /// for details. `read::FrameEncoder` doesn't support digests, and
+    /// ignores this setting.
+    ///
+    /// This is disabled by default.
+    pub fn digest_sha256(&mut self, yes: bool) -> &mut EncoderOptions {
+        self.digest_sha256 = yes;
+        self
+    }
+
+    /// Returns the maximum size of a Snappy frame formatted stream given the
+    /// total number of uncompressed bytes in the stream, when it's written by
+    /// an encoder with these options.
+    ///
+    /// This is like
+    /// [`frame::max_compress_len`](fn.max_compress_len.html), except that it
+    /// accounts for the block size, the filter and the digest of these
+    /// options, and it's subject to the same conditions otherwise.
+    ///
+    /// If the maximum size cannot be represented by a `usize`, then this
+    /// returns 0.
+    pub fn max_compress_len(&self, input_len: usize) -> usize {
+        let mut extra = 0;
+        if let Some(ref name) = self.filter {
+            extra += 4 + name.len();
+        }
+        if self.digest_sha256 {
+            // The trailer names the digest "sha256", and the digest itself
+            // is 32 bytes.
+            extra += 5 + 6 + 32;
+        }
+        match max_stream_len(input_len, self.block_size) {
+            0 => 0,
+            len => len.checked_add(extra).unwrap_or(0),
+        }
+    }
+
+    /// Returns the maximum number of uncompressed bytes in each chunk.
+    pub fn get_block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Returns the maximum number of entries in the hash table used to
+    /// compress each block.
+    pub fn get_max_table_size(&self) -> usize {
+        self.encoder.get_max_table_size()
+    }
+
+    /// Returns how many earlier occurrences of a byte sequence are compared
+    /// when looking for the longest match in each block.
+    pub fn get_search_depth(&self) -> usize {
+        self.encoder.get_search_depth()
+    }
+
+    /// Returns whether blocks that look incompressible are skipped.
+    pub fn get_skip_incompressible(&self) -> bool {
+        self.skip_incompressible
+    }
+
+    /// Returns the minimum percentage by which compressing a block must
+    /// shrink it for the block to be written compressed.
+    pub fn get_min_savings(&self) -> Option<u8> {
+        self.min_savings
+    }
+
+    /// Returns the name of the filter applied to every block.
+    pub fn get_filter(&self) -> Option<&str> {
+        self.filter.as_ref().map(|name| &name[..])
+    }
+
+    /// Returns whether a SHA-256 digest is appended to the stream.
+    pub fn get_digest_sha256(&self) -> bool {
+        self.digest_sha256
+    }
+
+    /// Returns a new instance of the filter of these options.
+    pub(crate) fn new_filter(&self) -> Option<Box<dyn ChunkFilter + Send>> {
+        self.filter.as_ref().and_then(|name| filter::by_name(name))
+    }
+
+    /// Returns a new instance of the digest of these options.
+    pub(crate) fn new_digest(&self) -> Option<Box<dyn StreamDigest + Send>> {
+        if self.digest_sha256 {
+            Some(Box::new(Sha256::new()))
+        } else {
+            None
+        }
+    }
+
+    /// Returns how the frame encoders decide whether to write a block
+    /// compressed with these options.
+    pub(crate) fn chunk_policy(&self) -> ChunkPolicy {
+        ChunkPolicy {
+            skip_incompressible: self.skip_incompressible,
+            min_savings: self.min_savings,
+        }
+    }
+}
+
+impl fmt::Display for EncoderOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "block-size={} max-table-size={} search-depth={} \
+             skip-incompressible={}",
+            self.get_block_size(),
+            self.get_max_table_size(),
+            self.get_search_depth(),
+            if self.skip_incompressible { "on" } else { "off" }
+        )?;
+        match self.min_savings {
+            None => write!(f, " min-savings=none")?,
+            Some(percent) => write!(f, " min-savings={}", percent)?,
+        }
+        if self.digest_sha256 {
+            write!(f, " checksum=crc32c+sha256")?;
+        } else {
+            write!(f, " checksum=crc32c")?;
+        }
+        write!(f, " filter={}", self.get_filter().unwrap_or("none"))
+    }
+}
+
+impl FromStr for EncoderOptions {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<EncoderOptions, Error> {
+        let mut options = EncoderOptions::new();
+        for setting in s.split_whitespace() {
+            let invalid = || Error::Options { setting: setting.to_string() };
+            let mut parts = setting.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => return Err(invalid()),
+            };
+            // Each value is given to its setter, and is only accepted if the
+            // setter keeps it as is, so that the options parsed are exactly
+            // the ones written.
+            let ok = match key {
+                "block-size" => value.parse().ok().map_or(false, |n| {
+                    options.block_size(n).get_block_size() == n
+                }),
+                "max-table-size" => value.parse().ok().map_or(false, |n| {
+                    options.max_table_size(n).get_max_table_size() == n
+                }),
+                "search-depth" => value.parse().ok().map_or(false, |n| {
+                    options.search_depth(n).get_search_depth() == n
+                }),
+                "skip-incompressible" if value == "on" || value == "off" => {
+                    options.skip_incompressible(value == "on");
+                    true
+                }
+                "min-savings" if value == "none" => {
+                    options.min_savings(None);
+                    true
+                }
+                "min-savings" => value.parse().ok().map_or(false, |n| {
+                    options.min_savings(Some(n)).get_min_savings() == Some(n)
+                }),
+                "checksum" => match value {
+                    "crc32c" => {
+                        options.digest_sha256(false);
+                        true
+                    }
+                    "crc32c+sha256" => {
+                        options.digest_sha256(true);
+                        true
+                    }
+                    _ => false,
+                },
+                "filter" if value == "none" => {
+                    options.filter(None);
+                    true
+                }
+                "filter" if filter::by_name(value).is_some() => {
+                    options.filter(Some(value));
+                    true
+                }
+                _ => false,
+            };
+            if !ok {
+                return Err(invalid());
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// The outcome of compressing a sample with one candidate set of options,
+/// which is given to the objective of [`autotune`](fn.autotune.html) to
+/// score.
+#[derive(Clone, Debug)]
+pub struct Trial {
+    options: EncoderOptions,
+    input_len: u64,
+    compressed_len: u64,
+}
+
+impl Trial {
+    /// Returns the options that the sample was compressed with.
+    pub fn options(&self) -> &EncoderOptions {
+        &self.options
+    }
+
+    /// Returns the size of the sample, in bytes.
+    pub fn input_len(&self) -> u64 {
+        self.input_len
+    }
+
+    /// Returns the size of the sample once compressed to the Snappy frame
+    /// format with these options, in bytes.
+    pub fn compressed_len(&self) -> u64 {
+        self.compressed_len
+    }
+
+    /// Returns the size of the compressed sample relative to the size of the
+    /// sample. Smaller is better. This is `1.0` for an empty sample.
+    pub fn ratio(&self) -> f64 {
+        if self.input_len == 0 {
+            return 1.0;
+        }
+        self.compressed_len as f64 / self.input_len as f64
+    }
+}
+
+/// Compresses `sample` with several candidate sets of options, and returns
+/// the options whose trial is given the highest score by `objective`.
+///
+/// The candidates vary the block size, the hash table size and whether
+/// incompressible blocks are skipped. Smaller tables and skipping
+/// incompressible blocks make compression faster, and smaller blocks reduce
+/// latency and memory use, but all of them usually cost some compression
+/// ratio. The objective decides how to weigh these against the ratio observed
+/// on the sample. For example, to find the options that compress the sample
+/// best, use `|trial| -trial.ratio()`.
+///
+/// When several candidates get the same score, the one closest to the
+/// default options is returned. The cost of this function is proportional to
+/// the size of `sample` multiplied by the number of candidates, so a sample
+/// of no more than a few hundred kilobytes is usually best.
+pub fn autotune<F>(sample: &[u8], mut objective: F) -> EncoderOptions
+where
+    F: FnMut(&Trial) -> f64,
+{
+    let mut best: Option<(f64, EncoderOptions)> = None;
+    for &skip_incompressible in &[false, true] {
+        for &block_size in &AUTOTUNE_BLOCK_SIZES {
+            for &table_size in &AUTOTUNE_TABLE_SIZES {
+                let mut options = EncoderOptions::new();
+                options
+                    .block_size(block_size)
+                    .max_table_size(table_size)
+                    .skip_incompressible(skip_incompressible);
+                let trial = Trial {
+                    compressed_len: compressed_len(sample, &options),
+                    input_len: sample.len() as u64,
+                    options,
+                };
+                let score = objective(&trial);
+                if best.as_ref().map_or(true, |&(max, _)| score > max) {
+                    best = Some((score, trial.options));
+                }
+            }
+        }
+    }
+    best.unwrap().1
+}
+
+/// Returns the size of `input` once compressed to the Snappy frame format
+/// with the given options.
+fn compressed_len(input: &[u8], options: &EncoderOptions) -> u64 {
+    if input.is_empty() {
+        return 0;
+    }
+    let mut enc = SnappyCodec::with_builder(&options.encoder);
+    let checksummer = CheckSummer::new();
+    let mut header = [0; CHUNK_HEADER_AND_CRC_SIZE];
+    let mut dst = vec![0; MAX_COMPRESS_BLOCK_SIZE];
+    let mut len = STREAM_IDENTIFIER.len() as u64;
+    for block in input.chunks(options.block_size) {
+        let data = compress_frame(
+            &mut enc,
+            checksummer,
+            block,
+            &mut header,
+            &mut dst,
+            false,
+            options.chunk_policy(),
+        )
+        // Blocks are never too big to compress, and dst is big enough.
+        .unwrap();
+        len += (CHUNK_HEADER_AND_CRC_SIZE + data.len()) as u64;
+    }
+    len
+}
@@ -0,0 +1,193 @@
+/*!
+This module provides a pool of raw Snappy encoders and decoders.
+
+An [`Encoder`](../raw/struct.Encoder.html) allocates its hash table the first
+time it compresses a big enough input, so it's beneficial to reuse encoders.
+Servers that compress on many threads at once can share a
+[`CodecPool`](struct.CodecPool.html) (e.g., in an `Arc`) instead of building
+their own. Each encoder or decoder is handed out in a guard that derefs to
+it, and returns it to the pool when the guard is dropped.
+*/
+
+use std::prelude::v1::*;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+use crate::compress::{Encoder, EncoderBuilder};
+use crate::decompress::Decoder;
+
+/// A thread safe pool of raw Snappy encoders and decoders.
+pub struct CodecPool {
+    /// The configuration of the encoders that this pool builds.
+    builder: EncoderBuilder,
+    /// The maximum number of idle encoders, and of idle decoders, kept.
+    max_size: usize,
+    /// Encoders that aren't in use.
+    encoders: Mutex<Vec<Encoder>>,
+    /// Decoders that aren't in use.
+    decoders: Mutex<Vec<Decoder>>,
+}
+
+impl CodecPool {
+    /// Create a new empty pool that keeps every encoder and decoder returned
+    /// to it.
+    ///
+    /// The pool then grows to as many encoders (and decoders) as were ever in
+    /// use at once.
+    pub fn new() -> CodecPool {
+        CodecPool::with_max_size(std::usize::MAX)
+    }
+
+    /// Create a new empty pool that keeps at most `max_size` idle encoders,
+    /// and at most `max_size` idle decoders.
+    ///
+    /// This doesn't limit how many are in use at once. When the pool is
+    /// full, an encoder or decoder that's returned to it is dropped instead.
+    pub fn with_max_size(max_size: usize) -> CodecPool {
+        CodecPool::with_builder(&EncoderBuilder::new(), max_size)
+    }
+
+    /// Like `with_max_size`, but the encoders handed out are built from
+    /// `builder`.
+    pub fn with_builder(
+        builder: &EncoderBuilder,
+        max_size: usize,
+    ) -> CodecPool {
+        CodecPool {
+            builder: builder.clone(),
+            max_size,
+            encoders: Mutex::new(vec![]),
+            decoders: Mutex::new(vec![]),
+        }
+    }
+
+    /// Returns an encoder from this pool, building a new one if none are
+    /// idle. The encoder is returned to the pool when the guard is dropped,
+    /// and its stats are reset then, so that every encoder handed out starts
+    /// out as though it had just been built.
+    pub fn encoder(&self) -> PooledEncoder<'_> {
+        let enc = self.encoders.lock().unwrap().pop();
+        PooledEncoder {
+            pool: self,
+            enc: Some(enc.unwrap_or_else(|| self.builder.build())),
+        }
+    }
+
+    /// Returns a decoder from this pool, building a new one if none are
+    /// idle. The decoder is returned to the pool when the guard is dropped.
+    pub fn decoder(&self) -> PooledDecoder<'_> {
+        let dec = self.decoders.lock().unwrap().pop();
+        PooledDecoder { pool: self, dec: Some(dec.unwrap_or_default()) }
+    }
+
+    /// Returns the number of idle encoders in this pool.
+    pub fn idle_encoders(&self) -> usize {
+        self.encoders.lock().unwrap().len()
+    }
+
+    /// Returns the number of idle decoders in this pool.
+    pub fn idle_decoders(&self) -> usize {
+        self.decoders.lock().unwrap().len()
+    }
+
+    /// Returns the maximum number of idle encoders, and of idle decoders,
+    /// that this pool keeps.
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+}
+
+impl Default for CodecPool {
+    fn default() -> CodecPool {
+        CodecPool::new()
+    }
+}
+
+impl fmt::Debug for CodecPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CodecPool")
+            .field("builder", &self.builder)
+            .field("max_size", &self.max_size)
+            .field("encoders", &"[...]")
+            .field("decoders", &"[...]")
+            .finish()
+    }
+}
+
+/// An encoder borrowed from a [`CodecPool`](struct.CodecPool.html).
+///
+/// This derefs to an [`Encoder`](../raw/struct.Encoder.html), and returns it
+/// to the pool when dropped.
+#[derive(Debug)]
+pub struct PooledEncoder<'a> {
+    pool: &'a CodecPool,
+    /// The encoder, which is only `None` once this guard is being dropped.
+    enc: Option<Encoder>,
+}
+
+impl<'a> Deref for PooledEncoder<'a> {
+    type Target = Encoder;
+
+    fn deref(&self) -> &Encoder {
+        self.enc.as_ref().unwrap()
+    }
+}
+
+impl<'a> DerefMut for PooledEncoder<'a> {
+    fn deref_mut(&mut self) -> &mut Encoder {
+        self.enc.as_mut().unwrap()
+    }
+}
+
+impl<'a> Drop for PooledEncoder<'a> {
+    fn drop(&mut self) {
+        let mut encoders = match self.pool.encoders.lock() {
+            Ok(encoders) => encoders,
+            Err(_) => return,
+        };
+        if encoders.len() < self.pool.max_size {
+            // Stats counted for this borrower mustn't show up for the next.
+            let mut enc = self.enc.take().unwrap();
+            enc.reset(&self.pool.builder);
+            encoders.push(enc);
+        }
+    }
+}
+
+/// A decoder borrowed from a [`CodecPool`](struct.CodecPool.html).
+///
+/// This derefs to a [`Decoder`](../raw/struct.Decoder.html), and returns it
+/// to the pool when dropped.
+#[derive(Debug)]
+pub struct PooledDecoder<'a> {
+    pool: &'a CodecPool,
+    /// The decoder, which is only `None` once this guard is being dropped.
+    dec: Option<Decoder>,
+}
+
+impl<'a> Deref for PooledDecoder<'a> {
+    type Target = Decoder;
+
+    fn deref(&self) -> &Decoder {
+        self.dec.as_ref().unwrap()
+    }
+}
+
+impl<'a> DerefMut for PooledDecoder<'a> {
+    fn deref_mut(&mut self) -> &mut Decoder {
+        self.dec.as_mut().unwrap()
+    }
+}
+
+impl<'a> Drop for PooledDecoder<'a> {
+    fn drop(&mut self) {
+        let mut decoders = match self.pool.decoders.lock() {
+            Ok(decoders) => decoders,
+            Err(_) => return,
+        };
+        if decoders.len() < self.pool.max_size {
+            decoders.push(self.dec.take().unwrap());
+        }
+    }
+}
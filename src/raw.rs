@@ -9,6 +9,48 @@ the [`read`](../read/index.html) and [`write`](../write/index.html) modules.
 Generally, one only needs to use the raw format if some other source is
 generating raw Snappy compressed data and you have no choice but to do the
 same. Otherwise, the Snappy frame format should probably always be preferred.
+
+# Compatibility
+
+A block compressed by [`Encoder`](struct.Encoder.html) starts with the
+varint encoded length of its uncompressed data, exactly as produced by
+`snappy::Compress` and `snappy::RawCompress` in the reference C++ library,
+and can be decompressed by `snappy::Uncompress` (whose
+`snappy::GetUncompressedLength` reads the same length as
+[`decompress_len`](fn.decompress_len.html)). Conversely,
+[`Decoder`](struct.Decoder.html) decompresses any block produced by the C++
+library. This is the format that LevelDB and RocksDB store their Snappy
+compressed blocks in, so this module can read and write those blocks
+directly, at any block size up to
+[`MAX_INPUT_SIZE`](constant.MAX_INPUT_SIZE.html).
+
+The compressed bytes themselves are not guaranteed to be identical to those
+produced by the C++ library, or by other versions of this crate, although
+they currently are for the test corpus. Callers that compare or hash
+compressed blocks should compare their decompressed contents instead.
 */
-pub use crate::compress::{max_compress_len, Encoder};
-pub use crate::decompress::{decompress_len, Decoder};
+#[cfg(feature = "compress")]
+pub use crate::compress::{
+    compress_chunked, max_compress_len, CompressChunked, Encoder,
+    EncoderBuilder,
+};
+#[cfg(any(feature = "compress", feature = "decompress"))]
+pub use crate::compressed::RawCompressed;
+#[cfg(feature = "decompress")]
+pub use crate::decompress::{
+    decompress_len, read_decompress_len, visit, Decoder, TagVisitor,
+};
+#[cfg(feature = "compress")]
+pub use crate::stats::EncoderStats;
+#[cfg(all(feature = "compress", feature = "decompress"))]
+pub use crate::vec::SnappyVec;
+
+/// The maximum number of bytes that can be compressed or decompressed as a
+/// single raw Snappy block.
+///
+/// This is `2^32 - 1`, or `2^20` (1MB) when the `small-input` feature is
+/// enabled. The limit also applies to compressed blocks, so with
+/// `small-input`, [`max_compress_len`](fn.max_compress_len.html) returns `0`
+/// for inputs a little under 1MB. The frame format works on blocks of at most
+/// 64KB, so it isn't affected.
+pub const MAX_INPUT_SIZE: u64 = crate::MAX_INPUT_SIZE;
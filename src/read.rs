@@ -1,5 +1,5 @@
 /*!
-This module provides two `std::io::Read` implementations:
+This module provides three `std::io::Read` implementations:
 
 * [`read::FrameDecoder`](struct.FrameDecoder.html)
   wraps another `std::io::Read` implemenation, and decompresses data encoded
@@ -9,6 +9,11 @@ This module provides two `std::io::Read` implementations:
   wraps another `std::io::Read` implemenation, and compresses data encoded
   using the Snappy frame format. Use this if you have uncompressed data source
   and wish to read it as compressed data.
+* [`read::SnappyFile`](struct.SnappyFile.html)
+  wraps another `std::io::Read` implementation that also implements
+  `std::io::Seek`, and provides random access to the uncompressed data of a
+  Snappy frame formatted file. Use this if you need to read parts of a large
+  compressed file without decompressing all of it.
 
 Typically, `read::FrameDecoder` is the version that you'll want.
 */
@@ -17,20 +22,37 @@ use std::prelude::v1::*;
 use std::cmp;
 use std::fmt;
 use std::io;
+#[cfg(feature = "decompress")]
+use std::io::Read;
+#[cfg(feature = "decompress")]
+use std::mem;
 
+#[cfg(feature = "decompress")]
 use crate::bytes;
-use crate::compress::Encoder;
+use crate::codec::{Codec, SnappyCodec};
 use crate::crc32::CheckSummer;
-use crate::decompress::{decompress_len, Decoder};
+#[cfg(feature = "decompress")]
 use crate::error::Error;
+#[cfg(feature = "compress")]
 use crate::frame::{
-    compress_frame, ChunkType, CHUNK_HEADER_AND_CRC_SIZE,
-    MAX_COMPRESS_BLOCK_SIZE, STREAM_BODY, STREAM_IDENTIFIER,
+    compress_frame, verify_frame, ChunkPolicy, EncoderOptions,
+    CHUNK_HEADER_AND_CRC_SIZE,
 };
+#[cfg(feature = "decompress")]
+use crate::frame::{
+    ChunkFilter, ChunkType, StreamDigest, DIGEST_CHUNK_TYPE,
+    FILTER_CHUNK_TYPE, STREAM_BODY,
+};
+use crate::frame::{MAX_COMPRESS_BLOCK_SIZE, STREAM_IDENTIFIER};
+#[cfg(feature = "decompress")]
 use crate::MAX_BLOCK_SIZE;
 
+#[cfg(feature = "decompress")]
+pub use crate::file::SnappyFile;
+
 /// The maximum size of a compressed block, including the header and stream
 /// identifier, that can be emitted by FrameEncoder.
+#[cfg(feature = "compress")]
 const MAX_READ_FRAME_ENCODER_BLOCK_SIZE: usize = STREAM_IDENTIFIER.len()
     + CHUNK_HEADER_AND_CRC_SIZE
     + MAX_COMPRESS_BLOCK_SIZE;
@@ -45,12 +67,20 @@ const MAX_READ_FRAME_ENCODER_BLOCK_SIZE: usize = STREAM_IDENTIFIER.len()
 /// This reader can potentially make many small reads from the underlying
 /// stream depending on its format, therefore, passing in a buffered reader
 /// may be beneficial.
-pub struct FrameDecoder<R: io::Read> {
+///
+/// Each call to `read` decompresses at most one chunk, which holds at most
+/// 64KB of data, so the time spent in any single call is bounded.
+///
+/// The type parameter `C` is the codec that decompresses each chunk, which
+/// is Snappy unless another one is given to
+/// [`with_codec`](#method.with_codec).
+#[cfg(feature = "decompress")]
+pub struct FrameDecoder<R: io::Read, C: Codec = SnappyCodec> {
     /// The underlying reader.
-    r: R,
-    /// A Snappy decoder that we reuse that does the actual block based
+    r: Rewind<R>,
+    /// A codec that we reuse that does the actual block based
     /// decompression.
-    dec: Decoder,
+    dec: C,
     /// A CRC32 checksummer that is configured to either use the portable
     /// fallback version or the SSE4.2 accelerated version when the right CPU
     /// features are available.
@@ -66,26 +96,104 @@ pub struct FrameDecoder<R: io::Read> {
     dste: usize,
     /// Whether we've read the special stream header or not.
     read_stream_ident: bool,
+    /// Whether to scan for the next stream identifier after corrupt data.
+    resync: bool,
+    /// Whether to treat corrupt data after a stream identifier as the end of
+    /// the stream.
+    trailing_garbage: bool,
+    /// Whether to reject streams that are valid but not canonical.
+    strict: bool,
+    /// Whether trailing garbage has been seen, which ends the stream.
+    garbage: bool,
+    /// The number of bytes from the underlying reader up to the end of the
+    /// last complete chunk.
+    consumed: u64,
+    /// The digest of the data read since the last digest trailer, which is
+    /// checked against the next trailer.
+    digest: Option<Box<dyn StreamDigest + Send>>,
+    /// Whether a digest trailer must be read before the stream ends.
+    digest_pending: bool,
+    /// The filters that this decoder can undo.
+    filters: Vec<Box<dyn ChunkFilter + Send>>,
+    /// Index into filters: the filter named by the current stream, if any.
+    filter: Option<usize>,
+    /// The chunks whose checksums didn't match, when these are collected
+    /// rather than returned as errors.
+    checksum_report: Option<ChecksumReport>,
+    /// What to do with data chunks that can't be decoded.
+    error_policy: ErrorPolicy,
+    /// The most bytes that any chunk read so far needed in `src` or `dst`.
+    largest_chunk: usize,
+    /// The number of times that `src` or `dst` had to grow.
+    reallocations: u64,
 }
 
+#[cfg(feature = "decompress")]
 impl<R: io::Read> FrameDecoder<R> {
     /// Create a new reader for streaming Snappy decompression.
     pub fn new(rdr: R) -> FrameDecoder<R> {
+        FrameDecoder::with_capacity(rdr, MAX_BLOCK_SIZE)
+    }
+
+    /// Create a new reader for streaming Snappy decompression whose internal
+    /// buffers start out large enough for chunks of `capacity` bytes.
+    ///
+    /// The buffers grow when a bigger chunk is read, up to the largest chunk
+    /// permitted by the Snappy frame format. `new` sizes them for the largest
+    /// chunk up front, so this is only useful for reducing memory usage when
+    /// every chunk in a stream is known to be small.
+    ///
+    /// [`largest_chunk`](#method.largest_chunk) on a decoder that read a
+    /// typical stream is a good choice of `capacity` for streams like it.
+    pub fn with_capacity(rdr: R, capacity: usize) -> FrameDecoder<R> {
+        FrameDecoder::with_codec_capacity(rdr, SnappyCodec::new(), capacity)
+    }
+}
+
+#[cfg(feature = "decompress")]
+impl<R: io::Read, C: Codec> FrameDecoder<R, C> {
+    /// Create a new reader for streaming decompression of a stream whose
+    /// chunks were compressed with `codec`.
+    ///
+    /// See [`frame::Codec`](../frame/trait.Codec.html) for more details.
+    pub fn with_codec(rdr: R, codec: C) -> FrameDecoder<R, C> {
+        FrameDecoder::with_codec_capacity(rdr, codec, MAX_BLOCK_SIZE)
+    }
+
+    /// Like `with_codec`, but with buffers sized as for `with_capacity`.
+    fn with_codec_capacity(
+        rdr: R,
+        codec: C,
+        capacity: usize,
+    ) -> FrameDecoder<R, C> {
         FrameDecoder {
-            r: rdr,
-            dec: Decoder::new(),
+            r: Rewind::new(rdr),
+            dec: codec,
             checksummer: CheckSummer::new(),
-            src: vec![0; MAX_COMPRESS_BLOCK_SIZE],
-            dst: vec![0; MAX_BLOCK_SIZE],
+            src: vec![0; cmp::min(capacity, MAX_COMPRESS_BLOCK_SIZE)],
+            dst: vec![0; cmp::min(capacity, MAX_BLOCK_SIZE)],
             dsts: 0,
             dste: 0,
             read_stream_ident: false,
+            resync: false,
+            trailing_garbage: false,
+            strict: false,
+            garbage: false,
+            consumed: 0,
+            digest: None,
+            digest_pending: false,
+            filters: vec![],
+            filter: None,
+            checksum_report: None,
+            error_policy: ErrorPolicy::Fail,
+            largest_chunk: 0,
+            reallocations: 0,
         }
     }
 
     /// Gets a reference to the underlying reader in this decoder.
     pub fn get_ref(&self) -> &R {
-        &self.r
+        &self.r.r
     }
 
     /// Gets a mutable reference to the underlying reader in this decoder.
@@ -93,27 +201,568 @@ impl<R: io::Read> FrameDecoder<R> {
     /// Note that mutation of the stream may result in surprising results if
     /// this decoder is continued to be used.
     pub fn get_mut(&mut self) -> &mut R {
-        &mut self.r
+        &mut self.r.r
+    }
+
+    /// Returns the number of bytes held by this decoder's buffers.
+    ///
+    /// This doesn't include the underlying reader. The buffers start out at
+    /// the capacity given to [`with_capacity`](#method.with_capacity), and
+    /// only grow if a bigger chunk is read or, with `resync`, corrupt data is
+    /// rescanned.
+    pub fn memory_usage(&self) -> usize {
+        self.dec.memory_usage()
+            + self.src.capacity()
+            + self.dst.capacity()
+            + self.r.unread.capacity()
+            + self.r.recorded.capacity()
+    }
+
+    /// Returns the most bytes that a single chunk read so far needed in this
+    /// decoder's buffers, i.e., the larger of its length in the stream and
+    /// the length of the data it decompressed to.
+    ///
+    /// A decoder created by [`with_capacity`](#method.with_capacity) with
+    /// at least this capacity reads the same chunks without growing its
+    /// buffers.
+    pub fn largest_chunk(&self) -> usize {
+        self.largest_chunk
+    }
+
+    /// Returns the number of bytes that this decoder's buffers for
+    /// compressed and decompressed chunks, respectively, hold without having
+    /// to grow.
+    ///
+    /// These start out at the capacity given to
+    /// [`with_capacity`](#method.with_capacity), capped at the largest chunk
+    /// that the Snappy frame format permits for each.
+    pub fn buffer_capacities(&self) -> (usize, usize) {
+        (self.src.len(), self.dst.len())
+    }
+
+    /// Returns the number of times that this decoder's buffers for
+    /// compressed and decompressed chunks had to grow to fit a chunk.
+    ///
+    /// This doesn't count the buffer that holds corrupt data while
+    /// [`resync`](#method.resync) rescans it, which is included in
+    /// [`memory_usage`](#method.memory_usage).
+    pub fn reallocations(&self) -> u64 {
+        self.reallocations
+    }
+
+    /// When enabled, this decoder recovers from corrupt data by scanning
+    /// forward for the next stream identifier and resuming decompression from
+    /// there, instead of returning an error.
+    ///
+    /// This is useful for streams that are built by appending to them over
+    /// time, such as logs, where a single corrupt chunk (for example, one left
+    /// truncated by a writer that crashed) shouldn't make everything after it
+    /// unreadable. The scan starts just after the beginning of the corrupt
+    /// chunk, so a stream identifier is found even if the corrupt chunk's
+    /// length overlaps it. All data before the next stream identifier is
+    /// lost. If no stream identifier is found, then the stream ends without
+    /// an error.
+    ///
+    /// Errors from the underlying reader, other than an unexpected EOF in the
+    /// middle of a chunk, are always returned.
+    ///
+    /// This is disabled by default.
+    pub fn resync(&mut self, yes: bool) -> &mut FrameDecoder<R, C> {
+        self.resync = yes;
+        self.r.record = yes;
+        self
+    }
+
+    /// When enabled, this decoder ends the stream cleanly at the first chunk
+    /// that cannot be decoded after a stream identifier has been read,
+    /// instead of returning an error. Everything from the start of that chunk
+    /// onwards is treated as trailing garbage and ignored.
+    ///
+    /// This is useful when reading a Snappy stream embedded in a container
+    /// format that pads it or follows it with other data. Use
+    /// [`consumed`](struct.FrameDecoder.html#method.consumed) to find where
+    /// the Snappy stream ended.
+    ///
+    /// Note that this makes corruption in the middle of a stream
+    /// indistinguishable from trailing garbage. If resynchronization is also
+    /// enabled, then it takes priority, and the stream only ends once no
+    /// further stream identifier can be found.
+    ///
+    /// This is disabled by default.
+    pub fn trailing_garbage(&mut self, yes: bool) -> &mut FrameDecoder<R, C> {
+        self.trailing_garbage = yes;
+        self
+    }
+
+    /// When enabled, this decoder rejects streams that the Snappy frame format
+    /// permits but that no conforming encoder needs to produce. This is
+    /// useful for validating that a stream is canonical.
+    ///
+    /// Specifically, in strict mode, padding chunks and reserved skippable
+    /// chunks result in an
+    /// [`UnsupportedChunkType`](../enum.Error.html#variant.UnsupportedChunkType)
+    /// error, and a stream that ends before a stream identifier has been read
+    /// (including an empty stream) results in an
+    /// [`EmptyStream`](../enum.Error.html#variant.EmptyStream) error.
+    ///
+    /// This is disabled by default.
+    pub fn strict(&mut self, yes: bool) -> &mut FrameDecoder<R, C> {
+        self.strict = yes;
+        self
+    }
+
+    /// When given a digest, this decoder checks the digest trailer that
+    /// [`write::FrameEncoder::digest`](../write/struct.FrameEncoder.html#method.digest)
+    /// appends to a stream against the data it decompresses, and returns
+    /// [`Digest`](../enum.Error.html#variant.Digest) if they differ.
+    ///
+    /// The last data chunk in the stream must be followed by a trailer with
+    /// the name of `digest`, or else
+    /// [`MissingDigest`](../enum.Error.html#variant.MissingDigest) is
+    /// returned at the end of the stream. This catches a stream that was
+    /// truncated at a chunk boundary. Trailers of other digests are skipped.
+    /// Streams that were concatenated, each with its own trailer, are checked
+    /// one by one.
+    ///
+    /// Since a trailer follows the data it covers, the error is returned
+    /// only after all of that data has been read, so data shouldn't be
+    /// trusted until reading reaches the end of the stream without an error.
+    /// Digest errors are returned even if
+    /// [`resync`](#method.resync) or
+    /// [`trailing_garbage`](#method.trailing_garbage) is enabled, and digest
+    /// trailers are accepted in [`strict`](#method.strict) mode.
+    ///
+    /// This is disabled by default.
+    pub fn verify_digest<D: StreamDigest + Send + 'static>(
+        &mut self,
+        digest: D,
+    ) -> &mut FrameDecoder<R, C> {
+        self.digest = Some(Box::new(digest));
+        self.digest_pending = true;
+        self
+    }
+
+    /// Undo `filter` in every chunk of a stream written by an encoder that
+    /// was given the same filter with
+    /// [`write::FrameEncoder::filter`](../write/struct.FrameEncoder.html#method.filter).
+    ///
+    /// More than one filter can be given. Each stream names the filter it
+    /// uses, which applies to the chunks after it until the next stream
+    /// identifier. A stream naming a filter that wasn't given to this decoder
+    /// results in an
+    /// [`UnknownFilter`](../enum.Error.html#variant.UnknownFilter) error,
+    /// even if [`resync`](#method.resync) or
+    /// [`trailing_garbage`](#method.trailing_garbage) is enabled. Chunks
+    /// naming a filter are accepted in [`strict`](#method.strict) mode.
+    pub fn filter<F: ChunkFilter + Send + 'static>(
+        &mut self,
+        filter: F,
+    ) -> &mut FrameDecoder<R, C> {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// When enabled, a data chunk whose checksum doesn't match its data is
+    /// returned as if it did, and recorded in the report returned by
+    /// [`checksum_report`](#method.checksum_report), instead of resulting in
+    /// a [`Checksum`](../enum.Error.html#variant.Checksum) error.
+    ///
+    /// This is useful for assessing the damage to a large archive before
+    /// deciding how to recover it: a single pass finds every chunk that is
+    /// corrupt, rather than just the first. The data of those chunks is
+    /// whatever they decompress to, so it shouldn't be trusted. A chunk that
+    /// can't be decompressed at all still results in an error.
+    ///
+    /// Since a checksum mismatch is no longer an error, it neither triggers
+    /// [`resync`](#method.resync) nor ends the stream with
+    /// [`trailing_garbage`](#method.trailing_garbage).
+    ///
+    /// If an [`error_policy`](#method.error_policy) other than
+    /// `ErrorPolicy::Fail` is set, then it decides what happens to the data
+    /// of these chunks instead, and they're still recorded in the report.
+    ///
+    /// This is disabled by default. Disabling it discards the report.
+    pub fn collect_checksum_errors(
+        &mut self,
+        yes: bool,
+    ) -> &mut FrameDecoder<R, C> {
+        if !yes {
+            self.checksum_report = None;
+        } else if self.checksum_report.is_none() {
+            self.checksum_report = Some(ChecksumReport::default());
+        }
+        self
+    }
+
+    /// Returns the report of the data chunks checked since
+    /// [`collect_checksum_errors`](#method.collect_checksum_errors) was
+    /// enabled, or `None` if it isn't.
+    ///
+    /// The report covers the whole stream once reading reaches its end.
+    pub fn checksum_report(&self) -> Option<&ChecksumReport> {
+        self.checksum_report.as_ref()
+    }
+
+    /// Sets what this decoder does with a data chunk that was read in full
+    /// but can't be decoded, because it doesn't decompress or its checksum
+    /// doesn't match its data.
+    ///
+    /// See [`ErrorPolicy`](enum.ErrorPolicy.html) for the choices. Whatever
+    /// the policy, corrupt chunk headers and truncated chunks are still
+    /// errors, since the chunks after them can't be found, but
+    /// [`resync`](#method.resync) and
+    /// [`trailing_garbage`](#method.trailing_garbage) apply to those as
+    /// usual. Since a chunk's data is lost either way, a
+    /// [digest](#method.verify_digest) covering it won't match.
+    ///
+    /// This is `ErrorPolicy::Fail` by default.
+    pub fn error_policy(
+        &mut self,
+        policy: ErrorPolicy,
+    ) -> &mut FrameDecoder<R, C> {
+        self.error_policy = policy;
+        self
+    }
+
+    /// Returns the number of bytes of the underlying reader that make up the
+    /// chunks read so far, up to the end of the last complete chunk.
+    ///
+    /// This may be less than the number of bytes actually read from the
+    /// underlying reader, for example, when part of a chunk has been read or
+    /// when trailing garbage was found.
+    pub fn consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    /// Skips over the next `n` bytes of uncompressed data in this stream.
+    ///
+    /// This is equivalent to reading `n` bytes and throwing them away, except
+    /// it is faster: decompressed bytes are never copied out of this
+    /// decoder's internal buffer. Every chunk skipped over is still
+    /// decompressed and has its checksum verified.
+    ///
+    /// This returns the number of bytes skipped, which is less than `n` only
+    /// if the end of the stream was reached.
+    pub fn skip(&mut self, n: u64) -> io::Result<u64> {
+        let mut skipped = 0;
+        while skipped < n {
+            if self.dsts == self.dste {
+                if !self.read_chunk()? {
+                    break;
+                }
+                continue;
+            }
+            let avail = (self.dste - self.dsts) as u64;
+            let len = cmp::min(avail, n - skipped) as usize;
+            self.dsts += len;
+            skipped += len as u64;
+        }
+        Ok(skipped)
+    }
+
+    /// Discards what's left of the current chunk, then reads the next chunk
+    /// in this stream and discards it too, returning the number of bytes it
+    /// decompressed to.
+    ///
+    /// This returns `None` if the underlying reader has no more chunks.
+    pub(crate) fn skip_chunk(&mut self) -> io::Result<Option<usize>> {
+        self.dsts = self.dste;
+        if !self.read_chunk()? {
+            return Ok(None);
+        }
+        let n = self.dste - self.dsts;
+        self.dsts = self.dste;
+        Ok(Some(n))
+    }
+
+    /// Returns an iterator over the remaining chunks in this stream. Each
+    /// item yielded is the decompressed data of a single chunk.
+    ///
+    /// If part of the current chunk has already been read via `io::Read`, then
+    /// the first item yielded contains only the remainder of that chunk.
+    ///
+    /// Iteration stops when the underlying reader has no more chunks. If an
+    /// error occurs while reading a chunk, then it is yielded as an item.
+    pub fn chunks(&mut self) -> Chunks<'_, R, C> {
+        Chunks { dec: self }
+    }
+
+    /// Reads the decompressed data of the next chunk in this stream into
+    /// `buf`, replacing its contents.
+    ///
+    /// This pairs with
+    /// [`write::FrameEncoder::write_message`](../write/struct.FrameEncoder.html#method.write_message),
+    /// which writes each message no bigger than the block size as exactly one
+    /// chunk, so that every call returns one message, even an empty one.
+    ///
+    /// If part of the current chunk has already been read via `io::Read`,
+    /// then only the remainder of that chunk is read.
+    ///
+    /// This returns false, leaving `buf` empty, if the underlying reader has
+    /// no more chunks.
+    pub fn read_message(&mut self, buf: &mut Vec<u8>) -> io::Result<bool> {
+        buf.clear();
+        if self.dsts == self.dste && !self.read_chunk()? {
+            return Ok(false);
+        }
+        buf.extend_from_slice(&self.dst[self.dsts..self.dste]);
+        self.dsts = self.dste;
+        Ok(true)
+    }
+
+    /// Decompresses the rest of this stream into `wtr`, passing every
+    /// decompressed byte to `digest` on the way.
+    ///
+    /// This lets a caller hash data while decompressing it, e.g., to store it
+    /// under its content's hash, in one pass: each chunk is given to `digest`
+    /// right after its checksum is verified, while it's still in cache, and
+    /// then written to `wtr` straight from this decoder's internal buffer.
+    /// `digest` sees exactly the bytes written to `wtr`, in the same order,
+    /// though possibly in differently sized pieces. If part of the current
+    /// chunk has already been read via `io::Read`, then only the remainder
+    /// of it is decompressed.
+    ///
+    /// On success, this returns the number of bytes written to `wtr`. If an
+    /// error occurs, then `digest` has only seen data from chunks before the
+    /// one that failed, which may or may not have been written to `wtr`.
+    pub fn tee<W, F>(&mut self, mut wtr: W, mut digest: F) -> io::Result<u64>
+    where
+        W: io::Write,
+        F: FnMut(&[u8]),
+    {
+        let mut written = 0;
+        loop {
+            if self.dsts == self.dste && !self.read_chunk()? {
+                return Ok(written);
+            }
+            let data = &self.dst[self.dsts..self.dste];
+            digest(data);
+            wtr.write_all(data)?;
+            written += data.len() as u64;
+            self.dsts = self.dste;
+        }
     }
 }
 
-impl<R: io::Read> io::Read for FrameDecoder<R> {
+#[cfg(feature = "decompress")]
+impl<R: io::Read, C: Codec> io::Read for FrameDecoder<R, C> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.dsts == self.dste {
+            if !self.read_chunk()? {
+                return Ok(0);
+            }
+        }
+        let len = cmp::min(self.dste - self.dsts, buf.len());
+        let dste = self.dsts.checked_add(len).unwrap();
+        buf[0..len].copy_from_slice(&self.dst[self.dsts..dste]);
+        self.dsts = dste;
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "decompress")]
+impl<R: io::Read, C: Codec> FrameDecoder<R, C> {
+    /// Reads chunks from the underlying reader until a chunk containing
+    /// uncompressed data is found, and decompresses it into `dst`. Any bytes
+    /// in `dst` not yet given back to the caller are discarded.
+    ///
+    /// This returns false if the underlying reader has no more chunks.
+    fn read_chunk(&mut self) -> io::Result<bool> {
+        if self.garbage {
+            return self.end_stream();
+        }
+        loop {
+            match self.try_read_chunk() {
+                Err(ref err) if self.resync && is_corrupt(err) => {}
+                Err(ref err)
+                    if self.trailing_garbage
+                        && self.read_stream_ident
+                        && is_corrupt(err) =>
+                {
+                    self.garbage = true;
+                    return self.end_stream();
+                }
+                Err(err) => return Err(err),
+                Ok(true) => {
+                    self.consumed = self.r.offset;
+                    return Ok(true);
+                }
+                Ok(false) => {
+                    self.consumed = self.r.offset;
+                    return self.end_stream();
+                }
+            }
+            if !self.resync_stream()? {
+                return self.end_stream();
+            }
+        }
+    }
+
+    /// Returns false to signal the end of the stream, unless a digest
+    /// trailer was expected, in which case this returns an error.
+    fn end_stream(&self) -> io::Result<bool> {
+        if self.digest_pending {
+            return Err(io::Error::from(Error::MissingDigest));
+        }
+        Ok(false)
+    }
+
+    /// Checks the digest trailer in the first `len` bytes of `src` against
+    /// the data read since the last trailer. Trailers of a digest with
+    /// another name are ignored.
+    fn check_digest(&mut self, len: usize) -> io::Result<()> {
+        let body = &self.src[..len];
+        let digest = self.digest.as_mut().unwrap();
+        let n = digest.name().len();
+        if body.len() <= n
+            || body[0] as usize != n
+            || &body[1..1 + n] != digest.name().as_bytes()
+        {
+            return Ok(());
+        }
+        let got = digest.finish();
+        if body[1 + n..] != got[..] {
+            let expected = body[1 + n..].to_vec();
+            return Err(io::Error::from(Error::Digest { expected, got }));
+        }
+        self.digest_pending = false;
+        Ok(())
+    }
+
+    /// Decompresses the compressed data in the first `len` bytes of `src`
+    /// into `dst` and checks it against `expected_sum`, returning the length
+    /// of the decompressed data.
+    ///
+    /// If this fails, then the length that the data declares for itself is
+    /// returned with the error, if it's a valid length.
+    fn decompress_chunk(
+        &mut self,
+        expected_sum: u32,
+        len: usize,
+    ) -> Result<usize, (Error, Option<usize>)> {
+        let dn = self
+            .dec
+            .decompress_len(&self.src[0..len])
+            .map_err(|err| (err, None))?;
+        if dn > MAX_BLOCK_SIZE {
+            let err = Error::UnsupportedChunkLength {
+                len: dn as u64,
+                header: false,
+            };
+            return Err((err, None));
+        }
+        self.largest_chunk = cmp::max(self.largest_chunk, dn);
+        self.reallocations += grow(&mut self.dst, dn) as u64;
+        self.dec
+            .decompress(&self.src[0..len], &mut self.dst[0..dn])
+            .map_err(|err| (err, Some(dn)))?;
+        self.check_sum(expected_sum, dn).map_err(|err| (err, Some(dn)))?;
+        Ok(dn)
+    }
+
+    /// Checks the checksum of the first `len` bytes of `dst` against
+    /// `expected`, the one stored in the chunk that was just read.
+    ///
+    /// A mismatch is recorded in the checksum report, if there is one, and is
+    /// then only returned as an error if the error policy has to act on it.
+    fn check_sum(&mut self, expected: u32, len: usize) -> Result<(), Error> {
+        let got = self.checksummer.crc32c_masked(&self.dst[0..len]);
+        let report = match self.checksum_report {
+            None if expected != got => {
+                return Err(Error::Checksum { expected, got });
+            }
+            None => return Ok(()),
+            Some(ref mut report) => report,
+        };
+        report.chunks += 1;
+        if expected != got {
+            report.failures.push(ChecksumFailure {
+                offset: self.consumed,
+                len,
+                expected,
+                got,
+            });
+            if self.error_policy != ErrorPolicy::Fail {
+                return Err(Error::Checksum { expected, got });
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives the `len` bytes of data that were just decoded into `dst` back
+    /// to the caller, after undoing the current filter and adding them to
+    /// the digest. This always returns true, for `try_read_chunk` to return.
+    fn accept_chunk(&mut self, len: usize) -> bool {
+        if let Some(i) = self.filter {
+            self.filters[i].decode(&mut self.dst[0..len]);
+        }
+        if let Some(ref mut digest) = self.digest {
+            digest.update(&self.dst[0..len]);
+            self.digest_pending = true;
+        }
+        self.dsts = 0;
+        self.dste = len;
+        true
+    }
+
+    /// Applies the error policy to a data chunk that was read in full but
+    /// failed to decode with `err`, where `len` is the length its data
+    /// declares for itself, if that's known.
+    ///
+    /// This returns true if data standing in for the chunk's was put in
+    /// `dst`, and false if the chunk should be skipped.
+    fn recover_chunk(
+        &mut self,
+        err: Error,
+        len: Option<usize>,
+    ) -> io::Result<bool> {
+        match (self.error_policy, len) {
+            (ErrorPolicy::SkipChunk, _) => Ok(false),
+            (ErrorPolicy::ZeroFill, Some(len)) => {
+                self.reallocations += grow(&mut self.dst, len) as u64;
+                for b in &mut self.dst[0..len] {
+                    *b = 0;
+                }
+                self.dsts = 0;
+                self.dste = len;
+                Ok(true)
+            }
+            _ => Err(io::Error::from(err)),
+        }
+    }
+
+    /// Selects the filter named in the first `len` bytes of `src` for the
+    /// chunks that follow.
+    fn select_filter(&mut self, len: usize) -> io::Result<()> {
+        let name = &self.src[..len];
+        match self.filters.iter().position(|f| f.name().as_bytes() == name) {
+            Some(i) => {
+                self.filter = Some(i);
+                Ok(())
+            }
+            None => Err(io::Error::from(Error::UnknownFilter {
+                name: name.to_vec(),
+            })),
+        }
+    }
+
+    /// Like `read_chunk`, but returns an error on corrupt data regardless of
+    /// whether resynchronization is enabled.
+    fn try_read_chunk(&mut self) -> io::Result<bool> {
         macro_rules! fail {
             ($err:expr) => {
                 return Err(io::Error::from($err));
             };
         }
         loop {
-            if self.dsts < self.dste {
-                let len = cmp::min(self.dste - self.dsts, buf.len());
-                let dste = self.dsts.checked_add(len).unwrap();
-                buf[0..len].copy_from_slice(&self.dst[self.dsts..dste]);
-                self.dsts = dste;
-                return Ok(len);
-            }
+            self.consumed = self.r.offset;
+            self.r.recorded.clear();
+            self.reallocations += grow(&mut self.src, 4) as u64;
             if !read_exact_eof(&mut self.r, &mut self.src[0..4])? {
-                return Ok(0);
+                if self.strict && !self.read_stream_ident {
+                    fail!(Error::EmptyStream);
+                }
+                return Ok(false);
             }
             let ty = ChunkType::from_u8(self.src[0]);
             if !self.read_stream_ident {
@@ -123,13 +772,15 @@ impl<R: io::Read> io::Read for FrameDecoder<R> {
                 self.read_stream_ident = true;
             }
             let len64 = bytes::read_u24_le(&self.src[1..]) as u64;
-            if len64 > self.src.len() as u64 {
+            if len64 > MAX_COMPRESS_BLOCK_SIZE as u64 {
                 fail!(Error::UnsupportedChunkLength {
                     len: len64,
                     header: false,
                 });
             }
             let len = len64 as usize;
+            self.largest_chunk = cmp::max(self.largest_chunk, len);
+            self.reallocations += grow(&mut self.src, len) as u64;
             match ty {
                 Err(b) if 0x02 <= b && b <= 0x7F => {
                     // Spec says that chunk types 0x02-0x7F are reserved and
@@ -139,7 +790,18 @@ impl<R: io::Read> io::Read for FrameDecoder<R> {
                 Err(b) if 0x80 <= b && b <= 0xFD => {
                     // Spec says that chunk types 0x80-0xFD are reserved but
                     // skippable.
+                    let digest =
+                        b == DIGEST_CHUNK_TYPE && self.digest.is_some();
+                    let filter = b == FILTER_CHUNK_TYPE;
+                    if self.strict && !digest && !filter {
+                        fail!(Error::UnsupportedChunkType { byte: b });
+                    }
                     self.r.read_exact(&mut self.src[0..len])?;
+                    if digest {
+                        self.check_digest(len)?;
+                    } else if filter {
+                        self.select_filter(len)?;
+                    }
                 }
                 Err(b) => {
                     // Can never happen. 0x02-0x7F and 0x80-0xFD are handled
@@ -149,6 +811,11 @@ impl<R: io::Read> io::Read for FrameDecoder<R> {
                     unreachable!("BUG: unhandled chunk type: {}", b);
                 }
                 Ok(ChunkType::Padding) => {
+                    if self.strict {
+                        fail!(Error::UnsupportedChunkType {
+                            byte: self.src[0]
+                        });
+                    }
                     // Just read and move on.
                     self.r.read_exact(&mut self.src[0..len])?;
                 }
@@ -165,67 +832,198 @@ impl<R: io::Read> io::Read for FrameDecoder<R> {
                             bytes: self.src[0..len].to_vec(),
                         });
                     }
+                    self.filter = None;
+                }
+                Ok(ChunkType::Uncompressed) | Ok(ChunkType::Compressed)
+                    if len < 4 =>
+                {
+                    // Too short to hold even the checksum.
+                    fail!(Error::UnsupportedChunkLength {
+                        len: len64,
+                        header: false,
+                    });
                 }
                 Ok(ChunkType::Uncompressed) => {
                     let expected_sum = bytes::io_read_u32_le(&mut self.r)?;
                     let n = len - 4;
-                    if n > self.dst.len() {
+                    if n > MAX_BLOCK_SIZE {
                         fail!(Error::UnsupportedChunkLength {
                             len: n as u64,
                             header: false,
                         });
                     }
+                    self.reallocations += grow(&mut self.dst, n) as u64;
                     self.r.read_exact(&mut self.dst[0..n])?;
-                    let got_sum =
-                        self.checksummer.crc32c_masked(&self.dst[0..n]);
-                    if expected_sum != got_sum {
-                        fail!(Error::Checksum {
-                            expected: expected_sum,
-                            got: got_sum,
-                        });
+                    match self.check_sum(expected_sum, n) {
+                        Ok(()) => return Ok(self.accept_chunk(n)),
+                        Err(err) => {
+                            if self.recover_chunk(err, Some(n))? {
+                                return Ok(true);
+                            }
+                        }
                     }
-                    self.dsts = 0;
-                    self.dste = n;
                 }
                 Ok(ChunkType::Compressed) => {
                     let expected_sum = bytes::io_read_u32_le(&mut self.r)?;
                     let sn = len - 4;
-                    if sn > self.src.len() {
+                    if sn > MAX_COMPRESS_BLOCK_SIZE {
                         fail!(Error::UnsupportedChunkLength {
                             len: len64,
                             header: false,
                         });
                     }
                     self.r.read_exact(&mut self.src[0..sn])?;
-                    let dn = decompress_len(&self.src)?;
-                    if dn > self.dst.len() {
-                        fail!(Error::UnsupportedChunkLength {
-                            len: dn as u64,
-                            header: false,
-                        });
+                    match self.decompress_chunk(expected_sum, sn) {
+                        Ok(dn) => return Ok(self.accept_chunk(dn)),
+                        Err((err, dn)) => {
+                            if self.recover_chunk(err, dn)? {
+                                return Ok(true);
+                            }
+                        }
                     }
-                    self.dec
-                        .decompress(&self.src[0..sn], &mut self.dst[0..dn])?;
-                    let got_sum =
-                        self.checksummer.crc32c_masked(&self.dst[0..dn]);
-                    if expected_sum != got_sum {
-                        fail!(Error::Checksum {
-                            expected: expected_sum,
-                            got: got_sum,
-                        });
-                    }
-                    self.dsts = 0;
-                    self.dste = dn;
                 }
             }
         }
     }
+
+    /// Scans forward for the next stream identifier, starting just after the
+    /// first byte of the chunk that was being read. If a stream identifier is
+    /// found, then it is consumed and true is returned. Otherwise, the
+    /// underlying reader is exhausted and false is returned.
+    fn resync_stream(&mut self) -> io::Result<bool> {
+        let mut recorded = mem::replace(&mut self.r.recorded, vec![]);
+        if !recorded.is_empty() {
+            self.r.unread(&recorded[1..]);
+        }
+        // The first byte of the stream identifier never appears anywhere
+        // else in it, so when a byte doesn't match, the only possible new
+        // match starts at that byte.
+        self.r.record = false;
+        let mut matched = 0;
+        let mut byte = [0];
+        while matched < STREAM_IDENTIFIER.len() {
+            if !read_exact_eof(&mut self.r, &mut byte)? {
+                break;
+            }
+            if byte[0] == STREAM_IDENTIFIER[matched] {
+                matched += 1;
+            } else if byte[0] == STREAM_IDENTIFIER[0] {
+                matched = 1;
+            } else {
+                matched = 0;
+            }
+        }
+        self.r.record = true;
+        recorded.clear();
+        self.r.recorded = recorded;
+        self.read_stream_ident = matched == STREAM_IDENTIFIER.len();
+        self.filter = None;
+        self.consumed = self.r.offset;
+        Ok(self.read_stream_ident)
+    }
 }
 
-impl<R: fmt::Debug + io::Read> fmt::Debug for FrameDecoder<R> {
+/// Resizes `buf` so that it has at least `len` bytes, returning true if it
+/// had to grow.
+#[cfg(feature = "decompress")]
+fn grow(buf: &mut Vec<u8>, len: usize) -> bool {
+    if buf.len() < len {
+        buf.resize(len, 0);
+        return true;
+    }
+    false
+}
+
+/// Returns true if the given error was caused by corrupt data, either because
+/// it is a Snappy error or because the stream ended in the middle of a chunk.
+#[cfg(feature = "decompress")]
+fn is_corrupt(err: &io::Error) -> bool {
+    // A digest error is about the stream as a whole rather than the chunk
+    // being read, so skipping that chunk can't recover from it. Neither can
+    // skipping the name of a filter, since the chunks after it would be
+    // returned filtered.
+    let is_chunk_error = |err: &Error| match *err {
+        Error::Digest { .. }
+        | Error::MissingDigest
+        | Error::UnknownFilter { .. } => false,
+        _ => true,
+    };
+    err.kind() == io::ErrorKind::UnexpectedEof
+        || err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<Error>())
+            .map_or(false, is_chunk_error)
+}
+
+/// A reader that permits pushing bytes back onto the front of it, and that
+/// can record the bytes read from it. `FrameDecoder` uses this to rescan the
+/// bytes of a corrupt chunk when resynchronizing.
+#[cfg(feature = "decompress")]
+struct Rewind<R> {
+    /// The underlying reader.
+    r: R,
+    /// Bytes that were pushed back. These are read before any more bytes are
+    /// read from `r`.
+    unread: Vec<u8>,
+    /// Index into unread: starting point of bytes not yet read.
+    pos: usize,
+    /// When true, every byte read is appended to `recorded`.
+    record: bool,
+    /// The bytes read since `recorded` was last cleared.
+    recorded: Vec<u8>,
+    /// The number of bytes read, less the number of bytes pushed back.
+    offset: u64,
+}
+
+#[cfg(feature = "decompress")]
+impl<R> Rewind<R> {
+    fn new(rdr: R) -> Rewind<R> {
+        Rewind {
+            r: rdr,
+            unread: vec![],
+            pos: 0,
+            record: false,
+            recorded: vec![],
+            offset: 0,
+        }
+    }
+
+    /// Pushes the given bytes back onto the front of this reader.
+    fn unread(&mut self, bytes: &[u8]) {
+        let mut unread = bytes.to_vec();
+        unread.extend_from_slice(&self.unread[self.pos..]);
+        self.unread = unread;
+        self.pos = 0;
+        self.offset -= bytes.len() as u64;
+    }
+}
+
+#[cfg(feature = "decompress")]
+impl<R: io::Read> io::Read for Rewind<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = if self.pos < self.unread.len() {
+            let n = cmp::min(self.unread.len() - self.pos, buf.len());
+            buf[..n].copy_from_slice(&self.unread[self.pos..self.pos + n]);
+            self.pos += n;
+            n
+        } else {
+            self.r.read(buf)?
+        };
+        if self.record {
+            self.recorded.extend_from_slice(&buf[..n]);
+        }
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "decompress")]
+impl<R: fmt::Debug + io::Read, C: fmt::Debug + Codec> fmt::Debug
+    for FrameDecoder<R, C>
+{
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("FrameDecoder")
-            .field("r", &self.r)
+            .field("r", &self.r.r)
             .field("dec", &self.dec)
             .field("checksummer", &self.checksummer)
             .field("src", &"[...]")
@@ -233,10 +1031,144 @@ impl<R: fmt::Debug + io::Read> fmt::Debug for FrameDecoder<R> {
             .field("dsts", &self.dsts)
             .field("dste", &self.dste)
             .field("read_stream_ident", &self.read_stream_ident)
+            .field("resync", &self.resync)
+            .field("trailing_garbage", &self.trailing_garbage)
+            .field("strict", &self.strict)
+            .field("garbage", &self.garbage)
+            .field("consumed", &self.consumed)
+            .field("digest", &self.digest.as_ref().map(|d| d.name()))
+            .field("digest_pending", &self.digest_pending)
+            .field(
+                "filters",
+                &self.filters.iter().map(|f| f.name()).collect::<Vec<_>>(),
+            )
+            .field("filter", &self.filter)
+            .field("checksum_report", &self.checksum_report)
+            .field("error_policy", &self.error_policy)
+            .field("largest_chunk", &self.largest_chunk)
+            .field("reallocations", &self.reallocations)
             .finish()
     }
 }
 
+/// What a [`FrameDecoder`](struct.FrameDecoder.html#method.error_policy)
+/// does with a data chunk that was read in full but can't be decoded.
+#[cfg(feature = "decompress")]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorPolicy {
+    /// Return an error. This is the default.
+    Fail,
+    /// Drop the chunk and carry on with the next one, as if the chunk wasn't
+    /// there.
+    SkipChunk,
+    /// Return zeros in place of the chunk's data, as many as the chunk
+    /// declares its data to be, so that the data after it stays at the same
+    /// offsets. This suits consumers that expect records at fixed offsets.
+    ///
+    /// A chunk whose declared length is unreadable or invalid can't be
+    /// replaced, and results in an error as with `Fail`.
+    ZeroFill,
+}
+
+/// A report of the data chunks whose checksums didn't match their data, as
+/// collected by a
+/// [`FrameDecoder`](struct.FrameDecoder.html#method.collect_checksum_errors).
+#[cfg(feature = "decompress")]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ChecksumReport {
+    chunks: u64,
+    failures: Vec<ChecksumFailure>,
+}
+
+#[cfg(feature = "decompress")]
+impl ChecksumReport {
+    /// Returns true if every data chunk checked had a matching checksum.
+    pub fn is_clean(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Returns the number of data chunks checked.
+    pub fn chunks(&self) -> u64 {
+        self.chunks
+    }
+
+    /// Returns the number of data chunks whose checksum didn't match.
+    pub fn failed_chunks(&self) -> u64 {
+        self.failures.len() as u64
+    }
+
+    /// Returns the chunks whose checksum didn't match, in stream order.
+    pub fn failures(&self) -> &[ChecksumFailure] {
+        &self.failures
+    }
+
+    /// Returns the number of decompressed bytes in the chunks whose checksum
+    /// didn't match.
+    pub fn failed_len(&self) -> u64 {
+        self.failures.iter().map(|f| f.len as u64).sum()
+    }
+}
+
+/// A data chunk whose checksum didn't match its data.
+#[cfg(feature = "decompress")]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChecksumFailure {
+    offset: u64,
+    len: usize,
+    expected: u32,
+    got: u32,
+}
+
+#[cfg(feature = "decompress")]
+impl ChecksumFailure {
+    /// Returns the offset of the chunk from the start of the underlying
+    /// reader.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the number of bytes that the chunk decompressed to.
+    pub fn decompressed_len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the masked checksum stored in the chunk.
+    pub fn expected(&self) -> u32 {
+        self.expected
+    }
+
+    /// Returns the masked checksum of the data that the chunk decompressed
+    /// to.
+    pub fn got(&self) -> u32 {
+        self.got
+    }
+}
+
+/// An iterator over the decompressed chunks of a Snappy frame formatted
+/// stream.
+///
+/// This iterator is created by
+/// [`FrameDecoder::chunks`](struct.FrameDecoder.html#method.chunks).
+#[cfg(feature = "decompress")]
+#[derive(Debug)]
+pub struct Chunks<'a, R: io::Read, C: Codec = SnappyCodec> {
+    dec: &'a mut FrameDecoder<R, C>,
+}
+
+#[cfg(feature = "decompress")]
+impl<'a, R: io::Read, C: Codec> Iterator for Chunks<'a, R, C> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        let mut chunk = vec![];
+        match self.dec.read_message(&mut chunk) {
+            Ok(true) => Some(Ok(chunk)),
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
 /// A reader for compressing data using snappy as it is read.
 ///
 /// This `FrameEncoder` wraps any other reader that implements `std::io::Read`.
@@ -253,11 +1185,16 @@ impl<R: fmt::Debug + io::Read> fmt::Debug for FrameDecoder<R> {
 /// Unlike `FrameDecoder`, this will attempt to make large reads roughly
 /// equivalent to the size of a single Snappy block. Therefore, callers may not
 /// benefit from using a buffered reader.
-pub struct FrameEncoder<R: io::Read> {
+///
+/// The type parameter `C` is the codec that compresses each chunk, which is
+/// Snappy unless another one is given to
+/// [`with_codec`](#method.with_codec).
+#[cfg(feature = "compress")]
+pub struct FrameEncoder<R: io::Read, C: Codec = SnappyCodec> {
     /// Internally, we split `FrameEncoder` in two to keep the borrow checker
     /// happy. The `inner` member contains everything that `read_frame` needs
     /// to fetch a frame's worth of data and compress it.
-    inner: Inner<R>,
+    inner: Inner<R, C>,
     /// Data that we've encoded and are ready to return to our caller.
     dst: Vec<u8>,
     /// Starting point of bytes in `dst` not yet given back to the caller.
@@ -266,31 +1203,71 @@ pub struct FrameEncoder<R: io::Read> {
     dste: usize,
 }
 
-struct Inner<R: io::Read> {
+#[cfg(feature = "compress")]
+struct Inner<R: io::Read, C: Codec> {
     /// The underlying data source.
     r: R,
-    /// An encoder that we reuse that does the actual block based compression.
-    enc: Encoder,
+    /// A codec that we reuse that does the actual block based compression.
+    enc: C,
     /// A CRC32 checksummer that is configured to either use the portable
     /// fallback version or the SSE4.2 accelerated version when the right CPU
     /// features are available.
     checksummer: CheckSummer,
-    /// Data taken from the underlying `r`, and not yet compressed.
+    /// Data taken from the underlying `r`, and not yet compressed. Its length
+    /// is the maximum number of bytes in a block.
     src: Vec<u8>,
     /// Have we written the standard snappy header to `dst` yet?
     wrote_stream_ident: bool,
+    /// How to decide whether to write each block compressed.
+    policy: ChunkPolicy,
+    /// Whether to check that every chunk decompresses to its block.
+    verify: bool,
+    /// Space for decompressing chunks into when verifying them.
+    verify_buf: Vec<u8>,
 }
 
+#[cfg(feature = "compress")]
 impl<R: io::Read> FrameEncoder<R> {
     /// Create a new reader for streaming Snappy compression.
     pub fn new(rdr: R) -> FrameEncoder<R> {
+        FrameEncoder::with_options(rdr, &EncoderOptions::new())
+    }
+
+    /// Create a new reader for streaming Snappy compression with the given
+    /// options.
+    pub fn with_options(rdr: R, options: &EncoderOptions) -> FrameEncoder<R> {
+        let codec = SnappyCodec::with_builder(&options.encoder);
+        FrameEncoder::with_codec_options(rdr, codec, options)
+    }
+}
+
+#[cfg(feature = "compress")]
+impl<R: io::Read, C: Codec> FrameEncoder<R, C> {
+    /// Create a new reader for streaming compression that compresses each
+    /// chunk with `codec`.
+    ///
+    /// See [`frame::Codec`](../frame/trait.Codec.html) for more details.
+    pub fn with_codec(rdr: R, codec: C) -> FrameEncoder<R, C> {
+        FrameEncoder::with_codec_options(rdr, codec, &EncoderOptions::new())
+    }
+
+    /// Like `with_codec`, but with the given options. The encoder
+    /// configuration in `options` is ignored, since `codec` compresses.
+    pub fn with_codec_options(
+        rdr: R,
+        codec: C,
+        options: &EncoderOptions,
+    ) -> FrameEncoder<R, C> {
         FrameEncoder {
             inner: Inner {
                 r: rdr,
-                enc: Encoder::new(),
+                enc: codec,
                 checksummer: CheckSummer::new(),
-                src: vec![0; MAX_BLOCK_SIZE],
+                src: vec![0; options.block_size],
                 wrote_stream_ident: false,
+                policy: options.chunk_policy(),
+                verify: false,
+                verify_buf: vec![],
             },
             dst: vec![0; MAX_READ_FRAME_ENCODER_BLOCK_SIZE],
             dsts: 0,
@@ -311,6 +1288,64 @@ impl<R: io::Read> FrameEncoder<R> {
         &mut self.inner.r
     }
 
+    /// Returns the number of bytes held by this encoder's hash tables and
+    /// buffers, not including the underlying reader.
+    pub fn memory_usage(&self) -> usize {
+        self.inner.enc.memory_usage()
+            + self.inner.src.capacity()
+            + self.inner.verify_buf.capacity()
+            + self.dst.capacity()
+    }
+
+    /// When enabled, this encoder checks whether each block looks like
+    /// random or already compressed data before compressing it, and if so,
+    /// emits it uncompressed without attempting compression at all.
+    ///
+    /// See
+    /// [`write::FrameEncoder::skip_incompressible`](../write/struct.FrameEncoder.html#method.skip_incompressible)
+    /// for more details.
+    ///
+    /// This is disabled by default.
+    pub fn skip_incompressible(
+        &mut self,
+        yes: bool,
+    ) -> &mut FrameEncoder<R, C> {
+        self.inner.policy.skip_incompressible = yes;
+        self
+    }
+
+    /// Set the minimum percentage by which compressing a block must shrink it
+    /// for the block to be emitted compressed.
+    ///
+    /// See
+    /// [`write::FrameEncoder::min_savings`](../write/struct.FrameEncoder.html#method.min_savings)
+    /// for more details.
+    ///
+    /// This is set to `None` by default, which keeps blocks that compress by
+    /// at least 12.5%.
+    pub fn min_savings(
+        &mut self,
+        percent: Option<u8>,
+    ) -> &mut FrameEncoder<R, C> {
+        self.inner.policy.min_savings = percent.map(|p| cmp::min(p, 100));
+        self
+    }
+
+    /// When enabled, this encoder decompresses every chunk right after
+    /// compressing it, and returns `Error::Verify` if the result differs from
+    /// the bytes that were compressed.
+    ///
+    /// See
+    /// [`write::FrameEncoder::verify`](../write/struct.FrameEncoder.html#method.verify)
+    /// for more details.
+    ///
+    /// This is disabled by default.
+    #[cfg(feature = "decompress")]
+    pub fn verify(&mut self, yes: bool) -> &mut FrameEncoder<R, C> {
+        self.inner.verify = yes;
+        self
+    }
+
     /// Read previously compressed data from `self.dst`, returning the number of
     /// bytes read. If `self.dst` is empty, returns 0.
     fn read_from_dst(&mut self, buf: &mut [u8]) -> usize {
@@ -322,7 +1357,8 @@ impl<R: io::Read> FrameEncoder<R> {
     }
 }
 
-impl<R: io::Read> io::Read for FrameEncoder<R> {
+#[cfg(feature = "compress")]
+impl<R: io::Read, C: Codec> io::Read for FrameEncoder<R, C> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         // Try reading previously compressed bytes from our `dst` buffer, if
         // any.
@@ -346,7 +1382,8 @@ impl<R: io::Read> io::Read for FrameEncoder<R> {
     }
 }
 
-impl<R: io::Read> Inner<R> {
+#[cfg(feature = "compress")]
+impl<R: io::Read, C: Codec> Inner<R, C> {
     /// Read from `self.r`, and create a new frame, writing it to `dst`, which
     /// must be at least `MAX_READ_FRAME_ENCODER_BLOCK_SIZE` bytes in size.
     fn read_frame(&mut self, dst: &mut [u8]) -> io::Result<usize> {
@@ -359,7 +1396,16 @@ impl<R: io::Read> Inner<R> {
         // more surprising. In general, io::Read implementations should try to
         // fill the caller's buffer as much as they can, so this seems like the
         // better choice.
-        let nread = self.r.read(&mut self.src)?;
+        //
+        // Interrupted reads are retried so that callers don't need their own
+        // retry loops.
+        let nread = loop {
+            match self.r.read(&mut self.src) {
+                Ok(nread) => break nread,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        };
         if nread == 0 {
             return Ok(0);
         }
@@ -388,12 +1434,25 @@ impl<R: io::Read> Inner<R> {
             chunk_header,
             remaining_dst,
             true,
+            self.policy,
         )?;
+        if self.verify {
+            verify_frame(
+                &mut self.enc,
+                chunk_header,
+                frame_data,
+                &self.src[..nread],
+                &mut self.verify_buf,
+            )?;
+        }
         Ok(dst_write_start + frame_data.len())
     }
 }
 
-impl<R: fmt::Debug + io::Read> fmt::Debug for FrameEncoder<R> {
+#[cfg(feature = "compress")]
+impl<R: fmt::Debug + io::Read, C: fmt::Debug + Codec> fmt::Debug
+    for FrameEncoder<R, C>
+{
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("FrameEncoder")
             .field("inner", &self.inner)
@@ -404,7 +1463,10 @@ impl<R: fmt::Debug + io::Read> fmt::Debug for FrameEncoder<R> {
     }
 }
 
-impl<R: fmt::Debug + io::Read> fmt::Debug for Inner<R> {
+#[cfg(feature = "compress")]
+impl<R: fmt::Debug + io::Read, C: fmt::Debug + Codec> fmt::Debug
+    for Inner<R, C>
+{
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Inner")
             .field("r", &self.r)
@@ -412,22 +1474,39 @@ impl<R: fmt::Debug + io::Read> fmt::Debug for Inner<R> {
             .field("checksummer", &self.checksummer)
             .field("src", &"[...]")
             .field("wrote_stream_ident", &self.wrote_stream_ident)
+            .field("policy", &self.policy)
+            .field("verify", &self.verify)
+            .field("verify_buf", &"[...]")
             .finish()
     }
 }
 
-// read_exact_eof is like Read::read_exact, except it converts an UnexpectedEof
-// error to a bool of false.
+#[cfg(feature = "decompress")]
+// read_exact_eof is like Read::read_exact, except it returns false if the
+// reader is at EOF before any bytes are read.
 //
-// If no error occurred, then this returns true.
+// If `buf` was filled, then this returns true. A reader that ends after some
+// but not all of `buf` was read still results in an UnexpectedEof error, since
+// that isn't a clean end of the stream.
 fn read_exact_eof<R: io::Read>(
     rdr: &mut R,
     buf: &mut [u8],
 ) -> io::Result<bool> {
-    use std::io::ErrorKind::UnexpectedEof;
-    match rdr.read_exact(buf) {
-        Ok(()) => Ok(true),
-        Err(ref err) if err.kind() == UnexpectedEof => Ok(false),
-        Err(err) => Err(err),
+    use std::io::ErrorKind::{Interrupted, UnexpectedEof};
+    let mut n = 0;
+    while n < buf.len() {
+        match rdr.read(&mut buf[n..]) {
+            Ok(0) if n == 0 => return Ok(false),
+            Ok(0) => {
+                return Err(io::Error::new(
+                    UnexpectedEof,
+                    "stream ends in the middle of a chunk header",
+                ))
+            }
+            Ok(m) => n += m,
+            Err(ref err) if err.kind() == Interrupted => {}
+            Err(err) => return Err(err),
+        }
     }
+    Ok(true)
 }
@@ -0,0 +1,303 @@
+use std::prelude::v1::*;
+use std::io;
+use std::ops::Range;
+
+use crate::bytes;
+use crate::crc32::CheckSummer;
+use crate::decompress::{decompress_len, Decoder};
+use crate::error::Error;
+use crate::frame::{
+    ChunkType, FILTER_CHUNK_TYPE, MAX_COMPRESS_BLOCK_SIZE, STREAM_BODY,
+};
+use crate::MAX_BLOCK_SIZE;
+
+/// The policy used by [`recover`](fn.recover.html) to decide which chunks are
+/// kept.
+///
+/// Regardless of policy, `recover` only resumes after a corrupt region at a
+/// stream identifier or at a data chunk whose checksum matches its data.
+/// Anything weaker would make it far too easy to mistake garbage for a chunk.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecoverPolicy {
+    /// Only keep data chunks whose checksum matches their data. Any other
+    /// data chunk is treated as the start of a corrupt region.
+    VerifyChecksums,
+    /// Keep data chunks that decompress successfully even if their checksum
+    /// does not match, as long as they immediately follow a chunk that was
+    /// kept.
+    IgnoreChecksums,
+}
+
+/// A report describing what happened during a call to
+/// [`recover`](fn.recover.html).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RecoverReport {
+    chunks: u64,
+    bytes_written: u64,
+    skipped: Vec<Range<u64>>,
+}
+
+impl RecoverReport {
+    /// Returns the total number of data chunks that were recovered.
+    pub fn chunks(&self) -> u64 {
+        self.chunks
+    }
+
+    /// Returns the total number of decompressed bytes written to the sink.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Returns the ranges of compressed input, as offsets from the start of
+    /// the reader, that were skipped because they could not be decoded.
+    pub fn skipped(&self) -> &[Range<u64>] {
+        &self.skipped
+    }
+
+    /// Returns the total number of compressed input bytes that were skipped.
+    pub fn bytes_lost(&self) -> u64 {
+        self.skipped.iter().map(|r| r.end - r.start).sum()
+    }
+}
+
+/// Decompresses as much as possible of a corrupt Snappy frame formatted
+/// stream from `rdr`, writing the decompressed bytes to `sink`.
+///
+/// Whenever a chunk cannot be decoded, this scans forward one byte at a time
+/// until it finds the next stream identifier or data chunk whose checksum
+/// matches its data, and continues decoding from there. The regions that were
+/// skipped over are recorded in the report returned.
+///
+/// Since the data in skipped regions is lost, the bytes written to `sink` are
+/// generally not the same as the original uncompressed data. Callers should
+/// inspect the report to determine how much data was lost.
+///
+/// Note that in order to scan for the next chunk, up to one chunk's worth of
+/// input is buffered in memory.
+///
+/// # Errors
+///
+/// This returns an error if reading from `rdr` or writing to `sink` fails,
+/// or `Error::UnknownFilter` if the stream names a
+/// [filter](trait.ChunkFilter.html), since the data it recovers would still
+/// be filtered. Corrupt data is never reported as an error.
+pub fn recover<R: io::Read, W: io::Write>(
+    rdr: R,
+    mut sink: W,
+    policy: RecoverPolicy,
+) -> io::Result<RecoverReport> {
+    let mut scanner = Scanner::new(rdr);
+    let mut dec = Decoder::new();
+    let mut dst = vec![0; MAX_BLOCK_SIZE];
+    let mut report = RecoverReport::default();
+    // The start of the corrupt region we are currently scanning through, if
+    // any. A stream must start with a stream identifier, so we start out of
+    // sync until we see one.
+    let mut gap_start: Option<u64> = Some(0);
+    loop {
+        if scanner.fill(1)? == 0 {
+            break;
+        }
+        let in_sync = gap_start.is_none();
+        let decoded = match scanner.fill_chunk(in_sync)? {
+            None => None,
+            // The chunks after a filter chunk hold filtered data, which
+            // there's no filter here to undo.
+            Some(chunk) if chunk[0] == FILTER_CHUNK_TYPE => {
+                return Err(io::Error::from(Error::UnknownFilter {
+                    name: chunk[4..].to_vec(),
+                }));
+            }
+            Some(chunk) => {
+                decode_chunk(chunk, in_sync, policy, &mut dec, &mut dst)
+            }
+        };
+        match decoded {
+            None => {
+                if gap_start.is_none() {
+                    gap_start = Some(scanner.offset());
+                }
+                scanner.consume(1);
+            }
+            Some(Decoded { len, data_len }) => {
+                if let Some(start) = gap_start.take() {
+                    if start < scanner.offset() {
+                        report.skipped.push(start..scanner.offset());
+                    }
+                }
+                if let Some(n) = data_len {
+                    sink.write_all(&dst[..n])?;
+                    report.chunks += 1;
+                    report.bytes_written += n as u64;
+                }
+                scanner.consume(len);
+            }
+        }
+    }
+    if let Some(start) = gap_start {
+        if start < scanner.offset() {
+            report.skipped.push(start..scanner.offset());
+        }
+    }
+    Ok(report)
+}
+
+/// A chunk that was successfully decoded.
+struct Decoded {
+    /// The total length of the chunk, including its header.
+    len: usize,
+    /// The number of decompressed bytes written to `dst`, if this was a data
+    /// chunk.
+    data_len: Option<usize>,
+}
+
+/// Attempts to decode the complete chunk at the beginning of `chunk`, whose
+/// header has already been deemed plausible. If the chunk should not be kept,
+/// then `None` is returned.
+///
+/// `in_sync` indicates whether the previous chunk was kept.
+fn decode_chunk(
+    chunk: &[u8],
+    in_sync: bool,
+    policy: RecoverPolicy,
+    dec: &mut Decoder,
+    dst: &mut [u8],
+) -> Option<Decoded> {
+    let body = &chunk[4..];
+    let len = chunk.len();
+    match ChunkType::from_u8(chunk[0]) {
+        Ok(ChunkType::Stream) => {
+            if body != STREAM_BODY {
+                return None;
+            }
+            Some(Decoded { len, data_len: None })
+        }
+        Ok(ChunkType::Uncompressed) => {
+            if body.len() < 4 {
+                return None;
+            }
+            let n = body.len() - 4;
+            dst[..n].copy_from_slice(&body[4..]);
+            check_sum(bytes::read_u32_le(body), &dst[..n], in_sync, policy)?;
+            Some(Decoded { len, data_len: Some(n) })
+        }
+        Ok(ChunkType::Compressed) => {
+            if body.len() < 4 {
+                return None;
+            }
+            let data = &body[4..];
+            let n = decompress_len(data).ok()?;
+            if n > dst.len() {
+                return None;
+            }
+            dec.decompress(data, &mut dst[..n]).ok()?;
+            check_sum(bytes::read_u32_le(body), &dst[..n], in_sync, policy)?;
+            Some(Decoded { len, data_len: Some(n) })
+        }
+        // Padding and skippable chunks, which are only plausible when we're
+        // in sync.
+        _ => Some(Decoded { len, data_len: None }),
+    }
+}
+
+/// Returns `Some(())` if a data chunk with the given expected checksum and
+/// decompressed data should be kept according to the given policy.
+fn check_sum(
+    expected_sum: u32,
+    data: &[u8],
+    in_sync: bool,
+    policy: RecoverPolicy,
+) -> Option<()> {
+    if CheckSummer::new().crc32c_masked(data) == expected_sum {
+        return Some(());
+    }
+    match policy {
+        RecoverPolicy::IgnoreChecksums if in_sync => Some(()),
+        _ => None,
+    }
+}
+
+/// A buffered reader that permits looking ahead by a full chunk, while
+/// tracking the offset of the current position in the underlying reader.
+struct Scanner<R> {
+    rdr: R,
+    buf: Vec<u8>,
+    /// The position in `buf` corresponding to the current position.
+    pos: usize,
+    /// The offset of `buf[0]` in the underlying reader.
+    base: u64,
+    /// Whether the underlying reader has been exhausted.
+    eof: bool,
+}
+
+impl<R: io::Read> Scanner<R> {
+    fn new(rdr: R) -> Scanner<R> {
+        Scanner { rdr, buf: vec![], pos: 0, base: 0, eof: false }
+    }
+
+    /// Returns the offset of the current position in the underlying reader.
+    fn offset(&self) -> u64 {
+        self.base + self.pos as u64
+    }
+
+    /// Advances the current position by `n` bytes. There must be at least
+    /// `n` bytes buffered.
+    fn consume(&mut self, n: usize) {
+        assert!(self.pos + n <= self.buf.len());
+        self.pos += n;
+    }
+
+    /// Tries to buffer at least `n` bytes after the current position, and
+    /// returns the number of bytes buffered. Fewer than `n` bytes are only
+    /// buffered if the underlying reader is exhausted.
+    fn fill(&mut self, n: usize) -> io::Result<usize> {
+        if self.eof || self.buf.len() - self.pos >= n {
+            return Ok(self.buf.len() - self.pos);
+        }
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.base += self.pos as u64;
+            self.pos = 0;
+        }
+        let mut chunk = [0; 8 * (1 << 10)];
+        while !self.eof && self.buf.len() < n {
+            match self.rdr.read(&mut chunk) {
+                Ok(0) => self.eof = true,
+                Ok(m) => self.buf.extend_from_slice(&chunk[..m]),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(self.buf.len())
+    }
+
+    /// Buffers the complete chunk at the current position and returns it,
+    /// including its header.
+    ///
+    /// If the chunk's header is not plausible, or if the underlying reader is
+    /// exhausted before the complete chunk could be buffered, then `None` is
+    /// returned. `in_sync` indicates whether the previous chunk was kept,
+    /// which determines which chunk types are plausible.
+    fn fill_chunk(&mut self, in_sync: bool) -> io::Result<Option<&[u8]>> {
+        if self.fill(4)? < 4 {
+            return Ok(None);
+        }
+        let max_len = match ChunkType::from_u8(self.buf[self.pos]) {
+            Ok(ChunkType::Stream) => STREAM_BODY.len(),
+            Ok(ChunkType::Compressed) => 4 + MAX_COMPRESS_BLOCK_SIZE,
+            Ok(ChunkType::Uncompressed) => 4 + MAX_BLOCK_SIZE,
+            Ok(ChunkType::Padding) if in_sync => 0xFFFFFF,
+            Err(b) if in_sync && (0x80..=0xFD).contains(&b) => 0xFFFFFF,
+            _ => return Ok(None),
+        };
+        let len = bytes::read_u24_le(&self.buf[self.pos + 1..]) as usize;
+        if len > max_len {
+            return Ok(None);
+        }
+        let len = 4 + len;
+        if self.fill(len)? < len {
+            return Ok(None);
+        }
+        Ok(Some(&self.buf[self.pos..self.pos + len]))
+    }
+}
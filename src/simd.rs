@@ -0,0 +1,40 @@
+/*!
+Kernels written with portable SIMD (`core::simd`), which the compiler lowers
+to the vector instructions of whatever target it builds for.
+
+These are only used with the `nightly-simd` feature, since `core::simd` is
+unstable. They replace the copy and match-compare loops whose scalar versions
+rely on LLVM to pick vector registers, so they also serve as a reference for
+when `core::simd` is stabilized.
+*/
+
+use core::ptr;
+use core::simd::cmp::SimdPartialEq;
+use core::simd::u8x16;
+
+/// Copies the 16 bytes at `src` to `dst` through a single vector register.
+///
+/// All 16 bytes are loaded before any are stored, so `src` and `dst` may
+/// overlap.
+///
+/// The caller must guarantee that `src` is valid for reading 16 bytes and
+/// that `dst` is valid for writing 16 bytes. Neither needs to be aligned.
+#[inline(always)]
+pub unsafe fn copy16(src: *const u8, dst: *mut u8) {
+    let v = ptr::read_unaligned(src as *const u8x16);
+    ptr::write_unaligned(dst as *mut u8x16, v);
+}
+
+/// Returns the number of bytes at the start of the 16 bytes at `a` that are
+/// equal to those at `b`. This is `16` when all of them are equal.
+///
+/// The caller must guarantee that both `a` and `b` are valid for reading 16
+/// bytes. Neither needs to be aligned.
+#[inline(always)]
+pub unsafe fn common_prefix16(a: *const u8, b: *const u8) -> usize {
+    let x = ptr::read_unaligned(a as *const u8x16);
+    let y = ptr::read_unaligned(b as *const u8x16);
+    // Setting bit 16 caps the count at 16 when every lane is equal.
+    let ne = x.simd_ne(y).to_bitmask() | (1 << 16);
+    ne.trailing_zeros() as usize
+}
@@ -0,0 +1,186 @@
+use std::cmp;
+
+/// The number of buckets in each histogram of
+/// [`EncoderStats`](struct.EncoderStats.html). The last one holds copies of
+/// 65536 bytes, the longest a block allows.
+const HISTOGRAM_LEN: usize = 17;
+
+/// Counts of what an [`Encoder`](struct.Encoder.html) found while
+/// compressing, collected when
+/// [`EncoderBuilder::collect_stats`](struct.EncoderBuilder.html#method.collect_stats)
+/// is enabled.
+///
+/// These show where the output of a block goes: how much of the input was
+/// copied from earlier in the block rather than written out as literals, how
+/// long and how far back those copies are, and how often a lookup in the
+/// encoder's hash table came up empty. They're meant to guide the choice of
+/// settings such as
+/// [`max_table_size`](struct.EncoderBuilder.html#method.max_table_size)
+/// and [`search_depth`](struct.EncoderBuilder.html#method.search_depth) for
+/// a particular kind of data.
+///
+/// A copy here is one match found by the encoder, even when it's longer than
+/// a single copy operation of the Snappy format can encode and so takes
+/// several of them in the output.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EncoderStats {
+    blocks: u64,
+    input_len: u64,
+    output_len: u64,
+    literals: u64,
+    literal_len: u64,
+    copies: u64,
+    copy_len: u64,
+    copy_lengths: [u64; HISTOGRAM_LEN],
+    copy_offsets: [u64; HISTOGRAM_LEN],
+    hash_probes: u64,
+    hash_hits: u64,
+}
+
+impl EncoderStats {
+    /// Returns the number of blocks compressed.
+    pub fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    /// Returns the number of bytes of input compressed.
+    pub fn input_len(&self) -> u64 {
+        self.input_len
+    }
+
+    /// Returns the number of bytes the compressed blocks take, not counting
+    /// the length that starts each raw block.
+    pub fn output_len(&self) -> u64 {
+        self.output_len
+    }
+
+    /// Returns the number of literals emitted.
+    pub fn literals(&self) -> u64 {
+        self.literals
+    }
+
+    /// Returns the number of bytes of input emitted as literals.
+    pub fn literal_len(&self) -> u64 {
+        self.literal_len
+    }
+
+    /// Returns the number of copies emitted.
+    pub fn copies(&self) -> u64 {
+        self.copies
+    }
+
+    /// Returns the number of bytes of input emitted as copies.
+    pub fn copy_len(&self) -> u64 {
+        self.copy_len
+    }
+
+    /// Returns a histogram of the lengths of copies, where the entry at `i`
+    /// counts the copies at least `2^i` bytes long but shorter than
+    /// `2^(i+1)`. Since a copy is at least 4 bytes long, the first two
+    /// entries are always zero.
+    pub fn copy_lengths(&self) -> &[u64] {
+        &self.copy_lengths
+    }
+
+    /// Returns a histogram of the offsets of copies, i.e., how far back the
+    /// bytes they copy start, where the entry at `i` counts the copies with
+    /// an offset at least `2^i` but less than `2^(i+1)`.
+    pub fn copy_offsets(&self) -> &[u64] {
+        &self.copy_offsets
+    }
+
+    /// Returns the number of times the encoder looked up a 4 byte sequence
+    /// in its hash table to find an earlier occurrence of it.
+    ///
+    /// Only the default matcher counts these. They're always zero with a
+    /// non-zero
+    /// [`search_depth`](struct.EncoderBuilder.html#method.search_depth).
+    pub fn hash_probes(&self) -> u64 {
+        self.hash_probes
+    }
+
+    /// Returns the number of hash table lookups that found a position whose
+    /// 4 bytes didn't match, either because another sequence with the same
+    /// hash replaced it or because the slot was never filled.
+    ///
+    /// A high rate of these, relative to
+    /// [`hash_probes`](#method.hash_probes), suggests that a bigger
+    /// [`max_table_size`](struct.EncoderBuilder.html#method.max_table_size)
+    /// would find more matches, although on data that doesn't repeat much,
+    /// most lookups miss regardless.
+    pub fn hash_collisions(&self) -> u64 {
+        self.hash_probes - self.hash_hits
+    }
+
+    /// Returns `hash_collisions` as a fraction of `hash_probes`, or `0.0` if
+    /// there were no lookups.
+    pub fn hash_collision_rate(&self) -> f64 {
+        if self.hash_probes == 0 {
+            return 0.0;
+        }
+        self.hash_collisions() as f64 / self.hash_probes as f64
+    }
+
+    /// Adds the counts of `other` to these.
+    pub fn add(&mut self, other: &EncoderStats) {
+        self.blocks += other.blocks;
+        self.input_len += other.input_len;
+        self.output_len += other.output_len;
+        self.literals += other.literals;
+        self.literal_len += other.literal_len;
+        self.copies += other.copies;
+        self.copy_len += other.copy_len;
+        for (a, b) in self.copy_lengths.iter_mut().zip(&other.copy_lengths) {
+            *a += b;
+        }
+        for (a, b) in self.copy_offsets.iter_mut().zip(&other.copy_offsets) {
+            *a += b;
+        }
+        self.hash_probes += other.hash_probes;
+        self.hash_hits += other.hash_hits;
+    }
+
+    /// Counts a literal of `len` bytes.
+    #[inline(always)]
+    pub(crate) fn record_literal(&mut self, len: usize) {
+        self.literals += 1;
+        self.literal_len += len as u64;
+    }
+
+    /// Counts a copy of `len` bytes from `offset` back.
+    #[inline(always)]
+    pub(crate) fn record_copy(&mut self, offset: usize, len: usize) {
+        self.copies += 1;
+        self.copy_len += len as u64;
+        self.copy_lengths[bucket(len)] += 1;
+        self.copy_offsets[bucket(offset)] += 1;
+    }
+
+    /// Counts a compressed block, given the length of its input and output
+    /// and the number of hash table lookups made while compressing it. These
+    /// must be the counts of that one block.
+    pub(crate) fn record_block(
+        &mut self,
+        input_len: usize,
+        output_len: usize,
+        hash_probes: u64,
+    ) {
+        self.blocks += 1;
+        self.input_len += input_len as u64;
+        self.output_len += output_len as u64;
+        self.hash_probes += hash_probes;
+        // Every lookup that finds a match is extended into exactly one copy,
+        // and a block that makes lookups finds copies no other way.
+        if hash_probes > 0 {
+            self.hash_hits += self.copies;
+        }
+    }
+}
+
+/// Returns the histogram bucket that `n`, which must be non-zero, is
+/// counted in.
+#[inline(always)]
+fn bucket(n: usize) -> usize {
+    debug_assert!(n > 0);
+    cmp::min(63 - (n as u64).leading_zeros() as usize, HISTOGRAM_LEN - 1)
+}
@@ -0,0 +1,98 @@
+use std::prelude::v1::*;
+use std::io;
+
+use crate::error::Error;
+use crate::read::FrameDecoder;
+
+/// A report describing a Snappy frame formatted stream checked by
+/// [`validate`](fn.validate.html).
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct StreamReport {
+    chunks: u64,
+    compressed_len: u64,
+    decompressed_len: u64,
+    error: Option<(u64, Error)>,
+}
+
+impl StreamReport {
+    /// Returns true if the entire stream is valid.
+    pub fn is_valid(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Returns the number of valid chunks holding data, i.e., compressed and
+    /// uncompressed chunks, before the first error.
+    pub fn chunks(&self) -> u64 {
+        self.chunks
+    }
+
+    /// Returns the number of bytes of the stream, from its start, that are
+    /// made up of valid chunks. This is the size of the whole stream if it is
+    /// valid, and the offset of the first error otherwise.
+    pub fn compressed_len(&self) -> u64 {
+        self.compressed_len
+    }
+
+    /// Returns the number of bytes that the valid chunks decompress to.
+    pub fn decompressed_len(&self) -> u64 {
+        self.decompressed_len
+    }
+
+    /// Returns the first error found in the stream, if any.
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref().map(|(_, err)| err)
+    }
+
+    /// Returns the offset, from the start of the stream, of the chunk in
+    /// which the first error was found, if any.
+    pub fn error_offset(&self) -> Option<u64> {
+        self.error.as_ref().map(|&(offset, _)| offset)
+    }
+}
+
+/// Checks that the Snappy frame formatted stream read from `rdr` is valid,
+/// without writing its decompressed data anywhere.
+///
+/// Every chunk is decompressed and has its checksum verified, exactly as
+/// [`read::FrameDecoder`](../read/struct.FrameDecoder.html) would, so a
+/// stream passes if and only if reading it with a `FrameDecoder` succeeds.
+/// Checking stops at the first error, which is recorded in the report along
+/// with the offset of the chunk it was found in. This is what `szip -t` does,
+/// for programs that scrub stored streams.
+///
+/// # Errors
+///
+/// This only returns an error if reading from `rdr` fails. Corrupt data is
+/// never reported as an error, and a stream that ends in the middle of a
+/// chunk is reported in the report as `Error::TruncatedChunk`.
+pub fn validate<R: io::Read>(rdr: R) -> io::Result<StreamReport> {
+    let mut dec = FrameDecoder::new(rdr);
+    let mut report = StreamReport::default();
+    loop {
+        match dec.skip_chunk() {
+            Ok(None) => break,
+            Ok(Some(n)) => {
+                report.chunks += 1;
+                report.decompressed_len += n as u64;
+            }
+            Err(err) => {
+                let err = if err.kind() == io::ErrorKind::UnexpectedEof {
+                    Error::TruncatedChunk
+                } else if err.get_ref().map_or(false, |err| err.is::<Error>())
+                {
+                    *err.into_inner().unwrap().downcast::<Error>().unwrap()
+                } else {
+                    return Err(err);
+                };
+                // After an error, this is the offset of the chunk that
+                // failed.
+                let offset = dec.consumed();
+                report.compressed_len = offset;
+                report.error = Some((offset, err));
+                return Ok(report);
+            }
+        }
+    }
+    report.compressed_len = dec.consumed();
+    Ok(report)
+}
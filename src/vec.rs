@@ -0,0 +1,166 @@
+use std::prelude::v1::*;
+use std::cmp;
+use std::fmt;
+use std::ops::Range;
+
+use crate::compress::Encoder;
+use crate::decompress::Decoder;
+use crate::MAX_BLOCK_SIZE;
+
+/// A growable buffer that holds its contents compressed in memory, as
+/// independently compressed segments.
+///
+/// Bytes are appended with [`append`](#method.append). Every time a full
+/// segment's worth of bytes has been appended, they are compressed into a
+/// raw Snappy block. The bytes of the last, partial segment are kept
+/// uncompressed until the segment fills up.
+///
+/// Since segments are compressed independently,
+/// [`read_range`](#method.read_range) only needs to decompress the segments
+/// that a range overlaps. Smaller segments make reading short ranges
+/// cheaper, but usually compress less.
+///
+/// This is useful for holding large amounts of cold data in memory, such as
+/// in a cache, while still permitting cheap access to parts of it.
+pub struct SnappyVec {
+    /// The number of uncompressed bytes in each segment.
+    segment_size: usize,
+    /// Each full segment, compressed.
+    segments: Vec<Vec<u8>>,
+    /// The bytes of the last segment, which isn't full yet.
+    tail: Vec<u8>,
+    /// An encoder that we reuse to compress segments.
+    enc: Encoder,
+}
+
+impl Clone for SnappyVec {
+    fn clone(&self) -> SnappyVec {
+        SnappyVec {
+            segment_size: self.segment_size,
+            segments: self.segments.clone(),
+            tail: self.tail.clone(),
+            enc: Encoder::new(),
+        }
+    }
+}
+
+impl fmt::Debug for SnappyVec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SnappyVec")
+            .field("segment_size", &self.segment_size)
+            .field("segments", &"[...]")
+            .field("tail", &"[...]")
+            .field("enc", &self.enc)
+            .finish()
+    }
+}
+
+impl Default for SnappyVec {
+    fn default() -> SnappyVec {
+        SnappyVec::new()
+    }
+}
+
+impl SnappyVec {
+    /// Create a new empty buffer with segments of 64KB.
+    pub fn new() -> SnappyVec {
+        SnappyVec::with_segment_size(MAX_BLOCK_SIZE)
+    }
+
+    /// Create a new empty buffer whose segments hold `size` uncompressed
+    /// bytes each.
+    ///
+    /// The size given is clamped to the range `[1, MAX_INPUT_SIZE]`, since a
+    /// raw Snappy block can't hold more than
+    /// [`raw::MAX_INPUT_SIZE`](constant.MAX_INPUT_SIZE.html) bytes.
+    /// That's 4294967295 bytes, or 1MB with the `small-input` feature.
+    pub fn with_segment_size(size: usize) -> SnappyVec {
+        let size = cmp::max(1, cmp::min(size as u64, crate::MAX_INPUT_SIZE));
+        SnappyVec {
+            segment_size: size as usize,
+            segments: vec![],
+            tail: vec![],
+            enc: Encoder::new(),
+        }
+    }
+
+    /// Appends `data` to the end of this buffer.
+    pub fn append(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let n = cmp::min(self.segment_size - self.tail.len(), data.len());
+            self.tail.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.tail.len() == self.segment_size {
+                // Segments are never bigger than the maximum input size.
+                let segment = self.enc.compress_vec(&self.tail).unwrap();
+                self.segments.push(segment);
+                self.tail.clear();
+            }
+        }
+    }
+
+    /// Returns the number of uncompressed bytes in this buffer.
+    pub fn len(&self) -> usize {
+        self.segments.len() * self.segment_size + self.tail.len()
+    }
+
+    /// Returns true if this buffer contains no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of bytes used to hold the contents of this buffer,
+    /// which is the size of every compressed segment plus the size of the
+    /// uncompressed last segment.
+    pub fn compressed_len(&self) -> usize {
+        self.segments.iter().map(|s| s.len()).sum::<usize>() + self.tail.len()
+    }
+
+    /// Returns the number of uncompressed bytes in each segment.
+    pub fn segment_size(&self) -> usize {
+        self.segment_size
+    }
+
+    /// Returns a copy of the bytes in the given range of this buffer.
+    ///
+    /// This decompresses every segment that the range overlaps.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the range is out of bounds or if its start is greater
+    /// than its end, just like indexing a slice.
+    pub fn read_range(&self, range: Range<usize>) -> Vec<u8> {
+        let (start, end) = (range.start, range.end);
+        assert!(start <= end, "range start {} > end {}", start, end);
+        assert!(end <= self.len(), "range end {} > len {}", end, self.len());
+        let mut out = vec![0; end - start];
+        let mut dec = Decoder::new();
+        let mut scratch = vec![];
+        let mut pos = start;
+        while pos < end {
+            let i = pos / self.segment_size;
+            let seg_start = i * self.segment_size;
+            let from = pos - seg_start;
+            let n = cmp::min(self.segment_size - from, end - pos);
+            let dst = &mut out[pos - start..pos - start + n];
+            if i == self.segments.len() {
+                dst.copy_from_slice(&self.tail[from..from + n]);
+            } else if from == 0 && n == self.segment_size {
+                // Segments were compressed by this buffer, so they always
+                // decompress to exactly one segment.
+                dec.decompress(&self.segments[i], dst).unwrap();
+            } else {
+                scratch.resize(self.segment_size, 0);
+                dec.decompress(&self.segments[i], &mut scratch).unwrap();
+                dst.copy_from_slice(&scratch[from..from + n]);
+            }
+            pos += n;
+        }
+        out
+    }
+
+    /// Returns a copy of all of the bytes in this buffer.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.read_range(0..self.len())
+    }
+}
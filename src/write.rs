@@ -1,26 +1,46 @@
 /*!
-This module provides a `std::io::Write` implementation:
+This module provides two `std::io::Write` implementations:
 
 - `write::FrameEncoder` wraps another `std::io::Write` implemenation, and
   compresses data encoded using the Snappy frame format. Use this if you have
   uncompressed data source and wish to write it as compressed data.
+- `write::BackgroundWriter` wraps another `std::io::Write` implementation, and
+  writes to it on a separate thread. Wrap a slow writer in it before handing
+  it to a `write::FrameEncoder` to compress and write at the same time.
+
+A `write::Checkpoint` records the state of a `write::FrameEncoder`, so that a
+stream can be resumed after a crash or a restart.
+
+A `write::FrameEncoder` whose writer implements `write::SyncWrite`, such as a
+`File`, can also make the data written so far durable with `sync_data` or
+`sync_all`.
 
 It would also be possible to provide a `write::FrameEncoder`, which decompresses
 data as it writes it, but it hasn't been implemented yet.
 */
 
 use std::prelude::v1::*;
+use core::time::Duration;
+use std::cmp;
 use std::fmt;
+use std::fs::File;
 use std::io::{self, Write};
+use std::mem;
+use std::panic;
+use std::sync::mpsc;
+use std::thread;
 
-use crate::compress::Encoder;
+use crate::bytes;
+use crate::codec::{Codec, SnappyCodec};
 use crate::crc32::CheckSummer;
+use crate::error::Error;
 pub use crate::error::IntoInnerError;
 use crate::frame::{
-    compress_frame, CHUNK_HEADER_AND_CRC_SIZE, MAX_COMPRESS_BLOCK_SIZE,
+    compress_frame, compress_frame_data, verify_frame, ChunkFilter,
+    ChunkPolicy, EncoderOptions, StreamDigest, CHUNK_HEADER_AND_CRC_SIZE,
+    DIGEST_CHUNK_TYPE, FILTER_CHUNK_TYPE, MAX_COMPRESS_BLOCK_SIZE,
     STREAM_IDENTIFIER,
 };
-use crate::MAX_BLOCK_SIZE;
 
 /// A writer for compressing a Snappy stream.
 ///
@@ -30,16 +50,26 @@ use crate::MAX_BLOCK_SIZE;
 /// (file extension `sz`, MIME type `application/x-snappy-framed`).
 ///
 /// Writes are buffered automatically, so there's no need to wrap the given
-/// writer in a `std::io::BufWriter`.
+/// writer in a `std::io::BufWriter`. To keep compressing while compressed
+/// data is being written to a slow writer, wrap the writer in a
+/// [`BackgroundWriter`](struct.BackgroundWriter.html) instead.
 ///
 /// The writer will be flushed automatically when it is dropped. If an error
 /// occurs, it is ignored.
-pub struct FrameEncoder<W: io::Write> {
+///
+/// The type parameter `C` is the codec that compresses each chunk, which is
+/// Snappy unless another one is given to
+/// [`with_codec`](#method.with_codec). A codec is cloned for every thread
+/// that compresses blocks concurrently, so it must be `Clone` and `Send`.
+pub struct FrameEncoder<
+    W: io::Write,
+    C: Codec + Clone + Send + 'static = SnappyCodec,
+> {
     /// Our main internal state, split out for borrowck reasons (happily paid).
     ///
     /// Also, it's an `Option` so we can move out of it even though
     /// `FrameEncoder` impls `Drop`.
-    inner: Option<Inner<W>>,
+    inner: Option<Inner<W, C>>,
     /// Our buffer of uncompressed bytes. This isn't part of `inner` because
     /// we may write bytes directly from the caller if the given buffer was
     /// big enough. As a result, the main `write` implementation needs to
@@ -49,11 +79,14 @@ pub struct FrameEncoder<W: io::Write> {
     src: Vec<u8>,
 }
 
-struct Inner<W> {
+struct Inner<W, C> {
     /// The underlying writer.
     w: W,
-    /// An encoder that we reuse that does the actual block based compression.
-    enc: Encoder,
+    /// A codec that we reuse that does the actual block based compression.
+    /// It's cloned for each thread that compresses a block concurrently.
+    enc: C,
+    /// The maximum number of bytes in a block.
+    block_size: usize,
     /// A CRC32 checksummer that is configured to either use the portable
     /// fallback version or the SSE4.2 accelerated version when the right CPU
     /// features are available.
@@ -67,21 +100,259 @@ struct Inner<W> {
     /// Space for writing the header of a chunk before writing it to the
     /// underlying writer.
     chunk_header: [u8; 8],
+    /// How to decide whether to write each block compressed.
+    policy: ChunkPolicy,
+    /// The number of blocks to compress concurrently.
+    threads: usize,
+    /// Blocks waiting to be compressed concurrently, as they were written and
+    /// so before they're filtered. This never has more than `threads`
+    /// blocks, and is always empty when `threads` is `1`.
+    pending: Vec<Vec<u8>>,
+    /// The allocations of blocks that have been compressed concurrently, to
+    /// copy the next pending blocks into.
+    spare_blocks: Vec<Vec<u8>>,
+    /// The threads that compress pending blocks, started when they're first
+    /// needed and kept until the encoder is dropped.
+    workers: Vec<Worker>,
+    /// Whether to checksum blocks on a helper thread.
+    checksum_thread: bool,
+    /// The helper thread, started when the first block is written.
+    checksums: Option<ChecksumThread>,
+    /// Whether to check that every chunk decompresses to its block.
+    verify: bool,
+    /// Space for decompressing chunks into when verifying them.
+    verify_buf: Vec<u8>,
+    /// The maximum number of blocks compressed by a single call to write, or
+    /// `0` for no limit.
+    max_write_blocks: usize,
+    /// Flush once this many bytes are buffered.
+    auto_flush_size: Option<usize>,
+    /// Flush once bytes have been buffered for this long.
+    auto_flush_interval: Option<Duration>,
+    /// The time given to the first call to `maybe_flush` that found buffered
+    /// bytes since the last flush.
+    buffered_since: Option<Duration>,
+    /// The number of uncompressed bytes written as chunks.
+    bytes_in: u64,
+    /// The number of bytes written to `w`.
+    bytes_out: u64,
+    /// The digest of every block written, appended to the stream when it
+    /// ends.
+    digest: Option<Box<dyn StreamDigest + Send>>,
+    /// The filter applied to every block before it's compressed.
+    filter: Option<Box<dyn ChunkFilter + Send>>,
+    /// When false, a chunk naming the filter must precede the next chunk.
+    wrote_filter: bool,
+    /// Space for filtering a block before compressing it.
+    filter_buf: Vec<u8>,
+}
+
+/// A helper thread that computes the checksum of each block sent to it.
+///
+/// Each block is sent back with its checksum, so that its allocation can be
+/// reused for the next block.
+struct ChecksumThread {
+    blocks: mpsc::Sender<Vec<u8>>,
+    sums: mpsc::Receiver<(Vec<u8>, u32)>,
+    /// The allocation to copy the next block into.
+    spare: Vec<u8>,
+}
+
+/// A thread that compresses the pending blocks sent to it with its own clone
+/// of the codec.
+///
+/// Each block is sent back along with the chunk it was compressed into, so
+/// that both allocations can be reused for the next batch of blocks.
+struct Worker {
+    jobs: mpsc::Sender<Job>,
+    chunks: mpsc::Receiver<thread::Result<Chunk>>,
+    /// The allocation to compress the next block into.
+    spare: Vec<u8>,
+}
+
+/// A block for a worker to compress, and the settings to compress it with.
+struct Job {
+    src: Vec<u8>,
+    dst: Vec<u8>,
+    checksummer: CheckSummer,
+    policy: ChunkPolicy,
+    verify: bool,
+}
+
+/// A block compressed by a worker.
+struct Chunk {
+    header: [u8; CHUNK_HEADER_AND_CRC_SIZE],
+    /// The length of the chunk data at the start of `dst`.
+    len: Result<usize, Error>,
+    src: Vec<u8>,
+    dst: Vec<u8>,
 }
 
 impl<W: io::Write> FrameEncoder<W> {
     /// Create a new writer for streaming Snappy compression.
     pub fn new(wtr: W) -> FrameEncoder<W> {
+        FrameEncoder::with_options(wtr, &EncoderOptions::new())
+    }
+
+    /// Create a new writer for streaming Snappy compression with the given
+    /// options.
+    pub fn with_options(wtr: W, options: &EncoderOptions) -> FrameEncoder<W> {
+        let codec = SnappyCodec::with_builder(&options.encoder);
+        FrameEncoder::with_codec_options(wtr, codec, options)
+    }
+
+    /// Create a new writer that continues the stream recorded by
+    /// `checkpoint`.
+    ///
+    /// `wtr` must be positioned at the end of the first
+    /// [`compressed_len`](struct.Checkpoint.html#method.compressed_len)
+    /// bytes of the stream, which is usually done by truncating the stream
+    /// to that length and opening it in append mode. Anything written to the
+    /// stream after the checkpoint was taken is then discarded, and the bytes
+    /// the checkpoint buffered are written again.
+    ///
+    /// The buffered bytes are written to the new encoder before it's
+    /// returned, which may write complete chunks to `wtr`.
+    ///
+    /// The filter of `options` must be the one that the stream was being
+    /// filtered with when the checkpoint was taken, if any, or else
+    /// `Error::UnknownFilter` is returned, since the buffered bytes must be
+    /// filtered too. Use [`resume_with_filter`](#method.resume_with_filter)
+    /// to resume a stream filtered with a filter that options can't name.
+    pub fn resume(
+        wtr: W,
+        options: &EncoderOptions,
+        checkpoint: &Checkpoint,
+    ) -> io::Result<FrameEncoder<W>> {
+        FrameEncoder::resume_filtered(wtr, options, checkpoint, None)
+    }
+
+    /// Like `resume`, but continues a stream that was being filtered with
+    /// `filter` when the checkpoint was taken, instead of with the filter of
+    /// `options`.
+    ///
+    /// The filter is set before the buffered bytes are written, so they're
+    /// filtered like the rest of the stream. If the checkpoint doesn't record
+    /// a filter with the same name as `filter` (see
+    /// [`Checkpoint::filter`](struct.Checkpoint.html#method.filter)), then
+    /// `Error::UnknownFilter` is returned.
+    pub fn resume_with_filter<F: ChunkFilter + Send + 'static>(
+        wtr: W,
+        options: &EncoderOptions,
+        checkpoint: &Checkpoint,
+        filter: F,
+    ) -> io::Result<FrameEncoder<W>> {
+        FrameEncoder::resume_filtered(
+            wtr,
+            options,
+            checkpoint,
+            Some(Box::new(filter)),
+        )
+    }
+
+    fn resume_filtered(
+        wtr: W,
+        options: &EncoderOptions,
+        checkpoint: &Checkpoint,
+        filter: Option<Box<dyn ChunkFilter + Send>>,
+    ) -> io::Result<FrameEncoder<W>> {
+        let filter = filter.or_else(|| options.new_filter());
+        if checkpoint.filter() != filter.as_ref().map(|f| f.name()) {
+            let name = match checkpoint.filter {
+                Some(ref name) => name.as_bytes().to_vec(),
+                None => filter.unwrap().name().as_bytes().to_vec(),
+            };
+            return Err(io::Error::from(Error::UnknownFilter { name }));
+        }
+        let mut enc = FrameEncoder::with_options(wtr, options);
+        {
+            let inner = enc.inner.as_mut().unwrap();
+            inner.wrote_stream_ident = checkpoint.stream_started;
+            inner.bytes_in = checkpoint.bytes_in;
+            inner.bytes_out = checkpoint.bytes_out;
+            inner.filter = filter;
+            inner.wrote_filter = checkpoint.named_filter;
+        }
+        enc.write_all(&checkpoint.buffered)?;
+        Ok(enc)
+    }
+}
+
+impl<W: io::Write, C: Codec + Clone + Send + 'static> FrameEncoder<W, C> {
+    /// Create a new writer for streaming compression that compresses each
+    /// chunk with `codec`.
+    ///
+    /// See [`frame::Codec`](../frame/trait.Codec.html) for more details.
+    pub fn with_codec(wtr: W, codec: C) -> FrameEncoder<W, C> {
+        FrameEncoder::with_codec_options(wtr, codec, &EncoderOptions::new())
+    }
+
+    /// Like `with_codec`, but with the given options. The encoder
+    /// configuration in `options` is ignored, since `codec` compresses.
+    pub fn with_codec_options(
+        wtr: W,
+        codec: C,
+        options: &EncoderOptions,
+    ) -> FrameEncoder<W, C> {
         FrameEncoder {
             inner: Some(Inner {
                 w: wtr,
-                enc: Encoder::new(),
+                enc: codec,
+                block_size: options.block_size,
                 checksummer: CheckSummer::new(),
                 dst: vec![0; MAX_COMPRESS_BLOCK_SIZE],
                 wrote_stream_ident: false,
                 chunk_header: [0; CHUNK_HEADER_AND_CRC_SIZE],
+                policy: options.chunk_policy(),
+                threads: 1,
+                pending: vec![],
+                spare_blocks: vec![],
+                workers: vec![],
+                checksum_thread: false,
+                checksums: None,
+                verify: false,
+                verify_buf: vec![],
+                max_write_blocks: 0,
+                auto_flush_size: None,
+                auto_flush_interval: None,
+                buffered_since: None,
+                bytes_in: 0,
+                bytes_out: 0,
+                digest: options.new_digest(),
+                filter: options.new_filter(),
+                wrote_filter: false,
+                filter_buf: vec![],
             }),
-            src: Vec::with_capacity(MAX_BLOCK_SIZE),
+            src: Vec::with_capacity(options.block_size),
+        }
+    }
+
+    /// Returns a checkpoint of this encoder's state.
+    ///
+    /// The checkpoint holds the bytes buffered by this encoder, which haven't
+    /// been written to the underlying writer as chunks yet, along with the
+    /// number of bytes this encoder has written to the underlying writer.
+    /// Nothing is written by taking a checkpoint, so doing so doesn't make
+    /// chunks any smaller.
+    ///
+    /// To recover from a crash, the underlying writer must hold everything
+    /// the checkpoint counts. For a file, that means flushing and syncing it
+    /// (e.g., with `get_mut().sync_data()`) before the checkpoint is saved.
+    /// See [`FrameEncoder::resume`](#method.resume) for using it.
+    pub fn checkpoint(&self) -> Checkpoint {
+        let inner = self.inner.as_ref().unwrap();
+        let mut buffered = Vec::with_capacity(self.buffered());
+        for block in &inner.pending {
+            buffered.extend_from_slice(block);
+        }
+        buffered.extend_from_slice(&self.src);
+        Checkpoint {
+            stream_started: inner.wrote_stream_ident,
+            bytes_in: inner.bytes_in,
+            bytes_out: inner.bytes_out,
+            filter: inner.filter.as_ref().map(|f| f.name().to_string()),
+            named_filter: inner.wrote_filter,
+            buffered,
         }
     }
 
@@ -89,13 +360,25 @@ impl<W: io::Write> FrameEncoder<W> {
     ///
     /// If flushing the writer caused an error, then an `IntoInnerError` is
     /// returned, which contains both the writer and the original writer.
-    pub fn into_inner(mut self) -> Result<W, IntoInnerError<FrameEncoder<W>>> {
-        match self.flush() {
+    // The error holds the encoder unboxed, as std's BufWriter::into_inner
+    // does, so that callers can take it back out and retry.
+    #[allow(clippy::result_large_err)]
+    pub fn into_inner(
+        mut self,
+    ) -> Result<W, IntoInnerError<FrameEncoder<W, C>>> {
+        match self.flush().and_then(|()| self.write_digest()) {
             Ok(()) => Ok(self.inner.take().unwrap().w),
             Err(err) => Err(IntoInnerError::new(self, err)),
         }
     }
 
+    /// Drops this encoder without flushing it, discarding anything it has
+    /// buffered.
+    #[cfg(feature = "capi")]
+    pub(crate) fn discard(mut self) {
+        self.inner = None;
+    }
+
     /// Gets a reference to the underlying writer in this encoder.
     pub fn get_ref(&self) -> &W {
         &self.inner.as_ref().unwrap().w
@@ -108,35 +391,411 @@ impl<W: io::Write> FrameEncoder<W> {
     pub fn get_mut(&mut self) -> &mut W {
         &mut self.inner.as_mut().unwrap().w
     }
+
+    /// Returns the number of bytes held by this encoder's hash tables and
+    /// buffers, including blocks waiting to be compressed concurrently.
+    ///
+    /// This doesn't include the underlying writer, nor the codecs and
+    /// buffers that concurrent compression keeps on its threads.
+    pub fn memory_usage(&self) -> usize {
+        let inner = self.inner.as_ref().unwrap();
+        let pending = inner
+            .pending
+            .iter()
+            .chain(&inner.spare_blocks)
+            .chain(inner.workers.iter().map(|w| &w.spare))
+            .map(|b| b.capacity())
+            .sum::<usize>();
+        let spare = inner.checksums.as_ref().map_or(0, |c| c.spare.capacity());
+        inner.enc.memory_usage()
+            + self.src.capacity()
+            + inner.dst.capacity()
+            + inner.verify_buf.capacity()
+            + pending
+            + spare
+    }
+
+    /// When enabled, this encoder checks whether each block looks like
+    /// random or already compressed data before compressing it, and if so,
+    /// writes it uncompressed without attempting compression at all.
+    ///
+    /// The check is much cheaper than compression, so this can improve
+    /// throughput considerably on inputs that mix compressible data with
+    /// incompressible data, such as PDFs or archives with embedded images.
+    /// The check is a heuristic though, and may occasionally cause a block
+    /// that would have compressed to be written uncompressed.
+    ///
+    /// This is disabled by default.
+    pub fn skip_incompressible(
+        &mut self,
+        yes: bool,
+    ) -> &mut FrameEncoder<W, C> {
+        self.inner.as_mut().unwrap().policy.skip_incompressible = yes;
+        self
+    }
+
+    /// Set the minimum percentage by which compressing a block must shrink it
+    /// for the block to be written compressed. Blocks that compress less are
+    /// written uncompressed instead, which costs space but is faster to read,
+    /// since decoding them is a plain copy.
+    ///
+    /// For example, `Some(25)` only keeps the compressed form of a block if
+    /// it's at most 75% of the size of the block. Unlike
+    /// [`skip_incompressible`](#method.skip_incompressible), this decides
+    /// after compressing each block, based on its exact compressed size. A
+    /// percentage over `100` is treated as `100`, which writes every block
+    /// uncompressed.
+    ///
+    /// This is set to `None` by default, which keeps blocks that compress by
+    /// at least 12.5%.
+    pub fn min_savings(
+        &mut self,
+        percent: Option<u8>,
+    ) -> &mut FrameEncoder<W, C> {
+        self.inner.as_mut().unwrap().policy.min_savings =
+            percent.map(|p| cmp::min(p, 100));
+        self
+    }
+
+    /// Set the number of blocks that this encoder compresses concurrently,
+    /// each on its own thread.
+    ///
+    /// Blocks are split up exactly as they would be without threads, and are
+    /// written in order, so the compressed stream is byte-for-byte identical
+    /// to the one produced with a single thread. Up to `threads` blocks are
+    /// buffered before they are compressed, so data may reach the underlying
+    /// writer later than it would otherwise. Calling `flush` always writes
+    /// every buffered block.
+    ///
+    /// The threads are started the first time blocks are compressed
+    /// concurrently, and are reused until the encoder is dropped.
+    ///
+    /// This is set to `1` by default, which compresses every block on the
+    /// calling thread. A value of `0` is treated as `1`.
+    pub fn threads(&mut self, threads: usize) -> &mut FrameEncoder<W, C> {
+        self.inner.as_mut().unwrap().threads = cmp::max(1, threads);
+        self
+    }
+
+    /// When enabled, this encoder computes the checksum of each block on a
+    /// helper thread while the block is being compressed, which overlaps the
+    /// two most expensive parts of writing a chunk.
+    ///
+    /// Each block is copied to give it to the helper thread, so this is only
+    /// worth it when a spare CPU is available. It has no effect when
+    /// [`threads`](#method.threads) is greater than `1`, since every block is
+    /// then compressed and checksummed on a thread of its own.
+    ///
+    /// This is disabled by default.
+    pub fn checksum_thread(&mut self, yes: bool) -> &mut FrameEncoder<W, C> {
+        let inner = self.inner.as_mut().unwrap();
+        inner.checksum_thread = yes;
+        if !yes {
+            inner.checksums = None;
+        }
+        self
+    }
+
+    /// When enabled, this encoder decompresses every chunk right after
+    /// compressing it, and checks that the result is identical to the bytes
+    /// that were compressed before writing the chunk. If it isn't, then
+    /// `Error::Verify` is returned and the chunk is not written.
+    ///
+    /// This guards against bugs in the compressor and against memory
+    /// corruption, at the expense of roughly doubling the cost of compression.
+    ///
+    /// This is disabled by default.
+    #[cfg(feature = "decompress")]
+    pub fn verify(&mut self, yes: bool) -> &mut FrameEncoder<W, C> {
+        self.inner.as_mut().unwrap().verify = yes;
+        self
+    }
+
+    /// Append a digest of all the uncompressed data written to this encoder
+    /// to the end of the stream, in a chunk of type
+    /// [`frame::DIGEST_CHUNK_TYPE`](../frame/constant.DIGEST_CHUNK_TYPE.html).
+    ///
+    /// Unlike the checksum of each chunk, the digest also catches chunks
+    /// that were lost, duplicated or reordered, and a stream that was cut
+    /// short at a chunk boundary, and it can be a cryptographic hash such as
+    /// [`frame::Sha256`](../frame/struct.Sha256.html). The trailer is a
+    /// skippable chunk, so any Snappy decoder can still read the stream. Use
+    /// [`read::FrameDecoder::verify_digest`](../read/struct.FrameDecoder.html#method.verify_digest)
+    /// to check it.
+    ///
+    /// The trailer is written when this encoder is consumed by `into_inner`
+    /// or dropped. A checkpoint doesn't record the state of the digest, so
+    /// an encoder resumed from a checkpoint only digests the data written to
+    /// it after resuming, and its trailer won't match the whole stream.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the name of `digest` is longer than 255 bytes.
+    pub fn digest<D: StreamDigest + Send + 'static>(
+        &mut self,
+        digest: D,
+    ) -> &mut FrameEncoder<W, C> {
+        assert!(digest.name().len() <= 255, "digest name is too long");
+        self.inner.as_mut().unwrap().digest = Some(Box::new(digest));
+        self
+    }
+
+    /// Apply `filter` to the data of every chunk before compressing it.
+    ///
+    /// A chunk of type
+    /// [`frame::FILTER_CHUNK_TYPE`](../frame/constant.FILTER_CHUNK_TYPE.html)
+    /// naming the filter is written before the next chunk, and every chunk
+    /// after it is filtered. A decoder must be given the same filter, with
+    /// [`read::FrameDecoder::filter`](../read/struct.FrameDecoder.html#method.filter),
+    /// to read the stream. A digest set with [`digest`](#method.digest) is
+    /// computed over the data before it's filtered.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the name of `filter` is longer than 255 bytes.
+    pub fn filter<F: ChunkFilter + Send + 'static>(
+        &mut self,
+        filter: F,
+    ) -> &mut FrameEncoder<W, C> {
+        assert!(filter.name().len() <= 255, "filter name is too long");
+        let inner = self.inner.as_mut().unwrap();
+        inner.filter = Some(Box::new(filter));
+        inner.wrote_filter = false;
+        self
+    }
+
+    /// Set the maximum number of bytes that a single call to `write`
+    /// compresses.
+    ///
+    /// When set, `write` stops once it has compressed as many whole blocks as
+    /// fit in `bytes` (but always at least one), and returns the number of
+    /// bytes it consumed, which may be fewer than it was given. Bytes that
+    /// just need buffering are always consumed. This bounds the time any
+    /// single call spends compressing, so callers with a time budget, such as
+    /// an audio or game loop, can spread a large write over several
+    /// iterations by calling `write` once per iteration. Use
+    /// [`EncoderOptions::block_size`](../frame/struct.EncoderOptions.html#method.block_size)
+    /// for a finer granularity than the default 64KB blocks.
+    ///
+    /// Note that `write_all` keeps calling `write` until everything is
+    /// consumed, so it isn't bounded by this. `flush` compresses at most one
+    /// block, plus any blocks pending concurrent compression when
+    /// [`threads`](#method.threads) is greater than `1`.
+    ///
+    /// A limit of `0` means no limit, which is the default.
+    pub fn max_write_size(&mut self, bytes: usize) -> &mut FrameEncoder<W, C> {
+        let inner = self.inner.as_mut().unwrap();
+        inner.max_write_blocks =
+            if bytes == 0 { 0 } else { cmp::max(1, bytes / inner.block_size) };
+        self
+    }
+
+    /// Set the number of buffered bytes at which this encoder flushes
+    /// automatically.
+    ///
+    /// Once at least `size` uncompressed bytes are buffered after a call to
+    /// `write` or [`maybe_flush`](#method.maybe_flush), this encoder flushes
+    /// just as `maybe_flush` does when its deadline passes. A size smaller
+    /// than the block size makes chunks reach the underlying writer sooner,
+    /// at the expense of compression ratio.
+    ///
+    /// This is disabled by default.
+    pub fn auto_flush_size(
+        &mut self,
+        size: Option<usize>,
+    ) -> &mut FrameEncoder<W, C> {
+        self.inner.as_mut().unwrap().auto_flush_size = size;
+        self
+    }
+
+    /// Set how long bytes may stay buffered before
+    /// [`maybe_flush`](#method.maybe_flush) flushes them.
+    ///
+    /// This is disabled by default.
+    pub fn auto_flush_interval(
+        &mut self,
+        interval: Option<Duration>,
+    ) -> &mut FrameEncoder<W, C> {
+        self.inner.as_mut().unwrap().auto_flush_interval = interval;
+        self
+    }
+
+    /// Flushes this encoder if the thresholds set by
+    /// [`auto_flush_size`](#method.auto_flush_size) or
+    /// [`auto_flush_interval`](#method.auto_flush_interval) have been reached,
+    /// and returns whether it did.
+    ///
+    /// `now` is the current time, measured from any fixed point the caller
+    /// likes (for example, `Instant::now() - start`), since this crate never
+    /// reads a clock itself. The time that bytes have been buffered is
+    /// measured from the first call that found them buffered, so call this
+    /// regularly, e.g., after every write or on a timer. Bytes then wait no
+    /// longer than the interval plus the time between two calls.
+    ///
+    /// Flushing writes the partial chunk being buffered (and any blocks
+    /// pending concurrent compression), and then flushes the underlying
+    /// writer, so that a live reader of the stream sees the data.
+    pub fn maybe_flush(&mut self, now: Duration) -> io::Result<bool> {
+        let buffered = self.buffered();
+        let inner = self.inner.as_mut().unwrap();
+        if buffered == 0 {
+            inner.buffered_since = None;
+            return Ok(false);
+        }
+        let since = *inner.buffered_since.get_or_insert(now);
+        let late = inner.auto_flush_interval.map_or(false, |interval| {
+            now.checked_sub(since).map_or(false, |waited| waited >= interval)
+        });
+        let full =
+            inner.auto_flush_size.map_or(false, |size| buffered >= size);
+        if !late && !full {
+            return Ok(false);
+        }
+        self.auto_flush()?;
+        Ok(true)
+    }
+
+    /// Returns the number of uncompressed bytes buffered by this encoder.
+    fn buffered(&self) -> usize {
+        let pending = &self.inner.as_ref().unwrap().pending;
+        self.src.len() + pending.iter().map(|b| b.len()).sum::<usize>()
+    }
+
+    /// Flushes this encoder and its underlying writer.
+    fn auto_flush(&mut self) -> io::Result<()> {
+        self.flush()?;
+        let inner = self.inner.as_mut().unwrap();
+        inner.buffered_since = None;
+        inner.w.flush()
+    }
+
+    /// Writes `msg` as a message of its own.
+    ///
+    /// Anything buffered by this encoder is written first, so that the
+    /// message starts a new chunk. The message is then written as whole
+    /// chunks, and the last one is ended, so that the next write starts a new
+    /// chunk too. A message no bigger than the block size occupies exactly
+    /// one chunk, even if it's empty, which lets a reader such as
+    /// `read::FrameDecoder::read_message` recover message boundaries without
+    /// another layer of framing. Bigger messages occupy as many chunks as
+    /// they have blocks.
+    ///
+    /// Like `flush`, this doesn't flush the underlying writer.
+    pub fn write_message(&mut self, msg: &[u8]) -> io::Result<()> {
+        self.flush()?;
+        let inner = self.inner.as_mut().unwrap();
+        if msg.is_empty() {
+            inner.write_stream_ident()?;
+            inner.write_block(msg)?;
+        } else {
+            inner.write(msg)?;
+        }
+        inner.write_pending()
+    }
+
+    /// Writes the digest trailer, if a digest was set and hasn't been
+    /// written yet. The caller must flush this encoder first.
+    fn write_digest(&mut self) -> io::Result<()> {
+        let inner = self.inner.as_mut().unwrap();
+        let mut digest = match inner.digest.take() {
+            None => return Ok(()),
+            Some(digest) => digest,
+        };
+        let name = digest.name().as_bytes().to_vec();
+        let sum = digest.finish();
+        let mut chunk = vec![DIGEST_CHUNK_TYPE, 0, 0, 0, name.len() as u8];
+        chunk.extend_from_slice(&name);
+        chunk.extend_from_slice(&sum);
+        let len = chunk.len() - 4;
+        bytes::write_u24_le(len as u32, &mut chunk[1..]);
+        inner.write_stream_ident()?;
+        inner.w.write_all(&chunk)?;
+        inner.bytes_out += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Compress and write the bytes in `src`, without waiting for blocks
+    /// that are pending concurrent compression.
+    fn flush_src(&mut self) -> io::Result<()> {
+        if self.src.is_empty() {
+            return Ok(());
+        }
+        self.inner.as_mut().unwrap().write(&self.src)?;
+        self.src.truncate(0);
+        Ok(())
+    }
 }
 
-impl<W: io::Write> Drop for FrameEncoder<W> {
+impl<W: SyncWrite, C: Codec + Clone + Send + 'static> FrameEncoder<W, C> {
+    /// Flushes this encoder and the underlying writer, and then makes the
+    /// data written so far durable with the underlying writer's
+    /// [`sync_data`](trait.SyncWrite.html#tymethod.sync_data).
+    ///
+    /// Since flushing ends the current chunk, everything written before this
+    /// call returns `Ok` can be read back after a crash, and the stream
+    /// stops at a chunk boundary. This makes the encoder suitable for
+    /// write-ahead logs and similar uses, at the cost of smaller chunks if
+    /// this is called often.
+    pub fn sync_data(&mut self) -> io::Result<()> {
+        self.auto_flush()?;
+        self.get_mut().sync_data()
+    }
+
+    /// Like [`sync_data`](#method.sync_data), but also makes the metadata of
+    /// the underlying writer durable, with its
+    /// [`sync_all`](trait.SyncWrite.html#tymethod.sync_all).
+    pub fn sync_all(&mut self) -> io::Result<()> {
+        self.auto_flush()?;
+        self.get_mut().sync_all()
+    }
+}
+
+impl<W: io::Write, C: Codec + Clone + Send + 'static> Drop
+    for FrameEncoder<W, C>
+{
     fn drop(&mut self) {
         if self.inner.is_some() {
             // Ignore errors because we can't conceivably return an error and
             // panicing in a dtor is bad juju.
-            let _ = self.flush();
+            let _ = self.flush().and_then(|()| self.write_digest());
         }
     }
 }
 
-impl<W: io::Write> io::Write for FrameEncoder<W> {
+impl<W: io::Write, C: Codec + Clone + Send + 'static> io::Write
+    for FrameEncoder<W, C>
+{
     fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
         let mut total = 0;
+        let block_size = self.inner.as_ref().unwrap().block_size;
+        let max_blocks = self.inner.as_ref().unwrap().max_write_blocks;
+        let mut blocks = 0;
         // If there isn't enough room to add buf to src, then add only a piece
         // of it, flush it and mush on.
         loop {
-            let free = self.src.capacity() - self.src.len();
+            let free = block_size - self.src.len();
             // n is the number of bytes extracted from buf.
             let n = if buf.len() <= free {
                 break;
+            } else if max_blocks > 0 && blocks >= max_blocks {
+                // We've compressed as much as one call is allowed to.
+                return Ok(total);
             } else if self.src.is_empty() {
                 // If buf is bigger than our entire buffer then avoid
-                // the indirection and write the buffer directly.
-                self.inner.as_mut().unwrap().write(buf)?
+                // the indirection and write the buffer directly. When the
+                // work per call is limited, only write whole blocks, and
+                // leave the rest for the next iteration or the next call.
+                let mut len = buf.len();
+                if max_blocks > 0 {
+                    len = cmp::min(len / block_size, max_blocks - blocks)
+                        * block_size;
+                }
+                blocks += (len + block_size - 1) / block_size;
+                self.inner.as_mut().unwrap().write(&buf[..len])?
             } else {
                 self.src.extend_from_slice(&buf[0..free]);
-                self.flush()?;
+                self.flush_src()?;
+                blocks += 1;
                 free
             };
             buf = &buf[n..];
@@ -144,56 +803,310 @@ impl<W: io::Write> io::Write for FrameEncoder<W> {
         }
         // We're only here if buf.len() will fit within the available space of
         // self.src.
-        debug_assert!(buf.len() <= (self.src.capacity() - self.src.len()));
+        debug_assert!(buf.len() <= (block_size - self.src.len()));
         self.src.extend_from_slice(buf);
         total += buf.len();
         // We should never expand or contract self.src.
-        debug_assert!(self.src.capacity() == MAX_BLOCK_SIZE);
+        debug_assert!(self.src.capacity() >= block_size);
+        let auto_flush_size = self.inner.as_ref().unwrap().auto_flush_size;
+        if auto_flush_size.map_or(false, |size| self.buffered() >= size) {
+            self.auto_flush()?;
+        }
         Ok(total)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        if self.src.is_empty() {
-            return Ok(());
-        }
-        self.inner.as_mut().unwrap().write(&self.src)?;
-        self.src.truncate(0);
-        Ok(())
+        self.flush_src()?;
+        self.inner.as_mut().unwrap().write_pending()
     }
 }
 
-impl<W: io::Write> Inner<W> {
+impl<W: io::Write, C: Codec + Clone + Send + 'static> Inner<W, C> {
     fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
         let mut total = 0;
-        if !self.wrote_stream_ident {
-            self.wrote_stream_ident = true;
-            self.w.write_all(STREAM_IDENTIFIER)?;
-        }
+        self.write_stream_ident()?;
         while !buf.is_empty() {
             // Advance buf and get our block.
             let mut src = buf;
-            if src.len() > MAX_BLOCK_SIZE {
-                src = &src[0..MAX_BLOCK_SIZE];
+            if src.len() > self.block_size {
+                src = &src[0..self.block_size];
             }
             buf = &buf[src.len()..];
+            self.write_block(src)?;
+            total += src.len();
+        }
+        Ok(total)
+    }
 
-            let frame_data = compress_frame(
+    /// Write the stream identifier, unless it has been written already,
+    /// followed by the chunk naming the filter if there is one that hasn't
+    /// been named yet.
+    fn write_stream_ident(&mut self) -> io::Result<()> {
+        if !self.wrote_stream_ident {
+            self.wrote_stream_ident = true;
+            self.w.write_all(STREAM_IDENTIFIER)?;
+            self.bytes_out += STREAM_IDENTIFIER.len() as u64;
+        }
+        if !self.wrote_filter {
+            self.wrote_filter = true;
+            if let Some(ref filter) = self.filter {
+                let name = filter.name().as_bytes();
+                let mut header = [FILTER_CHUNK_TYPE, 0, 0, 0];
+                bytes::write_u24_le(name.len() as u32, &mut header[1..]);
+                self.w.write_all(&header)?;
+                self.w.write_all(name)?;
+                self.bytes_out += (header.len() + name.len()) as u64;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compress and write a single block as one chunk, or add it to the
+    /// pending blocks when compressing blocks concurrently.
+    fn write_block(&mut self, src: &[u8]) -> io::Result<()> {
+        if let Some(ref mut digest) = self.digest {
+            digest.update(src);
+        }
+        if self.threads > 1 {
+            let mut block = self.spare_blocks.pop().unwrap_or_default();
+            block.clear();
+            block.extend_from_slice(src);
+            self.pending.push(block);
+            if self.pending.len() >= self.threads {
+                self.write_pending()?;
+            }
+            return Ok(());
+        }
+        let mut filtered = mem::replace(&mut self.filter_buf, vec![]);
+        let src = match self.filter {
+            None => src,
+            Some(ref mut filter) => {
+                filtered.clear();
+                filtered.extend_from_slice(src);
+                filter.encode(&mut filtered);
+                &filtered[..]
+            }
+        };
+        let frame_data = if self.checksum_thread {
+            if self.checksums.is_none() {
+                self.checksums = Some(ChecksumThread::spawn(self.checksummer));
+            }
+            let checksums = self.checksums.as_mut().unwrap();
+            checksums.send(src)?;
+            let frame_data = compress_frame_data(
+                &mut self.enc,
+                src,
+                &mut self.chunk_header,
+                &mut self.dst,
+                false,
+                self.policy,
+            )?;
+            let checksum = checksums.recv()?;
+            bytes::write_u32_le(checksum, &mut self.chunk_header[4..]);
+            frame_data
+        } else {
+            compress_frame(
                 &mut self.enc,
                 self.checksummer,
                 src,
                 &mut self.chunk_header,
                 &mut self.dst,
                 false,
+                self.policy,
+            )?
+        };
+        if self.verify {
+            verify_frame(
+                &mut self.enc,
+                &self.chunk_header,
+                frame_data,
+                src,
+                &mut self.verify_buf,
             )?;
-            self.w.write_all(&self.chunk_header)?;
-            self.w.write_all(frame_data)?;
-            total += src.len();
         }
-        Ok(total)
+        self.w.write_all(&self.chunk_header)?;
+        self.w.write_all(frame_data)?;
+        self.bytes_in += src.len() as u64;
+        self.bytes_out += (self.chunk_header.len() + frame_data.len()) as u64;
+        self.filter_buf = filtered;
+        Ok(())
+    }
+
+    /// Compress every pending block on its own worker, and write the
+    /// resulting chunks in the order their blocks were written.
+    fn write_pending(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        // A filter set since the blocks were written must be named before
+        // the chunks it's applied to.
+        self.write_stream_ident()?;
+        while self.workers.len() < self.pending.len() {
+            self.workers.push(Worker::spawn(self.enc.clone()));
+        }
+        let count = self.pending.len();
+        for (i, mut src) in self.pending.drain(..).enumerate() {
+            // The filter may keep state between blocks, so it's applied
+            // here, in order, rather than on the workers.
+            if let Some(ref mut filter) = self.filter {
+                filter.encode(&mut src);
+            }
+            let job = Job {
+                src,
+                dst: mem::replace(&mut self.workers[i].spare, vec![]),
+                checksummer: self.checksummer,
+                policy: self.policy,
+                verify: self.verify,
+            };
+            if let Err(err) = self.workers[i].send(job) {
+                // The workers that were given a block would send its chunk
+                // back with the next batch, so start over with new ones.
+                self.workers.clear();
+                return Err(err);
+            }
+        }
+        // Every chunk is received before any error is reported, so that the
+        // workers are all idle once this returns.
+        let received = self.workers[..count]
+            .iter()
+            .map(Worker::recv)
+            .collect::<io::Result<Vec<_>>>();
+        let received = match received {
+            Ok(received) => received,
+            Err(err) => {
+                self.workers.clear();
+                return Err(err);
+            }
+        };
+        let mut chunks = Vec::with_capacity(count);
+        for chunk in received {
+            match chunk {
+                Ok(chunk) => chunks.push(chunk),
+                Err(err) => {
+                    // The worker that panicked has exited.
+                    self.workers.clear();
+                    panic::resume_unwind(err);
+                }
+            }
+        }
+        for (worker, chunk) in self.workers.iter_mut().zip(chunks) {
+            let len = chunk.len?;
+            self.w.write_all(&chunk.header)?;
+            self.w.write_all(&chunk.dst[..len])?;
+            self.bytes_in += chunk.src.len() as u64;
+            self.bytes_out += (chunk.header.len() + len) as u64;
+            worker.spare = chunk.dst;
+            self.spare_blocks.push(chunk.src);
+        }
+        Ok(())
+    }
+}
+
+impl Worker {
+    fn spawn<C: Codec + Send + 'static>(mut enc: C) -> Worker {
+        let (jobs, jobs_rx) = mpsc::channel::<Job>();
+        let (chunks_tx, chunks) = mpsc::channel();
+        // The thread exits once `jobs` is dropped along with the encoder.
+        thread::spawn(move || {
+            let mut verify_buf = vec![];
+            for job in jobs_rx {
+                let Job { src, mut dst, checksummer, policy, verify } = job;
+                let mut header = [0; CHUNK_HEADER_AND_CRC_SIZE];
+                // A panic is sent back to be resumed on the encoder's thread.
+                let len = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+                    let len = compress_frame(
+                        &mut enc,
+                        checksummer,
+                        &src,
+                        &mut header,
+                        &mut dst,
+                        true,
+                        policy,
+                    )
+                    .map(|data| data.len());
+                    match len {
+                        Ok(len) if verify => verify_frame(
+                            &mut enc,
+                            &header,
+                            &dst[..len],
+                            &src,
+                            &mut verify_buf,
+                        )
+                        .map(|()| len),
+                        len => len,
+                    }
+                }));
+                let chunk = match len {
+                    Ok(len) => Ok(Chunk { header, len, src, dst }),
+                    Err(err) => {
+                        let _ = chunks_tx.send(Err(err));
+                        break;
+                    }
+                };
+                if chunks_tx.send(chunk).is_err() {
+                    break;
+                }
+            }
+        });
+        Worker { jobs, chunks, spare: vec![] }
+    }
+
+    /// Give a block to the worker to compress.
+    fn send(&mut self, mut job: Job) -> io::Result<()> {
+        if job.dst.len() < MAX_COMPRESS_BLOCK_SIZE {
+            job.dst.resize(MAX_COMPRESS_BLOCK_SIZE, 0);
+        }
+        self.jobs.send(job).map_err(|_| exited("compression"))
+    }
+
+    /// Wait for the chunk of the block most recently sent.
+    fn recv(&self) -> io::Result<thread::Result<Chunk>> {
+        self.chunks.recv().map_err(|_| exited("compression"))
     }
 }
 
-impl<W: fmt::Debug + io::Write> fmt::Debug for FrameEncoder<W> {
+impl ChecksumThread {
+    fn spawn(checksummer: CheckSummer) -> ChecksumThread {
+        let (blocks, blocks_rx) = mpsc::channel::<Vec<u8>>();
+        let (sums_tx, sums) = mpsc::channel();
+        // The thread exits once `blocks` is dropped along with the encoder.
+        thread::spawn(move || {
+            for block in blocks_rx {
+                let sum = checksummer.crc32c_masked(&block);
+                if sums_tx.send((block, sum)).is_err() {
+                    break;
+                }
+            }
+        });
+        ChecksumThread { blocks, sums, spare: vec![] }
+    }
+
+    /// Give a copy of `src` to the helper thread to checksum.
+    fn send(&mut self, src: &[u8]) -> io::Result<()> {
+        let mut block = mem::replace(&mut self.spare, vec![]);
+        block.clear();
+        block.extend_from_slice(src);
+        self.blocks.send(block).map_err(|_| exited("checksum"))
+    }
+
+    /// Wait for the checksum of the block most recently sent.
+    fn recv(&mut self) -> io::Result<u32> {
+        let (block, sum) = self.sums.recv().map_err(|_| exited("checksum"))?;
+        self.spare = block;
+        Ok(sum)
+    }
+}
+
+fn exited(name: &str) -> io::Error {
+    let msg = format!("snappy: {} thread exited", name);
+    io::Error::new(io::ErrorKind::Other, msg)
+}
+
+impl<W, C> fmt::Debug for FrameEncoder<W, C>
+where
+    W: fmt::Debug + io::Write,
+    C: fmt::Debug + Codec + Clone + Send + 'static,
+{
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("FrameEncoder")
             .field("inner", &self.inner)
@@ -202,15 +1115,419 @@ impl<W: fmt::Debug + io::Write> fmt::Debug for FrameEncoder<W> {
     }
 }
 
-impl<W: fmt::Debug + io::Write> fmt::Debug for Inner<W> {
+impl<W: fmt::Debug, C: fmt::Debug> fmt::Debug for Inner<W, C> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Inner")
             .field("w", &self.w)
             .field("enc", &self.enc)
+            .field("block_size", &self.block_size)
             .field("checksummer", &self.checksummer)
             .field("dst", &"[...]")
             .field("wrote_stream_ident", &self.wrote_stream_ident)
             .field("chunk_header", &self.chunk_header)
+            .field("policy", &self.policy)
+            .field("threads", &self.threads)
+            .field("pending", &"[...]")
+            .field("spare_blocks", &"[...]")
+            .field("workers", &self.workers.len())
+            .field("checksum_thread", &self.checksum_thread)
+            .field("checksums", &"...")
+            .field("verify", &self.verify)
+            .field("verify_buf", &"[...]")
+            .field("max_write_blocks", &self.max_write_blocks)
+            .field("auto_flush_size", &self.auto_flush_size)
+            .field("auto_flush_interval", &self.auto_flush_interval)
+            .field("buffered_since", &self.buffered_since)
+            .field("bytes_in", &self.bytes_in)
+            .field("bytes_out", &self.bytes_out)
+            .field("digest", &self.digest.as_ref().map(|d| d.name()))
+            .field("filter", &self.filter.as_ref().map(|f| f.name()))
+            .field("wrote_filter", &self.wrote_filter)
+            .field("filter_buf", &"[...]")
             .finish()
     }
 }
+
+/// The magic bytes that start a serialized `Checkpoint`.
+const CHECKPOINT_MAGIC: &[u8] = b"sNaPcKpT";
+
+/// The number of bytes in a serialized `Checkpoint` besides the bytes it
+/// buffered and the name of its filter: the magic bytes, a flag byte, two
+/// counters, a byte for the state of the filter and a checksum.
+const CHECKPOINT_OVERHEAD: usize = 8 + 1 + 8 + 8 + 1 + 4;
+
+/// The state of a [`FrameEncoder`](struct.FrameEncoder.html), from which
+/// it can resume writing a stream.
+///
+/// A checkpoint is taken with
+/// [`FrameEncoder::checkpoint`](struct.FrameEncoder.html#method.checkpoint)
+/// and resumed with
+/// [`FrameEncoder::resume`](struct.FrameEncoder.html#method.resume). In
+/// between, it can be saved with [`to_bytes`](#method.to_bytes) and loaded
+/// with [`from_bytes`](#method.from_bytes), so that an appender can continue
+/// a valid stream after a crash:
+///
+/// ```no_run
+/// use std::fs::{self, OpenOptions};
+/// use std::io::Write;
+///
+/// use snap::frame::EncoderOptions;
+/// use snap::write::{Checkpoint, FrameEncoder};
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let checkpoint = Checkpoint::from_bytes(&fs::read("log.sz.ckpt")?)?;
+/// let file = OpenOptions::new().write(true).open("log.sz")?;
+/// file.set_len(checkpoint.compressed_len())?;
+/// let file = OpenOptions::new().append(true).open("log.sz")?;
+/// let opts = EncoderOptions::new();
+/// let mut wtr = FrameEncoder::resume(file, &opts, &checkpoint)?;
+/// wtr.write_all(b"more data")?;
+/// # Ok(()) }
+/// ```
+#[derive(Clone, Eq, PartialEq)]
+pub struct Checkpoint {
+    /// Whether the stream identifier has been written.
+    stream_started: bool,
+    /// The number of uncompressed bytes written as chunks.
+    bytes_in: u64,
+    /// The number of bytes written to the underlying writer.
+    bytes_out: u64,
+    /// The name of the filter, if the stream was being filtered.
+    filter: Option<String>,
+    /// Whether the chunk naming the filter has been written.
+    named_filter: bool,
+    /// The uncompressed bytes not yet written as chunks.
+    buffered: Vec<u8>,
+}
+
+impl Checkpoint {
+    /// Returns the number of bytes that the encoder had written to its
+    /// underlying writer. The stream must be truncated to this length before
+    /// resuming it.
+    pub fn compressed_len(&self) -> u64 {
+        self.bytes_out
+    }
+
+    /// Returns the number of uncompressed bytes that had been written to the
+    /// encoder, including those it buffered.
+    pub fn uncompressed_len(&self) -> u64 {
+        self.bytes_in + self.buffered.len() as u64
+    }
+
+    /// Returns the uncompressed bytes that the encoder buffered, which
+    /// haven't been written to the underlying writer yet.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffered
+    }
+
+    /// Returns the name of the filter the encoder was filtering the stream
+    /// with, if any. A stream with a filter must be resumed with
+    /// [`FrameEncoder::resume_with_filter`](struct.FrameEncoder.html#method.resume_with_filter).
+    pub fn filter(&self) -> Option<&str> {
+        self.filter.as_ref().map(|name| &name[..])
+    }
+
+    /// Serializes this checkpoint.
+    ///
+    /// The result is the bytes buffered by the encoder plus 30 bytes, plus
+    /// the name of the filter and a byte for its length if there is one. It
+    /// includes a checksum, so that a checkpoint that was only partially
+    /// saved is rejected by [`from_bytes`](#method.from_bytes).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let name_len = self.filter.as_ref().map_or(0, |name| 1 + name.len());
+        let mut buf = Vec::with_capacity(
+            CHECKPOINT_OVERHEAD + name_len + self.buffered.len(),
+        );
+        buf.extend_from_slice(CHECKPOINT_MAGIC);
+        buf.push(self.stream_started as u8);
+        buf.extend_from_slice(&self.bytes_in.to_le_bytes());
+        buf.extend_from_slice(&self.bytes_out.to_le_bytes());
+        match self.filter {
+            None => buf.push(0),
+            Some(ref name) => {
+                buf.push(if self.named_filter { 1 } else { 2 });
+                buf.push(name.len() as u8);
+                buf.extend_from_slice(name.as_bytes());
+            }
+        }
+        buf.extend_from_slice(&self.buffered);
+        let checksum = CheckSummer::new().crc32c_masked(&buf);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+        buf
+    }
+
+    /// Deserializes a checkpoint serialized by
+    /// [`to_bytes`](#method.to_bytes).
+    ///
+    /// If the bytes aren't a checkpoint, or its checksum doesn't match, then
+    /// `Error::Checkpoint` is returned.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Checkpoint, Error> {
+        if bytes.len() < CHECKPOINT_OVERHEAD
+            || &bytes[..8] != CHECKPOINT_MAGIC
+            || bytes[8] > 1
+            || bytes[25] > 2
+        {
+            return Err(Error::Checkpoint);
+        }
+        let (body, sum) = bytes.split_at(bytes.len() - 4);
+        if CheckSummer::new().crc32c_masked(body) != bytes::read_u32_le(sum) {
+            return Err(Error::Checkpoint);
+        }
+        let read_u64 = |i: usize| {
+            let mut n = [0; 8];
+            n.copy_from_slice(&body[i..i + 8]);
+            u64::from_le_bytes(n)
+        };
+        let (filter, rest) = match body[25] {
+            0 => (None, &body[26..]),
+            _ => {
+                let name_len = match body.get(26) {
+                    Some(&n) if body.len() >= 27 + n as usize => n as usize,
+                    _ => return Err(Error::Checkpoint),
+                };
+                let name = body[27..27 + name_len].to_vec();
+                match String::from_utf8(name) {
+                    Ok(name) => (Some(name), &body[27 + name_len..]),
+                    Err(_) => return Err(Error::Checkpoint),
+                }
+            }
+        };
+        Ok(Checkpoint {
+            stream_started: body[8] == 1,
+            bytes_in: read_u64(9),
+            bytes_out: read_u64(17),
+            filter,
+            named_filter: body[25] == 1,
+            buffered: rest.to_vec(),
+        })
+    }
+}
+
+impl fmt::Debug for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Checkpoint")
+            .field("stream_started", &self.stream_started)
+            .field("bytes_in", &self.bytes_in)
+            .field("bytes_out", &self.bytes_out)
+            .field("filter", &self.filter)
+            .field("named_filter", &self.named_filter)
+            .field("buffered", &"[...]")
+            .finish()
+    }
+}
+
+/// The number of bytes `BackgroundWriter` buffers before handing them to its
+/// thread. This is enough for at least one chunk of the largest size.
+const BACKGROUND_BUFFER_SIZE: usize = 1 << 17;
+
+/// A writer that writes to another writer on a separate thread.
+///
+/// Bytes written to this writer are collected in a buffer. Once the buffer is
+/// full, it is handed to a thread that writes it to the underlying writer
+/// while a second buffer collects the bytes that follow. Only once that
+/// buffer is full too does a write wait for the thread to finish with the
+/// first one.
+///
+/// This is useful for wrapping a slow writer given to a
+/// [`FrameEncoder`](struct.FrameEncoder.html), so that compressing the next
+/// chunks isn't held up by writing the previous ones:
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::Write;
+///
+/// use snap::write::{BackgroundWriter, FrameEncoder};
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let file = File::create("data.sz")?;
+/// let mut wtr = FrameEncoder::new(BackgroundWriter::new(file));
+/// wtr.write_all(b"some data")?;
+/// let file = wtr.into_inner()?.into_inner()?;
+/// # Ok(()) }
+/// ```
+///
+/// An error returned by the underlying writer is reported by the next call to
+/// `write` or `flush`.
+///
+/// The writer will be flushed automatically when it is dropped. If an error
+/// occurs, it is ignored.
+pub struct BackgroundWriter<W: io::Write + Send + 'static> {
+    /// The bytes that have not been handed to the thread yet.
+    buf: Vec<u8>,
+    /// A buffer to swap with `buf` once it's full.
+    spare: Vec<u8>,
+    /// Whether the thread has a buffer that it hasn't sent back yet.
+    busy: bool,
+    /// Sends buffers to the thread, along with whether to flush afterwards.
+    /// This is only `None` once the thread has been told to stop.
+    to_thread: Option<mpsc::Sender<(Vec<u8>, bool)>>,
+    /// Receives emptied buffers from the thread, along with the result of
+    /// writing them.
+    from_thread: mpsc::Receiver<(Vec<u8>, io::Result<()>)>,
+    /// The thread, which returns the underlying writer when it stops.
+    thread: Option<thread::JoinHandle<W>>,
+}
+
+impl<W: io::Write + Send + 'static> BackgroundWriter<W> {
+    /// Create a new writer that writes to `wtr` on a separate thread.
+    pub fn new(mut wtr: W) -> BackgroundWriter<W> {
+        let (to_thread, rx) = mpsc::channel::<(Vec<u8>, bool)>();
+        let (tx, from_thread) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            for (mut buf, flush) in rx {
+                let mut res = wtr.write_all(&buf);
+                if res.is_ok() && flush {
+                    res = wtr.flush();
+                }
+                buf.clear();
+                if tx.send((buf, res)).is_err() {
+                    break;
+                }
+            }
+            wtr
+        });
+        BackgroundWriter {
+            buf: Vec::with_capacity(BACKGROUND_BUFFER_SIZE),
+            spare: Vec::with_capacity(BACKGROUND_BUFFER_SIZE),
+            busy: false,
+            to_thread: Some(to_thread),
+            from_thread,
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns the underlying writer, consuming and flushing this writer.
+    ///
+    /// If flushing the writer caused an error, then an `IntoInnerError` is
+    /// returned, which contains both this writer and the error.
+    pub fn into_inner(
+        mut self,
+    ) -> Result<W, IntoInnerError<BackgroundWriter<W>>> {
+        match self.flush() {
+            Ok(()) => Ok(self.stop()),
+            Err(err) => Err(IntoInnerError::new(self, err)),
+        }
+    }
+
+    /// Wait for the thread to send back the buffer it has, if any.
+    fn wait(&mut self) -> io::Result<()> {
+        if !self.busy {
+            return Ok(());
+        }
+        // The thread only hangs up early if it panicked.
+        let (buf, res) =
+            self.from_thread.recv().map_err(|_| exited("writer"))?;
+        self.busy = false;
+        self.spare = buf;
+        res
+    }
+
+    /// Hand `buf` to the thread, after waiting for it to finish with the
+    /// buffer it has.
+    fn send(&mut self, flush: bool) -> io::Result<()> {
+        self.wait()?;
+        let buf = mem::replace(&mut self.buf, vec![]);
+        self.buf = mem::replace(&mut self.spare, vec![]);
+        self.to_thread
+            .as_ref()
+            .unwrap()
+            .send((buf, flush))
+            .map_err(|_| exited("writer"))?;
+        self.busy = true;
+        Ok(())
+    }
+
+    /// Tell the thread to stop, and return the underlying writer once it has.
+    /// If the thread panicked, then the panic is resumed here.
+    fn stop(&mut self) -> W {
+        self.to_thread = None;
+        match self.thread.take().unwrap().join() {
+            Ok(wtr) => wtr,
+            Err(err) => panic::resume_unwind(err),
+        }
+    }
+}
+
+impl<W: io::Write + Send + 'static> Drop for BackgroundWriter<W> {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            // Ignore errors because we can't conceivably return an error and
+            // panicing in a dtor is bad juju.
+            let _ = self.flush();
+            self.to_thread = None;
+            let _ = self.thread.take().unwrap().join();
+        }
+    }
+}
+
+impl<W: io::Write + Send + 'static> io::Write for BackgroundWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= BACKGROUND_BUFFER_SIZE {
+            self.send(false)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send(true)?;
+        self.wait()
+    }
+}
+
+impl<W: io::Write + Send + 'static> fmt::Debug for BackgroundWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BackgroundWriter")
+            .field("buf", &"[...]")
+            .field("spare", &"[...]")
+            .field("busy", &self.busy)
+            .finish()
+    }
+}
+
+/// A writer that can make the bytes written to it durable.
+///
+/// This is what the `sync_data` and `sync_all` methods of
+/// [`FrameEncoder`](struct.FrameEncoder.html) need from its underlying
+/// writer. It is implemented for `File`, where it calls the methods of the
+/// same names, and passed through `&mut W` and `BufWriter<W>`.
+pub trait SyncWrite: io::Write {
+    /// Makes the data written so far durable, such as with `fdatasync`.
+    fn sync_data(&mut self) -> io::Result<()>;
+
+    /// Makes the data written so far, and any metadata, durable, such as
+    /// with `fsync`.
+    fn sync_all(&mut self) -> io::Result<()>;
+}
+
+impl SyncWrite for File {
+    fn sync_data(&mut self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+
+    fn sync_all(&mut self) -> io::Result<()> {
+        File::sync_all(self)
+    }
+}
+
+impl<W: SyncWrite + ?Sized> SyncWrite for &mut W {
+    fn sync_data(&mut self) -> io::Result<()> {
+        (**self).sync_data()
+    }
+
+    fn sync_all(&mut self) -> io::Result<()> {
+        (**self).sync_all()
+    }
+}
+
+impl<W: SyncWrite> SyncWrite for io::BufWriter<W> {
+    fn sync_data(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.get_mut().sync_data()
+    }
+
+    fn sync_all(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.get_mut().sync_all()
+    }
+}
@@ -1,10 +1,39 @@
+use std::cmp::Reverse;
+use std::collections::VecDeque;
 use std::fs::{self, File};
 use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::bail;
 use filetime::{set_file_times, FileTime};
 
+use crate::mmap::Output;
+use crate::sched::{Pool, Worker};
+
+mod mmap;
+mod sched;
+
+/// The number of bytes at the start of each input that --auto tunes on.
+const AUTO_SAMPLE_SIZE: u64 = 1 << 20;
+
+/// How much worse a compression ratio --auto accepts in exchange for faster
+/// options.
+const AUTO_RATIO_SLACK: f64 = 0.01;
+
+/// The number of blocks compressed by each job when compressing with more
+/// than one thread.
+const BLOCKS_PER_JOB: usize = 16;
+
+/// How long `tail --follow` waits before checking for more data again.
+const TAIL_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The search depth that `recompress --level best` uses. Deeper searches
+/// take longer for little gain.
+const BEST_SEARCH_DEPTH: usize = 64;
+
 const ABOUT: &'static str = "
 szip compresses and decompresses data in the Snappy format.
 
@@ -21,10 +50,113 @@ decompression.
 The --raw flag can be used for compressing/decompressing the raw Snappy format.
 Note that this requires reading the entire input/output into memory. In
 general, you shouldn't use this flag unless you have a specific need to.
+
+The --auto flag compresses a sample from the start of each input with several
+candidate options, and uses the fastest ones whose compression ratio is within
+one percentage point of the best. It has no effect on decompression, or with
+--raw.
+
+The -j (short for --threads) flag (de)compresses the given files on a pool of
+that many threads. Files are started biggest first, and while a file is being
+compressed, runs of its blocks are handed out to any thread that's idle, so a
+single huge file doesn't hold up the rest once every file has been started.
+The output is identical to what a single thread produces.
+
+The --summary flag prints the original and compressed sizes, compression ratio,
+number of chunks and throughput of each file (de)compressed to stderr, along
+with totals when more than one file is given.
+
+The --mmap flag memory maps the files being (de)compressed, instead of reading
+and writing them through buffers. This can be faster for big files on fast
+storage. A file that can't be mapped is read or written as usual. This has no
+effect on stdin and stdout.
+
+The repair subcommand salvages what it can from a corrupt Snappy frame
+formatted file. See `szip repair --help` for details.
+
+The tail subcommand decompresses a Snappy frame formatted file that is still
+being written, such as a log. See `szip tail --help` for details.
+
+The split subcommand cuts a Snappy frame formatted file into volumes of a
+maximum size, and the join subcommand puts them back together. See
+`szip split --help` for details.
+
+The recompress subcommand compresses a Snappy frame formatted file again with
+other options. See `szip recompress --help` for details.
+
+The completions subcommand prints a completion script for the given shell.
+";
+
+const ABOUT_COMPLETIONS: &str = "
+Print a shell completion script for szip to stdout.
+
+For example, to enable completions in bash, add the output of
+`szip completions bash` to a file sourced by your shell. For zsh, write the
+output to a file named _szip in a directory in your $fpath.
+";
+
+const ABOUT_TAIL: &str = "
+Decompress a Snappy frame formatted file that is still being written.
+
+Every complete chunk in the file is decompressed and written to stdout. If the
+file ends in the middle of a chunk, as it may while a chunk is being appended,
+then that chunk is left alone instead of being reported as an error.
+
+With --follow, szip then waits for more data to be appended to the file, and
+prints each chunk as soon as it is complete, like `tail -f`.
+";
+
+const ABOUT_REPAIR: &str = "
+Salvage a corrupt Snappy frame formatted file.
+
+Every chunk whose checksum verifies is decompressed and written to a new Snappy
+frame formatted file (or stdout, if no output path is given). Regions of the
+input that could not be decoded are skipped, and a report of the byte ranges
+skipped is printed to stderr.
+";
+
+const ABOUT_SPLIT: &str = "
+Split a Snappy frame formatted file into volumes of at most the given size.
+
+The file is cut between chunks, without decompressing it, and every volume
+starts with its own stream identifier, followed by the chunk naming the filter
+in effect if the file is filtered, so each one can be decompressed on its own. The volumes of some-file.sz are written next to it as some-file.001.sz,
+some-file.002.sz, and so on. Use `szip join` to put them back together.
+
+The size is a number of bytes, optionally followed by K, M, G or T for powers
+of 1024, e.g., 1G. It must leave room for the biggest possible chunk.
+";
+
+const ABOUT_JOIN: &str = "
+Join volumes written by `szip split` back into one Snappy frame formatted file.
+
+The volumes must be given in order. They're concatenated without the stream
+identifier at the start of every volume but the first, and without the chunk
+naming the filter that split repeats after it, which reproduces the file that
+was split. The result is written to stdout, unless an output path is
+given.
+";
+
+const ABOUT_RECOMPRESS: &str = "
+Compress a Snappy frame formatted file again with other options.
+
+The file is decompressed and compressed again one chunk at a time, so no
+decompressed copy of it is ever written out. Every chunk's checksum is
+verified along the way.
+
+--block-size sets the maximum amount of data in each chunk, which is at most
+64K. Smaller chunks let readers start sooner and use less memory, but usually
+compress worse.
+
+--level picks how hard to compress. fast skips compressing chunks that look
+incompressible, default compresses as szip normally does, and best searches
+much harder for matches, which is slower to compress but yields smaller files
+that decompress just as quickly. This suits archives that are written once and
+read many times.
 ";
 
 fn app() -> clap::App<'static, 'static> {
-    use clap::{crate_authors, crate_version, App, Arg};
+    use clap::{crate_authors, crate_version, App, Arg, Shell, SubCommand};
 
     App::new("szip")
         .about(ABOUT)
@@ -56,30 +188,201 @@ fn app() -> clap::App<'static, 'static> {
                 .short("r")
                 .help("Use the \"raw\" Snappy format (no framing)."),
         )
+        .arg(
+            Arg::with_name("auto")
+                .long("auto")
+                .short("a")
+                .help("Choose compression options by sampling each input."),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .short("j")
+                .takes_value(true)
+                .value_name("N")
+                .help("(De)compress files on N threads (default is 1)."),
+        )
+        .arg(
+            Arg::with_name("summary")
+                .long("summary")
+                .short("s")
+                .help("Print a summary of each file (de)compressed."),
+        )
+        .arg(
+            Arg::with_name("mmap")
+                .long("mmap")
+                .help("Memory map the files being (de)compressed."),
+        )
+        .subcommand(
+            SubCommand::with_name("repair")
+                .about(ABOUT_REPAIR)
+                .arg(
+                    Arg::with_name("path")
+                        .required(true)
+                        .help("The corrupt file to repair."),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .help("Write the repaired file to this path."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("tail")
+                .about(ABOUT_TAIL)
+                .arg(
+                    Arg::with_name("path")
+                        .required(true)
+                        .help("The file to decompress."),
+                )
+                .arg(
+                    Arg::with_name("follow").long("follow").short("f").help(
+                        "Wait for more data to be appended to the file.",
+                    ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("split")
+                .about(ABOUT_SPLIT)
+                .arg(
+                    Arg::with_name("path")
+                        .required(true)
+                        .help("The Snappy frame formatted file to split."),
+                )
+                .arg(
+                    Arg::with_name("size")
+                        .long("size")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("SIZE")
+                        .help("The maximum size of each volume, e.g., 1G."),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .short("f")
+                        .help("Overwrite volumes that already exist."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("join")
+                .about(ABOUT_JOIN)
+                .arg(
+                    Arg::with_name("volumes")
+                        .required(true)
+                        .multiple(true)
+                        .help("The volumes to join, in order."),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .help("Write the joined file to this path."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("recompress")
+                .about(ABOUT_RECOMPRESS)
+                .arg(
+                    Arg::with_name("old")
+                        .required(true)
+                        .help("The Snappy frame formatted file to read."),
+                )
+                .arg(
+                    Arg::with_name("new")
+                        .required(true)
+                        .help("The file to write."),
+                )
+                .arg(
+                    Arg::with_name("block-size")
+                        .long("block-size")
+                        .takes_value(true)
+                        .value_name("SIZE")
+                        .help("The maximum data in each chunk, e.g., 16K."),
+                )
+                .arg(
+                    Arg::with_name("level")
+                        .long("level")
+                        .takes_value(true)
+                        .possible_values(&["fast", "default", "best"])
+                        .default_value("default")
+                        .help("How hard to compress."),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .short("f")
+                        .help("Overwrite the new file if it already exists."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("completions").about(ABOUT_COMPLETIONS).arg(
+                Arg::with_name("shell")
+                    .required(true)
+                    .possible_values(&Shell::variants())
+                    .help("The shell to print completions for."),
+            ),
+        )
 }
 
 fn main() -> anyhow::Result<()> {
-    let args = Args::parse()?;
+    let parsed = app().get_matches();
+    match parsed.subcommand() {
+        ("repair", Some(m)) => return repair(m),
+        ("tail", Some(m)) => return tail(m),
+        ("split", Some(m)) => return split(m),
+        ("join", Some(m)) => return join(m),
+        ("recompress", Some(m)) => return recompress(m),
+        ("completions", Some(m)) => return completions(m),
+        _ => {}
+    }
+    let args = Arc::new(Args::parse(&parsed)?);
     if args.paths.is_empty() {
         let stdin = io::stdin();
         let mut stdin = stdin.lock();
         let stdout = io::stdout();
         let mut stdout = stdout.lock();
-        if args.decompress {
-            args.decompress(&mut stdin, &mut stdout)?;
+        let summary = if args.decompress {
+            args.decompress(&mut stdin, &mut stdout)?
         } else {
-            args.compress(&mut stdin, &mut stdout)?;
+            args.compress(&mut stdin, &mut stdout)?
+        };
+        if args.summary {
+            summary.print("<stdin>")?;
         }
     } else {
-        for p in &args.paths {
-            if let Err(err) = args.do_file(p) {
-                writeln!(
-                    &mut std::io::stderr(),
-                    "{}: {:?}",
-                    p.display(),
-                    err
-                )?;
+        let mut total = Summary::default();
+        let mut report = |p: &Path, result: anyhow::Result<Summary>| {
+            match result {
+                Ok(summary) => {
+                    if args.summary {
+                        summary.print(&p.display().to_string())?;
+                        total.add(&summary);
+                    }
+                }
+                Err(err) => {
+                    writeln!(
+                        &mut std::io::stderr(),
+                        "{}: {:?}",
+                        p.display(),
+                        err
+                    )?;
+                }
             }
+            Ok::<(), anyhow::Error>(())
+        };
+        if args.threads > 1 {
+            do_files_parallel(&args, report)?;
+        } else {
+            for p in &args.paths {
+                report(p, args.do_file(p, None))?;
+            }
+        }
+        if args.summary && args.paths.len() > 1 {
+            total.print("total")?;
         }
     }
     Ok(())
@@ -92,25 +395,47 @@ struct Args {
     force: bool,
     keep: bool,
     raw: bool,
+    auto: bool,
+    summary: bool,
+    mmap: bool,
+    threads: usize,
 }
 
 impl Args {
-    fn parse() -> anyhow::Result<Args> {
-        let parsed = app().get_matches();
+    fn parse(parsed: &clap::ArgMatches<'_>) -> anyhow::Result<Args> {
         let paths = parsed
             .values_of_os("paths")
             .map(|paths| paths.into_iter().map(PathBuf::from).collect())
             .unwrap_or(vec![]);
+        let threads = match parsed.value_of("threads") {
+            None => 1,
+            Some(n) => match n.parse::<usize>() {
+                Ok(n) if n > 0 => n,
+                _ => bail!("invalid number of threads: {}", n),
+            },
+        };
         Ok(Args {
             paths,
             decompress: parsed.is_present("decompress"),
             force: parsed.is_present("force"),
             keep: parsed.is_present("keep"),
             raw: parsed.is_present("raw"),
+            auto: parsed.is_present("auto"),
+            summary: parsed.is_present("summary"),
+            mmap: parsed.is_present("mmap"),
+            threads,
         })
     }
 
-    fn do_file(&self, old_path: &Path) -> anyhow::Result<()> {
+    /// (De)compresses the file at `old_path` to a new file next to it.
+    ///
+    /// When given a worker, compression splits the file into jobs spawned
+    /// on the worker's pool.
+    fn do_file(
+        &self,
+        old_path: &Path,
+        worker: Option<&Worker>,
+    ) -> anyhow::Result<Summary> {
         let old_md = old_path.metadata()?;
         if old_md.is_dir() {
             bail!("is a directory");
@@ -121,13 +446,23 @@ impl Args {
             bail!("skipping, file already exists: {}", new_path.display());
         }
 
-        let old_file = io::BufReader::new(File::open(old_path)?);
-        let new_file = io::BufWriter::new(File::create(&new_path)?);
-        if self.decompress {
-            self.decompress(old_file, new_file)?;
-        } else {
-            self.compress(old_file, new_file)?;
-        }
+        let old_file = File::open(old_path)?;
+        let map = if self.mmap { mmap::map_input(&old_file) } else { None };
+        let old_file: Box<dyn Read + '_> = match map {
+            Some(ref map) => Box::new(&map[..]),
+            None => Box::new(io::BufReader::new(old_file)),
+        };
+        let mut new_file = Output::new(File::create(&new_path)?, self.mmap);
+        let summary = match worker {
+            _ if self.decompress => {
+                self.decompress(old_file, &mut new_file)?
+            }
+            Some(worker) if !self.raw => {
+                self.compress_parallel(worker, old_file, &mut new_file)?
+            }
+            _ => self.compress(old_file, &mut new_file)?,
+        };
+        new_file.finish()?;
 
         let last_access = FileTime::from_last_access_time(&old_md);
         let last_mod = FileTime::from_last_modification_time(&old_md);
@@ -135,7 +470,7 @@ impl Args {
         if !self.keep {
             fs::remove_file(old_path)?;
         }
-        Ok(())
+        Ok(summary)
     }
 
     fn new_path(&self, old_path: &Path) -> anyhow::Result<PathBuf> {
@@ -161,38 +496,666 @@ impl Args {
 
     fn compress<R: Read, W: Write>(
         &self,
-        mut src: R,
-        mut dst: W,
-    ) -> anyhow::Result<()> {
-        if self.raw {
+        src: R,
+        dst: W,
+    ) -> anyhow::Result<Summary> {
+        let start = Instant::now();
+        let mut src = Counter::new(src);
+        let mut dst = Counter::new(dst);
+        let chunks = if self.raw {
             // Read the entire src into memory and compress it.
             let mut buf = Vec::with_capacity(10 * (1 << 20));
             src.read_to_end(&mut buf)?;
             let compressed = snap::raw::Encoder::new().compress_vec(&buf)?;
             dst.write_all(&compressed)?;
+            1
         } else {
-            let mut dst = snap::write::FrameEncoder::new(dst);
+            let mut sample = vec![];
+            let options = self.frame_options(&mut src, &mut sample)?;
+            let mut dst =
+                snap::write::FrameEncoder::with_options(&mut dst, &options);
+            dst.write_all(&sample)?;
             io::copy(&mut src, &mut dst)?;
+            dst.flush()?;
+            // The encoder only ever emits full blocks, except for the last
+            // one, since we never flush it early.
+            let block_size = options.get_block_size() as u64;
+            (src.count + block_size - 1) / block_size
+        };
+        Ok(Summary {
+            original: src.count,
+            compressed: dst.count,
+            chunks,
+            elapsed: start.elapsed(),
+        })
+    }
+
+    /// Like `compress`, but compresses runs of `BLOCKS_PER_JOB` blocks as
+    /// jobs spawned on `worker`'s pool, so that idle threads can help with a
+    /// big file.
+    ///
+    /// Blocks are split up exactly as `compress` splits them, so the output
+    /// is identical.
+    fn compress_parallel<R: Read, W: Write>(
+        &self,
+        worker: &Worker,
+        src: R,
+        dst: W,
+    ) -> anyhow::Result<Summary> {
+        let start = Instant::now();
+        let mut src = Counter::new(src);
+        let mut dst = Counter::new(dst);
+        let mut sample = vec![];
+        let options = Arc::new(self.frame_options(&mut src, &mut sample)?);
+        let block_size = options.get_block_size();
+        let run_len = (block_size * BLOCKS_PER_JOB) as u64;
+        let mut runs = VecDeque::new();
+        {
+            let mut src = io::Cursor::new(sample).chain(&mut src);
+            loop {
+                let mut run = vec![];
+                (&mut src).take(run_len).read_to_end(&mut run)?;
+                if run.is_empty() {
+                    break;
+                }
+                let (send, recv) = mpsc::channel();
+                let options = Arc::clone(&options);
+                worker.spawn(Box::new(move |_| {
+                    let _ = send.send(compress_run(&run, &options));
+                }));
+                runs.push_back(recv);
+                // Bound the memory used by a file that's read faster than
+                // it's compressed.
+                if runs.len() > self.threads {
+                    write_run(worker, &runs.pop_front().unwrap(), &mut dst)?;
+                }
+            }
+        }
+        while let Some(recv) = runs.pop_front() {
+            write_run(worker, &recv, &mut dst)?;
+        }
+        dst.flush()?;
+        let block_size = block_size as u64;
+        Ok(Summary {
+            original: src.count,
+            compressed: dst.count,
+            chunks: (src.count + block_size - 1) / block_size,
+            elapsed: start.elapsed(),
+        })
+    }
+
+    /// Returns the frame options to compress `src` with.
+    ///
+    /// With --auto, this reads a sample from the start of `src` into
+    /// `sample`, which must be compressed before the rest of `src`.
+    fn frame_options<R: Read>(
+        &self,
+        src: R,
+        sample: &mut Vec<u8>,
+    ) -> io::Result<snap::frame::EncoderOptions> {
+        if self.auto {
+            src.take(AUTO_SAMPLE_SIZE).read_to_end(sample)?;
+            Ok(auto_options(sample))
+        } else {
+            Ok(snap::frame::EncoderOptions::new())
         }
-        Ok(())
     }
 
     fn decompress<R: Read, W: Write>(
         &self,
-        mut src: R,
-        mut dst: W,
-    ) -> anyhow::Result<()> {
-        if self.raw {
+        src: R,
+        dst: W,
+    ) -> anyhow::Result<Summary> {
+        let start = Instant::now();
+        let mut src = Counter::new(src);
+        let mut dst = Counter::new(dst);
+        let chunks = if self.raw {
             // Read the entire src into memory and decompress it.
             let mut buf = Vec::with_capacity(10 * (1 << 20));
             src.read_to_end(&mut buf)?;
             let decompressed =
                 snap::raw::Decoder::new().decompress_vec(&buf)?;
             dst.write_all(&decompressed)?;
-        } else {
-            let mut src = snap::read::FrameDecoder::new(src);
+            1
+        } else if !self.summary {
+            // Chunks are only counted for the summary.
+            let mut src = snap::read::FrameDecoder::new(&mut src);
             io::copy(&mut src, &mut dst)?;
+            0
+        } else {
+            let mut src = snap::read::FrameDecoder::new(&mut src);
+            let mut buf = vec![];
+            let mut chunks = 0;
+            while src.read_message(&mut buf)? {
+                dst.write_all(&buf)?;
+                chunks += 1;
+            }
+            chunks
+        };
+        Ok(Summary {
+            original: dst.count,
+            compressed: src.count,
+            chunks,
+            elapsed: start.elapsed(),
+        })
+    }
+}
+
+/// (De)compresses every file in `args.paths` on a pool of `args.threads`
+/// threads, and calls `report` with the result of each file, in the order
+/// the files were given.
+fn do_files_parallel<F>(args: &Arc<Args>, mut report: F) -> anyhow::Result<()>
+where
+    F: FnMut(&Path, anyhow::Result<Summary>) -> anyhow::Result<()>,
+{
+    // Start the biggest files first, so that the last file started is a
+    // small one that the other threads don't have to wait long for.
+    let mut order: Vec<(usize, u64)> = args
+        .paths
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (i, p.metadata().map(|md| md.len()).unwrap_or(0)))
+        .collect();
+    order.sort_by_key(|&(_, len)| Reverse(len));
+
+    let pool = Pool::new(args.threads);
+    let (send, recv) = mpsc::channel();
+    for (i, _) in order {
+        let args = Arc::clone(args);
+        let send = send.clone();
+        pool.spawn(Box::new(move |worker| {
+            let result = args.do_file(&args.paths[i], Some(worker));
+            let _ = send.send((i, result));
+        }));
+    }
+    drop(send);
+
+    let mut results: Vec<_> = args.paths.iter().map(|_| None).collect();
+    let mut next = 0;
+    for (i, result) in recv {
+        results[i] = Some(result);
+        while let Some(result) = results.get_mut(next).and_then(Option::take) {
+            report(&args.paths[next], result)?;
+            next += 1;
+        }
+    }
+    pool.join();
+    Ok(())
+}
+
+/// Compresses `run` as a Snappy frame formatted stream, and returns its
+/// chunks without the stream identifier.
+fn compress_run(
+    run: &[u8],
+    options: &snap::frame::EncoderOptions,
+) -> io::Result<Vec<u8>> {
+    let mut data = vec![];
+    {
+        let mut enc =
+            snap::write::FrameEncoder::with_options(&mut data, options);
+        enc.write_all(run)?;
+        enc.flush()?;
+    }
+    data.drain(..snap::frame::STREAM_IDENTIFIER.len());
+    Ok(data)
+}
+
+/// Waits for the chunks of a run compressed by `compress_run` and writes
+/// them to `dst`, preceded by the stream identifier if nothing has been
+/// written yet.
+fn write_run<W: Write>(
+    worker: &Worker,
+    recv: &mpsc::Receiver<io::Result<Vec<u8>>>,
+    dst: &mut Counter<W>,
+) -> anyhow::Result<()> {
+    let data = match worker.wait(recv) {
+        Some(data) => data?,
+        None => bail!("a compression job panicked"),
+    };
+    if dst.count == 0 {
+        dst.write_all(snap::frame::STREAM_IDENTIFIER)?;
+    }
+    dst.write_all(&data)?;
+    Ok(())
+}
+
+/// Returns the options that --auto picks for compressing data like `sample`.
+///
+/// Smaller hash tables and skipping incompressible blocks are the options
+/// that make compression faster, so this picks the smallest table, preferring
+/// to skip, among the options whose ratio is close enough to the best.
+fn auto_options(sample: &[u8]) -> snap::frame::EncoderOptions {
+    let mut best_ratio = std::f64::MAX;
+    snap::frame::autotune(sample, |trial| {
+        best_ratio = best_ratio.min(trial.ratio());
+        0.0
+    });
+    snap::frame::autotune(sample, |trial| {
+        if trial.ratio() > best_ratio + AUTO_RATIO_SLACK {
+            return std::f64::MIN;
+        }
+        let options = trial.options();
+        let skip = if options.get_skip_incompressible() { 0.5 } else { 0.0 };
+        // Among equally fast options, prefer the best ratio.
+        skip - (options.get_max_table_size() as f64).log2() - trial.ratio()
+    })
+}
+
+/// A summary of a single (de)compression, printed by --summary.
+#[derive(Clone, Debug, Default)]
+struct Summary {
+    /// The size of the uncompressed data, in bytes.
+    original: u64,
+    /// The size of the compressed data, in bytes.
+    compressed: u64,
+    /// The number of chunks in the compressed data. This is always 1 for the
+    /// raw format.
+    chunks: u64,
+    /// The time it took to (de)compress.
+    elapsed: Duration,
+}
+
+impl Summary {
+    fn add(&mut self, other: &Summary) {
+        self.original += other.original;
+        self.compressed += other.compressed;
+        self.chunks += other.chunks;
+        self.elapsed += other.elapsed;
+    }
+
+    fn print(&self, name: &str) -> io::Result<()> {
+        let ratio = if self.original == 0 {
+            0.0
+        } else {
+            100.0 * self.compressed as f64 / self.original as f64
+        };
+        let secs = self.elapsed.as_secs_f64().max(1e-9);
+        let throughput = self.original as f64 / secs / (1 << 20) as f64;
+        writeln!(
+            io::stderr(),
+            "{}: {} bytes -> {} bytes ({:.2}%), {} chunks, {:.1} MB/s",
+            name,
+            self.original,
+            self.compressed,
+            ratio,
+            self.chunks,
+            throughput,
+        )
+    }
+}
+
+/// A reader or writer that counts the number of bytes passing through it.
+struct Counter<T> {
+    inner: T,
+    count: u64,
+}
+
+impl<T> Counter<T> {
+    fn new(inner: T) -> Counter<T> {
+        Counter { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for Counter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn completions(parsed: &clap::ArgMatches<'_>) -> anyhow::Result<()> {
+    let shell = parsed
+        .value_of("shell")
+        .unwrap()
+        .parse::<clap::Shell>()
+        .map_err(anyhow::Error::msg)?;
+    app().gen_completions_to("szip", shell, &mut io::stdout());
+    Ok(())
+}
+
+fn repair(parsed: &clap::ArgMatches<'_>) -> anyhow::Result<()> {
+    let path = Path::new(parsed.value_of_os("path").unwrap());
+    let src = io::BufReader::new(File::open(path)?);
+    let report = match parsed.value_of_os("output") {
+        Some(output) => {
+            let dst = io::BufWriter::new(File::create(output)?);
+            recover(src, dst)?
+        }
+        None => {
+            let stdout = io::stdout();
+            let dst = stdout.lock();
+            recover(src, dst)?
+        }
+    };
+
+    let mut stderr = io::stderr();
+    for range in report.skipped() {
+        writeln!(
+            stderr,
+            "{}: skipped bytes {}..{} ({} bytes)",
+            path.display(),
+            range.start,
+            range.end,
+            range.end - range.start,
+        )?;
+    }
+    writeln!(
+        stderr,
+        "{}: recovered {} chunks ({} bytes), lost {} compressed bytes",
+        path.display(),
+        report.chunks(),
+        report.bytes_written(),
+        report.bytes_lost(),
+    )?;
+    Ok(())
+}
+
+/// Salvages the readable chunks in `src` and writes them to `dst` as a new
+/// Snappy frame formatted stream.
+fn recover<R: Read, W: Write>(
+    src: R,
+    dst: W,
+) -> anyhow::Result<snap::frame::RecoverReport> {
+    use snap::frame::RecoverPolicy;
+
+    let mut dst = snap::write::FrameEncoder::new(dst);
+    let report =
+        snap::frame::recover(src, &mut dst, RecoverPolicy::VerifyChecksums)?;
+    dst.flush()?;
+    dst.get_mut().flush()?;
+    Ok(report)
+}
+
+fn tail(parsed: &clap::ArgMatches<'_>) -> anyhow::Result<()> {
+    let path = Path::new(parsed.value_of_os("path").unwrap());
+    let src = Follow {
+        file: File::open(path)?,
+        follow: parsed.is_present("follow"),
+    };
+    let mut src = snap::read::FrameDecoder::new(io::BufReader::new(src));
+    let stdout = io::stdout();
+    let mut dst = stdout.lock();
+    let mut chunk = vec![];
+    loop {
+        match src.read_message(&mut chunk) {
+            Ok(true) => {}
+            Ok(false) => break,
+            // Without --follow, a chunk that is still being appended ends
+            // the file.
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(err) => return Err(err.into()),
         }
-        Ok(())
+        dst.write_all(&chunk)?;
+        // Print each chunk as soon as it's complete, rather than when
+        // stdout's buffer fills up.
+        dst.flush()?;
     }
+    Ok(())
+}
+
+/// A reader of a file that, when following, waits for more data to be
+/// appended to the file instead of reporting the end of the file.
+struct Follow {
+    file: File,
+    follow: bool,
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.file.read(buf)?;
+            if n > 0 || buf.is_empty() || !self.follow {
+                return Ok(n);
+            }
+            thread::sleep(TAIL_POLL_INTERVAL);
+        }
+    }
+}
+
+fn split(parsed: &clap::ArgMatches<'_>) -> anyhow::Result<()> {
+    use snap::frame::{
+        FILTER_CHUNK_TYPE, MAX_COMPRESS_BLOCK_SIZE, STREAM_IDENTIFIER,
+    };
+
+    let path = Path::new(parsed.value_of_os("path").unwrap());
+    let size = parse_size(parsed.value_of("size").unwrap())?;
+    // A volume must hold the stream identifier, the chunk naming a filter
+    // (whose name is at most 255 bytes) and the biggest chunk.
+    let min = (2 * STREAM_IDENTIFIER.len() + 4 + 255 + MAX_COMPRESS_BLOCK_SIZE)
+        as u64;
+    if size < min {
+        bail!("volume size must be at least {} bytes", min);
+    }
+    let name = match path.file_name() {
+        None => bail!("missing file name"),
+        Some(name) => name.to_string_lossy(),
+    };
+    let stem = name.trim_end_matches(".sz");
+
+    let mut src = io::BufReader::new(File::open(path)?);
+    let mut chunk = vec![];
+    // An empty file is an empty stream, but anything else must start with
+    // a stream identifier.
+    if read_chunk(&mut src, &mut chunk).is_err()
+        || (!chunk.is_empty() && chunk[..] != *STREAM_IDENTIFIER)
+    {
+        bail!("{}: not a Snappy frame formatted file", path.display());
+    }
+    let mut volumes = 0;
+    let mut volume: Option<(io::BufWriter<File>, u64)> = None;
+    // The chunk naming the filter in effect, which every new volume repeats
+    // after its stream identifier. A stream identifier in the middle of the
+    // input ends the filter, and is kept like any other chunk.
+    let mut filter: Option<Vec<u8>> = None;
+    while let Some(len) = read_chunk(&mut src, &mut chunk)? {
+        let carried = filter.as_ref().map_or(0, |f| f.len() as u64);
+        if STREAM_IDENTIFIER.len() as u64 + carried + len > size {
+            bail!("{}: chunk of {} bytes doesn't fit in a volume", name, len);
+        }
+        if volume.as_ref().map_or(true, |&(_, used)| used + len > size) {
+            if let Some((mut dst, _)) = volume.take() {
+                dst.flush()?;
+            }
+            volumes += 1;
+            let (mut dst, mut used) =
+                create_volume(path, stem, volumes, parsed)?;
+            if let Some(ref filter) = filter {
+                dst.write_all(filter)?;
+                used += filter.len() as u64;
+            }
+            volume = Some((dst, used));
+        }
+        let &mut (ref mut dst, ref mut used) = volume.as_mut().unwrap();
+        dst.write_all(&chunk)?;
+        *used += len;
+        if chunk[..] == *STREAM_IDENTIFIER {
+            filter = None;
+        } else if chunk[0] == FILTER_CHUNK_TYPE {
+            filter = Some(chunk.clone());
+        }
+    }
+    match volume {
+        Some((mut dst, _)) => dst.flush()?,
+        // An empty stream still gets a volume, so that joining reproduces it.
+        None => {
+            volumes += 1;
+            create_volume(path, stem, volumes, parsed)?.0.flush()?;
+        }
+    }
+    writeln!(io::stderr(), "{}: wrote {} volumes", path.display(), volumes)?;
+    Ok(())
+}
+
+/// Creates the volume numbered `n` of the file at `path`, and writes a
+/// stream identifier to it.
+fn create_volume(
+    path: &Path,
+    stem: &str,
+    n: usize,
+    parsed: &clap::ArgMatches<'_>,
+) -> anyhow::Result<(io::BufWriter<File>, u64)> {
+    use snap::frame::STREAM_IDENTIFIER;
+
+    let volume_path = path.with_file_name(format!("{}.{:03}.sz", stem, n));
+    if !parsed.is_present("force") && volume_path.exists() {
+        bail!("volume already exists: {}", volume_path.display());
+    }
+    let mut dst = io::BufWriter::new(File::create(&volume_path)?);
+    dst.write_all(STREAM_IDENTIFIER)?;
+    Ok((dst, STREAM_IDENTIFIER.len() as u64))
+}
+
+/// Reads the next chunk, header and all, from `src` into `chunk`, and
+/// returns its length. At the end of `src`, this empties `chunk` and returns
+/// `None`.
+fn read_chunk<R: Read>(
+    src: &mut R,
+    chunk: &mut Vec<u8>,
+) -> anyhow::Result<Option<u64>> {
+    chunk.resize(4, 0);
+    let mut n = 0;
+    while n < 4 {
+        match src.read(&mut chunk[n..4])? {
+            0 if n == 0 => {
+                chunk.clear();
+                return Ok(None);
+            }
+            0 => bail!("file ends in the middle of a chunk"),
+            m => n += m,
+        }
+    }
+    let len = chunk[1] as usize
+        | (chunk[2] as usize) << 8
+        | (chunk[3] as usize) << 16;
+    chunk.resize(4 + len, 0);
+    if src.read_exact(&mut chunk[4..]).is_err() {
+        bail!("file ends in the middle of a chunk");
+    }
+    Ok(Some(chunk.len() as u64))
+}
+
+/// Parses a size in bytes, optionally followed by K, M, G or T.
+fn parse_size(size: &str) -> anyhow::Result<u64> {
+    let (digits, shift) = match size.chars().last() {
+        Some('K') | Some('k') => (&size[..size.len() - 1], 10),
+        Some('M') | Some('m') => (&size[..size.len() - 1], 20),
+        Some('G') | Some('g') => (&size[..size.len() - 1], 30),
+        Some('T') | Some('t') => (&size[..size.len() - 1], 40),
+        _ => (size, 0),
+    };
+    match digits.parse::<u64>() {
+        Ok(n) if n.leading_zeros() >= shift => Ok(n << shift),
+        _ => bail!("invalid size: {}", size),
+    }
+}
+
+fn recompress(parsed: &clap::ArgMatches<'_>) -> anyhow::Result<()> {
+    let old_path = Path::new(parsed.value_of_os("old").unwrap());
+    let new_path = Path::new(parsed.value_of_os("new").unwrap());
+    let mut options = snap::frame::EncoderOptions::new();
+    if let Some(size) = parsed.value_of("block-size") {
+        match parse_size(size)? {
+            0 => bail!("block size must not be 0"),
+            n if n > snap::frame::MAX_BLOCK_SIZE as u64 => {
+                bail!("block size must be at most 64K, got {}", size)
+            }
+            n => options.block_size(n as usize),
+        };
+    }
+    match parsed.value_of("level").unwrap() {
+        "fast" => options.skip_incompressible(true),
+        "best" => options.search_depth(BEST_SEARCH_DEPTH),
+        _ => &mut options,
+    };
+    if new_path.exists() {
+        // Creating the new file truncates it, which would destroy the old
+        // one before it's read if they're the same file.
+        if fs::canonicalize(old_path)? == fs::canonicalize(new_path)? {
+            bail!("cannot recompress a file into itself");
+        }
+        if !parsed.is_present("force") {
+            bail!("file already exists: {}", new_path.display());
+        }
+    }
+
+    let src = io::BufReader::new(File::open(old_path)?);
+    let dst = io::BufWriter::new(File::create(new_path)?);
+    if let Err(err) = snap::frame::transcode(src, dst, &options) {
+        let _ = fs::remove_file(new_path);
+        bail!("{}: {}", old_path.display(), err);
+    }
+    Ok(())
+}
+
+fn join(parsed: &clap::ArgMatches<'_>) -> anyhow::Result<()> {
+    let paths: Vec<PathBuf> =
+        parsed.values_of_os("volumes").unwrap().map(PathBuf::from).collect();
+    match parsed.value_of_os("output") {
+        Some(output) => {
+            let dst = io::BufWriter::new(File::create(output)?);
+            join_volumes(&paths, dst)
+        }
+        None => {
+            let stdout = io::stdout();
+            let dst = stdout.lock();
+            join_volumes(&paths, dst)
+        }
+    }
+}
+
+/// Writes the volumes at `paths` to `dst`, keeping only the first one's
+/// stream identifier, and dropping the chunk naming the filter in effect
+/// that `split` repeats at the start of every other volume.
+fn join_volumes<W: Write>(
+    paths: &[PathBuf],
+    mut dst: W,
+) -> anyhow::Result<()> {
+    use snap::frame::{FILTER_CHUNK_TYPE, STREAM_IDENTIFIER};
+
+    // The chunk naming the filter in effect at the end of what's been
+    // written so far.
+    let mut filter: Option<Vec<u8>> = None;
+    let mut chunk = vec![];
+    for (i, path) in paths.iter().enumerate() {
+        let mut src = io::BufReader::new(File::open(path)?);
+        if read_chunk(&mut src, &mut chunk).is_err()
+            || chunk[..] != *STREAM_IDENTIFIER
+        {
+            bail!("{}: not a Snappy frame formatted volume", path.display());
+        }
+        if i == 0 {
+            dst.write_all(&chunk)?;
+        }
+        let mut first = i > 0;
+        while read_chunk(&mut src, &mut chunk)?.is_some() {
+            let repeated = first && filter.as_ref() == Some(&chunk);
+            first = false;
+            if repeated {
+                continue;
+            }
+            if chunk[..] == *STREAM_IDENTIFIER {
+                filter = None;
+            } else if chunk[0] == FILTER_CHUNK_TYPE {
+                filter = Some(chunk.clone());
+            }
+            dst.write_all(&chunk)?;
+        }
+    }
+    dst.flush()?;
+    Ok(())
 }
@@ -0,0 +1,147 @@
+/*!
+Memory mapped input and output files, which szip uses with --mmap.
+
+Reading a mapped input hands the encoder or decoder the file's pages
+directly, instead of copying them through read calls into a buffer first.
+Since the size of an output isn't known up front, the output file is grown to
+double its size and mapped again whenever it fills up, and is cut down to
+what was written at the end.
+*/
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Write};
+
+use memmap::{Mmap, MmapMut};
+
+/// The length that a memory mapped output file starts out with. It doubles
+/// whenever it fills up, and is cut down to what was written at the end.
+const MIN_MAP_LEN: u64 = 1 << 20;
+
+/// Memory maps `file` for reading, or returns `None` if it can't be mapped,
+/// in which case it should be read as usual.
+///
+/// Empty files and anything other than regular files, such as pipes, are
+/// never mapped.
+pub fn map_input(file: &File) -> Option<Mmap> {
+    match file.metadata() {
+        Ok(ref md) if md.is_file() && md.len() > 0 => {}
+        _ => return None,
+    }
+    // This is only safe if nothing else changes the file while it's mapped.
+    // szip already assumes that its inputs don't change while it's working
+    // on them, although a file that's truncated now results in a crash
+    // (SIGBUS) rather than a short read.
+    unsafe { Mmap::map(file) }.ok()
+}
+
+/// The file that (de)compressed data is written to, either through a memory
+/// map or through a buffer.
+pub enum Output {
+    Mapped(MapWriter),
+    Buffered(io::BufWriter<File>),
+}
+
+impl Output {
+    /// Creates an output that writes to `file`, through a memory map if
+    /// `mmap` is true. If the file can't be mapped, then writes are buffered
+    /// as usual.
+    pub fn new(file: File, mmap: bool) -> Output {
+        let file = if mmap {
+            match MapWriter::new(file) {
+                Ok(wtr) => return Output::Mapped(wtr),
+                Err(file) => file,
+            }
+        } else {
+            file
+        };
+        Output::Buffered(io::BufWriter::new(file))
+    }
+
+    /// Finishes writing to this output. This must be called once everything
+    /// has been written, or a memory mapped file is left with garbage at the
+    /// end.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Mapped(wtr) => wtr.finish(),
+            Output::Buffered(mut wtr) => wtr.flush(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Output::Mapped(ref mut wtr) => wtr.write(buf),
+            Output::Buffered(ref mut wtr) => wtr.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Output::Mapped(ref mut wtr) => wtr.flush(),
+            Output::Buffered(ref mut wtr) => wtr.flush(),
+        }
+    }
+}
+
+/// A writer that copies everything written to it into a memory map of a
+/// file, growing the file as needed.
+pub struct MapWriter {
+    file: File,
+    /// The map of the whole file. This is only `None` while the file is
+    /// being grown, since some platforms can't resize a mapped file.
+    map: Option<MmapMut>,
+    /// The number of bytes written to the start of the map.
+    len: usize,
+}
+
+impl MapWriter {
+    /// Maps `file` for writing, or gives it back if it can't be mapped.
+    fn new(file: File) -> Result<MapWriter, File> {
+        if file.set_len(MIN_MAP_LEN).is_err() {
+            return Err(file);
+        }
+        match unsafe { MmapMut::map_mut(&file) } {
+            Ok(map) => Ok(MapWriter { file, map: Some(map), len: 0 }),
+            Err(_) => {
+                let _ = file.set_len(0);
+                Err(file)
+            }
+        }
+    }
+
+    /// Grows the file, and remaps it, so that at least `need` bytes fit.
+    fn grow(&mut self, need: usize) -> io::Result<()> {
+        let old = self.map.take().map_or(0, |map| map.len());
+        let len = cmp::max(need, 2 * old);
+        self.file.set_len(len as u64)?;
+        self.map = Some(unsafe { MmapMut::map_mut(&self.file)? });
+        Ok(())
+    }
+
+    /// Unmaps the file and cuts it down to the bytes written.
+    fn finish(mut self) -> io::Result<()> {
+        self.map = None;
+        self.file.set_len(self.len as u64)
+    }
+}
+
+impl Write for MapWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self.len + buf.len();
+        if self.map.as_ref().map_or(true, |map| map.len() < end) {
+            self.grow(end)?;
+        }
+        self.map.as_mut().unwrap()[self.len..end].copy_from_slice(buf);
+        self.len = end;
+        Ok(buf.len())
+    }
+
+    // The map shares the page cache with the file, so there's nothing to
+    // flush for other readers of the file to see what was written, just as
+    // with a `BufWriter` that was flushed.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
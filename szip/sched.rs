@@ -0,0 +1,208 @@
+/*!
+A small work-stealing thread pool, which szip uses to (de)compress many files
+at once.
+
+Jobs spawned from outside the pool, one per file, go on a shared queue that
+workers take from in order. Jobs spawned by a job, such as the runs of blocks
+of a big file, go on the spawning worker's own queue. A worker runs the newest
+job on its own queue first, and when that is empty, steals the oldest job
+from another worker's queue. This way, once every file has been started, the
+remaining threads help compress whatever big files are still going, rather
+than sitting idle while a single thread finishes them.
+*/
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// A unit of work run on the pool. It's given the worker that runs it, so
+/// that it can spawn more jobs.
+pub type Job = Box<dyn FnOnce(&Worker) + Send>;
+
+/// A fixed number of threads running jobs until the pool is joined.
+pub struct Pool {
+    shared: Arc<Shared>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Pool {
+    /// Start a pool with the given number of threads. A value of `0` is
+    /// treated as `1`.
+    pub fn new(threads: usize) -> Pool {
+        let threads = threads.max(1);
+        let shared = Arc::new(Shared {
+            injector: Mutex::new(VecDeque::new()),
+            deques: (0..threads)
+                .map(|_| Mutex::new(VecDeque::new()))
+                .collect(),
+            state: Mutex::new(State::default()),
+            wake: Condvar::new(),
+        });
+        let handles = (0..threads)
+            .map(|index| {
+                let worker = Worker { shared: Arc::clone(&shared), index };
+                thread::spawn(move || worker.run())
+            })
+            .collect();
+        Pool { shared, handles }
+    }
+
+    /// Add a job to the end of the shared queue.
+    pub fn spawn(&self, job: Job) {
+        self.shared.push(&self.shared.injector, job);
+    }
+
+    /// Wait for every job, including the ones spawned by other jobs, to
+    /// finish, and stop the threads.
+    ///
+    /// If any job panicked, then this panics with the first one's payload.
+    pub fn join(self) {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            state.closed = true;
+            self.shared.wake.notify_all();
+        }
+        for handle in self.handles {
+            // Workers catch the panics of their jobs, so they never panic
+            // themselves.
+            handle.join().unwrap();
+        }
+        let payload = self.shared.state.lock().unwrap().panic.take();
+        if let Some(payload) = payload {
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+/// A thread of a pool, as seen by the jobs it runs.
+pub struct Worker {
+    shared: Arc<Shared>,
+    index: usize,
+}
+
+impl Worker {
+    /// Add a job to this worker's own queue, where other workers can steal
+    /// it.
+    pub fn spawn(&self, job: Job) {
+        self.shared.push(&self.shared.deques[self.index], job);
+    }
+
+    /// Wait for a value from `recv`, which a job spawned on this pool sends,
+    /// running other spawned jobs in the meantime.
+    ///
+    /// Only jobs spawned by other jobs are run while waiting, never new
+    /// files, so that the file waiting isn't held up by another one. This
+    /// returns `None` if the job that would send the value panicked.
+    pub fn wait<T>(&self, recv: &Receiver<T>) -> Option<T> {
+        loop {
+            match recv.try_recv() {
+                Ok(value) => return Some(value),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => {}
+            }
+            match self.find(false) {
+                Some(job) => self.run_job(job),
+                // The job is already running on another worker.
+                None => return recv.recv().ok(),
+            }
+        }
+    }
+
+    /// Run jobs until the pool is joined and every job has finished.
+    fn run(&self) {
+        loop {
+            if let Some(job) = self.find(true) {
+                self.run_job(job);
+                continue;
+            }
+            let state = self.shared.state.lock().unwrap();
+            if state.queued > 0 {
+                // A job was pushed after we looked (or is about to be), or
+                // another worker has taken one but not uncounted it yet.
+                continue;
+            }
+            if state.closed && state.running == 0 {
+                return;
+            }
+            drop(self.shared.wake.wait(state).unwrap());
+        }
+    }
+
+    /// Take the next job to run: the newest job on this worker's queue,
+    /// then, if `injector` is true, the oldest one on the shared queue, and
+    /// finally the oldest one on the queue of any other worker.
+    fn find(&self, injector: bool) -> Option<Job> {
+        let shared = &*self.shared;
+        let mut job = shared.deques[self.index].lock().unwrap().pop_back();
+        if job.is_none() && injector {
+            job = shared.injector.lock().unwrap().pop_front();
+        }
+        let n = shared.deques.len();
+        for i in 1..n {
+            if job.is_some() {
+                break;
+            }
+            let victim = &shared.deques[(self.index + i) % n];
+            job = victim.lock().unwrap().pop_front();
+        }
+        if job.is_some() {
+            shared.state.lock().unwrap().queued -= 1;
+        }
+        job
+    }
+
+    fn run_job(&self, job: Job) {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| job(self)));
+        let mut state = self.shared.state.lock().unwrap();
+        state.running -= 1;
+        if let Err(payload) = result {
+            if state.panic.is_none() {
+                state.panic = Some(payload);
+            }
+        }
+        if state.running == 0 {
+            self.shared.wake.notify_all();
+        }
+    }
+}
+
+struct Shared {
+    /// The jobs spawned from outside the pool.
+    injector: Mutex<VecDeque<Job>>,
+    /// The jobs spawned by each worker's jobs.
+    deques: Vec<Mutex<VecDeque<Job>>>,
+    state: Mutex<State>,
+    /// Signaled when a job is pushed, when the last job finishes, and when
+    /// the pool is joined.
+    wake: Condvar,
+}
+
+impl Shared {
+    fn push(&self, queue: &Mutex<VecDeque<Job>>, job: Job) {
+        // Count the job before publishing it, so that a worker that takes
+        // and finishes it can't uncount it first.
+        {
+            let mut state = self.state.lock().unwrap();
+            state.queued += 1;
+            state.running += 1;
+        }
+        queue.lock().unwrap().push_back(job);
+        self.wake.notify_one();
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// The number of jobs on a queue.
+    queued: usize,
+    /// The number of jobs that haven't finished, including queued ones.
+    running: usize,
+    /// Whether the pool has been joined, after which workers stop once
+    /// `running` is `0`.
+    closed: bool,
+    /// The payload of the first job that panicked.
+    panic: Option<Box<dyn Any + Send>>,
+}
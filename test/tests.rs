@@ -1,6 +1,8 @@
-use quickcheck::{QuickCheck, StdGen, TestResult};
+use quickcheck::{QuickCheck, StdGen, TestResult, Testable};
 use snap::raw::{decompress_len, Decoder, Encoder};
 use snap::Error;
+#[cfg(feature = "upstream")]
+use snap_upstream as upstream;
 #[cfg(feature = "cpp")]
 use snappy_cpp as cpp;
 
@@ -64,6 +66,9 @@ decompressed (len == {:?})
 //
 // If tests are compiled with the cpp feature, then this also tests that the
 // C++ library compresses to the same bytes that the Rust library does.
+//
+// If tests are compiled with the upstream feature, then this also tests that
+// the upstream snap crate and this one decompress each other's output.
 macro_rules! testtrip {
     ($name:ident, $data:expr) => {
         mod $name {
@@ -87,6 +92,36 @@ macro_rules! testtrip {
                 assert_eq!(read_frame_press(d), write_frame_press(d));
             }
 
+            #[test]
+            fn write_frame_encoder_threads_match() {
+                use super::{write_frame_press, write_frame_press_threads};
+                let d = &$data[..];
+                for &threads in &[2, 3, 8] {
+                    assert_eq!(
+                        write_frame_press(d),
+                        write_frame_press_threads(d, threads)
+                    );
+                }
+            }
+
+            #[test]
+            #[cfg(feature = "upstream")]
+            fn cmpupstream() {
+                use super::{
+                    depress, depress_upstream, press, press_upstream,
+                    read_frame_depress, read_frame_depress_upstream,
+                    write_frame_press, write_frame_press_upstream,
+                };
+
+                let d = &$data[..];
+                assert_eq!(d, &*depress_upstream(&press(d)));
+                assert_eq!(d, &*depress(&press_upstream(d)));
+                let frame = write_frame_press(d);
+                assert_eq!(d, &*read_frame_depress_upstream(&frame));
+                let frame = write_frame_press_upstream(d);
+                assert_eq!(d, &*read_frame_depress(&frame));
+            }
+
             #[test]
             #[cfg(feature = "cpp")]
             fn cmpcpp() {
@@ -166,6 +201,19 @@ fn data_golden_rev() {
     assert_eq!(data, &*press(&depress(data)));
 }
 
+// The framed golden data was checked against an independent CRC32C, so this
+// catches byte order mistakes in chunk headers and checksums, which a
+// roundtrip on a big-endian target can't.
+#[test]
+fn data_golden_frame() {
+    let data = &include_bytes!("../data/Mark.Twain-Tom.Sawyer.txt")[..];
+    let framed =
+        &include_bytes!("../data/Mark.Twain-Tom.Sawyer.txt.framedsnappy")[..];
+    assert_eq!(framed, &*write_frame_press(data));
+    assert_eq!(framed, &*read_frame_press(data));
+    assert_eq!(data, &*read_frame_depress(framed));
+}
+
 // Miscellaneous tests.
 #[test]
 fn small_copy() {
@@ -177,6 +225,19 @@ fn small_copy() {
     }
 }
 
+// Data that repeats with a short period decompresses to copies whose offsets
+// are less than their lengths, which the decoder replicates specially.
+#[test]
+fn short_period_copy() {
+    for period in 1..=17 {
+        for &len in &[20, 33, 100, 1000, 70_000] {
+            let data: Vec<u8> =
+                (0..len).map(|i| (i % period) as u8 + b'a').collect();
+            roundtrip!(data);
+        }
+    }
+}
+
 #[test]
 fn small_regular() {
     let mut i = 1;
@@ -301,265 +362,3404 @@ fn read_frame_encoder_big_and_little_buffers() {
     assert_eq!(big_out, little_out);
 }
 
-// Tests decompression on malformed data.
+// Compressing in chunks should produce independent raw blocks that
+// decompress back to the original input when concatenated.
+#[test]
+fn raw_compress_chunked() {
+    use snap::raw::compress_chunked;
 
-// An empty buffer.
-testerrored!(err_empty, &b""[..], Error::Empty);
+    let data = &include_bytes!("../data/html_x_4")[..];
+    for &chunk_size in &[1, 1000, 65536, 100_000, data.len()] {
+        let blocks: Vec<Vec<u8>> =
+            compress_chunked(data, chunk_size).collect();
+        assert_eq!((data.len() + chunk_size - 1) / chunk_size, blocks.len());
+        let mut got = vec![];
+        for (i, block) in blocks.iter().enumerate() {
+            let start = i * chunk_size;
+            let len = std::cmp::min(chunk_size, data.len() - start);
+            assert_eq!(len, decompress_len(block).unwrap());
+            got.extend_from_slice(&depress(block));
+        }
+        assert_eq!(data, &*got);
+    }
+    assert_eq!(0, compress_chunked(&[], 10).count());
+}
 
-// Decompress fewer bytes than the header reports.
-testerrored!(
-    err_header_mismatch,
-    &b"\x05\x00a"[..],
-    Error::HeaderMismatch { expected_len: 5, got_len: 1 }
-);
+// Skipping over bytes in a framed stream should land on the same bytes that
+// reading would, both within a chunk and across many chunks.
+#[test]
+fn read_frame_decoder_skip() {
+    use snap::read;
+    use std::io::Read;
 
-// An invalid varint (final byte has continuation bit set).
-testerrored!(err_varint1, &b"\xFF"[..], Error::Header, true);
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let compressed = write_frame_press(data);
+    for &n in &[0, 1, 100, 65536, 65537, 200_000] {
+        let mut dec = read::FrameDecoder::new(&compressed[..]);
+        assert_eq!(n as u64, dec.skip(n as u64).unwrap());
+        let mut rest = vec![];
+        dec.read_to_end(&mut rest).unwrap();
+        assert_eq!(&data[n..], &*rest);
+    }
 
-// A varint that overflows u64.
-testerrored!(
-    err_varint2,
-    &b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\x00"[..],
-    Error::Header,
-    true
-);
+    let mut dec = read::FrameDecoder::new(&compressed[..]);
+    let mut buf = [0; 10];
+    dec.read_exact(&mut buf).unwrap();
+    assert_eq!(data.len() as u64 - 10, dec.skip(std::u64::MAX).unwrap());
+    assert_eq!(0, dec.read(&mut buf).unwrap());
+}
 
-// A varint that fits in u64 but overflows u32.
-testerrored!(
-    err_varint3,
-    &b"\x80\x80\x80\x80\x10"[..],
-    Error::TooBig { given: 4294967296, max: 4294967295 },
-    true
-);
+// Iterating over the chunks of a framed stream should yield every chunk the
+// encoder wrote, in order.
+#[test]
+fn read_frame_decoder_chunks() {
+    use snap::read;
+    use std::io::Read;
 
-// A literal whose length is too small.
-// Since the literal length is 1, 'h' is read as a literal and 'i' is
-// interpreted as a copy 1 operation missing its offset byte.
-testerrored!(
-    err_lit,
-    &b"\x02\x00hi"[..],
-    Error::CopyRead { len: 1, src_len: 0 }
-);
-// A literal whose length is too big.
-testerrored!(
-    err_lit_big1,
-    &b"\x02\xechi"[..],
-    Error::Literal { len: 60, src_len: 2, dst_len: 2 }
-);
-// A literal whose length is too big, requires 1 extra byte to be read, and
-// src is too short to read that byte.
-testerrored!(
-    err_lit_big2a,
-    &b"\x02\xf0hi"[..],
-    Error::Literal { len: 4, src_len: 2, dst_len: 2 }
-);
-// A literal whose length is too big, requires 1 extra byte to be read,
-// src is too short to read the full literal.
-testerrored!(
-    err_lit_big2b,
-    &b"\x02\xf0hi\x00\x00\x00"[..],
-    Error::Literal {
-        len: 105, // because 105 == 'h' as u8 + 1
-        src_len: 4,
-        dst_len: 2,
-    }
-);
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let compressed = write_frame_press(data);
 
-// A copy 1 operation that stops at the tag byte. This fails because there's
-// no byte to read for the copy offset.
-testerrored!(
-    err_copy1,
-    &b"\x02\x00a\x01"[..],
-    Error::CopyRead { len: 1, src_len: 0 }
-);
-// A copy 2 operation that stops at the tag byte and another copy 2 operation
-// that stops after the first byte in the offset.
-testerrored!(
-    err_copy2a,
-    &b"\x11\x00a\x3e"[..],
-    Error::CopyRead { len: 2, src_len: 0 }
-);
-testerrored!(
-    err_copy2b,
-    &b"\x11\x00a\x3e\x01"[..],
-    Error::CopyRead { len: 2, src_len: 1 }
-);
-// Same as copy 2, but for copy 4.
-testerrored!(
-    err_copy3a,
-    &b"\x11\x00a\x3f"[..],
-    Error::CopyRead { len: 4, src_len: 0 }
-);
-testerrored!(
-    err_copy3b,
-    &b"\x11\x00a\x3f\x00"[..],
-    Error::CopyRead { len: 4, src_len: 1 }
-);
-testerrored!(
-    err_copy3c,
-    &b"\x11\x00a\x3f\x00\x00"[..],
-    Error::CopyRead { len: 4, src_len: 2 }
-);
-testerrored!(
-    err_copy3d,
-    &b"\x11\x00a\x3f\x00\x00\x00"[..],
-    Error::CopyRead { len: 4, src_len: 3 }
-);
+    let mut dec = read::FrameDecoder::new(&compressed[..]);
+    let chunks: Vec<Vec<u8>> =
+        dec.chunks().collect::<std::io::Result<_>>().unwrap();
+    assert_eq!((data.len() + 65535) / 65536, chunks.len());
+    assert!(chunks.iter().all(|c| c.len() <= 65536));
+    assert_eq!(data, &*chunks.concat());
 
-// A copy operation whose offset is zero.
-testerrored!(
-    err_copy_offset_zero,
-    &b"\x11\x00a\x01\x00"[..],
-    Error::Offset { offset: 0, dst_pos: 1 }
-);
+    let mut dec = read::FrameDecoder::new(&compressed[..]);
+    let mut buf = [0; 10];
+    dec.read_exact(&mut buf).unwrap();
+    let first = dec.chunks().next().unwrap().unwrap();
+    assert_eq!(&data[10..65536], &*first);
+}
 
-// A copy operation whose offset is too big.
-testerrored!(
-    err_copy_offset_big,
-    &b"\x11\x00a\x01\xFF"[..],
-    Error::Offset { offset: 255, dst_pos: 1 }
-);
+// Recovering a corrupt framed stream should drop only the damaged chunk and
+// report the region of input that was skipped.
+#[test]
+fn frame_recover() {
+    use snap::frame::{recover, RecoverPolicy};
 
-// A copy operation whose length is too big.
-testerrored!(
-    err_copy_len_big,
-    &b"\x05\x00a\x1d\x01"[..],
-    Error::CopyWrite { len: 11, dst_len: 4 }
-);
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let compressed = write_frame_press(data);
 
-// Selected random inputs pulled from quickcheck failure witnesses.
-testtrip!(
-    random1,
-    &[
-        0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 5, 0, 0,
-        1, 1, 0, 0, 1, 2, 0, 0, 2, 1, 0, 0, 2, 2, 0, 0, 0, 6, 0, 0, 3, 1, 0,
-        0, 0, 7, 0, 0, 1, 3, 0, 0, 0, 8, 0, 0, 2, 3, 0, 0, 0, 9, 0, 0, 1, 4,
-        0, 0, 1, 0, 0, 3, 0, 0, 1, 0, 1, 0, 0, 0, 10, 0, 0, 0, 0, 2, 4, 0, 0,
-        2, 0, 0, 3, 0, 1, 0, 0, 1, 5, 0, 0, 6, 0, 0, 0, 0, 11, 0, 0, 1, 6, 0,
-        0, 1, 7, 0, 0, 0, 12, 0, 0, 3, 2, 0, 0, 0, 13, 0, 0, 2, 5, 0, 0, 0, 3,
-        3, 0, 0, 0, 1, 8, 0, 0, 1, 0, 1, 0, 0, 0, 4, 1, 0, 0, 0, 0, 14, 0, 0,
-        0, 1, 9, 0, 0, 0, 1, 10, 0, 0, 0, 0, 1, 11, 0, 0, 0, 1, 0, 2, 0, 0, 0,
-        1, 1, 1, 0, 0, 0, 0, 5, 1, 0, 0, 0, 1, 2, 1, 0, 0, 0, 0, 0, 2, 6, 0,
-        0, 0, 0, 0, 1, 12, 0, 0, 0, 0, 0, 3, 4, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0,
-        0, 1, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-        0, 0, 0, 0,
-    ]
-);
-testtrip!(
-    random2,
-    &[
-        10, 2, 14, 13, 0, 8, 2, 10, 2, 14, 13, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-        0, 0,
-    ]
-);
-testtrip!(
-    random3,
-    &[0, 0, 0, 4, 1, 4, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,]
-);
-testtrip!(
-    random4,
-    &[
-        0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 5, 0, 0,
-        1, 1, 0, 0, 1, 2, 0, 0, 1, 3, 0, 0, 1, 4, 0, 0, 2, 1, 0, 0, 0, 4, 0,
-        1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
-    ]
-);
+    let mut out = vec![];
+    let report =
+        recover(&compressed[..], &mut out, RecoverPolicy::VerifyChecksums)
+            .unwrap();
+    assert_eq!(data, &*out);
+    assert_eq!(0, report.bytes_lost());
+    assert_eq!(7, report.chunks());
 
-// QuickCheck properties for testing that random data roundtrips.
-// These properties tend to produce the inputs for the "random" tests above.
+    // Find the boundaries of the third data chunk, which follows the stream
+    // identifier and two other chunks, and corrupt its data.
+    let mut start = 10;
+    for _ in 0..2 {
+        start += 4 + chunk_len(&compressed[start..]);
+    }
+    let end = start + 4 + chunk_len(&compressed[start..]);
+    let mut corrupt = compressed.clone();
+    corrupt[start + 100] ^= 0xFF;
 
-#[test]
-fn qc_roundtrip() {
-    fn p(bytes: Vec<u8>) -> bool {
-        depress(&press(&bytes)) == bytes
+    let mut out = vec![];
+    let report =
+        recover(&corrupt[..], &mut out, RecoverPolicy::VerifyChecksums)
+            .unwrap();
+    let mut expected = data[..2 * 65536].to_vec();
+    expected.extend_from_slice(&data[3 * 65536..]);
+    assert_eq!(expected, out);
+    assert_eq!(1, report.skipped().len());
+    assert_eq!(start as u64..end as u64, report.skipped()[0]);
+    assert_eq!(6, report.chunks());
+
+    fn chunk_len(chunk: &[u8]) -> usize {
+        chunk[1] as usize
+            | (chunk[2] as usize) << 8
+            | (chunk[3] as usize) << 16
     }
-    QuickCheck::new()
-        .gen(StdGen::new(rand::thread_rng(), 10_000))
-        .tests(1_000)
-        .quickcheck(p as fn(_) -> _);
 }
 
+// The framed output of the encoders should never exceed the worst case bound
+// reported by `frame::max_compress_len`.
 #[test]
-fn qc_roundtrip_stream() {
-    fn p(bytes: Vec<u8>) -> TestResult {
-        if bytes.is_empty() {
-            return TestResult::discard();
-        }
-        TestResult::from_bool(
-            read_frame_depress(&write_frame_press(&bytes)) == bytes,
-        )
+fn frame_max_compress_len() {
+    use snap::frame::max_compress_len;
+
+    let corpora: &[&[u8]] = &[
+        &[],
+        &[0],
+        include_bytes!("../data/html"),
+        include_bytes!("../data/fireworks.jpeg"),
+        include_bytes!("../data/html_x_4"),
+    ];
+    for &data in corpora {
+        let max = max_compress_len(data.len());
+        assert!(write_frame_press(data).len() <= max);
+        assert!(read_frame_press(data).len() <= max);
     }
-    QuickCheck::new()
-        .gen(StdGen::new(rand::thread_rng(), 10_000))
-        .tests(1_000)
-        .quickcheck(p as fn(_) -> _);
 }
 
+// An encoder with a smaller block size can exceed `frame::max_compress_len`,
+// but never the bound that its options report.
 #[test]
-#[cfg(feature = "cpp")]
-fn qc_cmpcpp() {
-    fn p(bytes: Vec<u8>) -> bool {
-        press(&bytes) == press_cpp(&bytes)
+fn frame_options_max_compress_len() {
+    use snap::frame::{max_compress_len, EncoderOptions};
+    use snap::write;
+    use std::io::Write;
+
+    let data = &include_bytes!("../data/fireworks.jpeg")[..1000];
+    for &size in &[1, 100, 10_000, 1 << 16] {
+        let mut options = EncoderOptions::new();
+        options.block_size(size);
+        let mut wtr = write::FrameEncoder::with_options(vec![], &options);
+        wtr.write_all(data).unwrap();
+        let got = wtr.into_inner().unwrap();
+        assert!(got.len() <= options.max_compress_len(data.len()));
+        if size == 1 {
+            assert!(got.len() > max_compress_len(data.len()));
+        }
     }
-    QuickCheck::new()
-        .gen(StdGen::new(rand::thread_rng(), 10_000))
-        .tests(1_000)
-        .quickcheck(p as fn(_) -> _);
+    assert_eq!(
+        max_compress_len(data.len()),
+        EncoderOptions::new().max_compress_len(data.len())
+    );
 }
 
-// Regression tests.
+// With resynchronization enabled, a truncated stream followed by another
+// stream should lose only the truncated chunk.
+#[test]
+fn read_frame_decoder_resync() {
+    use snap::read;
+    use std::io::Read;
 
-// See: https://github.com/BurntSushi/rust-snappy/issues/3
-#[cfg(target_pointer_width = "32")]
-testerrored!(
-    err_lit_len_overflow1,
-    &b"\x11\x00\x00\xfc\xfe\xff\xff\xff"[..],
-    Error::Literal { len: std::u32::MAX as u64, src_len: 0, dst_len: 16 }
-);
-#[cfg(target_pointer_width = "32")]
-testerrored!(
-    err_lit_len_overflow2,
-    &b"\x11\x00\x00\xfc\xff\xff\xff\xff"[..],
-    Error::Literal { len: std::u32::MAX as u64 + 1, src_len: 0, dst_len: 16 }
-);
+    let data1 = &include_bytes!("../data/html")[..];
+    let data2 = &include_bytes!("../data/alice29.txt")[..];
+    let compressed1 = write_frame_press(data1);
+    let mut compressed = compressed1[..compressed1.len() - 100].to_vec();
+    compressed.extend_from_slice(&write_frame_press(data2));
 
-// Helper functions.
+    let mut got = vec![];
+    let mut dec = read::FrameDecoder::new(&compressed[..]);
+    assert!(dec.read_to_end(&mut got).is_err());
 
-fn press(bytes: &[u8]) -> Vec<u8> {
-    Encoder::new().compress_vec(bytes).unwrap()
+    let mut got = vec![];
+    let mut dec = read::FrameDecoder::new(&compressed[..]);
+    dec.resync(true).read_to_end(&mut got).unwrap();
+    let mut want = data1[..65536].to_vec();
+    want.extend_from_slice(data2);
+    assert_eq!(want, got);
 }
 
-fn depress(bytes: &[u8]) -> Vec<u8> {
-    Decoder::new().decompress_vec(bytes).unwrap()
-}
+// With trailing garbage tolerated, the decoder should stop cleanly at the end
+// of the Snappy stream and report where it ended.
+#[test]
+fn read_frame_decoder_trailing_garbage() {
+    use snap::read;
+    use std::io::Read;
 
-fn write_frame_press(bytes: &[u8]) -> Vec<u8> {
-    use snap::write;
-    use std::io::Write;
+    let data = &include_bytes!("../data/html")[..];
+    let mut compressed = write_frame_press(data);
+    let len = compressed.len() as u64;
+    compressed.extend_from_slice(b"\x02not snappy at all");
 
-    let mut wtr = write::FrameEncoder::new(vec![]);
-    wtr.write_all(bytes).unwrap();
-    wtr.into_inner().unwrap()
+    let mut got = vec![];
+    let mut dec = read::FrameDecoder::new(&compressed[..]);
+    assert!(dec.read_to_end(&mut got).is_err());
+
+    let mut got = vec![];
+    let mut dec = read::FrameDecoder::new(&compressed[..]);
+    dec.trailing_garbage(true).read_to_end(&mut got).unwrap();
+    assert_eq!(data, &*got);
+    assert_eq!(len, dec.consumed());
 }
 
-fn read_frame_depress(bytes: &[u8]) -> Vec<u8> {
+// When collecting checksum errors, the decoder should read the whole stream
+// and report every chunk with a bad checksum.
+#[test]
+fn read_frame_decoder_collect_checksum_errors() {
     use snap::read;
+    use snap::Error;
     use std::io::Read;
 
-    let mut buf = vec![];
-    read::FrameDecoder::new(bytes).read_to_end(&mut buf).unwrap();
-    buf
-}
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let mut compressed = write_frame_press(data);
+    // Corrupt the stored checksums of the second and fourth chunks, leaving
+    // their data intact.
+    let mut offsets = vec![];
+    let mut i = 10;
+    while i < compressed.len() {
+        offsets.push(i as u64);
+        i += 4 + read_u24_le(&compressed[i + 1..]);
+    }
+    assert!(offsets.len() >= 4);
+    for &offset in &[offsets[1], offsets[3]] {
+        compressed[offset as usize + 4] ^= 0xFF;
+    }
 
-fn read_frame_press(bytes: &[u8]) -> Vec<u8> {
-    use snap::read;
-    use std::io::Read;
+    let mut got = vec![];
+    let mut dec = read::FrameDecoder::new(&compressed[..]);
+    let err = dec.read_to_end(&mut got).unwrap_err();
+    match *err.get_ref().unwrap().downcast_ref::<Error>().unwrap() {
+        Error::Checksum { .. } => {}
+        ref err => panic!("expected a checksum error, got {:?}", err),
+    }
+
+    let mut got = vec![];
+    let mut dec = read::FrameDecoder::new(&compressed[..]);
+    dec.collect_checksum_errors(true).read_to_end(&mut got).unwrap();
+    assert_eq!(data, &*got);
+    let report = dec.checksum_report().unwrap();
+    assert!(!report.is_clean());
+    assert_eq!(offsets.len() as u64, report.chunks());
+    assert_eq!(2, report.failed_chunks());
+    assert_eq!(131072, report.failed_len());
+    let failed: Vec<u64> =
+        report.failures().iter().map(|f| f.offset()).collect();
+    assert_eq!(vec![offsets[1], offsets[3]], failed);
+    let failure = report.failures()[0];
+    assert_ne!(failure.expected(), failure.got());
+    assert_eq!(65536, failure.decompressed_len());
+
+    let mut dec = read::FrameDecoder::new(&compressed[..]);
+    dec.collect_checksum_errors(true).collect_checksum_errors(false);
+    assert!(dec.checksum_report().is_none());
+}
+
+// The error policy should decide what becomes of chunks that can't be
+// decoded, while corrupt headers remain errors.
+#[test]
+fn read_frame_decoder_error_policy() {
+    use snap::read::{ErrorPolicy, FrameDecoder};
+    use std::io::Read;
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let mut compressed = write_frame_press(data);
+    let mut offsets = vec![];
+    let mut i = 10;
+    while i < compressed.len() {
+        offsets.push(i);
+        i += 4 + read_u24_le(&compressed[i + 1..]);
+    }
+    // Give the second chunk a bad checksum, and the fourth an invalid
+    // decompressed length.
+    compressed[offsets[1] + 4] ^= 0xFF;
+    compressed[offsets[3] + 8..offsets[3] + 11]
+        .copy_from_slice(&[0xFF, 0xFF, 0x7F]);
+
+    let read = |compressed: &[u8], policy| {
+        let mut got = vec![];
+        let mut dec = FrameDecoder::new(compressed);
+        dec.error_policy(policy).read_to_end(&mut got).map(|_| got)
+    };
+    assert!(read(&compressed, ErrorPolicy::Fail).is_err());
+    // Only the bad checksum can be zero filled, since the length of the
+    // other chunk is unknown.
+    assert!(read(&compressed, ErrorPolicy::ZeroFill).is_err());
+
+    let mut want = data[..65536].to_vec();
+    want.extend_from_slice(&data[2 * 65536..3 * 65536]);
+    want.extend_from_slice(&data[4 * 65536..]);
+    assert_eq!(want, read(&compressed, ErrorPolicy::SkipChunk).unwrap());
+
+    compressed[offsets[3] + 8..offsets[3] + 11]
+        .copy_from_slice(&[0x80, 0x80, 0x04]);
+    compressed[offsets[3] + 11] ^= 0xFF;
+    let mut want = data.to_vec();
+    for b in &mut want[65536..2 * 65536] {
+        *b = 0;
+    }
+    let got = read(&compressed, ErrorPolicy::ZeroFill).unwrap();
+    assert_eq!(data.len(), got.len());
+    assert_eq!(&want[..3 * 65536], &got[..3 * 65536]);
+    assert_eq!(&data[4 * 65536..], &got[4 * 65536..]);
+    assert!(got[3 * 65536..4 * 65536].iter().all(|&b| b == 0));
+
+    let mut got = vec![];
+    let mut dec = FrameDecoder::new(&compressed[..]);
+    dec.error_policy(ErrorPolicy::SkipChunk)
+        .collect_checksum_errors(true)
+        .read_to_end(&mut got)
+        .unwrap();
+    assert_eq!(data.len() - 2 * 65536, got.len());
+    let report = dec.checksum_report().unwrap();
+    assert_eq!(offsets[1] as u64, report.failures()[0].offset());
+}
+
+// A decoder with undersized buffers should grow them as needed.
+#[test]
+fn read_frame_decoder_with_capacity() {
+    use snap::read;
+    use std::io::Read;
+
+    let data = &include_bytes!("../data/html")[..];
+    let compressed = write_frame_press(data);
+    for &cap in &[0, 16, 4096, 1 << 20] {
+        let mut got = vec![];
+        read::FrameDecoder::with_capacity(&compressed[..], cap)
+            .read_to_end(&mut got)
+            .unwrap();
+        assert_eq!(data, &*got);
+    }
+}
+
+// A decoder should report the largest chunk it read, and a decoder given that
+// as its capacity should read the same stream without growing its buffers.
+#[test]
+fn read_frame_decoder_buffer_introspection() {
+    use snap::read;
+    use std::io::Read;
+
+    let data = &include_bytes!("../data/html")[..];
+    let compressed = write_frame_press(data);
+
+    let mut dec = read::FrameDecoder::with_capacity(&compressed[..], 16);
+    assert_eq!((16, 16), dec.buffer_capacities());
+    assert_eq!(0, dec.largest_chunk());
+    dec.read_to_end(&mut vec![]).unwrap();
+    assert_eq!(65536, dec.largest_chunk());
+    assert!(dec.reallocations() >= 2);
+    let (src, dst) = dec.buffer_capacities();
+    assert!(src >= 16 && dst == 65536);
+
+    let largest = dec.largest_chunk();
+    let mut dec = read::FrameDecoder::with_capacity(&compressed[..], largest);
+    let mut got = vec![];
+    dec.read_to_end(&mut got).unwrap();
+    assert_eq!(data, &*got);
+    assert_eq!(0, dec.reallocations());
+    assert_eq!((largest, largest), dec.buffer_capacities());
+}
+
+// The published format constants should describe what the encoders produce.
+#[test]
+fn frame_format_constants() {
+    use snap::frame::{self, ChunkType};
+
+    let data = &include_bytes!("../data/html")[..];
+    let compressed = write_frame_press(data);
+    let ident = frame::STREAM_IDENTIFIER;
+    assert_eq!(ident, &compressed[..ident.len()]);
+    assert_eq!(frame::STREAM_BODY, &ident[4..]);
+    assert_eq!(Ok(ChunkType::Stream), ChunkType::from_u8(ident[0]));
+    assert_eq!(ChunkType::Compressed as u8, compressed[ident.len()]);
+    assert_eq!(Err(0x80), ChunkType::from_u8(0x80));
+    assert_eq!(
+        frame::MAX_COMPRESS_BLOCK_SIZE,
+        snap::raw::max_compress_len(frame::MAX_BLOCK_SIZE)
+    );
+    // The limit is only 1MB with the small-input feature.
+    let max = snap::raw::MAX_INPUT_SIZE;
+    assert!(max == std::u32::MAX as u64 || max == 1 << 20, "{}", max);
+}
+
+// Compressing with threads should produce the same chunks as compressing
+// without them, however the writes and flushes are split up.
+#[test]
+fn write_frame_encoder_threads_writes() {
+    use snap::write;
+    use std::io::Write;
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let sizes = [1, 70_000, 100, 200_000, 65_536, 3];
+    let press = |threads| {
+        let mut wtr = write::FrameEncoder::new(vec![]);
+        wtr.threads(threads);
+        let mut rest = data;
+        for (i, &size) in sizes.iter().cycle().enumerate() {
+            if rest.is_empty() {
+                break;
+            }
+            let n = std::cmp::min(size, rest.len());
+            wtr.write_all(&rest[..n]).unwrap();
+            rest = &rest[n..];
+            if i % 4 == 3 {
+                wtr.flush().unwrap();
+            }
+        }
+        wtr.into_inner().unwrap()
+    };
+    let serial = press(1);
+    assert_eq!(data, &*read_frame_depress(&serial));
+    for &threads in &[0, 2, 5] {
+        assert_eq!(serial, press(threads));
+    }
+}
+
+// The worker threads and their buffers are reused across flushes, which
+// should never leak one batch of blocks into the next.
+#[test]
+fn write_frame_encoder_threads_many_flushes() {
+    use snap::write;
+    use std::io::Write;
+
+    let data = &include_bytes!("../data/alice29.txt")[..];
+    let press = |threads| {
+        let mut wtr = write::FrameEncoder::new(vec![]);
+        wtr.threads(threads);
+        let mut rest = data;
+        let mut size = 1;
+        while !rest.is_empty() {
+            let n = std::cmp::min(size, rest.len());
+            wtr.write_all(&rest[..n]).unwrap();
+            rest = &rest[n..];
+            wtr.flush().unwrap();
+            size = size * 7 % 1000 + 1;
+        }
+        wtr.into_inner().unwrap()
+    };
+    let serial = press(1);
+    assert_eq!(data, &*read_frame_depress(&serial));
+    for &threads in &[2, 3] {
+        assert_eq!(serial, press(threads));
+    }
+}
+
+// Checksumming on a helper thread should not change the compressed output.
+#[test]
+fn write_frame_encoder_checksum_thread() {
+    use snap::write;
+    use std::io::Write;
+
+    let corpora: &[&[u8]] = &[
+        include_bytes!("../data/html"),
+        include_bytes!("../data/fireworks.jpeg"),
+        include_bytes!("../data/html_x_4"),
+    ];
+    for &data in corpora {
+        let mut wtr = write::FrameEncoder::new(vec![]);
+        wtr.checksum_thread(true).write_all(data).unwrap();
+        for chunk in data.chunks(10_000) {
+            wtr.write_all(chunk).unwrap();
+        }
+        let got = wtr.into_inner().unwrap();
+        let mut want = write_frame_press(data);
+        want.extend_from_slice(&write_frame_press(data)[10..]);
+        assert_eq!(want, got);
+    }
+}
+
+// Writing compressed data on a background thread should not change it, and
+// errors from the underlying writer should still be reported.
+#[test]
+fn write_frame_encoder_background_writer() {
+    use snap::write;
+    use std::io::{self, Write};
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let mut wtr =
+        write::FrameEncoder::new(write::BackgroundWriter::new(vec![]));
+    for chunk in data.chunks(1000) {
+        wtr.write_all(chunk).unwrap();
+    }
+    let got = wtr.into_inner().unwrap().into_inner().unwrap();
+    assert_eq!(write_frame_press(data), got);
+
+    #[derive(Debug)]
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::Other, "broken"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut wtr =
+        write::FrameEncoder::new(write::BackgroundWriter::new(Broken));
+    wtr.write_all(data).unwrap();
+    let err = wtr.into_inner().unwrap().into_inner().unwrap_err();
+    assert_eq!("broken", err.error().to_string());
+}
+
+// Verification should pass for everything the encoders produce.
+#[test]
+fn frame_encoder_verify() {
+    use snap::{read, write};
+    use std::io::{Read, Write};
+
+    let corpora: &[&[u8]] = &[
+        &[],
+        include_bytes!("../data/html"),
+        include_bytes!("../data/fireworks.jpeg"),
+        include_bytes!("../data/html_x_4"),
+    ];
+    for &data in corpora {
+        let want = write_frame_press(data);
+        for &threads in &[1, 4] {
+            let mut wtr = write::FrameEncoder::new(vec![]);
+            wtr.verify(true).threads(threads).write_all(data).unwrap();
+            assert_eq!(want, wtr.into_inner().unwrap());
+        }
+
+        let mut got = vec![];
+        read::FrameEncoder::new(data)
+            .verify(true)
+            .read_to_end(&mut got)
+            .unwrap();
+        assert_eq!(want, got);
+    }
+}
+
+// Both frame encoders should honor the same options, and produce streams that
+// decompress correctly.
+#[test]
+fn frame_encoder_options() {
+    use snap::frame::EncoderOptions;
+    use snap::{read, write};
+    use std::io::{Read, Write};
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let mut options = EncoderOptions::new();
+    assert_eq!(write_frame_press(data), {
+        let mut wtr = write::FrameEncoder::with_options(vec![], &options);
+        wtr.write_all(data).unwrap();
+        wtr.into_inner().unwrap()
+    });
+
+    options.block_size(10_000).max_table_size(1000);
+    assert_eq!(10_000, options.get_block_size());
+    assert_eq!(1024, options.get_max_table_size());
+    let mut wtr = write::FrameEncoder::with_options(vec![], &options);
+    wtr.write_all(data).unwrap();
+    let got = wtr.into_inner().unwrap();
+    assert_eq!(data, &*read_frame_depress(&got));
+    let chunks = read::FrameDecoder::new(&got[..]).chunks().count();
+    assert_eq!((data.len() + 9_999) / 10_000, chunks);
+
+    let mut got_read = vec![];
+    read::FrameEncoder::with_options(data, &options)
+        .read_to_end(&mut got_read)
+        .unwrap();
+    assert_eq!(got, got_read);
+}
+
+// Transcoding a stream should produce the same stream as compressing its
+// data with the new options directly.
+#[test]
+fn frame_transcode() {
+    use snap::frame::{transcode, EncoderOptions};
+    use snap::write;
+    use std::io::Write;
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let mut options = EncoderOptions::new();
+    options.block_size(10_000);
+    let small = {
+        let mut wtr = write::FrameEncoder::with_options(vec![], &options);
+        wtr.write_all(data).unwrap();
+        wtr.into_inner().unwrap()
+    };
+
+    let mut got = vec![];
+    let n = transcode(&small[..], &mut got, &EncoderOptions::new()).unwrap();
+    assert_eq!(data.len() as u64, n);
+    assert_eq!(write_frame_press(data), got);
+
+    let mut got = vec![];
+    transcode(&write_frame_press(data)[..], &mut got, &options).unwrap();
+    assert_eq!(small, got);
+
+    // Corrupt streams are reported.
+    let mut bad = small.clone();
+    let last = bad.len() - 1;
+    bad[last] ^= 0xFF;
+    let err = transcode(&bad[..], &mut vec![], &options).unwrap_err();
+    assert_eq!(std::io::ErrorKind::Other, err.kind());
+}
+
+// Raw blocks should convert to framed streams and back, keeping compressed
+// data as is where it fits in a chunk.
+#[test]
+fn frame_raw_conversion() {
+    use snap::frame::{from_raw_block, to_raw_blocks};
+
+    let html = &include_bytes!("../data/html")[..65_536];
+    let raw = press(html);
+    let stream = from_raw_block(&raw).unwrap();
+    assert_eq!(html, &*read_frame_depress(&stream));
+    assert_eq!(&raw[..], &stream[stream.len() - raw.len()..]);
+    assert_eq!(vec![raw], to_raw_blocks(&stream).unwrap());
+
+    // Bigger blocks are compressed again in chunks.
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let stream = from_raw_block(&press(data)).unwrap();
+    assert_eq!(write_frame_press(data), stream);
+    let blocks = to_raw_blocks(&stream).unwrap();
+    assert_eq!(7, blocks.len());
+    let got: Vec<u8> =
+        blocks.iter().flat_map(|block| depress(block)).collect();
+    assert_eq!(data, &*got);
+
+    // Uncompressed chunks become literals.
+    let data = &include_bytes!("../data/fireworks.jpeg")[..];
+    let stream = write_frame_press(data);
+    let got: Vec<u8> = to_raw_blocks(&stream)
+        .unwrap()
+        .iter()
+        .flat_map(|block| depress(block))
+        .collect();
+    assert_eq!(data, &*got);
+    let mut x = 1u32;
+    let noise: Vec<u8> = (0..65_536)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        })
+        .collect();
+    for &n in &[1, 60, 61, 256, 257, 65_536] {
+        let data = &noise[..n];
+        let stream = write_frame_press(data);
+        assert_eq!(0x01, stream[snap::frame::STREAM_IDENTIFIER.len()]);
+        let blocks = to_raw_blocks(&stream).unwrap();
+        assert_eq!(1, blocks.len());
+        assert_eq!(data, &*depress(&blocks[0]));
+    }
+
+    assert_eq!(
+        snap::frame::STREAM_IDENTIFIER,
+        &*from_raw_block(&press(b"")).unwrap()
+    );
+    assert!(from_raw_block(b"\x05abc").is_err());
+}
+
+// Autotuning should pick the options that score best, preferring the defaults
+// on ties.
+#[test]
+fn frame_autotune() {
+    use snap::frame::{autotune, EncoderOptions};
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    assert_eq!(EncoderOptions::new(), autotune(data, |_| 0.0));
+
+    let best = autotune(data, |trial| -trial.ratio());
+    assert_eq!(EncoderOptions::new(), best);
+
+    let fastest = autotune(data, |trial| {
+        let options = trial.options();
+        -(options.get_max_table_size() as f64)
+    });
+    assert_eq!(1 << 10, fastest.get_max_table_size());
+    assert_eq!(1 << 16, fastest.get_block_size());
+
+    let smallest = autotune(data, |trial| {
+        -(trial.options().get_block_size() as f64) - trial.ratio()
+    });
+    assert_eq!(1 << 14, smallest.get_block_size());
+    assert_eq!(1 << 14, smallest.get_max_table_size());
+}
+
+// Encoder options should survive being written as a string and parsed back,
+// and parsing should reject settings that wouldn't be kept as given.
+#[test]
+fn frame_encoder_options_string() {
+    use snap::frame::EncoderOptions;
+    use snap::Error;
+
+    let defaults = EncoderOptions::new();
+    assert_eq!(
+        "block-size=65536 max-table-size=16384 search-depth=0 \
+         skip-incompressible=off min-savings=none checksum=crc32c \
+         filter=none",
+        defaults.to_string()
+    );
+    assert_eq!(defaults, defaults.to_string().parse().unwrap());
+    assert_eq!(defaults, "".parse().unwrap());
+
+    let mut options = EncoderOptions::new();
+    options
+        .block_size(10_000)
+        .max_table_size(1024)
+        .search_depth(16)
+        .skip_incompressible(true)
+        .min_savings(Some(20))
+        .digest_sha256(true)
+        .filter(Some("delta32"));
+    let s = options.to_string();
+    assert_eq!(options, s.parse().unwrap());
+    assert_eq!(
+        options,
+        "min-savings=20  skip-incompressible=on search-depth=16 \
+         filter=delta32 block-size=10000 max-table-size=1024 \
+         checksum=crc32c+sha256"
+            .parse()
+            .unwrap()
+    );
+    for &name in &["delta8", "delta16", "delta64", "transpose1", "transpose12"]
+    {
+        options.filter(Some(name));
+        assert_eq!(options, options.to_string().parse().unwrap());
+    }
+
+    for &bad in &[
+        "block-size=0",
+        "block-size=65537",
+        "max-table-size=1000",
+        "search-depth=-1",
+        "skip-incompressible=yes",
+        "min-savings=101",
+        "checksum=sha256",
+        "filter=delta7",
+        "filter=transpose0",
+        "filter=transpose08",
+        "level=best",
+        "block-size",
+    ] {
+        let err = bad.parse::<EncoderOptions>().unwrap_err();
+        assert_eq!(Error::Options { setting: bad.to_string() }, err);
+    }
+}
+
+// A filter and a digest set in the options should be applied just like the
+// ones given to the encoder directly.
+#[test]
+fn frame_encoder_options_filter_digest() {
+    use snap::frame::{Delta, EncoderOptions, Sha256};
+    use snap::{read, write};
+    use std::io::{Read, Write};
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let options: EncoderOptions =
+        "filter=delta32 checksum=crc32c+sha256".parse().unwrap();
+    let mut wtr = write::FrameEncoder::with_options(vec![], &options);
+    wtr.write_all(data).unwrap();
+    let got = wtr.into_inner().unwrap();
+    assert!(got.len() <= options.max_compress_len(data.len()));
+
+    let mut wtr = write::FrameEncoder::new(vec![]);
+    wtr.filter(Delta::new(4)).digest(Sha256::new());
+    wtr.write_all(data).unwrap();
+    assert_eq!(wtr.into_inner().unwrap(), got);
+
+    let mut rdr = read::FrameDecoder::new(&got[..]);
+    rdr.filter(Delta::new(4)).verify_digest(Sha256::new());
+    let mut buf = vec![];
+    rdr.read_to_end(&mut buf).unwrap();
+    assert_eq!(data, &buf[..]);
+}
+
+// The C ABI should round trip data through callbacks, and report corrupt input.
+#[test]
+fn capi_round_trip() {
+    use snap::capi::*;
+    use std::ffi::c_void;
+
+    extern "C" fn read_slice(
+        ctx: *mut c_void,
+        buf: *mut u8,
+        len: usize,
+    ) -> isize {
+        let src = unsafe { &mut *(ctx as *mut &[u8]) };
+        let n = std::cmp::min(len, src.len());
+        unsafe { std::ptr::copy_nonoverlapping(src.as_ptr(), buf, n) };
+        *src = &src[n..];
+        n as isize
+    }
+
+    extern "C" fn write_vec(
+        ctx: *mut c_void,
+        buf: *const u8,
+        len: usize,
+    ) -> isize {
+        let dst = unsafe { &mut *(ctx as *mut Vec<u8>) };
+        dst.extend_from_slice(unsafe { std::slice::from_raw_parts(buf, len) });
+        len as isize
+    }
+
+    fn ctx<T>(x: &mut T) -> *mut c_void {
+        x as *mut T as *mut c_void
+    }
+
+    let data = &include_bytes!("../data/html")[..];
+    let (mut src, mut compressed) = (data, Vec::<u8>::new());
+    let status = snappy_frame_compress_stream(
+        read_slice,
+        ctx(&mut src),
+        write_vec,
+        ctx(&mut compressed),
+    );
+    assert_eq!(snappy_status::SNAPPY_OK, status);
+    assert_eq!(compressed, write_frame_press(data));
+
+    let (mut src, mut decompressed) = (&compressed[..], Vec::<u8>::new());
+    let status = snappy_frame_decompress_stream(
+        read_slice,
+        ctx(&mut src),
+        write_vec,
+        ctx(&mut decompressed),
+    );
+    assert_eq!(snappy_status::SNAPPY_OK, status);
+    assert_eq!(data, &decompressed[..]);
+
+    let mut streamed = vec![];
+    unsafe {
+        let enc = snappy_frame_encoder_new(write_vec, ctx(&mut streamed));
+        let (a, b) = data.split_at(1000);
+        let status = snappy_frame_encoder_write(enc, a.as_ptr(), a.len());
+        assert_eq!(snappy_status::SNAPPY_OK, status);
+        assert_eq!(snappy_status::SNAPPY_OK, snappy_frame_encoder_flush(enc));
+        let status = snappy_frame_encoder_write(enc, b.as_ptr(), b.len());
+        assert_eq!(snappy_status::SNAPPY_OK, status);
+        assert_eq!(snappy_status::SNAPPY_OK, snappy_frame_encoder_finish(enc));
+    }
+    assert_eq!(data, &read_frame_depress(&streamed)[..]);
+
+    // Finishing should report a failed write without writing again.
+    extern "C" fn write_fail(
+        ctx: *mut c_void,
+        _buf: *const u8,
+        _len: usize,
+    ) -> isize {
+        unsafe { *(ctx as *mut usize) += 1 };
+        -1
+    }
+    let mut calls = 0usize;
+    unsafe {
+        let enc = snappy_frame_encoder_new(write_fail, ctx(&mut calls));
+        let status = snappy_frame_encoder_write(enc, data.as_ptr(), 1000);
+        assert_eq!(snappy_status::SNAPPY_OK, status);
+        let status = snappy_frame_encoder_finish(enc);
+        assert_eq!(snappy_status::SNAPPY_IO_ERROR, status);
+    }
+    assert_eq!(1, calls);
+
+    let truncated = &compressed[..compressed.len() - 1];
+    let (mut src, mut decompressed) = (truncated, Vec::<u8>::new());
+    let status = snappy_frame_decompress_stream(
+        read_slice,
+        ctx(&mut src),
+        write_vec,
+        ctx(&mut decompressed),
+    );
+    assert_eq!(snappy_status::SNAPPY_INVALID_INPUT, status);
+}
+
+// The checksums in frame formatted output should match the CRC32C test
+// vectors from RFC 3720, whichever CRC32C implementation the crate was built
+// with.
+#[test]
+fn frame_checksum_vectors() {
+    let ascending: Vec<u8> = (0..32).collect();
+    let descending: Vec<u8> = (0..32).rev().collect();
+    let vectors: &[(&[u8], u32)] = &[
+        (b"123456789", 0xE3069283),
+        (&[0; 32], 0x8A9136AA),
+        (&[0xFF; 32], 0x62A8AB43),
+        (&ascending, 0x46DD794E),
+        (&descending, 0x113FDB5C),
+    ];
+    for &(data, crc) in vectors {
+        assert_eq!(crc, crc32c_bitwise(data));
+        let frame = write_frame_press(data);
+        assert_eq!(vec![mask_crc32c(crc)], frame_checksums(&frame));
+    }
+}
+
+// Compression and decompression should work for input and output buffers that
+// start at any alignment, which matters on targets that fault on misaligned
+// loads.
+#[test]
+fn roundtrip_unaligned() {
+    let data = &include_bytes!("../data/html")[..];
+    let compressed = press(data);
+    for offset in 0..16 {
+        let mut src = vec![0; offset];
+        src.extend_from_slice(data);
+        let mut dst =
+            vec![0; offset + snap::raw::max_compress_len(data.len())];
+        let n = Encoder::new().compress(&src[offset..], &mut dst[offset..]);
+        assert_eq!(compressed, &dst[offset..offset + n.unwrap()]);
+
+        let mut src = vec![0; offset];
+        src.extend_from_slice(&compressed);
+        let mut dst = vec![0; offset + data.len()];
+        let n = Decoder::new().decompress(&src[offset..], &mut dst[offset..]);
+        assert_eq!(data, &dst[offset..offset + n.unwrap()]);
+    }
+}
+
+// Raw blocks of the sizes that LevelDB and RocksDB typically use, including
+// blocks bigger than the 64KB used by the frame format, should roundtrip and
+// report their length the way snappy::GetUncompressedLength does.
+#[test]
+fn raw_db_blocks() {
+    for &size in DB_BLOCK_SIZES {
+        // Skip the sizes over the limit of the small-input feature.
+        if snap::raw::max_compress_len(size) == 0 {
+            continue;
+        }
+        let block = db_block(size);
+        let compressed = press(&block);
+        assert!(compressed.len() <= snap::raw::max_compress_len(size));
+        assert_eq!(size, decompress_len(&compressed).unwrap());
+        assert_eq!(block, depress(&compressed));
+    }
+}
+
+// Raw blocks written by the C++ library should be readable by this crate, and
+// vice versa.
+#[test]
+#[cfg(feature = "cpp")]
+fn raw_db_blocks_cpp() {
+    for &size in DB_BLOCK_SIZES {
+        let block = db_block(size);
+        assert_eq!(block, depress_cpp(&press(&block)));
+        assert_eq!(block, depress(&press_cpp(&block)));
+    }
+}
+
+// SnappyFile should read any range of the uncompressed data, whichever chunks
+// it spans.
+#[test]
+fn read_snappy_file() {
+    use snap::{read::SnappyFile, write};
+    use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    // Flushing makes a short chunk, so chunks don't all start at multiples
+    // of the block size.
+    let mut wtr = write::FrameEncoder::new(vec![]);
+    wtr.write_all(&data[..1000]).unwrap();
+    wtr.flush().unwrap();
+    wtr.write_all(&data[1000..]).unwrap();
+    let framed = wtr.into_inner().unwrap();
+
+    let mut file = SnappyFile::new(Cursor::new(&framed)).unwrap();
+    assert_eq!(data.len() as u64, file.len());
+    assert_eq!(1 + (data.len() - 1000 + 65535) / 65536, file.chunk_count());
+    file.cache_chunks(2);
+    let ranges =
+        [(0, 10), (990, 1020), (60000, 140000), (5, 5), (400_000, 409_600)];
+    for &(start, end) in &ranges {
+        let mut buf = vec![0; end - start];
+        assert_eq!(end - start, file.read_at(start as u64, &mut buf).unwrap());
+        assert_eq!(&data[start..end], &buf[..]);
+    }
+
+    let mut buf = vec![0; 100];
+    let n = file.read_at(data.len() as u64 - 10, &mut buf).unwrap();
+    assert_eq!(&data[data.len() - 10..], &buf[..n]);
+    assert_eq!(0, file.read_at(data.len() as u64 + 10, &mut buf).unwrap());
+
+    file.seek(SeekFrom::End(-70000)).unwrap();
+    let mut tail = vec![];
+    file.read_to_end(&mut tail).unwrap();
+    assert_eq!(&data[data.len() - 70000..], &tail[..]);
+    assert!(file.seek(SeekFrom::Current(-(data.len() as i64) - 1)).is_err());
+}
+
+// SnappyFile should report corrupt chunks when they are read, and truncated
+// files when it is created.
+#[test]
+fn read_snappy_file_errors() {
+    use snap::read::SnappyFile;
+    use std::io::Cursor;
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let mut framed = write_frame_press(data);
+    assert!(SnappyFile::new(Cursor::new(&framed[..framed.len() - 1])).is_err());
+    assert!(SnappyFile::new(Cursor::new(&framed[10..])).is_err());
+
+    // Corrupt the checksum of the first chunk, which starts right after the
+    // stream identifier.
+    framed[14] ^= 0xFF;
+    let mut file = SnappyFile::new(Cursor::new(&framed)).unwrap();
+    let mut buf = vec![0; 10];
+    assert_eq!(10, file.read_at(65536, &mut buf).unwrap());
+    let err = file.read_at(0, &mut buf).unwrap_err();
+    match *err.into_inner().unwrap().downcast::<Error>().unwrap() {
+        Error::Checksum { .. } => {}
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+// SnappyVec should return any range of the bytes appended to it, whether it
+// falls in compressed segments or in the uncompressed tail.
+#[test]
+fn raw_snappy_vec() {
+    use snap::raw::SnappyVec;
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let mut vec = SnappyVec::with_segment_size(10_000);
+    for piece in data.chunks(3333) {
+        vec.append(piece);
+    }
+    assert_eq!(data.len(), vec.len());
+    assert!(vec.compressed_len() < data.len() / 2);
+    assert_eq!(data, &vec.to_vec()[..]);
+
+    let ranges = [
+        (0, 0),
+        (0, 10_000),
+        (9_990, 10_010),
+        (15_000, 95_000),
+        (data.len() - 5_000, data.len()),
+    ];
+    for &(start, end) in &ranges {
+        assert_eq!(&data[start..end], &vec.read_range(start..end)[..]);
+    }
+
+    let vec = SnappyVec::new();
+    assert!(vec.is_empty());
+    assert_eq!(Vec::<u8>::new(), vec.to_vec());
+}
+
+// Decompressing to a sink should produce the same data as FrameDecoder, using
+// only the window given, and stop at the first error.
+#[test]
+fn frame_decompress_to_sink() {
+    use snap::frame::{decompress_to_sink, MAX_BLOCK_SIZE};
+    use std::io;
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let framed = write_frame_press(data);
+    let mut window = vec![0; MAX_BLOCK_SIZE];
+    let mut out = vec![];
+    decompress_to_sink(&framed, &mut window, |chunk| {
+        out.extend_from_slice(chunk);
+        Ok::<(), Error>(())
+    })
+    .unwrap();
+    assert_eq!(data, &out[..]);
+
+    let res = decompress_to_sink(&framed, &mut window[..1000], |_| Ok(()));
+    match res {
+        Err(Error::BufferTooSmall { given: 1000, .. }) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+    let truncated = &framed[..framed.len() - 1];
+    let res = decompress_to_sink(truncated, &mut window, |_| Ok(()));
+    assert_eq!(Err(Error::TruncatedChunk), res);
+
+    let mut chunks = 0;
+    let res = decompress_to_sink(&framed, &mut window, |_| {
+        chunks += 1;
+        Err(io::Error::new(io::ErrorKind::Other, "sink is full"))
+    });
+    assert_eq!("sink is full", res.unwrap_err().to_string());
+    assert_eq!(1, chunks);
+}
+
+// Limiting the work done by each write should make write consume at most that
+// many blocks per call, while still producing a valid stream.
+#[test]
+fn write_frame_encoder_max_write_size() {
+    use snap::write;
+    use std::io::Write;
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    // The data is 6.25 blocks long, and the last quarter block is always
+    // buffered by the call that writes the last whole block.
+    for &(limit, calls) in &[(2 * 65536 + 100, 3), (1, 6), (0, 1)] {
+        let mut wtr = write::FrameEncoder::new(vec![]);
+        wtr.max_write_size(limit);
+        let mut rest = data;
+        let mut n = 0;
+        while !rest.is_empty() {
+            let consumed = wtr.write(rest).unwrap();
+            assert!(consumed > 0);
+            rest = &rest[consumed..];
+            n += 1;
+        }
+        assert_eq!(calls, n);
+        assert_eq!(data, &read_frame_depress(&wtr.into_inner().unwrap())[..]);
+    }
+}
+
+// A frame encoder should flush automatically once enough bytes are buffered,
+// or once bytes have been buffered for long enough.
+#[test]
+fn write_frame_encoder_auto_flush() {
+    use snap::write;
+    use std::io::Write;
+    use std::time::Duration;
+
+    let mut wtr = write::FrameEncoder::new(vec![]);
+    wtr.auto_flush_size(Some(10));
+    wtr.write_all(b"hello").unwrap();
+    assert!(wtr.get_ref().is_empty());
+    wtr.write_all(b" world").unwrap();
+    assert_eq!(&b"hello world"[..], &read_frame_depress(wtr.get_ref())[..]);
+
+    let secs = Duration::from_secs;
+    let mut wtr = write::FrameEncoder::new(vec![]);
+    wtr.auto_flush_interval(Some(secs(5)));
+    assert!(!wtr.maybe_flush(secs(0)).unwrap());
+    wtr.write_all(b"hello").unwrap();
+    assert!(!wtr.maybe_flush(secs(10)).unwrap());
+    assert!(!wtr.maybe_flush(secs(14)).unwrap());
+    assert!(wtr.maybe_flush(secs(15)).unwrap());
+    assert_eq!(&b"hello"[..], &read_frame_depress(wtr.get_ref())[..]);
+    assert!(!wtr.maybe_flush(secs(30)).unwrap());
+    wtr.write_all(b" world").unwrap();
+    assert!(!wtr.maybe_flush(secs(31)).unwrap());
+    assert!(wtr.maybe_flush(secs(36)).unwrap());
+    let got = read_frame_depress(&wtr.into_inner().unwrap());
+    assert_eq!(&b"hello world"[..], &got[..]);
+}
+
+// Syncing a frame encoder should end the current chunk and flush everything
+// to the underlying writer before syncing it.
+#[test]
+fn write_frame_encoder_sync() {
+    use snap::write;
+    use std::io::{self, Write};
+
+    #[derive(Debug, Default)]
+    struct Log {
+        buf: Vec<u8>,
+        synced: Vec<(bool, usize)>,
+    }
+
+    impl Write for Log {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.buf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl write::SyncWrite for Log {
+        fn sync_data(&mut self) -> io::Result<()> {
+            self.synced.push((false, self.buf.len()));
+            Ok(())
+        }
+
+        fn sync_all(&mut self) -> io::Result<()> {
+            self.synced.push((true, self.buf.len()));
+            Ok(())
+        }
+    }
+
+    let mut wtr = write::FrameEncoder::new(io::BufWriter::new(Log::default()));
+    wtr.write_all(b"hello").unwrap();
+    wtr.sync_data().unwrap();
+    let first = wtr.get_ref().get_ref().buf.len();
+    assert_eq!(
+        &b"hello"[..],
+        &read_frame_depress(&wtr.get_ref().get_ref().buf)[..]
+    );
+    wtr.write_all(b" world").unwrap();
+    wtr.sync_all().unwrap();
+    let log = wtr.into_inner().unwrap().into_inner().unwrap();
+    assert_eq!(vec![(false, first), (true, log.buf.len())], log.synced);
+    assert_eq!(&b"hello world"[..], &read_frame_depress(&log.buf)[..]);
+}
+
+// A frame encoder resumed from a checkpoint should continue the stream as if
+// it had never stopped, even if more was written after the checkpoint.
+#[test]
+fn write_frame_encoder_checkpoint() {
+    use snap::frame::EncoderOptions;
+    use snap::write::{Checkpoint, FrameEncoder};
+    use std::io::Write;
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let opts = EncoderOptions::new();
+    for &threads in &[1, 3] {
+        let mut wtr = FrameEncoder::new(vec![]);
+        wtr.threads(threads);
+        wtr.write_all(&data[..100_000]).unwrap();
+        wtr.get_mut().flush().unwrap();
+        let saved = wtr.checkpoint().to_bytes();
+        // Whatever is written after the checkpoint is lost in the "crash".
+        wtr.write_all(b"lost").unwrap();
+        let mut stream = wtr.into_inner().unwrap();
+
+        let checkpoint = Checkpoint::from_bytes(&saved).unwrap();
+        assert_eq!(100_000, checkpoint.uncompressed_len());
+        stream.truncate(checkpoint.compressed_len() as usize);
+        let mut wtr =
+            FrameEncoder::resume(stream, &opts, &checkpoint).unwrap();
+        wtr.write_all(&data[100_000..]).unwrap();
+        let got = read_frame_depress(&wtr.into_inner().unwrap());
+        assert_eq!(data, &got[..]);
+    }
+
+    // A checkpoint taken before anything was written starts a new stream.
+    let checkpoint = FrameEncoder::new(vec![]).checkpoint();
+    let mut wtr = FrameEncoder::resume(vec![], &opts, &checkpoint).unwrap();
+    wtr.write_all(b"hello").unwrap();
+    let got = read_frame_depress(&wtr.into_inner().unwrap());
+    assert_eq!(&b"hello"[..], &got[..]);
+
+    let mut saved = checkpoint.to_bytes();
+    assert_eq!(Ok(checkpoint), Checkpoint::from_bytes(&saved));
+    assert_eq!(
+        Err(Error::Checkpoint),
+        Checkpoint::from_bytes(&saved[..saved.len() - 1])
+    );
+    saved[10] ^= 1;
+    assert_eq!(Err(Error::Checkpoint), Checkpoint::from_bytes(&saved));
+}
+
+// A checkpoint of a filtered stream should hold the bytes as they were
+// written, even while blocks wait to be compressed concurrently.
+#[test]
+fn write_frame_encoder_checkpoint_filtered() {
+    use snap::frame::Delta;
+    use snap::read::FrameDecoder;
+    use snap::write::FrameEncoder;
+    use std::io::{Read, Write};
+
+    let data = &include_bytes!("../data/html_x_4")[..2 * 65536 + 100];
+    let mut wtr = FrameEncoder::new(vec![]);
+    wtr.threads(4).filter(Delta::new(4));
+    wtr.write_all(data).unwrap();
+    assert_eq!(data, wtr.checkpoint().buffered());
+
+    let stream = wtr.into_inner().unwrap();
+    let mut dec = FrameDecoder::new(&stream[..]);
+    dec.filter(Delta::new(4));
+    let mut got = vec![];
+    dec.read_to_end(&mut got).unwrap();
+    assert_eq!(data, &got[..]);
+
+    // A filter set while blocks are pending is applied to them, and so must
+    // be named before them.
+    let mut wtr = FrameEncoder::new(vec![]);
+    wtr.threads(4).write_all(&data[..65537]).unwrap();
+    wtr.filter(Delta::new(4)).flush().unwrap();
+    let stream = wtr.into_inner().unwrap();
+    let mut dec = FrameDecoder::new(&stream[..]);
+    dec.filter(Delta::new(4));
+    let mut got = vec![];
+    dec.read_to_end(&mut got).unwrap();
+    assert_eq!(&data[..65537], &got[..]);
+}
+
+// A filtered stream resumed from a checkpoint should be filtered throughout,
+// whether or not the filter had been named when the checkpoint was taken.
+#[test]
+fn write_frame_encoder_resume_filtered() {
+    use snap::frame::{Delta, EncoderOptions};
+    use snap::read::FrameDecoder;
+    use snap::write::{Checkpoint, FrameEncoder};
+    use std::io::{Read, Write};
+
+    let data = &include_bytes!("../data/html_x_4")[..2 * 65536 + 100];
+    let opts = EncoderOptions::new();
+    for &split in &[0, 100, 65536 + 100] {
+        let mut wtr = FrameEncoder::new(vec![]);
+        wtr.filter(Delta::new(4));
+        wtr.write_all(&data[..split]).unwrap();
+        if split > 0 {
+            wtr.flush().unwrap();
+        }
+        let checkpoint = wtr.checkpoint();
+        assert_eq!(Some("delta32"), checkpoint.filter());
+        let saved = checkpoint.to_bytes();
+        assert_eq!(Ok(checkpoint), Checkpoint::from_bytes(&saved));
+
+        let checkpoint = Checkpoint::from_bytes(&saved).unwrap();
+        let stream = wtr.into_inner().unwrap();
+        let mut wtr = FrameEncoder::resume_with_filter(
+            stream,
+            &opts,
+            &checkpoint,
+            Delta::new(4),
+        )
+        .unwrap();
+        wtr.write_all(&data[split..]).unwrap();
+        let stream = wtr.into_inner().unwrap();
+
+        let mut dec = FrameDecoder::new(&stream[..]);
+        dec.filter(Delta::new(4));
+        let mut got = vec![];
+        dec.read_to_end(&mut got).unwrap();
+        assert_eq!(data, &got[..]);
+
+        let err =
+            FrameEncoder::resume(vec![], &opts, &checkpoint).err().unwrap();
+        assert_eq!(
+            Error::UnknownFilter { name: b"delta32".to_vec() },
+            *err.into_inner().unwrap().downcast::<Error>().unwrap()
+        );
+        let err = FrameEncoder::resume_with_filter(
+            vec![],
+            &opts,
+            &checkpoint,
+            Delta::new(2),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            Error::UnknownFilter { name: b"delta32".to_vec() },
+            *err.into_inner().unwrap().downcast::<Error>().unwrap()
+        );
+    }
+}
+
+// Every message written with write_message should occupy whole chunks, with
+// a message no bigger than a block occupying exactly one.
+#[test]
+fn write_frame_encoder_write_message() {
+    use snap::frame::{self, EncoderOptions};
+    use snap::write::FrameEncoder;
+    use std::io::Write;
+
+    let data = &include_bytes!("../data/html")[..];
+    let mut opts = EncoderOptions::new();
+    opts.block_size(10_000);
+    for &threads in &[1, 3] {
+        let mut wtr = FrameEncoder::with_options(vec![], &opts);
+        wtr.threads(threads);
+        wtr.write_all(b"buffered").unwrap();
+        wtr.write_message(&data[..5_000]).unwrap();
+        wtr.write_message(b"").unwrap();
+        wtr.write_message(&data[..25_000]).unwrap();
+        wtr.write_all(b"more").unwrap();
+        let stream = wtr.into_inner().unwrap();
+
+        let mut lens = vec![];
+        let mut window = vec![0; 10_000];
+        frame::decompress_to_sink(&stream, &mut window, |block| {
+            lens.push(block.len());
+            Ok::<(), Error>(())
+        })
+        .unwrap();
+        assert_eq!(vec![8, 5_000, 0, 10_000, 10_000, 5_000, 4], lens);
+
+        let mut want = b"buffered".to_vec();
+        want.extend_from_slice(&data[..5_000]);
+        want.extend_from_slice(&data[..25_000]);
+        want.extend_from_slice(b"more");
+        assert_eq!(want, read_frame_depress(&stream));
+    }
+}
+
+// Reading messages from a frame decoder should return the messages written
+// with write_message, one per call.
+#[test]
+fn read_frame_decoder_read_message() {
+    use snap::read::FrameDecoder;
+    use snap::write::FrameEncoder;
+    use std::io::Read;
+
+    let data = &include_bytes!("../data/html")[..];
+    let msgs: &[&[u8]] = &[b"hello", b"", &data[..10_000], b"world"];
+    let mut wtr = FrameEncoder::new(vec![]);
+    for msg in msgs {
+        wtr.write_message(msg).unwrap();
+    }
+    let stream = wtr.into_inner().unwrap();
+
+    let mut rdr = FrameDecoder::new(&stream[..]);
+    let mut buf = b"junk".to_vec();
+    for msg in msgs {
+        assert!(rdr.read_message(&mut buf).unwrap());
+        assert_eq!(msg, &buf);
+    }
+    assert!(!rdr.read_message(&mut buf).unwrap());
+    assert!(buf.is_empty());
+
+    // A partially read chunk yields only its remainder.
+    let mut rdr = FrameDecoder::new(&stream[..]);
+    let mut two = [0; 2];
+    rdr.read_exact(&mut two).unwrap();
+    assert!(rdr.read_message(&mut buf).unwrap());
+    assert_eq!(&b"llo"[..], &buf[..]);
+}
+
+// The memory reported by encoders and decoders should account for their hash
+// tables and buffers.
+#[test]
+fn memory_usage() {
+    use snap::{read, write};
+    use std::io::{Read, Write};
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let mut enc = Encoder::new();
+    let small = enc.memory_usage();
+    assert!(small > 0);
+    enc.compress_vec(&data[..100]).unwrap();
+    assert_eq!(small, enc.memory_usage());
+    enc.compress_vec(data).unwrap();
+    assert!(enc.memory_usage() > small);
+    let big = enc.memory_usage();
+    enc.compress_vec(data).unwrap();
+    assert_eq!(big, enc.memory_usage());
+    assert_eq!(0, Decoder::new().memory_usage());
+
+    let mut wtr = write::FrameEncoder::new(vec![]);
+    assert!(wtr.memory_usage() > 2 * 65536);
+    wtr.threads(4);
+    wtr.write_all(&data[..3 * 65536]).unwrap();
+    let pending = wtr.memory_usage();
+    wtr.flush().unwrap();
+    // The blocks and the chunks they were compressed into are kept to be
+    // reused by the next ones.
+    let flushed = wtr.memory_usage();
+    assert!(flushed > pending);
+    wtr.write_all(&data[3 * 65536..6 * 65536]).unwrap();
+    assert_eq!(flushed, wtr.memory_usage());
+    wtr.flush().unwrap();
+    assert_eq!(flushed, wtr.memory_usage());
+
+    let compressed = write_frame_press(data);
+    let small = read::FrameDecoder::with_capacity(&compressed[..], 1024);
+    let full = read::FrameDecoder::new(&compressed[..]);
+    assert!(small.memory_usage() < full.memory_usage());
+    assert!(full.memory_usage() >= 2 * 65536);
+
+    let mut rdr = read::FrameEncoder::new(data);
+    let before = rdr.memory_usage();
+    assert!(before > 2 * 65536);
+    rdr.read_to_end(&mut vec![]).unwrap();
+    assert!(rdr.memory_usage() >= before);
+}
+
+// The raw max_compress_len should be usable in constants, and still return 0
+// for inputs that are too big.
+#[test]
+fn raw_max_compress_len_const() {
+    use snap::raw::max_compress_len;
+
+    const MAX: usize = max_compress_len(1000);
+    let buf = [0u8; max_compress_len(10)];
+    assert_eq!(32 + 10 + 10 / 6, buf.len());
+    assert_eq!(32 + 1000 + 1000 / 6, MAX);
+    assert_eq!(32, max_compress_len(0));
+    if cfg!(target_pointer_width = "64") {
+        let limit = snap::raw::MAX_INPUT_SIZE as usize;
+        let largest = (limit - 32) * 6 / 7;
+        assert_eq!(32 + largest + largest / 6, max_compress_len(largest));
+        assert_eq!(0, max_compress_len(largest + 7));
+        assert_eq!(0, max_compress_len(limit + 1));
+    }
+    assert_eq!(0, max_compress_len(std::usize::MAX));
+}
+
+// Partial decompression of a damaged block should keep the valid prefix.
+#[test]
+fn raw_decompress_partial() {
+    let data = &include_bytes!("../data/html")[..];
+    let compressed = press(data);
+    let mut got = vec![0; data.len()];
+
+    let (n, result) = Decoder::new().decompress_partial(&compressed, &mut got);
+    assert_eq!(data.len(), n);
+    assert!(result.is_ok());
+    assert_eq!(data, &*got);
+
+    let truncated = &compressed[..compressed.len() / 2];
+    let mut got = vec![0; data.len()];
+    let (n, result) = Decoder::new().decompress_partial(truncated, &mut got);
+    assert!(result.is_err());
+    assert!(n > 0 && n < data.len());
+    assert_eq!(&data[..n], &got[..n]);
+
+    let (n, result) = Decoder::new().decompress_partial(&compressed, &mut []);
+    assert_eq!(0, n);
+    assert!(result.is_err());
+}
+
+// The typed wrappers should round trip data in their own format, and refuse
+// data in the other format.
+#[test]
+fn typed_compressed_roundtrip() {
+    use snap::frame::FrameCompressed;
+    use snap::raw::RawCompressed;
+
+    let data = &include_bytes!("../data/html")[..];
+    let raw = RawCompressed::compress(data).unwrap();
+    assert_eq!(press(data), raw.as_bytes());
+    assert_eq!(data.len(), raw.decompress_len().unwrap());
+    assert_eq!(data, &*raw.decompress().unwrap());
+
+    let framed = FrameCompressed::compress(data).unwrap();
+    assert_eq!(write_frame_press(data), framed.as_bytes());
+    assert_eq!(data, &*framed.decompress().unwrap());
+
+    let borrowed = RawCompressed::new(framed.as_bytes());
+    assert!(borrowed.decompress().is_err());
+    let borrowed = FrameCompressed::new(raw.as_bytes());
+    assert!(borrowed.decompress().is_err());
+    assert_eq!(press(data), raw.into_inner());
+}
+
+// The frame encoders and decoders should work with a codec other than Snappy.
+#[test]
+fn frame_codec_custom() {
+    use snap::frame::Codec;
+    use snap::{read, write};
+    use std::io::{Read, Write};
+
+    // A run length encoding, as pairs of a count and a byte. When that isn't
+    // smaller, it claims to produce as many bytes as it was given, which makes
+    // the encoders store the block uncompressed.
+    #[derive(Clone, Debug)]
+    struct Rle;
+
+    impl Codec for Rle {
+        fn max_compress_len(&self, input_len: usize) -> usize {
+            input_len
+        }
+
+        fn compress(
+            &mut self,
+            input: &[u8],
+            output: &mut [u8],
+        ) -> Result<usize, snap::Error> {
+            let mut n = 0;
+            for run in input.chunks(1) {
+                if n > 0 && output[n - 1] == run[0] && output[n - 2] < 255 {
+                    output[n - 2] += 1;
+                } else if n + 2 > output.len() {
+                    return Ok(input.len());
+                } else {
+                    output[n] = 1;
+                    output[n + 1] = run[0];
+                    n += 2;
+                }
+            }
+            Ok(n)
+        }
+
+        fn decompress_len(&self, input: &[u8]) -> Result<usize, snap::Error> {
+            Ok(input.chunks(2).map(|pair| pair[0] as usize).sum())
+        }
+
+        fn decompress(
+            &mut self,
+            input: &[u8],
+            output: &mut [u8],
+        ) -> Result<usize, snap::Error> {
+            let mut n = 0;
+            for pair in input.chunks(2) {
+                let end = n + pair[0] as usize;
+                for b in &mut output[n..end] {
+                    *b = pair[1];
+                }
+                n = end;
+            }
+            Ok(n)
+        }
+    }
+
+    let mut data = vec![];
+    for i in 0..200_000u32 {
+        data.push((i / 1000) as u8);
+    }
+
+    let mut wtr = write::FrameEncoder::with_codec(vec![], Rle);
+    wtr.threads(3).verify(true);
+    wtr.write_all(&data).unwrap();
+    let compressed = wtr.into_inner().unwrap();
+    assert!(compressed.len() < data.len() / 10);
+
+    let mut got = vec![];
+    read::FrameEncoder::with_codec(&data[..], Rle)
+        .read_to_end(&mut got)
+        .unwrap();
+    assert_eq!(compressed, got);
+
+    let mut got = vec![];
+    read::FrameDecoder::with_codec(&compressed[..], Rle)
+        .read_to_end(&mut got)
+        .unwrap();
+    assert_eq!(data, got);
+}
+
+// Batch compression should compress every file it's given, and report the
+// files it couldn't compress without giving up on the others.
+#[test]
+fn batch_compress_paths() {
+    use snap::batch::{compress_paths, BatchOptions};
+    use std::fs;
+
+    let dir = std::env::temp_dir()
+        .join(format!("snap-batch-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let data = &include_bytes!("../data/html")[..];
+    let mut paths = vec![];
+    for i in 0..5 {
+        let path = dir.join(format!("file{}", i));
+        fs::write(&path, &data[..data.len() / (i + 1)]).unwrap();
+        paths.push(path);
+    }
+    paths.insert(2, dir.join("missing"));
+
+    let mut options = BatchOptions::new();
+    options.threads(3);
+    let results = compress_paths(&paths, &options);
+    assert_eq!(paths.len(), results.len());
+    for (path, (got_path, result)) in paths.iter().zip(&results) {
+        assert_eq!(path, got_path);
+        if path.ends_with("missing") {
+            assert!(result.is_err());
+            continue;
+        }
+        let report = result.as_ref().unwrap();
+        let compressed = fs::read(report.output()).unwrap();
+        assert_eq!(report.compressed_len(), compressed.len() as u64);
+        let original = fs::read(path).unwrap();
+        assert_eq!(report.original_len(), original.len() as u64);
+        assert_eq!(original, read_frame_depress(&compressed));
+    }
+
+    let results = compress_paths(&paths[..1], &options);
+    let err = results[0].1.as_ref().unwrap_err();
+    assert_eq!(std::io::ErrorKind::AlreadyExists, err.kind());
+    options.overwrite(true);
+    assert!(compress_paths(&paths[..1], &options)[0].1.is_ok());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Encoders and decoders from a pool should be reused, and the pool should
+// keep at most its maximum number of idle ones.
+#[test]
+fn codec_pool() {
+    use snap::pool::CodecPool;
+
+    let data = &include_bytes!("../data/html")[..];
+    let pool = CodecPool::with_max_size(1);
+    {
+        let mut enc1 = pool.encoder();
+        let mut enc2 = pool.encoder();
+        let compressed = enc1.compress_vec(data).unwrap();
+        assert_eq!(compressed, enc2.compress_vec(data).unwrap());
+        let decompressed = pool.decoder().decompress_vec(&compressed);
+        assert_eq!(data, &decompressed.unwrap()[..]);
+    }
+    assert_eq!(1, pool.idle_encoders());
+    assert_eq!(1, pool.idle_decoders());
+
+    let mut enc = pool.encoder();
+    assert_eq!(0, pool.idle_encoders());
+    assert_eq!(press(data), enc.compress_vec(data).unwrap());
+    drop(enc);
+    assert_eq!(1, pool.idle_encoders());
+}
+
+// An encoder returned to a pool should be handed out again with its stats
+// reset, so that one borrower's counts never show up for the next.
+#[test]
+fn codec_pool_resets_encoder() {
+    use snap::pool::CodecPool;
+    use snap::raw::EncoderBuilder;
+
+    let data = &include_bytes!("../data/html")[..];
+    let mut builder = EncoderBuilder::new();
+    builder.collect_stats(true);
+    let pool = CodecPool::with_builder(&builder, 1);
+
+    let mut enc = pool.encoder();
+    enc.compress_vec(data).unwrap();
+    let stats = enc.stats().unwrap().clone();
+    assert_eq!(data.len() as u64, stats.input_len());
+    drop(enc);
+
+    let mut enc = pool.encoder();
+    assert_eq!(Some(&Default::default()), enc.stats());
+    assert_eq!(None, enc.last_block_stats());
+    assert_eq!(press(data), enc.compress_vec(data).unwrap());
+    assert_eq!(Some(&stats), enc.stats());
+}
+
+// The reported accelerated paths should match the configuration the crate
+// was built with. The features of snap itself (e.g., small-crc or
+// nightly-simd) aren't visible here, so the paths they select are only checked
+// against the line that reports them.
+#[test]
+fn cpu_active_features() {
+    use snap::cpu::{active_features, Crc32c};
+
+    let features = active_features();
+    let x86 = cfg!(any(target_arch = "x86", target_arch = "x86_64"));
+    if x86 && cfg!(target_feature = "sse4.2") {
+        assert_eq!(Some(Crc32c::Sse42), features.crc32c());
+    } else if cfg!(not(any(target_arch = "riscv64", target_arch = "s390x"))) {
+        let crc32c = features.crc32c();
+        assert!(
+            crc32c == Some(Crc32c::Slice16) || crc32c == Some(Crc32c::Nibble),
+            "{:?}",
+            crc32c
+        );
+    }
+    assert_eq!(x86 && cfg!(target_feature = "ssse3"), features.ssse3_copy());
+    assert_eq!(x86 && cfg!(target_feature = "bmi2"), features.bmi2_tags());
+
+    let line = features.to_string();
+    let crc32c = format!("crc32c={} ", features.crc32c().unwrap());
+    assert!(line.starts_with(&crc32c), "{}", line);
+    let simd = if features.portable_simd() { "on" } else { "off" };
+    assert!(line.ends_with(&format!("portable-simd={}", simd)), "{}", line);
+}
+
+// Blocks should only be written compressed when compressing them saves at
+// least the minimum percentage, and both frame encoders should agree.
+#[test]
+fn frame_min_savings() {
+    use snap::frame::{ChunkType, EncoderOptions, STREAM_IDENTIFIER};
+    use snap::{read, write};
+    use std::io::{Read, Write};
+
+    fn compressed_chunks(mut frame: &[u8]) -> usize {
+        frame = &frame[STREAM_IDENTIFIER.len()..];
+        let mut count = 0;
+        while !frame.is_empty() {
+            let len = frame[1] as usize
+                | (frame[2] as usize) << 8
+                | (frame[3] as usize) << 16;
+            if frame[0] == ChunkType::Compressed as u8 {
+                count += 1;
+            }
+            frame = &frame[4 + len..];
+        }
+        count
+    }
+
+    // This compresses every block to less than half its size.
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let chunks = (data.len() + 65535) / 65536;
+    for &(percent, want) in
+        &[(None, chunks), (Some(50), chunks), (Some(90), 0), (Some(200), 0)]
+    {
+        let mut options = EncoderOptions::new();
+        options.min_savings(percent);
+        let mut wtr = write::FrameEncoder::with_options(vec![], &options);
+        wtr.write_all(data).unwrap();
+        let frame = wtr.into_inner().unwrap();
+        assert_eq!(want, compressed_chunks(&frame), "{:?}", percent);
+        assert_eq!(data, &read_frame_depress(&frame)[..]);
+
+        let mut got = vec![];
+        let mut rdr = read::FrameEncoder::with_options(data, &options);
+        rdr.read_to_end(&mut got).unwrap();
+        assert_eq!(frame, got);
+    }
+}
+
+// Validating a stream should count its chunks, and report the first error
+// along with the offset of the chunk it's in.
+#[test]
+fn frame_validate() {
+    use snap::frame::{validate, STREAM_IDENTIFIER};
+    use snap::read::FrameDecoder;
+    use std::io::{self, Read};
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let frame = write_frame_press(data);
+    let report = validate(&frame[..]).unwrap();
+    assert!(report.is_valid());
+    assert_eq!(7, report.chunks());
+    assert_eq!(frame.len() as u64, report.compressed_len());
+    assert_eq!(data.len() as u64, report.decompressed_len());
+    assert_eq!(None, report.error());
+
+    let first_len = frame[11] as usize
+        | (frame[12] as usize) << 8
+        | (frame[13] as usize) << 16;
+    let second = (STREAM_IDENTIFIER.len() + 4 + first_len) as u64;
+    let mut bad = frame.clone();
+    bad[second as usize + 4] ^= 1;
+    let report = validate(&bad[..]).unwrap();
+    assert!(!report.is_valid());
+    assert_eq!(1, report.chunks());
+    assert_eq!(Some(second), report.error_offset());
+    assert_eq!(second, report.compressed_len());
+    assert_eq!(65536, report.decompressed_len());
+    match report.error() {
+        Some(&Error::Checksum { .. }) => {}
+        err => panic!("expected a checksum error, got {:?}", err),
+    }
+
+    let report = validate(&frame[..frame.len() - 1]).unwrap();
+    assert_eq!(Some(&Error::TruncatedChunk), report.error());
+    assert_eq!(6, report.chunks());
+
+    // A stream cut inside a chunk header is truncated too, not just short.
+    let cut = &frame[..second as usize + 2];
+    let report = validate(cut).unwrap();
+    assert_eq!(Some(&Error::TruncatedChunk), report.error());
+    assert_eq!(Some(second), report.error_offset());
+    assert_eq!(1, report.chunks());
+    let err = FrameDecoder::new(cut).read_to_end(&mut vec![]).unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+}
+
+// The long-range format should round trip, find the repeats in html_x_4 that
+// are too far apart for Snappy, and be rejected by the standard decoders.
+#[test]
+fn long_range() {
+    use snap::long;
+    use std::io::Read;
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let compressed = long::Encoder::new().compress_vec(data).unwrap();
+    assert!(compressed.starts_with(long::MAGIC));
+    assert_eq!(data.len(), long::decompress_len(&compressed).unwrap());
+    let got = long::Decoder::new().decompress_vec(&compressed).unwrap();
+    assert_eq!(data, &got[..]);
+    // Every copy of html after the first is made of copies 100KB back.
+    let raw = press(data);
+    assert!(compressed.len() < raw.len() * 2 / 3);
+
+    // Copies 1MB back, and a second block starting with a literal too long
+    // for Snappy's blocks. This is over the limit of the small-input feature.
+    let jpg = &include_bytes!("../data/fireworks.jpeg")[..];
+    let mut data = vec![0; long::MAX_BLOCK_SIZE + 2 * jpg.len()];
+    if data.len() as u64 <= snap::raw::MAX_INPUT_SIZE {
+        data[..jpg.len()].copy_from_slice(jpg);
+        data.copy_within(..jpg.len(), 1 << 20);
+        data.copy_within(..2 * jpg.len(), long::MAX_BLOCK_SIZE);
+        let compressed = long::Encoder::new().compress_vec(&data).unwrap();
+        assert!(compressed.len() <= long::max_compress_len(data.len()));
+        let got = long::Decoder::new().decompress_vec(&compressed).unwrap();
+        assert_eq!(data, got);
+    }
+
+    let empty = long::Encoder::new().compress_vec(&[]).unwrap();
+    assert_eq!(0, long::Decoder::new().decompress_vec(&empty).unwrap().len());
+
+    match long::Decoder::new().decompress_vec(&raw) {
+        Err(Error::Header) => {}
+        res => panic!("expected a header error, got {:?}", res),
+    }
+    assert!(Decoder::new().decompress_vec(&compressed).is_err());
+    let mut framed = vec![];
+    let res = snap::read::FrameDecoder::new(&compressed[..])
+        .read_to_end(&mut framed);
+    assert!(res.is_err());
+}
+
+// A copy 4 operation of every length, at offsets around the limits of the
+// other copy operations and far beyond 64KB, should decompress exactly like a
+// byte at a time copy, both with bytes after it and at the end of the output.
+#[test]
+fn copy4_decode() {
+    let prefix: Vec<u8> = (0..1u32 << 20)
+        .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+        .collect();
+    let offsets = &[
+        1,
+        2,
+        3,
+        4,
+        7,
+        8,
+        9,
+        15,
+        16,
+        17,
+        63,
+        64,
+        2047,
+        2048,
+        65535,
+        65536,
+        65537,
+        1 << 20,
+    ];
+    for &offset in offsets {
+        // Skip the offsets over the limit of the small-input feature.
+        if (offset + 128) as u64 > snap::raw::MAX_INPUT_SIZE {
+            continue;
+        }
+        for len in 1..=64 {
+            for &tail in
+                &[&b""[..], &b"after the copy, with room to spare"[..]]
+            {
+                let mut want = prefix[..offset].to_vec();
+                for _ in 0..len {
+                    want.push(want[want.len() - offset]);
+                }
+                want.extend_from_slice(tail);
+
+                let mut block = vec![];
+                push_varint(&mut block, want.len() as u64);
+                push_literal(&mut block, &prefix[..offset]);
+                push_copy4(&mut block, offset as u32, len);
+                if !tail.is_empty() {
+                    push_literal(&mut block, tail);
+                }
+                let got = depress(&block);
+                assert!(got == want, "offset {}, len {}", offset, len);
+                assert_eq!(
+                    copy_ops(&block),
+                    vec![(0b11, offset, len)],
+                    "offset {}, len {}",
+                    offset,
+                    len
+                );
+            }
+        }
+    }
+
+    let mut block = vec![];
+    push_varint(&mut block, 65536 + 4);
+    push_literal(&mut block, &prefix[..65536]);
+    let mut bad = block.clone();
+    push_copy4(&mut bad, 65537, 4);
+    match Decoder::new().decompress_vec(&bad) {
+        Err(Error::Offset { offset: 65537, dst_pos: 65536 }) => {}
+        res => panic!("expected an offset error, got {:?}", res.err()),
+    }
+    let mut bad = block.clone();
+    push_copy4(&mut bad, std::u32::MAX, 4);
+    match Decoder::new().decompress_vec(&bad) {
+        Err(Error::Offset { offset, dst_pos: 65536 }) => {
+            assert_eq!(std::u32::MAX as u64, offset);
+        }
+        res => panic!("expected an offset error, got {:?}", res.err()),
+    }
+    let mut bad = block.clone();
+    push_copy4(&mut bad, 0, 4);
+    match Decoder::new().decompress_vec(&bad) {
+        Err(Error::ZeroOffset { dst_pos: 65536 }) => {}
+        res => panic!("expected a zero offset error, got {:?}", res.err()),
+    }
+    let mut bad = block.clone();
+    push_copy4(&mut bad, 65536, 5);
+    match Decoder::new().decompress_vec(&bad) {
+        Err(Error::CopyWrite { len: 5, dst_len: 4 }) => {}
+        res => panic!("expected a copy write error, got {:?}", res.err()),
+    }
+}
+
+// The long-range encoder should emit copy 4 operations for exactly the
+// offsets that are too big for a copy 2 operation.
+#[test]
+fn copy4_encode() {
+    use snap::long;
+
+    let html = &include_bytes!("../data/html")[..];
+    for &period in &[65535, 65536, 100_000] {
+        let mut data = html[..period].to_vec();
+        data.extend_from_slice(&html[..period]);
+        let compressed = long::Encoder::new().compress_vec(&data).unwrap();
+        let got = long::Decoder::new().decompress_vec(&compressed).unwrap();
+        assert!(got == data, "period {}", period);
+
+        let ops = copy_ops(&compressed[long::MAGIC.len()..]);
+        for &(tag, offset, _) in &ops {
+            assert_eq!(offset > 65535, tag == 0b11, "offset {}", offset);
+        }
+        // The repeat of html is mostly copied from the first one.
+        let repeated: usize = ops
+            .iter()
+            .filter(|&&(_, offset, _)| offset == period)
+            .map(|&(_, _, len)| len)
+            .sum();
+        assert!(repeated > period / 2, "period {}", period);
+    }
+
+    // The standard encoder never emits them.
+    let ops = copy_ops(&press(include_bytes!("../data/html_x_4")));
+    assert!(ops.iter().all(|&(tag, _, _)| tag != 0b11));
+}
+
+// Masking a checksum can be undone, and the checksum stored in a chunk
+// header is the masked CRC32C of the chunk's data.
+#[test]
+fn checksum_mask() {
+    use snap::frame::{mask_checksum, unmask_checksum, STREAM_IDENTIFIER};
+
+    for &sum in &[0, 1, 0x7FFF, 0x8000, 0xA282EAD8, 0xDEADBEEF, !0] {
+        assert_eq!(sum, unmask_checksum(mask_checksum(sum)));
+        assert_eq!(sum, mask_checksum(unmask_checksum(sum)));
+    }
+
+    // 0xE3069283 is the standard CRC32C check value.
+    let framed = write_frame_press(b"123456789");
+    let header = &framed[STREAM_IDENTIFIER.len()..];
+    let masked =
+        u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    assert_eq!(0xE3069283, unmask_checksum(masked));
+}
+
+// Teeing a frame decoder should write the decompressed stream and pass the
+// same bytes to the digest, stopping at the first corrupt chunk.
+#[test]
+fn read_frame_decoder_tee() {
+    use snap::read::FrameDecoder;
+    use std::io::Read;
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let stream = write_frame_press(data);
+
+    let (mut out, mut seen) = (vec![], vec![]);
+    let n = FrameDecoder::new(&stream[..])
+        .tee(&mut out, |bytes| seen.extend_from_slice(bytes))
+        .unwrap();
+    assert_eq!(data.len() as u64, n);
+    assert!(out == data);
+    assert!(seen == data);
+
+    // Only the rest of a partially read stream is teed.
+    let mut rdr = FrameDecoder::new(&stream[..]);
+    rdr.read_exact(&mut [0; 100]).unwrap();
+    let (mut out, mut seen) = (vec![], vec![]);
+    rdr.tee(&mut out, |bytes| seen.extend_from_slice(bytes)).unwrap();
+    assert!(out[..] == data[100..]);
+    assert!(seen[..] == data[100..]);
+
+    // Corrupt the checksum of the first chunk.
+    let mut corrupt = stream.clone();
+    corrupt[14] ^= 0xFF;
+    let mut seen = vec![];
+    let err = FrameDecoder::new(&corrupt[..])
+        .tee(vec![], |bytes| seen.extend_from_slice(bytes))
+        .unwrap_err();
+    match *err.into_inner().unwrap().downcast::<Error>().unwrap() {
+        Error::Checksum { .. } => {}
+        err => panic!("unexpected error: {}", err),
+    }
+    assert!(seen.is_empty());
+}
+
+// Sha256 should match the FIPS 180-4 test vectors, however its input is split
+// up, and should start over after finishing.
+#[test]
+fn sha256() {
+    use snap::frame::{Sha256, StreamDigest};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    let million = vec![b'a'; 1_000_000];
+    let vectors: &[(&[u8], &str)] = &[
+        (
+            b"",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        ),
+        (
+            b"abc",
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        ),
+        (
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        ),
+        (
+            &million,
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+        ),
+    ];
+    let mut digest = Sha256::new();
+    assert_eq!("sha256", digest.name());
+    for &(data, want) in vectors {
+        digest.update(data);
+        assert_eq!(want, hex(&digest.finish()));
+        for piece in data.chunks(7) {
+            digest.update(piece);
+        }
+        assert_eq!(want, hex(&digest.finish()));
+    }
+}
+
+// A digest trailer should be readable by any decoder, and should catch
+// streams that were truncated or had chunks removed.
+#[test]
+fn frame_digest() {
+    use snap::frame::{Sha256, DIGEST_CHUNK_TYPE, STREAM_IDENTIFIER};
+    use snap::read::FrameDecoder;
+    use snap::write::FrameEncoder;
+    use std::io::{Read, Write};
+
+    fn depress_digest(stream: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![];
+        let mut dec = FrameDecoder::new(stream);
+        match dec.verify_digest(Sha256::new()).read_to_end(&mut buf) {
+            Ok(_) => Ok(buf),
+            Err(err) => Err(*err.into_inner().unwrap().downcast().unwrap()),
+        }
+    }
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let mut wtr = FrameEncoder::new(vec![]);
+    wtr.digest(Sha256::new());
+    wtr.write_all(data).unwrap();
+    let stream = wtr.into_inner().unwrap();
+    assert_eq!(data, &depress_digest(&stream).unwrap()[..]);
+    // Decoders that don't verify digests skip the trailer, except in
+    // strict mode.
+    assert_eq!(data, &read_frame_depress(&stream)[..]);
+    let mut strict = FrameDecoder::new(&stream[..]);
+    strict.strict(true);
+    assert!(strict.read_to_end(&mut vec![]).is_err());
+    let mut strict = FrameDecoder::new(&stream[..]);
+    strict.strict(true).verify_digest(Sha256::new());
+    assert!(strict.read_to_end(&mut vec![]).is_ok());
+
+    // The trailer is the last chunk, and holds the name and the digest.
+    let trailer = stream.len() - (4 + 1 + 6 + 32);
+    assert_eq!(DIGEST_CHUNK_TYPE, stream[trailer]);
+    assert_eq!(&b"\x06sha256"[..], &stream[trailer + 4..trailer + 11]);
+
+    // An empty stream has a digest too.
+    let mut wtr = FrameEncoder::new(vec![]);
+    wtr.digest(Sha256::new());
+    let empty = wtr.into_inner().unwrap();
+    assert_eq!(STREAM_IDENTIFIER.len() + 4 + 1 + 6 + 32, empty.len());
+    assert_eq!(Ok(vec![]), depress_digest(&empty));
+
+    // Truncating the stream at a chunk boundary loses the trailer.
+    assert_eq!(Err(Error::MissingDigest), depress_digest(&stream[..trailer]));
+    assert_eq!(Err(Error::MissingDigest), depress_digest(STREAM_IDENTIFIER));
+    // Removing the last data chunk makes the digest mismatch.
+    let first_len = read_u24_le(&stream[STREAM_IDENTIFIER.len() + 1..]);
+    let first_end = STREAM_IDENTIFIER.len() + 4 + first_len;
+    let mut cut = stream[..first_end].to_vec();
+    cut.extend_from_slice(&stream[trailer..]);
+    match depress_digest(&cut) {
+        Err(Error::Digest { expected, got }) => {
+            assert_eq!(&stream[trailer + 11..], &expected[..]);
+            assert_ne!(expected, got);
+        }
+        got => panic!("unexpected result: {:?}", got.map(|v| v.len())),
+    }
+    // Concatenated streams are checked one by one.
+    let mut twice = stream.clone();
+    twice.extend_from_slice(&stream);
+    assert_eq!(2 * data.len(), depress_digest(&twice).unwrap().len());
+}
+
+// A filter should be applied to every chunk by the encoder and undone by a
+// decoder that was given it, and a decoder that wasn't should say so.
+#[test]
+fn frame_filter() {
+    use snap::frame::{
+        decompress_to_sink, recover, to_raw_blocks, ChunkFilter,
+        RecoverPolicy, Sha256, FILTER_CHUNK_TYPE, STREAM_IDENTIFIER,
+    };
+    use snap::read::{FrameDecoder, SnappyFile};
+    use snap::write::FrameEncoder;
+    use std::io::{Cursor, Read, Write};
+
+    struct Xor(&'static str, u8);
+
+    impl ChunkFilter for Xor {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn encode(&mut self, data: &mut [u8]) {
+            for b in data {
+                *b ^= self.1;
+            }
+        }
+
+        fn decode(&mut self, data: &mut [u8]) {
+            self.encode(data);
+        }
+    }
+
+    fn depress_filter(
+        stream: &[u8],
+        filters: Vec<Xor>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![];
+        let mut dec = FrameDecoder::new(stream);
+        for filter in filters {
+            dec.filter(filter);
+        }
+        match dec.read_to_end(&mut buf) {
+            Ok(_) => Ok(buf),
+            Err(err) => Err(*err.into_inner().unwrap().downcast().unwrap()),
+        }
+    }
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let mut wtr = FrameEncoder::new(vec![]);
+    wtr.filter(Xor("xor", 0x5A)).digest(Sha256::new());
+    wtr.write_all(data).unwrap();
+    let stream = wtr.into_inner().unwrap();
+    // The filter is named right after the stream identifier.
+    let at = STREAM_IDENTIFIER.len();
+    assert_eq!(FILTER_CHUNK_TYPE, stream[at]);
+    assert_eq!(&b"\x03\x00\x00xor"[..], &stream[at + 1..at + 7]);
+
+    let filters = vec![Xor("other", 0x11), Xor("xor", 0x5A)];
+    assert_eq!(data, &depress_filter(&stream, filters).unwrap()[..]);
+    // The digest covers the data before it was filtered.
+    let mut dec = FrameDecoder::new(&stream[..]);
+    dec.strict(true).filter(Xor("xor", 0x5A)).verify_digest(Sha256::new());
+    let mut buf = vec![];
+    dec.read_to_end(&mut buf).unwrap();
+    assert_eq!(data, &buf[..]);
+
+    // A decoder without the filter fails, even when resynchronizing.
+    let unknown = Err(Error::UnknownFilter { name: b"xor".to_vec() });
+    assert_eq!(unknown, depress_filter(&stream, vec![]));
+    assert_eq!(unknown, depress_filter(&stream, vec![Xor("other", 0x5A)]));
+    let mut dec = FrameDecoder::new(&stream[..]);
+    assert!(dec.resync(true).read_to_end(&mut vec![]).is_err());
+
+    // Readers that can't be given a filter reject the stream rather than
+    // returning filtered data.
+    let xor = || Error::UnknownFilter { name: b"xor".to_vec() };
+    let mut window = vec![0; 1 << 16];
+    let res = decompress_to_sink(&stream, &mut window, |_| Ok::<_, Error>(()));
+    assert_eq!(Err(xor()), res);
+    assert_eq!(Err(xor()), to_raw_blocks(&stream));
+    let err = SnappyFile::new(Cursor::new(&stream)).err().unwrap();
+    assert_eq!(xor(), *err.into_inner().unwrap().downcast().unwrap());
+    let policy = RecoverPolicy::VerifyChecksums;
+    let err = recover(&stream[..], vec![], policy).unwrap_err();
+    assert_eq!(xor(), *err.into_inner().unwrap().downcast().unwrap());
+
+    // The filter ends with its stream.
+    let mut mixed = stream.clone();
+    mixed.extend_from_slice(&write_frame_press(data));
+    let out = depress_filter(&mixed, vec![Xor("xor", 0x5A)]).unwrap();
+    assert_eq!(data, &out[..data.len()]);
+    assert_eq!(data, &out[data.len()..]);
+}
+
+// The built-in filters should undo themselves for any length of data, and
+// should help with the kind of data they're meant for.
+#[test]
+fn frame_filter_numeric() {
+    use snap::frame::{ChunkFilter, Delta, Transpose};
+    use snap::read::FrameDecoder;
+    use snap::write::FrameEncoder;
+    use std::io::{Read, Write};
+
+    fn press_filter<F: ChunkFilter + Send + 'static>(
+        data: &[u8],
+        filter: F,
+    ) -> Vec<u8> {
+        let mut wtr = FrameEncoder::new(vec![]);
+        wtr.filter(filter);
+        wtr.write_all(data).unwrap();
+        wtr.into_inner().unwrap()
+    }
+
+    fn depress_filter<F: ChunkFilter + Send + 'static>(
+        stream: &[u8],
+        filter: F,
+    ) -> Vec<u8> {
+        let mut buf = vec![];
+        let mut dec = FrameDecoder::new(stream);
+        dec.filter(filter).read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    let data = &include_bytes!("../data/html")[..];
+    for &len in &[0, 1, 7, 8, 9, 1000, 65537, data.len()] {
+        let data = &data[..len];
+        for &width in &[1, 2, 4, 8] {
+            let stream = press_filter(data, Delta::new(width));
+            assert_eq!(data, &depress_filter(&stream, Delta::new(width))[..]);
+        }
+        for &stride in &[1, 3, 4, 8] {
+            let stream = press_filter(data, Transpose::new(stride));
+            let got = depress_filter(&stream, Transpose::new(stride));
+            assert_eq!(data, &got[..]);
+        }
+    }
+    assert_eq!("delta32", Delta::new(4).name());
+    assert_eq!("transpose12", Transpose::new(12).name());
+
+    // Timestamps a few milliseconds apart.
+    let mut ts = 1_600_000_000_000u64;
+    let mut stamps = vec![];
+    for i in 0..100_000u64 {
+        ts += 1 + (i.wrapping_mul(2_654_435_761) >> 13) % 4;
+        stamps.extend_from_slice(&ts.to_le_bytes());
+    }
+    let plain = write_frame_press(&stamps).len();
+    let delta = press_filter(&stamps, Delta::new(8)).len();
+    assert!(delta * 3 < plain, "delta: {} vs {}", delta, plain);
+
+    // Readings of a slowly changing sensor, as floats.
+    let mut floats = vec![];
+    for i in 0..100_000 {
+        let x = 20.0 + (i as f32 / 1000.0).sin();
+        floats.extend_from_slice(&x.to_bits().to_le_bytes());
+    }
+    let plain = write_frame_press(&floats).len();
+    let transposed = press_filter(&floats, Transpose::new(4)).len();
+    assert!(transposed < plain, "transpose: {} vs {}", transposed, plain);
+}
+
+// Reading the declared length of a raw block should read only its header.
+#[test]
+fn raw_read_decompress_len() {
+    use snap::raw::read_decompress_len;
+    use std::io::{self, Read};
+
+    fn read_len(bytes: &[u8]) -> Result<(u64, usize), Error> {
+        read_decompress_len(&mut &bytes[..])
+            .map_err(|err| *err.into_inner().unwrap().downcast().unwrap())
+    }
+
+    let data = &include_bytes!("../data/html")[..];
+    let compressed = press(data);
+    let mut rdr = io::Cursor::new(&compressed);
+    let (len, consumed) = read_decompress_len(&mut rdr).unwrap();
+    assert_eq!(data.len() as u64, len);
+    assert_eq!(consumed as u64, rdr.position());
+    assert_eq!(decompress_len(&compressed).unwrap() as u64, len);
+    // The rest of the block is left for the caller.
+    let mut rest = vec![];
+    rdr.read_to_end(&mut rest).unwrap();
+    assert_eq!(&compressed[consumed..], &rest[..]);
+
+    assert_eq!(Ok((0, 0)), read_len(b""));
+    assert_eq!(Ok((0, 1)), read_len(b"\x00"));
+    assert_eq!(Ok((300, 2)), read_len(b"\xAC\x02\xFF"));
+    assert_eq!(Err(Error::Header), read_len(b"\xAC"));
+    assert_eq!(Err(Error::Header), read_len(&[0xFF; 11]));
+    match read_len(b"\xFF\xFF\xFF\xFF\xFF\x01") {
+        Err(Error::TooBig { given, .. }) => assert_eq!((1 << 36) - 1, given),
+        got => panic!("unexpected result: {:?}", got),
+    }
+}
+
+// Decompressing a raw block followed by other bytes should report the length
+// of the block.
+#[test]
+fn raw_decompress_prefix() {
+    let data = &include_bytes!("../data/html")[..];
+    let mut compressed = press(data);
+    let len = compressed.len();
+    compressed.extend_from_slice(b"trailing bytes");
+
+    let mut got = vec![0; data.len()];
+    let (consumed, written) =
+        Decoder::new().decompress_prefix(&compressed, &mut got).unwrap();
+    assert_eq!((len, data.len()), (consumed, written));
+    assert_eq!(data, &*got);
+    assert!(Decoder::new().decompress(&compressed, &mut got).is_err());
+}
+
+// Strict mode should reject padding chunks and empty streams, but accept the
+// output of the frame encoders.
+#[test]
+fn read_frame_decoder_strict() {
+    use snap::read;
+    use std::io::Read;
+
+    fn strict_depress(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut buf = vec![];
+        let mut dec = read::FrameDecoder::new(bytes);
+        dec.strict(true).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn snap_error(err: std::io::Error) -> Error {
+        *err.into_inner().unwrap().downcast::<Error>().unwrap()
+    }
+
+    let data = &include_bytes!("../data/html")[..];
+    let compressed = write_frame_press(data);
+    assert_eq!(data, &*strict_depress(&compressed).unwrap());
+
+    let mut padded = compressed.clone();
+    padded.extend_from_slice(b"\xFE\x02\x00\x00\x00\x00");
+    assert_eq!(data, &*read_frame_depress(&padded));
+    assert_eq!(
+        Error::UnsupportedChunkType { byte: 0xFE },
+        snap_error(strict_depress(&padded).unwrap_err()),
+    );
+    assert_eq!(
+        Error::EmptyStream,
+        snap_error(strict_depress(&[]).unwrap_err()),
+    );
+}
+
+// Data chunks too short to hold a checksum should be rejected rather than
+// panicking the decoder.
+#[test]
+fn read_frame_short_data_chunk() {
+    use snap::read;
+    use std::io::Read;
+
+    for &ty in &[0x00, 0x01] {
+        for len in 0..4 {
+            let mut stream = b"\xFF\x06\x00\x00sNaPpY".to_vec();
+            stream.extend_from_slice(&[ty, len as u8, 0, 0]);
+            stream.extend_from_slice(&[1, 2, 3, 4][..len]);
+            let mut got = vec![];
+            let err = read::FrameDecoder::new(&stream[..])
+                .read_to_end(&mut got)
+                .unwrap_err();
+            let err = *err.into_inner().unwrap().downcast::<Error>().unwrap();
+            let len = len as u64;
+            assert_eq!(
+                Error::UnsupportedChunkLength { len, header: false },
+                err
+            );
+        }
+    }
+}
+
+// The frame adapters should retry reads that are interrupted rather than
+// returning an error to the caller.
+#[test]
+fn read_frame_interrupted() {
+    use snap::read;
+    use std::io::{self, Read};
+
+    struct Interrupting<R>(R, bool);
+
+    impl<R: Read> Read for Interrupting<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.1 = !self.1;
+            if self.1 {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            self.0.read(buf)
+        }
+    }
+
+    fn read_all<R: Read>(mut rdr: R) -> Vec<u8> {
+        let mut out = vec![];
+        let mut buf = [0; 4096];
+        loop {
+            match rdr.read(&mut buf).unwrap() {
+                0 => return out,
+                n => out.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    let data = &include_bytes!("../data/html")[..];
+    let enc = read::FrameEncoder::new(Interrupting(data, false));
+    let compressed = read_all(enc);
+    let dec = read::FrameDecoder::new(Interrupting(&compressed[..], false));
+    assert_eq!(data, &*read_all(dec));
+}
+
+// Encoders with any hash table size should roundtrip.
+#[test]
+fn raw_encoder_builder_table_size() {
+    use snap::raw::EncoderBuilder;
+
+    let data = &include_bytes!("../data/html")[..];
+    for &size in &[0, 256, 1000, 4096, 1 << 20] {
+        let compressed = EncoderBuilder::new()
+            .max_table_size(size)
+            .build()
+            .compress_vec(data)
+            .unwrap();
+        assert_eq!(data, &*depress(&compressed));
+    }
+}
+
+// Encoders that search hash chains should roundtrip, and should compress
+// better than the default encoder.
+#[test]
+fn raw_encoder_builder_search_depth() {
+    use snap::raw::EncoderBuilder;
+
+    let corpus: &[&[u8]] = &[
+        include_bytes!("../data/html"),
+        include_bytes!("../data/html_x_4"),
+        include_bytes!("../data/urls.10K"),
+        include_bytes!("../data/fireworks.jpeg"),
+        include_bytes!("../data/paper-100k.pdf"),
+        include_bytes!("../data/geo.protodata"),
+        include_bytes!("../data/kppkn.gtb"),
+        include_bytes!("../data/alice29.txt"),
+        &[0; 100_000],
+    ];
+    for data in corpus {
+        let default = press(data);
+        for &depth in &[1, 16, 4096] {
+            let compressed = EncoderBuilder::new()
+                .search_depth(depth)
+                .build()
+                .compress_vec(data)
+                .unwrap();
+            assert_eq!(*data, &*depress(&compressed));
+            // Incompressible data can come out a few bytes longer, since
+            // the longest match isn't always the cheapest one to encode.
+            if depth >= 16 && default.len() < data.len() * 9 / 10 {
+                assert!(compressed.len() <= default.len());
+            }
+        }
+    }
+    // Text compresses noticeably better.
+    let data = &include_bytes!("../data/alice29.txt")[..];
+    let hc = EncoderBuilder::new()
+        .search_depth(64)
+        .build()
+        .compress_vec(data)
+        .unwrap();
+    assert!(hc.len() * 100 < press(data).len() * 95);
+    // Blocks of every small size still roundtrip.
+    let mut enc = EncoderBuilder::new().search_depth(16).build();
+    for n in 0..100 {
+        let data = &include_bytes!("../data/html")[..n];
+        assert_eq!(data, &*depress(&enc.compress_vec(data).unwrap()));
+    }
+    assert_eq!(
+        4096,
+        EncoderBuilder::new().search_depth(1 << 20).get_search_depth()
+    );
+}
+
+// The optimal parser should roundtrip, and should never do worse than
+// parsing the same matches greedily.
+#[test]
+fn raw_encoder_builder_optimal_parse() {
+    use snap::raw::EncoderBuilder;
+
+    let corpus: &[&[u8]] = &[
+        include_bytes!("../data/html"),
+        include_bytes!("../data/urls.10K"),
+        include_bytes!("../data/fireworks.jpeg"),
+        include_bytes!("../data/geo.protodata"),
+        include_bytes!("../data/kppkn.gtb"),
+        include_bytes!("../data/alice29.txt"),
+        &[0; 100_000],
+    ];
+    for data in corpus {
+        let chains = EncoderBuilder::new()
+            .search_depth(64)
+            .build()
+            .compress_vec(data)
+            .unwrap();
+        let optimal = EncoderBuilder::new()
+            .search_depth(64)
+            .optimal_parse(true)
+            .build()
+            .compress_vec(data)
+            .unwrap();
+        assert_eq!(*data, &*depress(&optimal));
+        assert!(optimal.len() <= chains.len());
+    }
+    // With no search depth, the deepest search is used.
+    let data = &include_bytes!("../data/html")[..];
+    let mut enc = EncoderBuilder::new().optimal_parse(true).build();
+    assert_eq!(data, &*depress(&enc.compress_vec(data).unwrap()));
+    for n in 0..100 {
+        let data = &data[..n];
+        assert_eq!(data, &*depress(&enc.compress_vec(data).unwrap()));
+    }
+}
+
+// Encoder stats should account for every byte of input and output.
+#[test]
+fn raw_encoder_stats() {
+    use snap::raw::{read_decompress_len, EncoderBuilder};
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    assert!(EncoderBuilder::new().build().stats().is_none());
+
+    let mut enc = EncoderBuilder::new().collect_stats(true).build();
+    assert_eq!(0, enc.stats().unwrap().blocks());
+    assert!(enc.last_block_stats().is_none());
+    let compressed = enc.compress_vec(data).unwrap();
+    let stats = enc.stats().unwrap().clone();
+    assert_eq!(7, stats.blocks());
+    assert_eq!(data.len() as u64, stats.input_len());
+    assert_eq!(data.len() as u64, stats.literal_len() + stats.copy_len());
+    // The length of 409,600 bytes is written in 3 bytes.
+    assert_eq!(
+        (data.len() as u64, 3),
+        read_decompress_len(&mut &compressed[..]).unwrap()
+    );
+    assert_eq!((compressed.len() - 3) as u64, stats.output_len());
+    assert_eq!(stats.copies(), stats.copy_lengths().iter().sum::<u64>());
+    assert_eq!(stats.copies(), stats.copy_offsets().iter().sum::<u64>());
+    assert_eq!(&[0, 0], &stats.copy_lengths()[..2]);
+    assert!(stats.hash_collisions() < stats.hash_probes());
+    assert!(stats.hash_collision_rate() > 0.0);
+    assert!(stats.hash_collision_rate() < 1.0);
+
+    // The last block is the 16,384 bytes left after six full blocks.
+    let last = enc.last_block_stats().unwrap();
+    assert_eq!(1, last.blocks());
+    assert_eq!(16_384, last.input_len());
+
+    // Compressing again adds to the totals, until they're reset.
+    enc.compress_vec(data).unwrap();
+    let mut twice = stats.clone();
+    twice.add(&stats);
+    assert_eq!(&twice, enc.stats().unwrap());
+    enc.reset_stats();
+    assert_eq!(0, enc.stats().unwrap().blocks());
+    assert!(enc.last_block_stats().is_none());
+
+    // Searching hash chains makes no hash table lookups, and finds more
+    // copies than the default matcher.
+    let mut enc =
+        EncoderBuilder::new().collect_stats(true).search_depth(16).build();
+    enc.compress_vec(data).unwrap();
+    let chains = enc.stats().unwrap();
+    assert_eq!(0, chains.hash_probes());
+    assert_eq!(0.0, chains.hash_collision_rate());
+    assert!(chains.copy_len() > stats.copy_len());
+}
+
+// The size computed without compressing to an output buffer should be
+// exactly the size of the output.
+#[test]
+fn raw_encoder_compressed_size() {
+    use snap::raw::EncoderBuilder;
+
+    let corpus: &[&[u8]] = &[
+        b"",
+        b"a",
+        &include_bytes!("../data/html")[..100],
+        include_bytes!("../data/html_x_4"),
+        include_bytes!("../data/fireworks.jpeg"),
+        &[0; 200_000],
+    ];
+    let mut enc = Encoder::new();
+    let mut deep = EncoderBuilder::new().search_depth(16).build();
+    for data in corpus {
+        assert_eq!(press(data).len(), enc.compressed_size(data).unwrap());
+        assert_eq!(
+            deep.compress_vec(data).unwrap().len(),
+            deep.compressed_size(data).unwrap()
+        );
+    }
+
+    let mut enc = EncoderBuilder::new().collect_stats(true).build();
+    enc.compressed_size(include_bytes!("../data/html")).unwrap();
+    assert_eq!(0, enc.stats().unwrap().blocks());
+}
+
+// Compressing scattered slices should produce exactly the same output as
+// compressing their concatenation.
+#[test]
+fn raw_compress_slices() {
+    use std::io::IoSlice;
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let splits: &[&[usize]] = &[
+        &[],
+        &[0, 0],
+        &[1],
+        &[65536],
+        &[100, 65000, 65600, 200000],
+        &[1000, 1001, 1002, 131072, 131073],
+    ];
+    for &split in splits {
+        let mut slices = vec![];
+        let mut last = 0;
+        for &at in split {
+            slices.push(IoSlice::new(&data[last..at]));
+            last = at;
+        }
+        slices.push(IoSlice::new(&data[last..]));
+
+        let mut buf = vec![0; snap::raw::max_compress_len(data.len())];
+        let n = Encoder::new().compress_slices(&slices, &mut buf).unwrap();
+        assert_eq!(press(data), &buf[..n]);
+    }
+}
+
+// Decompressing into a sequence of buffers should produce the same bytes as
+// decompressing into one buffer, regardless of how the buffers are sized.
+#[test]
+fn raw_decompress_slices() {
+    use std::io::IoSliceMut;
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let compressed = press(data);
+    for &page_size in &[1, 7, 4096, 65536, data.len()] {
+        let mut out = vec![0; data.len()];
+        let mut pages: Vec<IoSliceMut<'_>> =
+            out.chunks_mut(page_size).map(IoSliceMut::new).collect();
+        pages.insert(1, IoSliceMut::new(&mut []));
+        let n =
+            Decoder::new().decompress_slices(&compressed, &mut pages).unwrap();
+        assert_eq!(data.len(), n);
+        assert_eq!(data, &*out);
+    }
+
+    let mut small = vec![0; data.len() - 1];
+    let mut pages = [IoSliceMut::new(&mut small)];
+    assert_eq!(
+        Error::BufferTooSmall {
+            given: data.len() as u64 - 1,
+            min: data.len() as u64,
+        },
+        Decoder::new().decompress_slices(&compressed, &mut pages).unwrap_err(),
+    );
+}
+
+// Visiting a block should pass on literals and copies that decompress to the
+// original data, and should stop at the first invalid element.
+#[test]
+fn raw_visit() {
+    use snap::raw::{visit, TagVisitor};
+
+    #[derive(Default)]
+    struct Rebuild {
+        out: Vec<u8>,
+        literals: usize,
+        copies: usize,
+    }
+
+    impl TagVisitor for Rebuild {
+        fn on_literal(&mut self, literal: &[u8]) {
+            self.out.extend_from_slice(literal);
+            self.literals += 1;
+        }
+
+        fn on_copy(&mut self, offset: usize, len: usize) {
+            for _ in 0..len {
+                let b = self.out[self.out.len() - offset];
+                self.out.push(b);
+            }
+            self.copies += 1;
+        }
+    }
+
+    for &data in &[
+        &include_bytes!("../data/html")[..],
+        &include_bytes!("../data/fireworks.jpeg")[..],
+        &[],
+    ] {
+        let mut rebuild = Rebuild::default();
+        let n = visit(&press(data), &mut rebuild).unwrap();
+        assert_eq!(data.len(), n);
+        assert_eq!(data, &*rebuild.out);
+    }
+    let mut rebuild = Rebuild::default();
+    visit(&press(include_bytes!("../data/html")), &mut rebuild).unwrap();
+    assert!(rebuild.literals > 0 && rebuild.copies > 0);
+
+    // A literal 'a', then a copy from 5 bytes back.
+    let mut rebuild = Rebuild::default();
+    assert_eq!(
+        Error::Offset { offset: 5, dst_pos: 1 },
+        visit(&[8, 0, b'a', 1, 5], &mut rebuild).unwrap_err(),
+    );
+    assert_eq!(b"a", &*rebuild.out);
+    // The same copy with an offset of zero.
+    assert_eq!(
+        Error::ZeroOffset { dst_pos: 1 },
+        visit(&[8, 0, b'a', 1, 0], Rebuild::default()).unwrap_err(),
+    );
+    assert_eq!(
+        Error::HeaderMismatch { expected_len: 8, got_len: 1 },
+        visit(&[8, 0, b'a'], Rebuild::default()).unwrap_err(),
+    );
+}
+
+// The compressibility estimate should rank already compressed data below
+// text, and should stay in range.
+#[test]
+fn estimate_compressibility() {
+    use snap::estimate_compressibility as estimate;
+
+    let jpeg = estimate(include_bytes!("../data/fireworks.jpeg"));
+    let html = estimate(include_bytes!("../data/html"));
+    let text = estimate(include_bytes!("../data/Mark.Twain-Tom.Sawyer.txt"));
+    assert!(jpeg < 0.1);
+    assert!(html > 0.5);
+    assert!(text > jpeg);
+    assert!(estimate(&[0; 1 << 20]) > 0.9);
+    assert_eq!(0.0, estimate(&[]));
+    assert_eq!(0.0, estimate(b"abc"));
+}
+
+// Skipping incompressible blocks should leave random data uncompressed, while
+// still compressing text and roundtripping.
+#[test]
+fn frame_encoder_skip_incompressible() {
+    use snap::{read, write};
+    use std::io::{Read, Write};
+
+    let jpeg = &include_bytes!("../data/fireworks.jpeg")[..];
+    let html = &include_bytes!("../data/html")[..];
+    for &data in &[jpeg, html] {
+        let mut wtr = write::FrameEncoder::new(vec![]);
+        wtr.skip_incompressible(true).write_all(data).unwrap();
+        let written = wtr.into_inner().unwrap();
+
+        let mut rdr = read::FrameEncoder::new(data);
+        let mut read = vec![];
+        rdr.skip_incompressible(true).read_to_end(&mut read).unwrap();
+
+        for compressed in &[written, read] {
+            assert_eq!(data, &*read_frame_depress(compressed));
+            if data == jpeg {
+                // The stream identifier, plus one uncompressed chunk per
+                // block.
+                let chunks = (data.len() + 65535) / 65536;
+                assert_eq!(10 + 8 * chunks + data.len(), compressed.len());
+            } else {
+                assert!(compressed.len() < data.len() / 2);
+            }
+        }
+    }
+}
+
+// Estimating the framed size should be exact when every chunk is sampled, and
+// close when only some of them are.
+#[test]
+fn frame_estimate_compressed_size() {
+    use snap::frame::estimate_compressed_size;
+
+    let data = &include_bytes!("../data/html_x_4")[..];
+    let actual = write_frame_press(data).len() as u64;
+    assert_eq!(actual, estimate_compressed_size(data, 100));
+    for &samples in &[0, 1, 2, 3] {
+        let estimate = estimate_compressed_size(data, samples) as f64;
+        assert!((estimate - actual as f64).abs() / (actual as f64) < 0.2);
+    }
+    assert_eq!(0, estimate_compressed_size(&[], 1));
+}
+
+// Tests decompression on malformed data.
+
+// An empty buffer.
+testerrored!(err_empty, &b""[..], Error::Empty);
+
+// Decompress fewer bytes than the header reports.
+testerrored!(
+    err_header_mismatch,
+    &b"\x05\x00a"[..],
+    Error::HeaderMismatch { expected_len: 5, got_len: 1 }
+);
+
+// An invalid varint (final byte has continuation bit set).
+testerrored!(err_varint1, &b"\xFF"[..], Error::Header, true);
+
+// A varint that overflows u64.
+testerrored!(
+    err_varint2,
+    &b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\x00"[..],
+    Error::Header,
+    true
+);
+
+// A varint that fits in u64 but overflows u32.
+testerrored!(
+    err_varint3,
+    &b"\x80\x80\x80\x80\x10"[..],
+    Error::TooBig { given: 4294967296, max: snap::raw::MAX_INPUT_SIZE },
+    true
+);
+
+// A literal whose length is too small.
+// Since the literal length is 1, 'h' is read as a literal and 'i' is
+// interpreted as a copy 1 operation missing its offset byte.
+testerrored!(
+    err_lit,
+    &b"\x02\x00hi"[..],
+    Error::CopyRead { len: 1, src_len: 0 }
+);
+// A literal whose length is too big.
+testerrored!(
+    err_lit_big1,
+    &b"\x02\xechi"[..],
+    Error::Literal { len: 60, src_len: 2, dst_len: 2 }
+);
+// A literal whose length is too big, requires 1 extra byte to be read, and
+// src is too short to read that byte.
+testerrored!(
+    err_lit_big2a,
+    &b"\x02\xf0hi"[..],
+    Error::Literal { len: 4, src_len: 2, dst_len: 2 }
+);
+// A literal whose length is too big, requires 1 extra byte to be read,
+// src is too short to read the full literal.
+testerrored!(
+    err_lit_big2b,
+    &b"\x02\xf0hi\x00\x00\x00"[..],
+    Error::Literal {
+        len: 105, // because 105 == 'h' as u8 + 1
+        src_len: 4,
+        dst_len: 2,
+    }
+);
+
+// A copy 1 operation that stops at the tag byte. This fails because there's
+// no byte to read for the copy offset.
+testerrored!(
+    err_copy1,
+    &b"\x02\x00a\x01"[..],
+    Error::CopyRead { len: 1, src_len: 0 }
+);
+// A copy 2 operation that stops at the tag byte and another copy 2 operation
+// that stops after the first byte in the offset.
+testerrored!(
+    err_copy2a,
+    &b"\x11\x00a\x3e"[..],
+    Error::CopyRead { len: 2, src_len: 0 }
+);
+testerrored!(
+    err_copy2b,
+    &b"\x11\x00a\x3e\x01"[..],
+    Error::CopyRead { len: 2, src_len: 1 }
+);
+// Same as copy 2, but for copy 4.
+testerrored!(
+    err_copy3a,
+    &b"\x11\x00a\x3f"[..],
+    Error::CopyRead { len: 4, src_len: 0 }
+);
+testerrored!(
+    err_copy3b,
+    &b"\x11\x00a\x3f\x00"[..],
+    Error::CopyRead { len: 4, src_len: 1 }
+);
+testerrored!(
+    err_copy3c,
+    &b"\x11\x00a\x3f\x00\x00"[..],
+    Error::CopyRead { len: 4, src_len: 2 }
+);
+testerrored!(
+    err_copy3d,
+    &b"\x11\x00a\x3f\x00\x00\x00"[..],
+    Error::CopyRead { len: 4, src_len: 3 }
+);
+
+// A copy operation whose offset is zero.
+testerrored!(
+    err_copy_offset_zero,
+    &b"\x11\x00a\x01\x00"[..],
+    Error::ZeroOffset { dst_pos: 1 }
+);
+
+// A copy operation whose offset is too big.
+testerrored!(
+    err_copy_offset_big,
+    &b"\x11\x00a\x01\xFF"[..],
+    Error::Offset { offset: 255, dst_pos: 1 }
+);
+
+// A copy operation whose length is too big.
+testerrored!(
+    err_copy_len_big,
+    &b"\x05\x00a\x1d\x01"[..],
+    Error::CopyWrite { len: 11, dst_len: 4 }
+);
+
+// Selected random inputs pulled from quickcheck failure witnesses.
+testtrip!(
+    random1,
+    &[
+        0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 5, 0, 0,
+        1, 1, 0, 0, 1, 2, 0, 0, 2, 1, 0, 0, 2, 2, 0, 0, 0, 6, 0, 0, 3, 1, 0,
+        0, 0, 7, 0, 0, 1, 3, 0, 0, 0, 8, 0, 0, 2, 3, 0, 0, 0, 9, 0, 0, 1, 4,
+        0, 0, 1, 0, 0, 3, 0, 0, 1, 0, 1, 0, 0, 0, 10, 0, 0, 0, 0, 2, 4, 0, 0,
+        2, 0, 0, 3, 0, 1, 0, 0, 1, 5, 0, 0, 6, 0, 0, 0, 0, 11, 0, 0, 1, 6, 0,
+        0, 1, 7, 0, 0, 0, 12, 0, 0, 3, 2, 0, 0, 0, 13, 0, 0, 2, 5, 0, 0, 0, 3,
+        3, 0, 0, 0, 1, 8, 0, 0, 1, 0, 1, 0, 0, 0, 4, 1, 0, 0, 0, 0, 14, 0, 0,
+        0, 1, 9, 0, 0, 0, 1, 10, 0, 0, 0, 0, 1, 11, 0, 0, 0, 1, 0, 2, 0, 0, 0,
+        1, 1, 1, 0, 0, 0, 0, 5, 1, 0, 0, 0, 1, 2, 1, 0, 0, 0, 0, 0, 2, 6, 0,
+        0, 0, 0, 0, 1, 12, 0, 0, 0, 0, 0, 3, 4, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0,
+        0, 1, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0,
+    ]
+);
+testtrip!(
+    random2,
+    &[
+        10, 2, 14, 13, 0, 8, 2, 10, 2, 14, 13, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0,
+    ]
+);
+testtrip!(
+    random3,
+    &[0, 0, 0, 4, 1, 4, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,]
+);
+testtrip!(
+    random4,
+    &[
+        0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 5, 0, 0,
+        1, 1, 0, 0, 1, 2, 0, 0, 1, 3, 0, 0, 1, 4, 0, 0, 2, 1, 0, 0, 0, 4, 0,
+        1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+    ]
+);
+
+// Every failure witness saved by the QuickCheck properties below should pass
+// the same checks as the "random" tests above.
+#[test]
+fn witness_corpus() {
+    for (path, d) in read_witnesses() {
+        let name = path.display();
+        assert_eq!(d, depress(&press(&d)), "{}", name);
+        let frame = write_frame_press(&d);
+        assert_eq!(d, read_frame_depress(&frame), "{}", name);
+        assert_eq!(frame, read_frame_press(&d), "{}", name);
+        for &threads in &[2, 3, 8] {
+            let got = write_frame_press_threads(&d, threads);
+            assert_eq!(frame, got, "{}", name);
+        }
+        let expected: Vec<u32> = d
+            .chunks(snap::frame::MAX_BLOCK_SIZE)
+            .map(|chunk| mask_crc32c(crc32c_bitwise(chunk)))
+            .collect();
+        assert_eq!(expected, frame_checksums(&frame), "{}", name);
+        #[cfg(feature = "upstream")]
+        {
+            assert_eq!(d, depress_upstream(&press(&d)), "{}", name);
+            assert_eq!(d, depress(&press_upstream(&d)), "{}", name);
+            assert_eq!(d, read_frame_depress_upstream(&frame), "{}", name);
+        }
+        #[cfg(feature = "cpp")]
+        assert_eq!(press(&d), press_cpp(&d), "{}", name);
+    }
+}
+
+// QuickCheck properties for testing that random data roundtrips.
+// These properties tend to produce the inputs for the "random" tests above.
+// Their shrunk failure witnesses are saved to the corpus in test/witnesses,
+// which should be committed along with the fix.
+
+#[test]
+fn qc_roundtrip() {
+    fn p(bytes: Vec<u8>) -> bool {
+        witnessed(&bytes, || depress(&press(&bytes)) == bytes)
+    }
+    quickcheck_witnessed("roundtrip", p as fn(_) -> _);
+}
+
+#[test]
+fn qc_roundtrip_stream() {
+    fn p(bytes: Vec<u8>) -> TestResult {
+        if bytes.is_empty() {
+            return TestResult::discard();
+        }
+        TestResult::from_bool(witnessed(&bytes, || {
+            read_frame_depress(&write_frame_press(&bytes)) == bytes
+        }))
+    }
+    quickcheck_witnessed("roundtrip-stream", p as fn(_) -> _);
+}
+
+// The checksum of every chunk should match a bitwise CRC32C, for chunks of
+// arbitrary lengths starting at arbitrary alignments.
+#[test]
+fn qc_frame_checksums() {
+    fn p(bytes: Vec<u8>, offset: u8) -> TestResult {
+        use snap::{frame::EncoderOptions, write};
+        use std::io::Write;
+
+        if bytes.is_empty() {
+            return TestResult::discard();
+        }
+
+        let offset = offset as usize % 16;
+        let mut buf = vec![0; offset];
+        buf.extend_from_slice(&bytes);
+        let block_size = 1 + bytes.len() % 100;
+        let mut options = EncoderOptions::new();
+        options.block_size(block_size);
+        let mut wtr = write::FrameEncoder::with_options(vec![], &options);
+        wtr.write_all(&buf[offset..]).unwrap();
+        let frame = wtr.into_inner().unwrap();
+
+        let expected: Vec<u32> = bytes
+            .chunks(block_size)
+            .map(|chunk| mask_crc32c(crc32c_bitwise(chunk)))
+            .collect();
+        TestResult::from_bool(witnessed(&bytes, || {
+            frame_checksums(&frame) == expected
+        }))
+    }
+    quickcheck_witnessed("frame-checksums", p as fn(_, _) -> _);
+}
+
+#[test]
+#[cfg(feature = "cpp")]
+fn qc_cmpcpp() {
+    fn p(bytes: Vec<u8>) -> bool {
+        witnessed(&bytes, || press(&bytes) == press_cpp(&bytes))
+    }
+    quickcheck_witnessed("cmpcpp", p as fn(_) -> _);
+}
+
+#[test]
+#[cfg(feature = "upstream")]
+fn qc_cmpupstream() {
+    fn p(bytes: Vec<u8>) -> bool {
+        witnessed(&bytes, || {
+            depress_upstream(&press(&bytes)) == bytes
+                && depress(&press_upstream(&bytes)) == bytes
+                && read_frame_depress_upstream(&write_frame_press(&bytes))
+                    == bytes
+                && read_frame_depress(&write_frame_press_upstream(&bytes))
+                    == bytes
+        })
+    }
+    quickcheck_witnessed("cmpupstream", p as fn(_) -> _);
+}
+
+// Regression tests.
+
+// See: https://github.com/BurntSushi/rust-snappy/issues/3
+#[cfg(target_pointer_width = "32")]
+testerrored!(
+    err_lit_len_overflow1,
+    &b"\x11\x00\x00\xfc\xfe\xff\xff\xff"[..],
+    Error::Literal { len: std::u32::MAX as u64, src_len: 0, dst_len: 16 }
+);
+#[cfg(target_pointer_width = "32")]
+testerrored!(
+    err_lit_len_overflow2,
+    &b"\x11\x00\x00\xfc\xff\xff\xff\xff"[..],
+    Error::Literal { len: std::u32::MAX as u64 + 1, src_len: 0, dst_len: 16 }
+);
+
+// Helper functions.
+
+fn press(bytes: &[u8]) -> Vec<u8> {
+    Encoder::new().compress_vec(bytes).unwrap()
+}
+
+fn depress(bytes: &[u8]) -> Vec<u8> {
+    Decoder::new().decompress_vec(bytes).unwrap()
+}
+
+fn read_u24_le(bytes: &[u8]) -> usize {
+    bytes[0] as usize | (bytes[1] as usize) << 8 | (bytes[2] as usize) << 16
+}
+
+fn write_frame_press(bytes: &[u8]) -> Vec<u8> {
+    use snap::write;
+    use std::io::Write;
+
+    let mut wtr = write::FrameEncoder::new(vec![]);
+    wtr.write_all(bytes).unwrap();
+    wtr.into_inner().unwrap()
+}
+
+fn write_frame_press_threads(bytes: &[u8], threads: usize) -> Vec<u8> {
+    use snap::write;
+    use std::io::Write;
+
+    let mut wtr = write::FrameEncoder::new(vec![]);
+    wtr.threads(threads).write_all(bytes).unwrap();
+    wtr.into_inner().unwrap()
+}
+
+fn read_frame_depress(bytes: &[u8]) -> Vec<u8> {
+    use snap::read;
+    use std::io::Read;
+
+    let mut buf = vec![];
+    read::FrameDecoder::new(bytes).read_to_end(&mut buf).unwrap();
+    buf
+}
+
+fn read_frame_press(bytes: &[u8]) -> Vec<u8> {
+    use snap::read;
+    use std::io::Read;
 
     let mut buf = vec![];
     read::FrameEncoder::new(bytes).read_to_end(&mut buf).unwrap();
     buf
 }
 
+// Returns the CRC32C of `bytes`, computed one bit at a time. This is the
+// reference that every CRC32C implementation in the crate should agree with.
+fn crc32c_bitwise(bytes: &[u8]) -> u32 {
+    let mut crc: u32 = !0;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc =
+                if crc & 1 == 1 { (crc >> 1) ^ 0x82F63B78 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn mask_crc32c(crc: u32) -> u32 {
+    crc.rotate_right(15).wrapping_add(0xA282EAD8)
+}
+
+// Returns the checksums of the data chunks in a frame formatted stream written
+// by this crate, which starts with a single stream identifier.
+fn frame_checksums(mut frame: &[u8]) -> Vec<u32> {
+    use snap::frame::STREAM_IDENTIFIER;
+
+    assert!(frame.starts_with(STREAM_IDENTIFIER));
+    frame = &frame[STREAM_IDENTIFIER.len()..];
+    let mut sums = vec![];
+    while !frame.is_empty() {
+        let len = frame[1] as usize
+            | (frame[2] as usize) << 8
+            | (frame[3] as usize) << 16;
+        let crc = &frame[4..8];
+        sums.push(u32::from_le_bytes([crc[0], crc[1], crc[2], crc[3]]));
+        frame = &frame[4 + len..];
+    }
+    sums
+}
+
+// Appends `n` to `block` as a varint, which is how a raw Snappy block starts.
+fn push_varint(block: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        block.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    block.push(n as u8);
+}
+
+// Appends a literal operation holding `lit` to a raw Snappy block.
+fn push_literal(block: &mut Vec<u8>, lit: &[u8]) {
+    let n = lit.len() - 1;
+    if n < 60 {
+        block.push((n as u8) << 2);
+    } else if n < 1 << 8 {
+        block.push(60 << 2);
+        block.push(n as u8);
+    } else if n < 1 << 16 {
+        block.push(61 << 2);
+        block.extend_from_slice(&(n as u16).to_le_bytes());
+    } else {
+        block.push(62 << 2);
+        block.extend_from_slice(&(n as u32).to_le_bytes()[..3]);
+    }
+    block.extend_from_slice(lit);
+}
+
+// Appends a copy 4 operation to a raw Snappy block.
+fn push_copy4(block: &mut Vec<u8>, offset: u32, len: usize) {
+    assert!((1..=64).contains(&len));
+    block.push(((len - 1) as u8) << 2 | 0b11);
+    block.extend_from_slice(&offset.to_le_bytes());
+}
+
+// Returns the tag type, offset and length of every copy operation in a raw
+// Snappy block.
+fn copy_ops(block: &[u8]) -> Vec<(u8, usize, usize)> {
+    let mut i = 0;
+    while block[i] & 0x80 != 0 {
+        i += 1;
+    }
+    i += 1;
+    let le =
+        |bytes: &[u8]| bytes.iter().rev().fold(0, |n, &b| n << 8 | b as usize);
+    let mut ops = vec![];
+    while i < block.len() {
+        let tag = block[i];
+        let arg = (tag >> 2) as usize;
+        i += 1;
+        match tag & 0b11 {
+            0b00 if arg < 60 => i += arg + 1,
+            0b00 => {
+                let n = arg - 59;
+                i += n + le(&block[i..i + n]) + 1;
+            }
+            0b01 => {
+                let offset = (arg >> 3) << 8 | block[i] as usize;
+                ops.push((0b01, offset, (arg & 0b111) + 4));
+                i += 1;
+            }
+            0b10 => {
+                ops.push((0b10, le(&block[i..i + 2]), arg + 1));
+                i += 2;
+            }
+            _ => {
+                ops.push((0b11, le(&block[i..i + 4]), arg + 1));
+                i += 4;
+            }
+        }
+    }
+    ops
+}
+
+std::thread_local! {
+    // The last input for which a property failed or panicked, as recorded by
+    // `witnessed`.
+    static LAST_WITNESS: std::cell::RefCell<Option<Vec<u8>>> =
+        std::cell::RefCell::new(None);
+}
+
+// Runs `check` on `bytes`, the input of a property, and returns its result.
+// If the check fails or panics, then `bytes` is recorded as the latest
+// failure witness. Since QuickCheck stops shrinking once every smaller input
+// passes, the latest witness is the shrunk one.
+fn witnessed<F: FnOnce() -> bool>(bytes: &[u8], check: F) -> bool {
+    struct Guard<'a> {
+        bytes: &'a [u8],
+        passed: bool,
+    }
+
+    impl<'a> Drop for Guard<'a> {
+        fn drop(&mut self) {
+            if !self.passed {
+                let bytes = self.bytes.to_vec();
+                LAST_WITNESS.with(|w| *w.borrow_mut() = Some(bytes));
+            }
+        }
+    }
+
+    let mut guard = Guard { bytes, passed: false };
+    guard.passed = check();
+    guard.passed
+}
+
+// Runs the property `p`, whose checks are wrapped in `witnessed`, like
+// `QuickCheck::quickcheck` does. When it fails, its shrunk witness is saved to
+// the corpus before failing the test.
+fn quickcheck_witnessed<A: Testable>(name: &str, p: A) {
+    LAST_WITNESS.with(|w| w.borrow_mut().take());
+    let result = QuickCheck::new()
+        .gen(StdGen::new(rand::thread_rng(), 10_000))
+        .tests(1_000)
+        .quicktest(p);
+    if let Err(result) = result {
+        let witness = LAST_WITNESS.with(|w| w.borrow_mut().take());
+        match witness {
+            None => panic!("[quickcheck] TEST FAILED: {:?}", result),
+            Some(bytes) => panic!(
+                "[quickcheck] TEST FAILED: {:?}, witness saved to {}",
+                result,
+                save_witness(name, &bytes).display()
+            ),
+        }
+    }
+}
+
+// The directory of failure witnesses saved by `quickcheck_witnessed`.
+fn witness_dir() -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("witnesses")
+}
+
+// Saves a failure witness of the property `name`, and returns its path. The
+// file is named after the CRC32C of its contents, so that saving the same
+// witness twice doesn't add a file.
+fn save_witness(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+    let dir = witness_dir();
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}-{:08x}.bin", name, crc32c_bitwise(bytes)));
+    std::fs::write(&path, bytes).unwrap();
+    path
+}
+
+// Returns every witness in the corpus, sorted by path.
+fn read_witnesses() -> Vec<(std::path::PathBuf, Vec<u8>)> {
+    let mut paths: Vec<_> = std::fs::read_dir(witness_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("bin".as_ref()))
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| {
+            let bytes = std::fs::read(&path).unwrap();
+            (path, bytes)
+        })
+        .collect()
+}
+
+// Block sizes used by the LevelDB and RocksDB compatibility tests.
+const DB_BLOCK_SIZES: &[usize] =
+    &[1, 4 << 10, 16 << 10, 64 << 10, (64 << 10) + 1, 256 << 10, 4 << 20];
+
+// Returns a block of the given size that compresses like real data, by
+// repeating a corpus file.
+fn db_block(size: usize) -> Vec<u8> {
+    let data = &include_bytes!("../data/html_x_4")[..];
+    data.iter().cycle().take(size).cloned().collect()
+}
+
 #[cfg(feature = "cpp")]
 fn press_cpp(bytes: &[u8]) -> Vec<u8> {
     use snap::raw::max_compress_len;
@@ -569,3 +3769,39 @@ fn press_cpp(bytes: &[u8]) -> Vec<u8> {
     buf.truncate(n);
     buf
 }
+
+#[cfg(feature = "cpp")]
+fn depress_cpp(bytes: &[u8]) -> Vec<u8> {
+    let mut buf = vec![0; decompress_len(bytes).unwrap()];
+    let n = cpp::decompress(bytes, &mut buf).unwrap();
+    buf.truncate(n);
+    buf
+}
+
+#[cfg(feature = "upstream")]
+fn press_upstream(bytes: &[u8]) -> Vec<u8> {
+    upstream::raw::Encoder::new().compress_vec(bytes).unwrap()
+}
+
+#[cfg(feature = "upstream")]
+fn depress_upstream(bytes: &[u8]) -> Vec<u8> {
+    upstream::raw::Decoder::new().decompress_vec(bytes).unwrap()
+}
+
+#[cfg(feature = "upstream")]
+fn write_frame_press_upstream(bytes: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut wtr = upstream::write::FrameEncoder::new(vec![]);
+    wtr.write_all(bytes).unwrap();
+    wtr.into_inner().unwrap()
+}
+
+#[cfg(feature = "upstream")]
+fn read_frame_depress_upstream(bytes: &[u8]) -> Vec<u8> {
+    use std::io::Read;
+
+    let mut buf = vec![];
+    upstream::read::FrameDecoder::new(bytes).read_to_end(&mut buf).unwrap();
+    buf
+}